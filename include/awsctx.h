@@ -0,0 +1,25 @@
+/* C ABI of awsctx, built with the `ffi` feature. See `src/ffi.rs` for details. */
+#ifndef AWSCTX_H
+#define AWSCTX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* JSON array of contexts, or NULL on failure. Free it by awsctx_string_free. */
+char *awsctx_list_contexts(void);
+/* JSON object of the active context, or NULL on failure. Free it by awsctx_string_free. */
+char *awsctx_active_context(void);
+/* 0 on success, -1 on failure */
+int awsctx_use_context(const char *profile);
+/* 0 on success, -1 on failure */
+int awsctx_auth(const char *profile);
+/* Message of the last failure in the thread, or NULL. Never free it. */
+const char *awsctx_last_error(void);
+void awsctx_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AWSCTX_H */
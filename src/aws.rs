@@ -1,19 +1,53 @@
+use crate::awscli::{run_concurrently, AwsCli};
+use crate::cache::CredentialsCache;
 use crate::config::Config;
-use crate::configs::Configs;
-use crate::creds::Credentials;
-use crate::ctx;
+use crate::configs::{AuthCommand, Configs};
+use crate::confirm::Confirmation;
+use crate::console;
+use crate::creds::{Credentials, ProcessCredentials, TemporaryCredentials};
+use crate::ctx::{self, CTX};
+use crate::diff;
+use crate::effective::{EffectiveContext, Override};
+use crate::file::TextEncoding;
+use crate::import::ImportedProfile;
+use crate::lock;
+use crate::manifest::{
+    placeholder_credentials, Change, Manifest, ManifestProfile,
+};
+use crate::mfa::MfaCode;
+use crate::onboard::{self, ProfileKind};
+use crate::organizations::GeneratedProfile;
+use crate::picker;
+use crate::rename::Rename;
+use crate::saml;
+use crate::setting::Setting;
+use crate::sso::{self, SsoProfile};
+use crate::sso_cache::SsoTokenCache;
+use crate::state::{AccountAliases, AdhocContexts, BACKUPS_DIR};
+use crate::storage::{FileStorage, Storage};
+use crate::sts::{self, CallerIdentity};
+use crate::template;
+use crate::trash::DeletedProfile;
+use crate::validate;
 
 use dirs::home_dir;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Output, Stdio};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::{thread, time};
 
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
 use handlebars::Handlebars;
 use once_cell::sync::Lazy;
+use once_cell::unsync::OnceCell;
 use serde_json::json;
-use skim::prelude::{unbounded, Key};
+use skim::prelude::unbounded;
 use skim::{Skim, SkimItemReceiver, SkimItemSender, SkimOptions};
 
 pub static CREDENTIALS_PATH: Lazy<PathBuf> = Lazy::new(|| {
@@ -28,94 +62,1742 @@ pub static CONFIG_PATH: Lazy<PathBuf> = Lazy::new(|| {
     path
 });
 
+/// Default profiles of the credentials and the config which differ, like after one of them was edited by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultMismatch {
+    /// Context of the default profile in the credentials
+    pub credentials: String,
+    /// Default profile in the config
+    pub config: String,
+}
+
 #[derive(Debug)]
-pub struct AWS<'a, P: AsRef<Path>> {
-    config_path: P,
+pub struct AWS<'a> {
+    cli: AwsCli,
     config: Config,
     configs: Rc<Configs>,
-    credentials_path: P,
     credentials: Credentials,
-    reg: Handlebars<'a>,
+    storage: Box<dyn Storage>,
+    force: bool,
+    lock_path: Option<PathBuf>,
+    credentials_cache_path: Option<PathBuf>,
+    diff_confirmation: Option<Confirmation>,
+    mfa_code: Option<MfaCode>,
+    /// Whether expired credentials are being refreshed, not to refresh them again
+    /// on the switch at the end of the auth
+    refreshing: bool,
+    /// Registry of auth command templates, created on the first auth
+    /// not to slow down read-only commands like listing contexts
+    reg: OnceCell<Handlebars<'a>>,
 }
 
-impl<P: AsRef<Path>> AWS<'_, P> {
-    pub fn new(
+impl AWS<'_> {
+    /// Loads the config and credentials from the files.
+    pub fn new<P: AsRef<Path>>(
         configs: Rc<Configs>,
         credentials_path: P,
         config_path: P,
     ) -> Result<Self> {
-        let credentials = Credentials::load_credentials(&credentials_path)?;
-        let config = Config::load_config(&config_path)?;
-        Ok(Self {
-            config_path,
+        let storage = FileStorage::new(credentials_path, config_path)
+            .with_lenient(configs.lenient)
+            .with_protect_version_controlled(configs.protect_version_controlled)
+            .with_file_modes(configs.file_modes.clone())
+            .with_backups(configs.backups.clone(), BACKUPS_DIR.as_path())
+            .with_credentials_routes(configs.credentials_routes.clone())
+            .with_config_fragments_dir(configs.config_fragments_dir.as_ref());
+        Self::with_storage(configs, Box::new(storage))
+    }
+
+    /// Loads the config and credentials from the storage instead of the files.
+    pub fn with_storage(
+        configs: Rc<Configs>,
+        storage: Box<dyn Storage>,
+    ) -> Result<Self> {
+        let credentials = storage.load_credentials()?;
+        let config = storage.load_config()?;
+        let mut aws = Self {
+            cli: AwsCli::from_configs(&configs),
             config,
             configs,
-            credentials_path,
             credentials,
-            reg: Handlebars::new(),
+            storage,
+            force: false,
+            lock_path: None,
+            credentials_cache_path: None,
+            diff_confirmation: None,
+            mfa_code: None,
+            refreshing: false,
+            reg: OnceCell::new(),
+        };
+        aws.prefer_credentials_default();
+        Ok(aws)
+    }
+
+    /// Overwrites the files even if other processes changed them after they were loaded.
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Shows the diffs of the files and asks for the confirmation before writing them.
+    pub fn with_diff_confirmation(
+        mut self,
+        confirmation: Option<Confirmation>,
+    ) -> Self {
+        self.diff_confirmation = confirmation;
+        self
+    }
+
+    /// Reads codes of MFA devices from the source for profiles with `mfa_serial`,
+    /// which fail without one.
+    pub fn with_mfa_code(mut self, mfa_code: Option<MfaCode>) -> Self {
+        self.mfa_code = mfa_code;
+        self
+    }
+
+    /// Refuses switches to other contexts while a lock of the file is held.
+    pub fn with_lock<P: AsRef<Path>>(mut self, lock_path: P) -> Self {
+        self.lock_path = Some(lock_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Reuses credentials of assumed roles and SSO roles cached in the file until they are
+    /// about to expire, instead of calling STS or IAM Identity Center on every auth.
+    pub fn with_credentials_cache<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.credentials_cache_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Returns credentials of the role from the cache of `with_credentials_cache`,
+    /// or gets them by `fetch` and caches them. `role` is a key like the one of `role_key`.
+    fn cached_credentials<F>(
+        &self,
+        role: &str,
+        duration_seconds: Option<i64>,
+        fetch: F,
+    ) -> Result<TemporaryCredentials, ctx::CTXError>
+    where
+        F: FnOnce() -> Result<TemporaryCredentials, ctx::CTXError>,
+    {
+        let Some(path) = self.credentials_cache_path.as_ref() else {
+            return fetch();
+        };
+        let mut cache = CredentialsCache::load(Some(path))?;
+        if let Some(credentials) = cache.get(role, duration_seconds) {
+            debug!("use cached credentials for role: {}", role);
+            return Ok(credentials.clone());
+        }
+        let credentials = fetch()?;
+        cache.insert(role, duration_seconds, credentials.clone());
+        // failing to cache credentials does not affect auth itself
+        if let Err(e) = cache.dump() {
+            debug!("failed to write credentials cache: {:?}", e);
+        }
+        Ok(credentials)
+    }
+
+    /// Writes the credentials and/or the config, confirmed once for both with `--show-diff`.
+    fn dump_files(
+        &self,
+        credentials: bool,
+        config: bool,
+    ) -> Result<(), ctx::CTXError> {
+        self.ensure_unchanged()?;
+        self.confirm_diff(credentials, config)?;
+        if credentials {
+            self.storage.store_credentials(&self.credentials)?;
+        }
+        if config {
+            self.storage.store_config(&self.config)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the unified diffs of the files to be written on stderr, then asks whether to write them.
+    /// Nothing is asked if the files would be written as they are.
+    fn confirm_diff(
+        &self,
+        credentials: bool,
+        config: bool,
+    ) -> Result<(), ctx::CTXError> {
+        let confirmation = match &self.diff_confirmation {
+            Some(confirmation) => confirmation,
+            None => return Ok(()),
+        };
+        let writes = self.storage.pending_writes(
+            credentials.then_some(&self.credentials),
+            config.then_some(&self.config),
+        )?;
+        let mut changed = vec![];
+        for (path, new) in writes {
+            let path = path.to_str().unwrap().to_string();
+            // missing files are created
+            let old = fs::read(&path)
+                .map(|bytes| TextEncoding::decode(bytes).0)
+                .unwrap_or_default();
+            let diff = diff::unified(&path, &old, &new);
+            if !diff.is_empty() {
+                eprint!("{}", diff);
+                changed.push(path);
+            }
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        confirmation.confirm(
+            &mut io::stdin().lock(),
+            &mut io::stderr(),
+            "write the changes to",
+            &changed.join(", "),
+        )
+    }
+
+    fn ensure_unchanged(&self) -> Result<(), ctx::CTXError> {
+        if self.force {
+            return Ok(());
+        }
+        self.storage.ensure_unchanged()
+    }
+
+    /// Reloads the config and credentials if other processes changed them,
+    /// so that long-running modes never act on stale data.
+    /// Returns whether they were reloaded.
+    pub fn reload_if_changed(&mut self) -> Result<bool, ctx::CTXError> {
+        if self.storage.ensure_unchanged().is_ok() {
+            return Ok(false);
+        }
+        debug!("reload config and credentials changed by another process");
+        self.credentials = self.storage.load_credentials()?;
+        self.config = self.storage.load_config()?;
+        self.prefer_credentials_default();
+        Ok(true)
+    }
+
+    /// Resolves the default section of the config matching several profiles
+    /// to the default profile of the credentials, not to report them differing.
+    fn prefer_credentials_default(&mut self) {
+        if let Ok(profile) = self.credentials.get_default_profile() {
+            let name = self.context_name(&profile.name).to_string();
+            self.config.prefer_default_profile(&name);
+        }
+    }
+
+    /// Stores temporary credentials for the profile and makes it active.
+    ///
+    /// With session profiles enabled, the credentials are written to the session profile
+    /// of `profile` and the default section points there instead of the profile itself.
+    pub fn store_session_credentials(
+        &mut self,
+        profile: &str,
+        credentials: &TemporaryCredentials,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        let session_profiles = &self.configs.session_profiles;
+        let name = if session_profiles.enabled {
+            session_profiles.profile_name(profile)
+        } else {
+            profile.to_string()
+        };
+        self.credentials.set_profile_credentials(&name, credentials);
+        self.use_context(profile)
+    }
+
+    /// Mints credentials by AssumeRoleWithWebIdentity for a profile configured with
+    /// `role_arn` and `web_identity_token_file`, then makes the profile active.
+    ///
+    /// Returns `None` if the profile is not configured for web identity federation.
+    fn auth_with_web_identity(
+        &mut self,
+        profile: &str,
+    ) -> Result<Option<ctx::Context>, ctx::CTXError> {
+        let config_profile = match self.config.get_profile(profile) {
+            Ok(p) => p,
+            Err(_) => return Ok(None),
+        };
+        let (role_arn, token_file) = match (
+            config_profile.get("role_arn"),
+            config_profile.get("web_identity_token_file"),
+        ) {
+            (Some(role_arn), Some(token_file)) => (role_arn, token_file),
+            _ => return Ok(None),
+        };
+        let duration_seconds = config_profile
+            .get("duration_seconds")
+            .map(|d| d.parse::<i64>())
+            .transpose()
+            .map_err(|e| ctx::CTXError::InvalidConfigurations {
+                message: format!(
+                    "invalid duration_seconds of profile ({})",
+                    profile
+                ),
+                source: Some(e.into()),
+            })?;
+
+        let key = role_key(
+            &format!("web_identity:{}", token_file),
+            [(role_arn, config_profile.get("role_session_name"), None)],
+        );
+        let credentials =
+            self.cached_credentials(&key, duration_seconds, || {
+                let role_session_name = config_profile
+                    .get("role_session_name")
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(sts::default_role_session_name);
+                sts::assume_role_with_web_identity(
+                    &self.cli,
+                    role_arn,
+                    &role_session_name,
+                    Path::new(token_file),
+                    duration_seconds,
+                    config_profile.get("region"),
+                )
+            })?;
+        self.store_session_credentials(profile, &credentials)
+            .map(Some)
+    }
+
+    fn read_mfa_code(
+        &self,
+        profile: &str,
+        serial: &str,
+    ) -> Result<String, ctx::CTXError> {
+        match &self.mfa_code {
+            Some(mfa_code) => {
+                mfa_code.read(&mut io::stdin().lock(), &mut io::stderr(), serial)
+            }
+            None => Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "profile ({}) requires a code of MFA device ({}), give `--mfa-code`",
+                    profile, serial
+                ),
+                source: None,
+            }),
+        }
+    }
+
+    /// Returns whether the profile requires a code of its MFA device to get new credentials,
+    /// that is it has `mfa_serial` and no credentials which are still valid.
+    /// Profiles without roles get them only as session profiles, never to overwrite long-lived keys.
+    fn needs_mfa(&self, profile: &str) -> bool {
+        let Some(config_profile) = self.config.profile(profile) else {
+            return false;
+        };
+        config_profile.get("mfa_serial").is_some()
+            && (config_profile.get("role_arn").is_some()
+                || self.configs.session_profiles.enabled)
+            && self
+                .get_expiration(profile)
+                .is_none_or(|expiration| expiration <= Utc::now())
+    }
+
+    /// Returns whether the credentials of the profile are expired and `auto_refresh` renews them on switches.
+    fn needs_refresh(&self, profile: &str) -> bool {
+        self.configs.auto_refresh
+            && !self.refreshing
+            && self
+                .get_expiration(profile)
+                .is_some_and(|expiration| expiration <= Utc::now())
+    }
+
+    /// Returns why switching to the context needs the terminal, for callers without one like the daemon:
+    /// a code of the MFA device, or the auth of expired credentials by `auto_refresh`.
+    pub fn interaction_needed(&self, name: &str) -> Option<&'static str> {
+        if self.needs_mfa(name) {
+            Some("a code of the MFA device is required")
+        } else if self.needs_refresh(name) {
+            Some("the credentials are expired and need the auth")
+        } else {
+            None
+        }
+    }
+
+    /// Runs the auth of the profile for its expired credentials, which makes the profile active.
+    fn refresh_expired(&mut self, profile: &str) -> Result<(), ctx::CTXError> {
+        info!(
+            "credentials of profile ({}) are expired, authenticate again",
+            profile
+        );
+        self.refreshing = true;
+        let result = self.auth(profile);
+        self.refreshing = false;
+        result.map(|_| ())
+    }
+
+    /// Gets session credentials by GetSessionToken with a code of the MFA device
+    /// for a profile with `mfa_serial` and long-lived keys, then makes the profile active.
+    ///
+    /// Returns `None` if the profile has no `mfa_serial` or assumes a role.
+    fn auth_with_mfa_session(
+        &mut self,
+        profile: &str,
+    ) -> Result<Option<ctx::Context>, ctx::CTXError> {
+        let (serial, duration_seconds) = match self.config.profile(profile) {
+            Some(p) if p.get("role_arn").is_none() => {
+                match p.get("mfa_serial") {
+                    Some(serial) => (
+                        serial.to_string(),
+                        p.get("duration_seconds").map(|d| d.to_string()),
+                    ),
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        if !self.configs.session_profiles.enabled {
+            return Err(ctx::CTXError::InvalidConfigurations {
+                message: format!(
+                    "session credentials of profile ({}) would overwrite its long-lived keys, enable `session_profiles`",
+                    profile
+                ),
+                source: None,
+            });
+        }
+        let duration_seconds = duration_seconds
+            .map(|d| d.parse::<i64>())
+            .transpose()
+            .map_err(|e| ctx::CTXError::InvalidConfigurations {
+                message: format!(
+                    "invalid duration_seconds of profile ({})",
+                    profile
+                ),
+                source: Some(e.into()),
+            })?;
+        let code = self.read_mfa_code(profile, &serial)?;
+        let credentials = sts::get_session_token(
+            &self.cli,
+            profile,
+            &serial,
+            &code,
+            duration_seconds,
+        )?;
+        self.store_session_credentials(profile, &credentials)
+            .map(Some)
+    }
+
+    /// Signs in IAM Identity Center for a profile configured with `sso_*` items or `sso_session`,
+    /// then saves credentials of its role and makes the profile active.
+    ///
+    /// Returns `None` if the profile is not an SSO profile.
+    fn auth_with_sso(
+        &mut self,
+        profile: &str,
+    ) -> Result<Option<ctx::Context>, ctx::CTXError> {
+        let sso_profile = match SsoProfile::from_config(&self.config, profile)?
+        {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let credentials =
+            self.cached_credentials(&sso_role(&sso_profile), None, || {
+                sso::login(
+                    &self.cli,
+                    &sso_profile,
+                    &self.sso_token_cache(),
+                    |url| {
+                        // the URL is shown anyway, so a missing browser is not fatal
+                        if let Err(e) = console::open_browser(url) {
+                            debug!("failed to open a browser: {:?}", e);
+                        }
+                    },
+                )
+            })?;
+        self.store_session_credentials(profile, &credentials)
+            .map(Some)
+    }
+
+    fn sso_token_cache(&self) -> SsoTokenCache {
+        SsoTokenCache::new::<PathBuf>(None, self.configs.sso_sessions.clone())
+    }
+
+    /// Returns the key of the SSO token shared by the profile and whether the token is cached,
+    /// if it is an SSO profile.
+    fn sso_token_state(&self, profile: &str) -> Option<(String, bool)> {
+        let sso_profile =
+            SsoProfile::from_config(&self.config, profile).ok()??;
+        let cache = self.sso_token_cache();
+        Some((
+            cache.key(&sso_profile).to_string(),
+            cache.get(&sso_profile, Utc::now()).is_some(),
+        ))
+    }
+
+    /// Gets credentials of an SSO profile with the cached token of its session,
+    /// instead of running the auth command of the profile to sign in again.
+    ///
+    /// Returns `None` if the profile is not an SSO profile or no token is cached.
+    fn auth_with_cached_sso_token(
+        &mut self,
+        profile: &str,
+    ) -> Result<Option<ctx::Context>, ctx::CTXError> {
+        let Some(sso_profile) = SsoProfile::from_config(&self.config, profile)?
+        else {
+            return Ok(None);
+        };
+        let cache = self.sso_token_cache();
+        let Some(token) = cache.get(&sso_profile, Utc::now()) else {
+            return Ok(None);
+        };
+        info!(
+            "reuse the SSO token of ({}) for profile ({})",
+            cache.key(&sso_profile),
+            profile
+        );
+        let credentials =
+            self.cached_credentials(&sso_role(&sso_profile), None, || {
+                sso::get_role_credentials(&self.cli, &sso_profile, &token)
+            })?;
+        self.store_session_credentials(profile, &credentials)
+            .map(Some)
+    }
+
+    /// Saves temporary credentials as the profile without changing the active context.
+    pub fn import_credentials(
+        &mut self,
+        profile: &str,
+        credentials: &TemporaryCredentials,
+    ) -> Result<(), ctx::CTXError> {
+        self.credentials
+            .set_profile_credentials(profile, credentials);
+        self.dump_credentials()
+    }
+
+    /// Merges imported profiles into the credentials without changing the active context.
+    pub fn import_profiles(
+        &mut self,
+        profiles: Vec<ImportedProfile>,
+    ) -> Result<(), ctx::CTXError> {
+        for (name, items) in profiles {
+            self.credentials.merge_profile(&name, items);
+        }
+        self.dump_credentials()
+    }
+
+    /// Removes the profile from both files, keeping its sections in the trash
+    /// so that it can be restored by `restore_profile`.
+    pub fn delete_profile<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        trash_dir: Option<P>,
+    ) -> Result<DeletedProfile, ctx::CTXError> {
+        if self.credentials.profile(name).is_some_and(|p| p.default) {
+            return Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "cannot delete the active context ({}), switch to another one first",
+                    name
+                ),
+                source: None,
+            });
+        }
+        self.ensure_not_fragment(name)?;
+        self.ensure_unchanged()?;
+        let deleted = DeletedProfile {
+            name: name.to_string(),
+            deleted_at: Utc::now(),
+            credentials: self.credentials.remove_profile(name),
+            config: self.config.remove_profile(name),
+        };
+        if deleted.credentials.is_none() && deleted.config.is_none() {
+            return Err(ctx::CTXError::NoSuchProfile {
+                profile: name.to_string(),
+                source: Some(anyhow!("unknown context name: {}", name)),
+            });
+        }
+        // keep the sections before removing them from the files, never to lose them
+        deleted.dump(trash_dir)?;
+        self.dump_files(true, true)?;
+        Ok(deleted)
+    }
+
+    /// Brings back the profile deleted by `delete_profile`, unless a profile of the name exists.
+    pub fn restore_profile<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        trash_dir: Option<P>,
+    ) -> Result<DeletedProfile, ctx::CTXError> {
+        let trash_dir = trash_dir.map(|d| d.as_ref().to_path_buf());
+        let deleted = DeletedProfile::load(name, trash_dir.as_ref()).ok_or(
+            ctx::CTXError::NoSuchProfile {
+                profile: name.to_string(),
+                source: Some(anyhow!("no deleted profile: {}", name)),
+            },
+        )?;
+        if self.credentials.profile(name).is_some()
+            || self.config.profile(name).is_some()
+        {
+            return Err(ctx::CTXError::InvalidInput {
+                message: format!("profile ({}) already exists", name),
+                source: None,
+            });
+        }
+        self.ensure_unchanged()?;
+        if let Some(items) = &deleted.credentials {
+            self.credentials.insert_profile(name, items.clone());
+        }
+        if let Some(items) = &deleted.config {
+            self.config.insert_profile(name, items.clone());
+        }
+        self.dump_files(true, true)?;
+        deleted.discard(trash_dir.as_ref())?;
+        Ok(deleted)
+    }
+
+    /// Adds the generated profiles to the config, skipping profiles of the same names
+    /// in either file so that hand-written ones are never overwritten.
+    /// Returns names of the added profiles.
+    pub fn add_config_profiles(
+        &mut self,
+        profiles: Vec<GeneratedProfile>,
+    ) -> Result<Vec<String>, ctx::CTXError> {
+        let mut added = vec![];
+        for (name, items) in profiles {
+            if self.config.profile(&name).is_some()
+                || self.credentials.profile(&name).is_some()
+            {
+                debug!("skip existing profile: {}", name);
+                continue;
+            }
+            self.config.insert_profile(&name, items);
+            added.push(name);
+        }
+        if !added.is_empty() {
+            self.dump_config()?;
+        }
+        Ok(added)
+    }
+
+    /// Resolves the context in effect under the override of the environment, whose credentials
+    /// are matched to a profile by the access key.
+    pub fn effective_context(
+        &self,
+        overridden_by: Option<Override>,
+    ) -> EffectiveContext {
+        let active = self.get_active_context().ok().map(|c| c.name);
+        let name = match &overridden_by {
+            None => active.clone(),
+            Some(Override::Profile { name, .. }) => {
+                Some(self.context_name(name).to_string())
+            }
+            Some(Override::Credentials { access_key_id }) => {
+                let mut matched = self
+                    .credentials
+                    .iter_profiles()
+                    .filter(|p| {
+                        p.get("aws_access_key_id")
+                            == Some(access_key_id.as_str())
+                    })
+                    .collect::<Vec<_>>();
+                // the active context is preferred among profiles sharing the keys
+                matched.sort_by_key(|p| (!p.default, p.name));
+                matched
+                    .first()
+                    .map(|p| self.context_name(p.name).to_string())
+            }
+        };
+        EffectiveContext {
+            name,
+            active,
+            overridden_by,
+        }
+    }
+
+    /// Returns names of SSO profiles of the config, like ones created by `aws configure sso`,
+    /// which have no auth command of their own.
+    pub fn adoptable_profiles(&self) -> Vec<String> {
+        onboard::detect_profiles(Some(&self.config), None)
+            .into_iter()
+            .filter(|p| {
+                p.kind == ProfileKind::Sso
+                    && !self.configs.auth_commands.contains_key(&p.name)
+            })
+            .map(|p| p.name)
+            .collect()
+    }
+
+    /// Adds placeholder sections of the profiles to the credentials, so that they are listed
+    /// as contexts until their auth commands write credentials.
+    /// Returns names of the profiles whose sections are added.
+    pub fn adopt_profiles(
+        &mut self,
+        names: &[String],
+    ) -> Result<Vec<String>, ctx::CTXError> {
+        let mut added = vec![];
+        for name in names {
+            if self.credentials.profile(name).is_some() {
+                continue;
+            }
+            self.credentials
+                .insert_profile(name, placeholder_credentials());
+            added.push(name.clone());
+        }
+        if !added.is_empty() {
+            self.dump_credentials()?;
+        }
+        Ok(added)
+    }
+
+    /// Returns names of all the profiles in both files, except session profiles.
+    pub fn profile_names(&self) -> Result<Vec<String>, ctx::CTXError> {
+        let mut names = self
+            .list_contexts()?
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<String>>();
+        for profile in self.config.iter_profiles() {
+            if !names.iter().any(|n| n == profile.name) {
+                names.push(profile.name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Plans setting `key` of the profiles to `value` in the config,
+    /// skipping profiles which have the value already.
+    pub fn plan_settings(
+        &self,
+        names: &[String],
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<Setting>, ctx::CTXError> {
+        for name in names.iter() {
+            self.ensure_not_fragment(name)?;
+        }
+        Ok(names
+            .iter()
+            .filter_map(|name| {
+                let from = self
+                    .config
+                    .profile(name)
+                    .and_then(|p| p.get(key))
+                    .map(|v| v.to_string());
+                (from.as_deref() != Some(value)).then(|| Setting {
+                    profile: name.clone(),
+                    key: key.to_string(),
+                    from,
+                    to: value.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Applies the settings to the config, leaving the credentials as they are.
+    pub fn apply_settings(
+        &mut self,
+        settings: &[Setting],
+    ) -> Result<(), ctx::CTXError> {
+        for setting in settings.iter() {
+            self.ensure_not_fragment(&setting.profile)?;
+        }
+        self.ensure_unchanged()?;
+        for setting in settings.iter() {
+            self.config.set_profile_value(
+                &setting.profile,
+                &setting.key,
+                &setting.to,
+            );
+        }
+        self.dump_config()
+    }
+
+    /// Renames the profiles in both files, fixing `source_profile` referring to them,
+    /// together with their session profiles. The active context stays active by the new name.
+    pub fn rename_profiles(
+        &mut self,
+        renames: &[Rename],
+    ) -> Result<(), ctx::CTXError> {
+        for rename in renames.iter() {
+            self.ensure_not_fragment(&rename.from)?;
+        }
+        self.ensure_unchanged()?;
+        let session_profiles = &self.configs.session_profiles;
+        for rename in renames.iter() {
+            let renamed =
+                self.credentials.rename_profile(&rename.from, &rename.to)
+                    | self.config.rename_profile(&rename.from, &rename.to);
+            if !renamed {
+                return Err(ctx::CTXError::NoSuchProfile {
+                    profile: rename.from.clone(),
+                    source: Some(anyhow!(
+                        "unknown context name: {}",
+                        rename.from
+                    )),
+                });
+            }
+            if session_profiles.enabled {
+                self.credentials.rename_profile(
+                    &session_profiles.profile_name(&rename.from),
+                    &session_profiles.profile_name(&rename.to),
+                );
+            }
+        }
+        self.dump_files(true, true)
+    }
+
+    /// Plans changes reconciling both files and the configurations with the manifest.
+    pub fn plan_manifest(
+        &self,
+        manifest: &Manifest,
+        managed: &[String],
+    ) -> Vec<Change> {
+        manifest.plan(&self.config, &self.credentials, &self.configs, managed)
+    }
+
+    /// Describes the profiles in both files as a manifest, except session profiles, ad-hoc contexts
+    /// and profiles of config fragments. Secrets are never included.
+    pub fn export_manifest<P: AsRef<Path>>(
+        &self,
+        adhoc_contexts_path: Option<P>,
+    ) -> Result<Manifest, ctx::CTXError> {
+        let adhoc = AdhocContexts::load(adhoc_contexts_path);
+        let empty = HashMap::new();
+        let profiles = self
+            .profile_names()?
+            .into_iter()
+            .filter(|name| {
+                !adhoc.contains(name) && !self.config.is_fragment(name)
+            })
+            .map(|name| {
+                let items = self
+                    .config
+                    .profile(&name)
+                    .map(|p| p.items())
+                    .unwrap_or(&empty);
+                ManifestProfile::from_items(&name, items, &self.configs)
+            })
+            .collect();
+        Ok(Manifest { profiles })
+    }
+
+    /// Applies the changes planned by `plan_manifest` to both files. Sections of profiles are
+    /// replaced by the manifest, and removed profiles are kept in the trash like `delete_profile`.
+    /// Changes of the configurations are applied by `Manifest::apply_configs` instead.
+    pub fn apply_manifest<P: AsRef<Path>>(
+        &mut self,
+        manifest: &Manifest,
+        changes: &[Change],
+        trash_dir: Option<P>,
+    ) -> Result<(), ctx::CTXError> {
+        for change in changes.iter() {
+            if let Change::Remove(name) = change {
+                if self.credentials.profile(name).is_some_and(|p| p.default) {
+                    return Err(ctx::CTXError::InvalidInput {
+                        message: format!(
+                            "cannot remove the active context ({}), switch to another one first",
+                            name
+                        ),
+                        source: None,
+                    });
+                }
+                self.ensure_not_fragment(name)?;
+            }
+        }
+        self.ensure_unchanged()?;
+        for change in changes.iter() {
+            match change {
+                Change::Add(name) | Change::Update(name) => {
+                    let profile = manifest.profile(name).ok_or_else(|| {
+                        ctx::CTXError::UnexpectedError {
+                            source: Some(anyhow!(
+                                "no profile of the change in the manifest: {}",
+                                name
+                            )),
+                        }
+                    })?;
+                    let items = profile.config_items();
+                    if !items.is_empty() || self.config.profile(name).is_some()
+                    {
+                        self.config.insert_profile(name, items);
+                    }
+                    // never overwrite access keys filled in already
+                    if profile.needs_credentials()
+                        && self.credentials.profile(name).is_none()
+                    {
+                        self.credentials
+                            .insert_profile(name, placeholder_credentials());
+                    }
+                }
+                Change::Remove(name) => {
+                    DeletedProfile {
+                        name: name.clone(),
+                        deleted_at: Utc::now(),
+                        credentials: self.credentials.remove_profile(name),
+                        config: self.config.remove_profile(name),
+                    }
+                    .dump(trash_dir.as_ref())?;
+                }
+            }
+        }
+        self.dump_files(true, true)
+    }
+
+    /// Returns whether the profile is of config fragments, which cannot be changed.
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.config.is_fragment(name)
+    }
+
+    /// Profiles of config fragments are read-only, so that changes to them never get lost silently.
+    fn ensure_not_fragment(&self, name: &str) -> Result<(), ctx::CTXError> {
+        if self.is_read_only(name) {
+            return Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "profile ({}) is defined in a config fragment, which is read-only",
+                    name
+                ),
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Assumes the role with credentials of the active context, saves the credentials
+    /// as an ad-hoc context named after the role unless `name` is given, then makes it active.
+    ///
+    /// Ad-hoc contexts whose credentials expired are removed from the files meanwhile,
+    /// and existing profiles other than ad-hoc contexts are never overwritten.
+    pub fn assume_role<P: AsRef<Path>>(
+        &mut self,
+        role_arn: &str,
+        name: Option<&str>,
+        duration_seconds: Option<i64>,
+        adhoc_contexts_path: Option<P>,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => sts::adhoc_context_name(role_arn).ok_or_else(|| {
+                ctx::CTXError::InvalidInput {
+                    message: format!("invalid role ARN ({})", role_arn),
+                    source: None,
+                }
+            })?,
+        };
+        let mut adhoc = AdhocContexts::load(adhoc_contexts_path);
+        if !adhoc.contains(&name)
+            && (self.credentials.profile(&name).is_some()
+                || self.config.profile(&name).is_some())
+        {
+            return Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "profile ({}) already exists, give another name",
+                    name
+                ),
+                source: None,
+            });
+        }
+        self.ensure_unchanged()?;
+        let source = self.get_active_context()?.name;
+        let key = role_key(
+            &format!("profile:{}", self.credentials_profile_name(&source)),
+            [(role_arn, None, None)],
+        );
+        let credentials =
+            self.cached_credentials(&key, duration_seconds, || {
+                sts::assume_role(
+                    &self.cli,
+                    &self.credentials_profile_name(&source),
+                    role_arn,
+                    &sts::default_role_session_name(),
+                    duration_seconds,
+                )
+            })?;
+        for expired in adhoc.expired(Utc::now()) {
+            // the active context is kept until switched to another one
+            if expired != source {
+                debug!("remove expired ad-hoc context: {}", expired);
+                self.credentials.remove_profile(&expired);
+                self.config.remove_profile(&expired);
+                adhoc.remove(&expired);
+            }
+        }
+        // the ad-hoc context works in the region of the source one
+        let region = self
+            .config
+            .get_default_region()
+            .or_else(|| {
+                self.config.profile(&source).and_then(|p| p.get("region"))
+            })
+            .map(|r| r.to_string());
+        self.config.insert_profile(
+            &name,
+            region
+                .map(|r| HashMap::from([("region".to_string(), r)]))
+                .unwrap_or_default(),
+        );
+        self.credentials
+            .set_profile_credentials(&name, &credentials);
+        adhoc.insert(&name, credentials.expiration);
+        let context = self.use_context(&name)?;
+        adhoc.dump()?;
+        Ok(context)
+    }
+
+    /// Re-syncs the default profile to `profile`, or to the profile closest to
+    /// the default section when it was edited by hand.
+    pub fn repair_default_profile(
+        &mut self,
+        profile: Option<&str>,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        let name = match profile {
+            Some(profile) => profile.to_string(),
+            None => match self.credentials.get_default_profile() {
+                Ok(profile) => self.context_name(&profile.name).to_string(),
+                Err(ctx::CTXError::DefaultDrifted {
+                    closest: Some(closest),
+                    ..
+                }) => self.context_name(&closest).to_string(),
+                Err(e) => return Err(e),
+            },
+        };
+        self.use_context(&name)
+    }
+
+    /// Reports the default profiles of the credentials and the config differing,
+    /// where the AWS CLI reads keys of one profile and the region of another.
+    /// A default section matching no profile is reported as a drift instead.
+    pub fn default_mismatch(&self) -> Option<DefaultMismatch> {
+        let credentials = self.credentials.get_default_profile().ok()?;
+        let credentials = self.context_name(&credentials.name);
+        let config = self.config.default_profile_name()?;
+        (credentials != config).then(|| DefaultMismatch {
+            credentials: credentials.to_string(),
+            config: config.to_string(),
         })
     }
-}
 
-impl<P: AsRef<Path>> ctx::CTX for AWS<'_, P> {
-    fn auth(&mut self, profile: &str) -> Result<ctx::Context, ctx::CTXError> {
-        let script_template = self
+    /// Switches the default profile, overriding the region in the default section if given,
+    /// then writes both files.
+    fn switch_context(
+        &mut self,
+        name: &str,
+        region: Option<&str>,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        if let Some(lock_path) = &self.lock_path {
+            lock::ensure_switchable(lock_path, name)?;
+        }
+        // new credentials make the profile active, and the region is set on top of it
+        let authenticated = if self.needs_mfa(name) {
+            if self
+                .config
+                .profile(name)
+                .is_some_and(|p| p.get("role_arn").is_some())
+            {
+                self.assume(name)?;
+            } else {
+                self.auth_with_mfa_session(name)?;
+            }
+            true
+        } else if self.needs_refresh(name) {
+            self.refresh_expired(name)?;
+            true
+        } else {
+            false
+        };
+        let identity = self.verify_identity(name)?;
+        let mut context = if authenticated && region.is_none() {
+            self.get_active_context()?
+        } else {
+            // check both files first not to leave one of them switched
+            self.ensure_unchanged()?;
+            let context = self.set_default_profile(name)?;
+            if let Some(region) = region {
+                self.config.set_default_region(region)?;
+            }
+            self.dump_files(true, true)?;
+            context
+        };
+        if let Some(identity) = identity {
+            context.account = Some(identity.account);
+            context.arn = Some(identity.arn);
+        }
+        Ok(context)
+    }
+
+    /// Calls GetCallerIdentity with credentials of the context when `verify_identity` is enabled,
+    /// failing with `CredentialsInvalid` before the switch if AWS rejects them.
+    /// Nothing is verified in offline mode.
+    fn verify_identity(
+        &self,
+        name: &str,
+    ) -> Result<Option<CallerIdentity>, ctx::CTXError> {
+        if !self.configs.verify_identity || self.configs.offline {
+            return Ok(None);
+        }
+        // the credentials are passed directly not to let the AWS CLI assume roles of the config
+        let cli = self
+            .cli
+            .clone()
+            .with_credentials_env(&self.context_env(name, None)?);
+        validate::verify_credentials(&cli, name).map(Some)
+    }
+
+    /// Returns when the session of the context expires, if it is recorded in the credentials.
+    pub fn get_expiration(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.credentials
+            .profile(&self.credentials_profile_name(name))
+            .and_then(|p| p.expiration())
+    }
+
+    /// Returns credentials of the context for `credential_process`, reading the credentials only.
+    pub fn process_credentials(
+        &self,
+        name: &str,
+    ) -> Result<ProcessCredentials, ctx::CTXError> {
+        self.credentials
+            .get_profile(&self.credentials_profile_name(name))?
+            .process_credentials()
+    }
+
+    /// Returns environment variables of AWS SDKs and CLI to use credentials of the context.
+    /// The region is taken from the config unless `region` overrides it.
+    pub fn context_env(
+        &self,
+        name: &str,
+        region: Option<&str>,
+    ) -> Result<Vec<(String, String)>, ctx::CTXError> {
+        let profile = self
+            .credentials
+            .get_profile(&self.credentials_profile_name(name))?;
+        let get = |key: &str| {
+            profile
+                .get(key)
+                .ok_or_else(|| ctx::CTXError::CredentialsIsBroken {
+                    source: Some(anyhow!("no {} in profile ({})", key, name)),
+                })
+        };
+        let mut vars = vec![
+            (
+                "AWS_ACCESS_KEY_ID".to_string(),
+                get("aws_access_key_id")?.to_string(),
+            ),
+            (
+                "AWS_SECRET_ACCESS_KEY".to_string(),
+                get("aws_secret_access_key")?.to_string(),
+            ),
+        ];
+        if let Some(token) = profile.get("aws_session_token") {
+            vars.push(("AWS_SESSION_TOKEN".to_string(), token.to_string()));
+        }
+        if let Some(expiration) = profile.expiration() {
+            vars.push((
+                "AWS_CREDENTIAL_EXPIRATION".to_string(),
+                expiration.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ));
+        }
+        let region = region.map(|r| r.to_string()).or_else(|| {
+            self.config.get_profile(name).ok().and_then(|p| {
+                // the active context may be switched with another region
+                self.config
+                    .get_default_region()
+                    .filter(|_| p.default)
+                    .or_else(|| p.get("region"))
+                    .map(|r| r.to_string())
+            })
+        });
+        if let Some(region) = region {
+            vars.push(("AWS_REGION".to_string(), region.clone()));
+            vars.push(("AWS_DEFAULT_REGION".to_string(), region));
+        }
+        Ok(vars)
+    }
+
+    pub fn describe_context(
+        &self,
+        context: ctx::Context,
+    ) -> ctx::ContextMetadata {
+        let config_profile = self.config.profile(&context.name);
+        let config_value = |key| config_profile.and_then(|p| p.get(key));
+        let account = config_value("sso_account_id")
+            .or_else(|| config_value("aws_account_id"))
+            // arn:aws:iam::123456789012:role/name
+            .or_else(|| {
+                config_value("role_arn").and_then(|a| a.split(':').nth(4))
+            })
+            .map(|a| a.to_string())
+            .or_else(|| {
+                self.credentials
+                    .profile(&self.credentials_profile_name(&context.name))
+                    .and_then(|p| p.get("aws_account_id"))
+                    .map(|a| a.to_string())
+            });
+        let tags = self
+            .configs
+            .tags
+            .get(&context.name)
+            .cloned()
+            .unwrap_or_default();
+        ctx::ContextMetadata {
+            account,
+            region: self
+                .config
+                .get_default_region()
+                .filter(|_| context.active)
+                .or_else(|| config_value("region"))
+                .map(|r| r.to_string()),
+            expires_at: self.get_expiration(&context.name),
+            label: self.configs.label_of(&context.name, &tags),
+            tags,
+            description: self.configs.descriptions.get(&context.name).cloned(),
+            aliases: self.configs.aliases_of(&context.name),
+            missing_credentials: self
+                .credentials
+                .profile(&self.credentials_profile_name(&context.name))
+                .is_some_and(|p| {
+                    p.get("aws_access_key_id").is_none_or(|k| k.is_empty())
+                }),
+            source: ProfileKind::of_config(|k| {
+                config_profile.is_some_and(|p| p.get(k).is_some())
+            })
+            .or_else(|| {
+                self.credentials
+                    .profile(&self.credentials_profile_name(&context.name))
+                    .filter(|p| p.get("aws_access_key_id").is_some())
+                    .map(|_| ProfileKind::Static)
+            })
+            .map(|k| k.keyword().to_string()),
+            name: context.name,
+            active: context.active,
+            identity: None,
+        }
+    }
+
+    /// Returns names of profiles which can provide credentials, that is all the contexts
+    /// and profiles in the config which get credentials by SSO, roles or external processes.
+    pub fn credential_profile_names(
+        &self,
+    ) -> Result<Vec<String>, ctx::CTXError> {
+        let mut names = self
+            .list_contexts()?
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<String>>();
+        for profile in self.config.iter_profiles() {
+            let provides_credentials = [
+                "sso_start_url",
+                "sso_session",
+                "role_arn",
+                "credential_process",
+            ]
+            .iter()
+            .any(|key| profile.get(key).is_some());
+            if provides_credentials && !names.iter().any(|n| n == profile.name)
+            {
+                names.push(profile.name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Picks a context by the finder, and returns it with the action of the key accepting it.
+    /// Only `enter` is accepted unless other keys are given to `expect` of the options.
+    fn pick_context_with_action(
+        &self,
+        skim_options: &SkimOptions,
+    ) -> Result<(ctx::PickerAction, Option<String>), ctx::CTXError> {
+        let contexts = self.describe_contexts()?;
+        let items = match &self.configs.listing.format {
+            Some(format) => ctx::PickerItem::render(
+                &contexts,
+                format,
+                &AccountAliases::load::<PathBuf>(None),
+            )?,
+            None => ctx::PickerItem::from_contexts(&contexts),
+        };
+        let items = ctx::PickerItem::mark_unusable(
+            items,
+            &contexts,
+            Utc::now(),
+            self.configs.picker.expired_last,
+        );
+        if let Some(command) = &self.configs.picker.command {
+            let lines = items
+                .iter()
+                .map(|i| i.line.clone())
+                .collect::<Vec<String>>();
+            let picked = picker::pick_by_command(command, &lines)?;
+            return Ok((
+                ctx::PickerAction::Switch,
+                Some(items[picked].name.clone()),
+            ));
+        }
+        let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) =
+            unbounded();
+        // skim shows reverse order
+        for item in items.into_iter().rev() {
+            tx_item
+                .send(Arc::new(item))
+                .context("failed to send an item to skim")
+                .map_err(|e| ctx::CTXError::UnexpectedError {
+                    source: Some(e),
+                })?;
+        }
+        drop(tx_item);
+
+        let out = Skim::run_with(skim_options, Some(rx_item))
+            .ok_or(ctx::CTXError::NoContextIsSelected { source: None })?;
+        let action = ctx::PickerAction::from_key(&out.final_key)
+            .filter(|_| !out.is_abort)
+            .ok_or(ctx::CTXError::NoContextIsSelected { source: None })?;
+        let name = match out.selected_items.first() {
+            Some(item) => Some(
+                (*item)
+                    .as_any()
+                    .downcast_ref::<ctx::PickerItem>()
+                    .ok_or(ctx::CTXError::UnexpectedError {
+                        source: Some(anyhow!("unexpected error")),
+                    })?
+                    .name
+                    .clone(),
+            ),
+            None => None,
+        };
+        Ok((action, name))
+    }
+
+    /// Returns the credentials profile which backs the context `name`.
+    fn credentials_profile_name(&self, name: &str) -> String {
+        let session_profiles = &self.configs.session_profiles;
+        if session_profiles.enabled {
+            let session_name = session_profiles.profile_name(name);
+            if self.credentials.profile(&session_name).is_some() {
+                return session_name;
+            }
+        }
+        name.to_string()
+    }
+
+    /// Returns the context name for the credentials profile `name`,
+    /// which is the base profile name for session profiles.
+    fn context_name<'b>(&self, name: &'b str) -> &'b str {
+        self.configs
+            .session_profiles
+            .base_profile_name(name)
+            .filter(|base| {
+                self.credentials.profile(base).is_some()
+                    || self.config.profile(base).is_some()
+            })
+            .unwrap_or(name)
+    }
+
+    /// Renders the auth command of the profile, falling back to the default one.
+    fn auth_process(
+        &self,
+        profile: &str,
+    ) -> Result<AuthProcess, ctx::CTXError> {
+        let (command_key, command_template) = self
             .configs
             .auth_commands
-            .get(profile)
+            .get_key_value(profile)
             // fallback to default configuration if a command for the profile is not found
             .or_else(|| {
                 self.configs
                     .auth_commands
-                    .get(Configs::DEFAULT_AUTH_COMMAND_KEY)
+                    .get_key_value(Configs::DEFAULT_AUTH_COMMAND_KEY)
             })
             .ok_or_else(|| ctx::CTXError::NoAuthConfiguration {
                 profile: profile.to_string(),
                 source: None,
             })?;
-        let script = self
-            .reg
-            .render_template(script_template, &json!({ "profile": profile }))
-            .map_err(|e| ctx::CTXError::InvalidConfigurations {
+        let render = |template: &str| {
+            self.reg
+                .get_or_init(template::registry)
+                .render_template(template, &json!({ "profile": profile }))
+                .map_err(|e| ctx::CTXError::InvalidConfigurations {
+                    message: format!(
+                        "failed to render script of profile {}",
+                        profile
+                    ),
+                    source: Some(anyhow!("failed to render script {}", e)),
+                })
+        };
+        let argv = match &command_template.command {
+            AuthCommand::Script(script) => {
+                vec!["sh".to_string(), "-c".to_string(), render(script)?]
+            }
+            AuthCommand::Argv(argv) => {
+                argv.iter()
+                    .map(|arg| render(arg))
+                    .collect::<Result<Vec<String>, ctx::CTXError>>()?
+            }
+        };
+        if argv.is_empty() {
+            return Err(ctx::CTXError::InvalidConfigurations {
                 message: format!(
-                    "failed to render script of profile {}",
+                    "auth command of profile ({}) is empty, check configurations",
                     profile
                 ),
-                source: Some(anyhow!("failed to render script {}", e)),
-            })?;
+                source: None,
+            });
+        }
+        Ok(AuthProcess {
+            profile: profile.to_string(),
+            argv,
+            credential_output: self
+                .configs
+                .credential_outputs
+                .contains(command_key),
+            timeout_seconds: command_template.timeout_seconds,
+            retries: command_template.retries,
+        })
+    }
 
-        let status = Command::new("sh")
-            .arg("-c")
-            .arg(script)
-            .status()
-            .map_err(|e| ctx::CTXError::InvalidConfigurations {
-                message: format!(
-                    "failed to execute an auth script of profile ({}), check configurations",
-                    profile
-                ),
-                source: Some(anyhow!("failed to execute an auth script: {}", e)),
-            })?;
-        if !status.success() {
-            return Err(ctx::CTXError::InvalidConfigurations {
+    /// Makes the profile active after its auth command, storing the credentials it printed if any.
+    fn finish_auth(
+        &mut self,
+        profile: &str,
+        credentials: Option<TemporaryCredentials>,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        if let Some(credentials) = credentials {
+            return self.store_session_credentials(profile, &credentials);
+        }
+        // scripts like `aws configure` write the files themselves
+        self.reload_if_changed()?;
+        self.use_context(profile)
+    }
+}
+
+/// Key of credentials of the role of an SSO profile in `CredentialsCache`, which have no role ARN.
+/// Key of credentials of the last of roles assumed in turn from the source in the cache,
+/// telling apart ones of the same role obtained from other sources, or with other session names
+/// or external IDs. Default session names are left out, as they differ on every call.
+fn role_key<'b, I>(source: &str, roles: I) -> String
+where
+    I: IntoIterator<Item = (&'b str, Option<&'b str>, Option<&'b str>)>,
+{
+    roles.into_iter().fold(
+        source.to_string(),
+        |key, (role_arn, session, external_id)| {
+            format!(
+                "{}>{}|{}|{}",
+                key,
+                role_arn,
+                session.unwrap_or_default(),
+                external_id.unwrap_or_default()
+            )
+        },
+    )
+}
+
+fn sso_role(profile: &SsoProfile) -> String {
+    format!(
+        "sso:{}:{}:{}",
+        profile.start_url, profile.account_id, profile.role_name
+    )
+}
+
+/// Auth command of a profile rendered by `AWS::auth_process`, which runs without touching the files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AuthProcess {
+    profile: String,
+    argv: Vec<String>,
+    /// Whether the command prints credentials in the JSON of `credential_process` on stdout
+    credential_output: bool,
+    timeout_seconds: Option<u64>,
+    retries: u32,
+}
+
+impl AuthProcess {
+    /// Runs the command, again up to `retries` times when it fails or times out.
+    /// Returns the credentials printed by the command if it prints them.
+    fn run(
+        &self,
+        attached: bool,
+    ) -> Result<Option<TemporaryCredentials>, ctx::CTXError> {
+        let mut attempt = 0;
+        loop {
+            match self.run_once(attached) {
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        "retrying auth command of profile ({}) ({}/{}): {}",
+                        self.profile, attempt, self.retries, e
+                    );
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs the command once, attached to the terminal or with stdin closed and outputs captured
+    /// not to mix them with ones of other commands running alongside.
+    fn run_once(
+        &self,
+        attached: bool,
+    ) -> Result<Option<TemporaryCredentials>, ctx::CTXError> {
+        let failed = |source| {
+            ctx::CTXError::InvalidConfigurations {
                 message: format!(
                     "failed to execute an auth script of profile ({}), check configurations",
-                    profile
+                    self.profile
                 ),
-                source: Some(anyhow!("failed to run auth script, check output logs")),
-            });
+                source: Some(source),
+            }
+        };
+        let mut command = Command::new(&self.argv[0]);
+        command.args(&self.argv[1..]);
+        // children of detached scripts, like browsers opened by them, are killed together
+        // on the timeout. Attached ones stay in the foreground group to read the terminal.
+        let grouped = !attached && self.timeout_seconds.is_some();
+        if grouped {
+            command.process_group(0);
         }
-        self.use_context(profile)
+        if !attached {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        } else if self.credential_output {
+            // prompts are left on the terminal while credentials are read from stdout
+            command.stdout(Stdio::piped());
+        }
+        let child = command.spawn().map_err(|e| {
+            failed(anyhow!("failed to execute an auth script: {}", e))
+        })?;
+        let output = self.wait(child, grouped).map_err(|e| match e {
+            WaitError::TimedOut => ctx::CTXError::AuthTimedOut {
+                profile: self.profile.clone(),
+                timeout_seconds: self.timeout_seconds.unwrap_or_default(),
+                source: None,
+            },
+            WaitError::Io(e) => {
+                failed(anyhow!("failed to wait for an auth script: {}", e))
+            }
+        })?;
+        if !output.status.success() {
+            return Err(failed(if attached {
+                anyhow!("failed to run auth script, check output logs")
+            } else {
+                anyhow!(
+                    "failed to run auth script: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+            }));
+        }
+        if !self.credential_output {
+            return Ok(None);
+        }
+        serde_json::from_slice::<TemporaryCredentials>(&output.stdout)
+            .context("failed to parse credentials printed by the auth script")
+            .map(Some)
+            .map_err(failed)
+    }
+
+    /// Waits for the command, killing it once `timeout_seconds` passes,
+    /// together with its process group if it leads one.
+    fn wait(
+        &self,
+        mut child: Child,
+        grouped: bool,
+    ) -> Result<Output, WaitError> {
+        let Some(timeout_seconds) = self.timeout_seconds else {
+            return Ok(child.wait_with_output()?);
+        };
+        // pipes are drained alongside not to block the command writing a lot
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            pipe.map(|mut pipe| {
+                thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    pipe.read_to_end(&mut buf).map(|_| buf)
+                })
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+        let deadline =
+            time::Instant::now() + time::Duration::from_secs(timeout_seconds);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if time::Instant::now() >= deadline {
+                if grouped {
+                    // the command leads its own group, whose ID is the PID of the command
+                    unsafe {
+                        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                    }
+                } else {
+                    child.kill()?;
+                }
+                child.wait()?;
+                return Err(WaitError::TimedOut);
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        };
+        let join = |reader: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| {
+            reader
+                .map(|r| r.join().unwrap_or_else(|_| Ok(Vec::new())))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
+        Ok(Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum WaitError {
+    TimedOut,
+    Io(io::Error),
+}
+
+impl From<io::Error> for WaitError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl ctx::CTX for AWS<'_> {
+    fn auth(&mut self, profile: &str) -> Result<ctx::Context, ctx::CTXError> {
+        let configs = Rc::clone(&self.configs);
+        if let Some(saml_configs) = configs.saml.get(profile) {
+            configs.ensure_online("SAML login")?;
+            let credentials = saml::login(saml_configs, profile)?;
+            return self.store_session_credentials(profile, &credentials);
+        }
+        // a command configured for the profile takes precedence over implicit built-in flows
+        if !self.configs.auth_commands.contains_key(profile) {
+            if let Some(context) = self.auth_with_web_identity(profile)? {
+                return Ok(context);
+            }
+            if let Some(context) = self.auth_with_sso(profile)? {
+                return Ok(context);
+            }
+            if self
+                .config
+                .profile(profile)
+                .is_some_and(|p| p.get("source_profile").is_some())
+            {
+                return self.assume(profile);
+            }
+            if let Some(context) = self.auth_with_mfa_session(profile)? {
+                return Ok(context);
+            }
+        } else if let Some(context) =
+            self.auth_with_cached_sso_token(profile)?
+        {
+            return Ok(context);
+        }
+        let process = self.auth_process(profile)?;
+        let credentials = process.run(true)?;
+        self.finish_auth(profile, credentials)
+    }
+
+    fn auth_all(
+        &mut self,
+        profiles: &[String],
+        parallelism: usize,
+    ) -> Vec<Result<ctx::Context, ctx::CTXError>> {
+        // commands configured for the profiles run concurrently, and the files are written
+        // one by one after them. Other profiles are authenticated in turn by `auth`,
+        // like SSO profiles reusing the token of a session signed in by another one.
+        let mut sso_tokens = HashSet::new();
+        let processes = profiles
+            .iter()
+            .filter(|p| {
+                self.configs.auth_commands.contains_key(*p)
+                    && !self.configs.saml.contains_key(*p)
+            })
+            .filter(|p| match self.sso_token_state(p) {
+                Some((key, cached)) => !cached && sso_tokens.insert(key),
+                None => true,
+            })
+            .filter_map(|p| self.auth_process(p).ok())
+            .collect::<Vec<AuthProcess>>();
+        // a single command is left attached to the terminal, which may prompt
+        let attached = parallelism <= 1;
+        let mut outputs = processes
+            .iter()
+            .map(|p| p.profile.clone())
+            .zip(run_concurrently(&processes, parallelism, |p| {
+                p.run(attached)
+            }))
+            .collect::<HashMap<String, _>>();
+        profiles
+            .iter()
+            .map(|profile| match outputs.remove(profile) {
+                Some(credentials) => {
+                    credentials.and_then(|c| self.finish_auth(profile, c))
+                }
+                None => self.auth(profile),
+            })
+            .collect()
+    }
+
+    fn assume(&mut self, profile: &str) -> Result<ctx::Context, ctx::CTXError> {
+        let chain = self.config.role_chain(profile)?;
+        // credentials of the chain are the ones of its last role
+        let last = chain.roles.last().ok_or_else(|| {
+            ctx::CTXError::InvalidConfigurations {
+                message: format!("no role to assume for profile ({})", profile),
+                source: None,
+            }
+        })?;
+        let key = role_key(
+            &format!(
+                "profile:{}",
+                self.credentials_profile_name(&chain.source)
+            ),
+            chain.roles.iter().map(|r| {
+                (
+                    r.role_arn.as_str(),
+                    r.role_session_name.as_deref(),
+                    r.external_id.as_deref(),
+                )
+            }),
+        );
+        let credentials =
+            self.cached_credentials(&key, last.duration_seconds, || {
+                // a code is valid only once, so it is read for the first role requiring it
+                let code = chain
+                    .roles
+                    .iter()
+                    .find_map(|r| {
+                        r.mfa_serial.as_ref().map(|s| (&r.profile, s))
+                    })
+                    .map(|(profile, serial)| {
+                        self.read_mfa_code(profile, serial)
+                    })
+                    .transpose()?;
+                sts::assume_role_chain(
+                    &self.cli,
+                    &self.credentials_profile_name(&chain.source),
+                    &chain.roles,
+                    code.as_deref(),
+                )
+            })?;
+        self.store_session_credentials(profile, &credentials)
     }
 
     fn list_contexts(&self) -> Result<Vec<ctx::Context>, ctx::CTXError> {
-        Ok(self
+        let active_name = self
             .credentials
-            .list_profiles()
-            .into_iter()
+            .get_default_profile()
+            .map(|p| self.context_name(&p.name).to_string())
+            .ok();
+        let mut contexts = self
+            .credentials
+            .iter_profiles()
+            // session profiles are listed as their base profiles
+            .filter(|p| self.context_name(p.name) == p.name)
             .map(|p| ctx::Context {
+                active: active_name.as_deref() == Some(p.name),
                 name: p.name.to_string(),
-                active: p.default,
+                expires_at: self.get_expiration(p.name),
+                ..Default::default()
             })
+            .collect::<Vec<ctx::Context>>();
+        contexts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(contexts)
+    }
+
+    fn describe_contexts(
+        &self,
+    ) -> Result<Vec<ctx::ContextMetadata>, ctx::CTXError> {
+        Ok(self
+            .list_contexts()?
+            .into_iter()
+            .map(|c| self.describe_context(c))
             .collect())
     }
 
@@ -123,8 +1805,10 @@ impl<P: AsRef<Path>> ctx::CTX for AWS<'_, P> {
         self.credentials
             .get_default_profile()
             .map(|p| ctx::Context {
-                name: p.name.to_string(),
+                name: self.context_name(&p.name).to_string(),
                 active: p.default,
+                expires_at: self.get_expiration(self.context_name(&p.name)),
+                ..Default::default()
             })
     }
 
@@ -132,70 +1816,78 @@ impl<P: AsRef<Path>> ctx::CTX for AWS<'_, P> {
         &mut self,
         name: &str,
     ) -> Result<ctx::Context, ctx::CTXError> {
+        let creds_profile_name = self.credentials_profile_name(name);
         let creds = &mut self.credentials;
         let config = &mut self.config;
-        let creds_profile = creds.set_default_profile(name)?;
+        let creds_profile = creds.set_default_profile(&creds_profile_name)?;
         config.set_default_profile(name)?;
         Ok(ctx::Context {
-            name: creds_profile.name.to_string(),
+            name: name.to_string(),
             active: creds_profile.default,
+            expires_at: creds_profile.expiration(),
+            ..Default::default()
         })
     }
 
     fn dump_credentials(&self) -> Result<(), ctx::CTXError> {
-        self.credentials.dump_credentials(&self.credentials_path)?;
-        Ok(())
+        self.dump_files(true, false)
     }
 
     fn dump_config(&self) -> Result<(), ctx::CTXError> {
-        self.config.dump_config(&self.config_path)?;
-        Ok(())
+        self.dump_files(false, true)
     }
 
     fn use_context(
         &mut self,
         name: &str,
     ) -> Result<ctx::Context, ctx::CTXError> {
-        let profile = self.set_default_profile(name)?;
-        self.dump_credentials()?;
-        self.dump_config()?;
-        Ok(ctx::Context {
-            name: profile.name.to_string(),
-            active: profile.active,
-        })
+        self.switch_context(name, None)
     }
 
-    fn use_context_interactive(
+    fn use_context_in_region(
         &mut self,
-        skim_options: SkimOptions,
+        name: &str,
+        region: &str,
     ) -> Result<ctx::Context, ctx::CTXError> {
-        let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) =
-            unbounded();
-        // skim shows reverse order
-        for context in self.list_contexts()?.into_iter().rev() {
-            tx_item
-                .send(Arc::new(context))
-                .context("failed to send an item to skim")
-                .map_err(|e| ctx::CTXError::UnexpectedError {
-                    source: Some(e),
-                })?;
+        self.switch_context(name, Some(region))
+    }
+
+    fn pick_context_interactive(
+        &self,
+        skim_options: &SkimOptions,
+    ) -> Result<String, ctx::CTXError> {
+        match self.pick_context_with_action(skim_options)? {
+            (ctx::PickerAction::Switch, Some(name)) => Ok(name),
+            _ => Err(ctx::CTXError::NoContextIsSelected { source: None }),
         }
-        drop(tx_item);
+    }
 
-        let selected_items = Skim::run_with(&skim_options, Some(rx_item))
-            .map(|out| match out.final_key {
-                Key::Enter => Ok(out.selected_items),
-                _ => Err(ctx::CTXError::NoContextIsSelected { source: None }),
-            })
-            .unwrap_or(Ok(Vec::new()))?;
-        let item = selected_items
-            .get(0)
-            .ok_or(ctx::CTXError::NoContextIsSelected { source: None })?;
-        let context = (*item).as_any().downcast_ref::<ctx::Context>().ok_or(
-            ctx::CTXError::UnexpectedError {
-                source: Some(anyhow!("unexpected error")),
-            },
-        )?;
-        self.use_context(&context.name)
+    fn use_context_interactive(
+        &mut self,
+        mut skim_options: SkimOptions,
+    ) -> Result<ctx::Context, ctx::CTXError> {
+        skim_options.expect =
+            Some(ctx::PickerAction::EXPECTED_KEYS.to_string());
+        skim_options.header = Some(ctx::PickerAction::HEADER);
+        loop {
+            let (action, name) =
+                self.pick_context_with_action(&skim_options)?;
+            // the finder may stay open for a long time, act on the latest files
+            self.reload_if_changed()?;
+            match (action, name) {
+                (ctx::PickerAction::Reload, _) => continue,
+                (ctx::PickerAction::Switch, Some(name)) => {
+                    return self.use_context(&name)
+                }
+                (ctx::PickerAction::Auth, Some(name)) => {
+                    return self.auth(&name)
+                }
+                (_, None) => {
+                    return Err(ctx::CTXError::NoContextIsSelected {
+                        source: None,
+                    })
+                }
+            }
+        }
     }
 }
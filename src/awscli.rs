@@ -0,0 +1,422 @@
+use crate::configs::{AwsCliConfigs, Configs, ProxyConfigs};
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+
+/// Variables of credentials inherited from the environment, removed when credentials are given
+/// by `envs`, not to mix a session token of the shell with static keys of a profile.
+const CREDENTIALS_VARS: [&str; 6] = [
+    "AWS_PROFILE",
+    "AWS_DEFAULT_PROFILE",
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_SECURITY_TOKEN",
+];
+
+/// Runs the AWS CLI for the built-in features that talk to AWS APIs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsCli {
+    configs: AwsCliConfigs,
+    envs: Vec<(String, String)>,
+    offline: bool,
+    /// Whether credentials are given by `envs` instead of `AWS_PROFILE`
+    with_credentials: bool,
+}
+
+impl Default for AwsCli {
+    fn default() -> Self {
+        Self::new("aws")
+    }
+}
+
+impl AwsCli {
+    pub fn new(program: &str) -> Self {
+        Self::with_configs(&AwsCliConfigs {
+            program: program.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn with_configs(configs: &AwsCliConfigs) -> Self {
+        Self {
+            configs: configs.clone(),
+            envs: vec![
+                (
+                    "AWS_MAX_ATTEMPTS".to_string(),
+                    configs.max_attempts.to_string(),
+                ),
+                ("AWS_RETRY_MODE".to_string(), "standard".to_string()),
+            ],
+            offline: false,
+            with_credentials: false,
+        }
+    }
+
+    pub fn from_configs(configs: &Configs) -> Self {
+        let cli = Self {
+            offline: configs.offline,
+            ..Self::with_configs(&configs.aws_cli)
+        };
+        match &configs.proxy {
+            Some(proxy) => cli.with_proxy(proxy),
+            None => cli,
+        }
+    }
+
+    /// Routes requests of the AWS CLI through the proxy.
+    /// Without this, the AWS CLI still honors proxy variables of the environment.
+    pub fn with_proxy(mut self, proxy: &ProxyConfigs) -> Self {
+        self.envs.extend(
+            proxy
+                .envs()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value)),
+        );
+        self
+    }
+
+    /// Signs requests with the temporary credentials instead of ones of profiles,
+    /// like to assume the next role of a chain.
+    pub fn with_credentials(self, credentials: &TemporaryCredentials) -> Self {
+        self.with_credentials_env(&[
+            (
+                "AWS_ACCESS_KEY_ID".to_string(),
+                credentials.access_key_id.clone(),
+            ),
+            (
+                "AWS_SECRET_ACCESS_KEY".to_string(),
+                credentials.secret_access_key.clone(),
+            ),
+            (
+                "AWS_SESSION_TOKEN".to_string(),
+                credentials.session_token.clone(),
+            ),
+        ])
+    }
+
+    /// Signs requests with credentials of `AWS_*` environment variables instead of ones of profiles,
+    /// like ones of `AWS::context_env`.
+    pub fn with_credentials_env(mut self, envs: &[(String, String)]) -> Self {
+        self.envs.extend(envs.iter().cloned());
+        self.with_credentials = true;
+        self
+    }
+
+    /// Command of the AWS CLI with `envs`, removing credentials inherited from the environment
+    /// if they are given otherwise.
+    fn command(&self, remove_credentials: bool) -> Command {
+        let mut process = Command::new(&self.configs.program);
+        if remove_credentials {
+            for var in CREDENTIALS_VARS {
+                process.env_remove(var);
+            }
+        }
+        process.envs(self.envs.iter().cloned());
+        process
+    }
+
+    /// Runs the AWS CLI with `args` and deserializes its JSON output.
+    pub fn run<T: DeserializeOwned>(
+        &self,
+        args: &[&str],
+    ) -> Result<T, ctx::CTXError> {
+        let command = args
+            .iter()
+            .take_while(|a| !a.starts_with('-'))
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if self.offline {
+            return Err(ctx::CTXError::Offline {
+                operation: format!("`aws {}`", command),
+                source: None,
+            });
+        }
+        let program = &self.configs.program;
+        debug!("run aws cli: {} {}", program, command);
+        let output = self
+            .command(self.with_credentials)
+            .args(args)
+            .args(["--output", "json"])
+            .arg(format!(
+                "--cli-connect-timeout={}",
+                self.configs.connect_timeout_seconds
+            ))
+            .arg(format!(
+                "--cli-read-timeout={}",
+                self.configs.read_timeout_seconds
+            ))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| ctx::CTXError::AWSRequestFailed {
+                message: format!("failed to execute `{}`", program),
+                source: Some(e.into()),
+            })?;
+        if !output.status.success() {
+            return Err(ctx::CTXError::AWSRequestFailed {
+                message: format!("`aws {}` failed", command),
+                source: Some(anyhow!(
+                    "{}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )),
+            });
+        }
+        serde_json::from_slice(&output.stdout)
+            .context(format!("failed to parse output of `aws {}`", command))
+            .map_err(|e| ctx::CTXError::AWSRequestFailed {
+                message: format!("unexpected output of `aws {}`", command),
+                source: Some(e),
+            })
+    }
+
+    /// Runs the AWS CLI with `args` attached to the terminal, for interactive commands like
+    /// `ssm start-session`, with credentials of `envs` instead of ones inherited from the environment.
+    pub fn run_attached(
+        &self,
+        args: &[&str],
+        envs: &[(String, String)],
+    ) -> Result<ExitStatus, ctx::CTXError> {
+        let command = args
+            .iter()
+            .take_while(|a| !a.starts_with('-'))
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if self.offline {
+            return Err(ctx::CTXError::Offline {
+                operation: format!("`aws {}`", command),
+                source: None,
+            });
+        }
+        let program = &self.configs.program;
+        debug!("run aws cli attached: {} {}", program, command);
+        self.command(true)
+            .args(args)
+            .envs(envs.iter().cloned())
+            .status()
+            .map_err(|e| ctx::CTXError::AWSRequestFailed {
+                message: format!("failed to execute `{}`", program),
+                source: Some(e.into()),
+            })
+    }
+}
+
+/// Applies `f` to the items with at most `parallelism` concurrent threads,
+/// for requests to AWS across many profiles.
+/// Results are returned in the same order as `items`.
+pub fn run_concurrently<T, R, F>(
+    items: &[T],
+    parallelism: usize,
+    f: F,
+) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results =
+        Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|s| {
+        for _ in 0..parallelism.clamp(1, items.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let item = match items.get(i) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = f(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use rstest::*;
+    use serde_json::Value;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Creates an executable shell script named `name` in `dir`.
+    pub fn fake_executable(dir: &TempDir, name: &str, script: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        path
+    }
+
+    /// Creates an executable script which behaves as the AWS CLI.
+    pub fn fake_aws_cli(dir: &TempDir, script: &str) -> AwsCli {
+        AwsCli::new(fake_executable(dir, "aws", script).to_str().unwrap())
+    }
+
+    #[rstest]
+    fn test_run_attached() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"[ "$*" = "ssm start-session --target i-0123456789abcdef0" ] && [ "$AWS_ACCESS_KEY_ID" = "XXXX" ]"#,
+        );
+        let envs = vec![("AWS_ACCESS_KEY_ID".to_string(), "XXXX".to_string())];
+        let status = cli
+            .run_attached(
+                &["ssm", "start-session", "--target", "i-0123456789abcdef0"],
+                &envs,
+            )
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[rstest(::trace)]
+    #[case(r#"echo '{"Account": "123456789012"}'"#, true)]
+    #[case("echo 'An error occurred' >&2; exit 255", false)]
+    #[case("echo 'not json'", false)]
+    fn test_aws_cli_run(#[case] script: &str, #[case] expect_ok: bool) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(&tmpdir, script);
+        let actual = cli.run::<Value>(&["sts", "get-caller-identity"]);
+        match actual {
+            Ok(value) => {
+                assert!(expect_ok);
+                assert_eq!("123456789012", value["Account"]);
+            }
+            Err(ctx::CTXError::AWSRequestFailed { .. }) => {
+                assert!(!expect_ok)
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[rstest]
+    fn test_aws_cli_from_configs() {
+        let tmpdir = TempDir::new().unwrap();
+        let program = fake_executable(
+            &tmpdir,
+            "aws",
+            r#"echo "{\"args\": \"$*\", \"max_attempts\": \"$AWS_MAX_ATTEMPTS\"}""#,
+        );
+        let cli = AwsCli::from_configs(&Configs {
+            aws_cli: AwsCliConfigs {
+                program: program.to_str().unwrap().to_string(),
+                connect_timeout_seconds: 5,
+                read_timeout_seconds: 15,
+                max_attempts: 2,
+            },
+            ..Default::default()
+        });
+        let actual = cli.run::<Value>(&["sts", "get-caller-identity"]).unwrap();
+        assert_eq!(
+            "sts get-caller-identity --output json --cli-connect-timeout=5 --cli-read-timeout=15",
+            actual["args"]
+        );
+        assert_eq!("2", actual["max_attempts"]);
+    }
+
+    #[rstest]
+    fn test_aws_cli_offline() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = AwsCli::from_configs(&Configs {
+            aws_cli: AwsCliConfigs {
+                program: fake_executable(&tmpdir, "aws", "echo '{}'")
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+                ..Default::default()
+            },
+            offline: true,
+            ..Default::default()
+        });
+        match cli.run::<Value>(&["sts", "get-caller-identity"]) {
+            Err(ctx::CTXError::Offline { operation, .. }) => {
+                assert_eq!("`aws sts get-caller-identity`", operation)
+            }
+            actual => panic!("unexpected result: {:?}", actual),
+        }
+    }
+
+    #[rstest]
+    fn test_aws_cli_with_proxy() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"echo "{\"proxy\": \"$HTTPS_PROXY\", \"no_proxy\": \"$NO_PROXY\"}""#,
+        )
+        .with_proxy(&ProxyConfigs {
+            url: "http://proxy.example.com:8080".to_string(),
+            no_proxy: vec!["localhost".to_string(), "10.0.0.0/8".to_string()],
+        });
+        let actual = cli.run::<Value>(&["sts", "get-caller-identity"]).unwrap();
+        assert_eq!("http://proxy.example.com:8080", actual["proxy"]);
+        assert_eq!("localhost,10.0.0.0/8", actual["no_proxy"]);
+    }
+
+    #[rstest]
+    fn test_aws_cli_with_credentials() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"echo "{\"key\": \"$AWS_ACCESS_KEY_ID\", \"token\": \"$AWS_SESSION_TOKEN\", \"profile\": \"${AWS_PROFILE-unset}\"}""#,
+        )
+        .with_credentials(&TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXX".to_string(),
+            expiration: chrono::Utc::now(),
+        });
+        let actual = cli.run::<Value>(&["sts", "get-caller-identity"]).unwrap();
+        assert_eq!("XXXXXXXXXXX", actual["key"]);
+        assert_eq!("XXXX", actual["token"]);
+        assert_eq!("unset", actual["profile"]);
+    }
+
+    #[rstest]
+    fn test_aws_cli_with_credentials_env_removes_inherited() {
+        let cli = AwsCli::new("aws").with_credentials_env(&[
+            ("AWS_ACCESS_KEY_ID".to_string(), "XXXXXXXXXXX".to_string()),
+            (
+                "AWS_SECRET_ACCESS_KEY".to_string(),
+                "XXXXXXXXXXX".to_string(),
+            ),
+        ]);
+        let command = cli.command(true);
+        let envs = command
+            .get_envs()
+            .map(|(k, v)| (k.to_str().unwrap(), v.map(|v| v.to_str().unwrap())))
+            .collect::<HashMap<_, _>>();
+        // tokens of the shell never go with static keys
+        assert_eq!(Some(&None), envs.get("AWS_SESSION_TOKEN"));
+        assert_eq!(Some(&None), envs.get("AWS_SECURITY_TOKEN"));
+        assert_eq!(Some(&None), envs.get("AWS_PROFILE"));
+        assert_eq!(Some(&Some("XXXXXXXXXXX")), envs.get("AWS_ACCESS_KEY_ID"));
+        assert!(AwsCli::new("aws")
+            .command(false)
+            .get_envs()
+            .all(|(k, _)| k != "AWS_SESSION_TOKEN"));
+    }
+}
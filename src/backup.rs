@@ -0,0 +1,145 @@
+use crate::configs::BackupsConfigs;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+
+/// Format of timestamps suffixed to names of backups, which sorts in time order
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/// Copy of `~/.aws/credentials` or `~/.aws/config` taken before it was overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Name of the backed up file, like `credentials`
+    pub file: String,
+    pub created_at: DateTime<Utc>,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    fn parse(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (file, timestamp) = name.rsplit_once('.')?;
+        let created_at =
+            NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+        Some(Self {
+            file: file.to_string(),
+            created_at: DateTime::from_utc(created_at, Utc),
+            path,
+        })
+    }
+}
+
+/// Copies the file into the directory of backups, readable only by the user since
+/// the credentials are secrets. Files which do not exist yet are not backed up.
+pub fn back_up(
+    source: &Path,
+    dir: &Path,
+    now: DateTime<Utc>,
+) -> io::Result<Option<PathBuf>> {
+    let Some(name) = source.file_name().and_then(|n| n.to_str()) else {
+        return Ok(None);
+    };
+    if !source.exists() {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.{}", name, now.format(TIMESTAMP_FORMAT)));
+    fs::copy(source, &path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(Some(path))
+}
+
+/// Lists backups in the directory, the newest first.
+pub fn list(dir: &Path) -> Vec<Backup> {
+    let mut backups = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| Backup::parse(e.path()))
+                .collect::<Vec<Backup>>()
+        })
+        .unwrap_or_default();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    backups
+}
+
+/// Removes backups beyond `keep_last` of each file or older than `max_age_days`,
+/// and returns the removed ones.
+pub fn prune(
+    dir: &Path,
+    configs: &BackupsConfigs,
+    now: DateTime<Utc>,
+) -> io::Result<Vec<Backup>> {
+    let mut kept = std::collections::HashMap::<String, usize>::new();
+    let mut removed = Vec::new();
+    for backup in list(dir) {
+        let count = kept.entry(backup.file.clone()).or_default();
+        let too_many = configs.keep_last.is_some_and(|n| *count >= n);
+        let too_old = configs
+            .max_age_days
+            .is_some_and(|d| now - backup.created_at > Duration::days(d));
+        if too_many || too_old {
+            fs::remove_file(&backup.path)?;
+            removed.push(backup);
+        } else {
+            *count += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(Some(2), None, vec!["config-0", "credentials-0", "credentials-1"])]
+    #[case(None, Some(2), vec!["config-0", "credentials-0", "credentials-1"])]
+    #[case(Some(1), Some(2), vec!["config-0", "credentials-0"])]
+    #[case(
+        None,
+        None,
+        vec!["config-0", "credentials-0", "credentials-1", "credentials-3"]
+    )]
+    fn test_back_up_and_prune(
+        #[case] keep_last: Option<usize>,
+        #[case] max_age_days: Option<i64>,
+        #[case] expect: Vec<&str>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let dir = tmpdir.path().join("backups");
+        let now = Utc::now();
+        let credentials = tmpdir.path().join("credentials");
+        let config = tmpdir.path().join("config");
+        assert_eq!(None, back_up(&credentials, &dir, now).unwrap());
+        for days in [3, 1, 0] {
+            fs::write(&credentials, format!("credentials-{}", days)).unwrap();
+            back_up(&credentials, &dir, now - Duration::days(days)).unwrap();
+        }
+        fs::write(&config, "config-0").unwrap();
+        back_up(&config, &dir, now).unwrap();
+
+        let configs = BackupsConfigs {
+            enabled: true,
+            keep_last,
+            max_age_days,
+        };
+        prune(&dir, &configs, now).unwrap();
+        let mut actual = list(&dir)
+            .iter()
+            .map(|b| fs::read_to_string(&b.path).unwrap())
+            .collect::<Vec<String>>();
+        actual.sort();
+        assert_eq!(expect, actual);
+    }
+}
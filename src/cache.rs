@@ -0,0 +1,141 @@
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+use crate::state::{self, CREDENTIALS_CACHE_PATH};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+
+/// Cached credentials are treated as expired this long before their actual expiration
+/// so that callers never receive credentials which die in the middle of a command.
+const REFRESH_WINDOW_SECONDS: i64 = 5 * 60;
+
+/// Credentials of assumed roles and SSO roles, keyed by roles with their sources and durations.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct CredentialsCache {
+    path: PathBuf,
+    entries: HashMap<String, TemporaryCredentials>,
+}
+
+impl CredentialsCache {
+    pub fn load<P: AsRef<Path>>(
+        path: Option<P>,
+    ) -> Result<Self, ctx::CTXError> {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| CREDENTIALS_CACHE_PATH.clone());
+        Ok(Self {
+            // a missing or broken cache is never fatal, just start over with an empty one
+            entries: state::load(&path).unwrap_or_default(),
+            path,
+        })
+    }
+
+    fn key(role_arn: &str, duration_seconds: Option<i64>) -> String {
+        match duration_seconds {
+            Some(d) => format!("{}:{}", role_arn, d),
+            None => role_arn.to_string(),
+        }
+    }
+
+    fn is_fresh(
+        credentials: &TemporaryCredentials,
+        now: DateTime<Utc>,
+    ) -> bool {
+        credentials.expiration - Duration::seconds(REFRESH_WINDOW_SECONDS) > now
+    }
+
+    /// Returns cached credentials for the role unless they are expired or about to expire.
+    pub fn get(
+        &self,
+        role_arn: &str,
+        duration_seconds: Option<i64>,
+    ) -> Option<&TemporaryCredentials> {
+        self.entries
+            .get(&Self::key(role_arn, duration_seconds))
+            .filter(|c| Self::is_fresh(c, Utc::now()))
+    }
+
+    pub fn insert(
+        &mut self,
+        role_arn: &str,
+        duration_seconds: Option<i64>,
+        credentials: TemporaryCredentials,
+    ) {
+        self.entries
+            .insert(Self::key(role_arn, duration_seconds), credentials);
+    }
+
+    pub fn dump(&mut self) -> Result<(), ctx::CTXError> {
+        let now = Utc::now();
+        self.entries.retain(|_, c| Self::is_fresh(c, now));
+
+        state::save(&self.path, &self.entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[fixture]
+    pub fn credentials() -> TemporaryCredentials {
+        TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"
+                .to_string(),
+            expiration: Utc::now() + Duration::hours(1),
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case(Duration::hours(1), true)]
+    #[case(Duration::minutes(1), false)]
+    #[case(Duration::hours(-1), false)]
+    fn test_credentials_cache_get(
+        mut credentials: TemporaryCredentials,
+        #[case] expires_in: Duration,
+        #[case] expect_hit: bool,
+    ) {
+        let role_arn = "arn:aws:iam::123456789012:role/foo";
+        credentials.expiration = Utc::now() + expires_in;
+        let mut cache = CredentialsCache::default();
+        cache.insert(role_arn, Some(3600), credentials.clone());
+
+        assert_eq!(expect_hit, cache.get(role_arn, Some(3600)).is_some());
+        // a different duration is a different cache entry
+        assert_eq!(None, cache.get(role_arn, Some(900)));
+    }
+
+    #[rstest]
+    fn test_credentials_cache_dump_and_load(credentials: TemporaryCredentials) {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("cache/credentials.json");
+        let role_arn = "arn:aws:iam::123456789012:role/foo";
+
+        let mut cache = CredentialsCache::load(Some(&path)).unwrap();
+        cache.insert(role_arn, None, credentials.clone());
+        cache.dump().unwrap();
+
+        let actual = CredentialsCache::load(Some(&path)).unwrap();
+        assert_eq!(Some(&credentials), actual.get(role_arn, None));
+    }
+
+    #[rstest]
+    fn test_credentials_cache_load_broken() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("credentials.json");
+        fs::write(&path, "{broken").unwrap();
+
+        let actual = CredentialsCache::load(Some(&path)).unwrap();
+        assert_eq!(HashMap::new(), actual.entries);
+    }
+}
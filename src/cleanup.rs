@@ -0,0 +1,60 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// Profile not used for a while, found by `find_unused`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnusedProfile {
+    pub name: String,
+    /// `None` if it has never been used as far as recorded
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// Finds the profiles not used in the days by `last_used`, the least recently used first.
+pub fn find_unused<F: Fn(&str) -> Option<DateTime<Utc>>>(
+    names: &[String],
+    last_used: F,
+    now: DateTime<Utc>,
+    days: i64,
+) -> Vec<UnusedProfile> {
+    let threshold = now - Duration::days(days);
+    let mut unused = names
+        .iter()
+        .map(|name| UnusedProfile {
+            name: name.clone(),
+            last_used: last_used(name),
+        })
+        .filter(|p| p.last_used.is_none_or(|t| t < threshold))
+        .collect::<Vec<UnusedProfile>>();
+    // profiles never used come first since `None` is the least
+    unused.sort_by(|a, b| {
+        a.last_used.cmp(&b.last_used).then(a.name.cmp(&b.name))
+    });
+    unused
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_find_unused() {
+        let now = Utc::now();
+        let used = HashMap::from([
+            ("bar", now - Duration::days(100)),
+            ("baz", now - Duration::days(10)),
+            ("qux", now - Duration::days(200)),
+        ]);
+        let names = ["bar", "baz", "foo", "qux"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>();
+        let actual = find_unused(&names, |n| used.get(n).copied(), now, 90)
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["foo", "qux", "bar"], actual);
+    }
+}
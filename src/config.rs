@@ -1,14 +1,16 @@
+use crate::configs::{FileMode, FileModesConfigs};
 use crate::ctx;
+use crate::file::{write_atomic, TextEncoding};
 
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::BufReader;
 use std::io::{BufWriter, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use anyhow::{anyhow, Context, Result};
@@ -17,27 +19,84 @@ use ini::Ini;
 
 const DEFAULT_PROFILE_NAME: &str = "default";
 const PROFILE_PREFIX: &str = "profile ";
+const REGION_KEY: &str = "region";
+const SOURCE_PROFILE_KEY: &str = "source_profile";
+const ROLE_ARN_KEY: &str = "role_arn";
 
 #[derive(Default, Debug, PartialEq, Eq)]
 pub struct Profile {
     pub name: String,
     pub default: bool,
-    #[allow(dead_code)]
     items: Rc<HashMap<String, String>>,
 }
 
+impl Profile {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items.get(key).map(|v| v.as_str())
+    }
+}
+
+/// Borrowed view of a profile, which never clones the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileRef<'a> {
+    pub name: &'a str,
+    pub default: bool,
+    items: &'a HashMap<String, String>,
+}
+
+impl<'a> ProfileRef<'a> {
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.items.get(key).map(|v| v.as_str())
+    }
+
+    pub fn items(&self) -> &'a HashMap<String, String> {
+        self.items
+    }
+}
+
+/// Role which a profile assumes with credentials of its `source_profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssumedRole {
+    pub profile: String,
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    /// MFA device whose code the role requires
+    pub mfa_serial: Option<String>,
+    pub role_session_name: Option<String>,
+    pub duration_seconds: Option<i64>,
+}
+
+/// Roles assumed in turn from credentials of the `source` profile to reach a profile,
+/// the first of them with the credentials and the others with ones of the previous role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleChain {
+    pub source: String,
+    pub roles: Vec<AssumedRole>,
+}
+
 type ConfigData = HashMap<String, Rc<HashMap<String, String>>>;
 
 #[derive(Default, Debug, PartialEq, Eq)]
 pub struct Config {
     data: ConfigData,
     default_profile_name: Option<String>,
+    /// Region in the default section overriding the one of the default profile
+    default_region: Option<String>,
+    encoding: TextEncoding,
+    /// Names of profiles read from fragments, which are never written back
+    fragments: HashSet<String>,
+    /// Sections other than profiles, like `[sso-session corp]`, written back as they are
+    sections: ConfigData,
 }
 
 impl fmt::Display for Config {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let mut conf = Ini::new();
-        let mut profile_names = Vec::from_iter(self.data.keys());
+        let mut profile_names = Vec::from_iter(
+            self.data
+                .keys()
+                .filter(|name| !self.fragments.contains(*name)),
+        );
 
         // sort profile names by reverse order to write ascending order
         profile_names.sort();
@@ -54,12 +113,33 @@ impl fmt::Display for Config {
             }
         }
 
+        let mut section_names = Vec::from_iter(
+            self.sections
+                .keys()
+                .filter(|name| !self.fragments.contains(*name)),
+        );
+        section_names.sort();
+        for section_name in section_names {
+            let mut sec = conf.with_section(Some(section_name));
+            let mut s = sec.borrow_mut();
+            let data = self.sections.get(section_name).unwrap();
+            let mut data_keys = Vec::from_iter(data.keys());
+            data_keys.sort();
+            for data_key in data_keys {
+                s = s.set(data_key, data.get(data_key).unwrap());
+            }
+        }
+
         // write default profile to section first to write last
         if let Some(default_profile_name) = &self.default_profile_name {
             let mut sec = conf.with_section(Some(DEFAULT_PROFILE_NAME));
             // NOTE: to use method chain of `&mut SectionSetter`, declare `s` before
             let mut s = sec.borrow_mut();
-            let data = self.data.get(default_profile_name).unwrap();
+            let mut data =
+                (**self.data.get(default_profile_name).unwrap()).clone();
+            if let Some(region) = &self.default_region {
+                data.insert(REGION_KEY.to_string(), region.clone());
+            }
             let mut data_keys = Vec::from_iter(data.keys());
             data_keys.sort();
             for data_key in data_keys {
@@ -81,29 +161,79 @@ impl Config {
     pub fn load_config<P: AsRef<Path>>(
         config_path: P,
     ) -> Result<Self, ctx::CTXError> {
-        let file = fs::File::open(config_path).map_err(|e| {
-            ctx::CTXError::CannotReadConfig {
-                source: Some(e.into()),
+        Self::load_config_with_fragments(config_path, &[])
+    }
+
+    /// Loads the config together with fragments, INI files of additional profiles read in the order.
+    /// Profiles of the config take precedence over ones of fragments, the first one among fragments,
+    /// and default sections of fragments are ignored.
+    pub fn load_config_with_fragments<P: AsRef<Path>>(
+        config_path: P,
+        fragment_paths: &[PathBuf],
+    ) -> Result<Self, ctx::CTXError> {
+        let open = |path: &Path| {
+            fs::File::open(path).map_err(|e| ctx::CTXError::CannotReadConfig {
+                source: Some(anyhow!("failed to open {:?}: {}", path, e)),
+            })
+        };
+        let (mut data, encoding) =
+            parse_aws_config(&open(config_path.as_ref())?)?;
+
+        let mut fragments = HashSet::new();
+        for path in fragment_paths.iter() {
+            let (fragment, _) = parse_aws_config(&open(path)?)?;
+            for (section, items) in fragment {
+                let name = profile_name_of_section(&section);
+                if name == DEFAULT_PROFILE_NAME
+                    || data.keys().any(|s| profile_name_of_section(s) == name)
+                {
+                    continue;
+                }
+                fragments.insert(name.to_string());
+                data.insert(section, items);
             }
-        })?;
+        }
+        Ok(Self {
+            encoding,
+            fragments,
+            ..Self::from_data(data)
+        })
+    }
 
-        let mut data = parse_aws_config(&file)?;
+    /// Parses the config in the INI format of `~/.aws/config`, for storages other than files.
+    pub fn from_text(contents: &str) -> Result<Self, ctx::CTXError> {
+        Ok(Self::from_data(parse_aws_config_text(contents)?))
+    }
+
+    fn from_data(mut data: ConfigData) -> Self {
         let ck = find_default_from_parsed_aws_config(&data);
+        let default_region = ck
+            .as_ref()
+            .and_then(|name| find_default_region(&data, name));
         // remove DEFAULT_KEY after retrain current key
         data.remove(DEFAULT_PROFILE_NAME);
         data.remove(&format!("{}{}", PROFILE_PREFIX, DEFAULT_PROFILE_NAME));
 
+        let (data, sections): (ConfigData, ConfigData) =
+            data.into_iter().partition(|(k, _)| is_profile_section(k));
         let data = data
             .into_iter()
-            .map(|(k, v)| {
-                (k.strip_prefix(PROFILE_PREFIX).unwrap_or(&k).to_string(), v)
-            })
+            .map(|(k, v)| (profile_name_of_section(&k).to_string(), v))
             .collect();
 
-        Ok(Config {
+        Config {
             data,
             default_profile_name: ck,
-        })
+            default_region,
+            encoding: TextEncoding::Utf8,
+            fragments: HashSet::new(),
+            sections,
+        }
+    }
+
+    /// Returns whether the profile is read from a fragment, so that it cannot be changed.
+    pub fn is_fragment(&self, name: &str) -> bool {
+        self.fragments.contains(name)
     }
 
     fn is_default_profile(&self, name: &str) -> bool {
@@ -129,6 +259,11 @@ impl Config {
         })
     }
 
+    /// Returns the name of the profile which the default section matches, if any.
+    pub fn default_profile_name(&self) -> Option<&str> {
+        self.default_profile_name.as_deref()
+    }
+
     pub fn get_default_profile(&self) -> Result<Profile, ctx::CTXError> {
         let name = self
             .default_profile_name
@@ -150,6 +285,7 @@ impl Config {
                 ))),
             })?;
         self.default_profile_name = Some(name.to_string());
+        self.default_region = None;
         Ok(Profile {
             name: name.into(),
             items: items.clone(),
@@ -157,24 +293,239 @@ impl Config {
         })
     }
 
+    /// Removes the profile, and the default section too if it is the default profile.
+    pub fn remove_profile(
+        &mut self,
+        name: &str,
+    ) -> Option<HashMap<String, String>> {
+        let items = self.data.remove(name)?;
+        if self.default_profile_name.as_deref() == Some(name) {
+            self.default_profile_name = None;
+            self.default_region = None;
+        }
+        Some(items.as_ref().clone())
+    }
+
+    /// Renames the profile, keeping it as the default profile if it is,
+    /// and fixes `source_profile` of profiles referring to it.
+    /// Returns whether the profile exists.
+    pub fn rename_profile(&mut self, from: &str, to: &str) -> bool {
+        let items = match self.data.remove(from) {
+            Some(items) => items,
+            None => return false,
+        };
+        self.data.insert(to.to_string(), items);
+        if self.is_default_profile(from) {
+            self.default_profile_name = Some(to.to_string());
+        }
+        for items in self.data.values_mut() {
+            if items.get(SOURCE_PROFILE_KEY).map(|s| s.as_str()) == Some(from) {
+                Rc::make_mut(items)
+                    .insert(SOURCE_PROFILE_KEY.to_string(), to.to_string());
+            }
+        }
+        true
+    }
+
+    /// Adds the profile as it is, replacing the one of the same name even if it is of a fragment.
+    pub fn insert_profile(
+        &mut self,
+        name: &str,
+        items: HashMap<String, String>,
+    ) {
+        self.fragments.remove(name);
+        self.data.insert(name.to_string(), Rc::new(items));
+    }
+
+    /// Sets the key of the profile, adding a section of the profile if it has none.
+    pub fn set_profile_value(&mut self, name: &str, key: &str, value: &str) {
+        let items = self.data.entry(name.to_string()).or_default();
+        Rc::make_mut(items).insert(key.to_string(), value.to_string());
+    }
+
+    /// Returns the region in the default section if it overrides the one of the default profile.
+    pub fn get_default_region(&self) -> Option<&str> {
+        self.default_region.as_deref()
+    }
+
+    /// Makes the profile the default one if the default section matches it as well,
+    /// apart from the region, so that profiles equal to each other are resolved
+    /// to the one chosen by the credentials. The default section is kept as is.
+    pub fn prefer_default_profile(&mut self, name: &str) {
+        let Some(current) = self.default_profile_name.as_deref() else {
+            return;
+        };
+        if current == name {
+            return;
+        }
+        let (Some(current_items), Some(items)) =
+            (self.data.get(current), self.data.get(name))
+        else {
+            return;
+        };
+        let region = self
+            .default_region
+            .as_ref()
+            .or_else(|| current_items.get(REGION_KEY));
+        // a region of the profile missing in the default section cannot be written as an override
+        if without_region(current_items) != without_region(items)
+            || (region.is_none() && items.get(REGION_KEY).is_some())
+        {
+            return;
+        }
+        self.default_region = region
+            .filter(|region| items.get(REGION_KEY) != Some(*region))
+            .cloned();
+        self.default_profile_name = Some(name.to_string());
+    }
+
+    /// Overrides the region in the default section, keeping the default profile as is.
+    pub fn set_default_region(
+        &mut self,
+        region: &str,
+    ) -> Result<(), ctx::CTXError> {
+        let profile = self.get_default_profile()?;
+        self.default_region = if profile.get(REGION_KEY) == Some(region) {
+            None
+        } else {
+            Some(region.to_string())
+        };
+        Ok(())
+    }
+
+    /// Writes the config in the default mode of `FileModesConfigs`.
     pub fn dump_config<P: AsRef<Path>>(
         &self,
         config_path: P,
     ) -> Result<(), ctx::CTXError> {
-        let mut file = fs::File::create(config_path).map_err(|e| {
-            ctx::CTXError::CannotWriteConfig {
+        self.dump_config_with_mode(
+            config_path,
+            FileModesConfigs::default().config,
+        )
+    }
+
+    pub fn dump_config_with_mode<P: AsRef<Path>>(
+        &self,
+        config_path: P,
+        mode: FileMode,
+    ) -> Result<(), ctx::CTXError> {
+        self.encoding
+            .encode(&self.to_string())
+            .and_then(|bytes| {
+                write_atomic(config_path.as_ref(), &bytes, mode.0)
+            })
+            .map_err(|e| ctx::CTXError::CannotWriteConfig {
                 source: Some(e.into()),
+            })
+    }
+
+    /// Returns the encoding of the file, which it is written back in.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Looks up the profile by the name without cloning it.
+    pub fn profile(&self, name: &str) -> Option<ProfileRef<'_>> {
+        self.data
+            .get_key_value(name)
+            .map(|(name, items)| ProfileRef {
+                name,
+                default: self.is_default_profile(name),
+                items,
+            })
+    }
+
+    /// Looks up a section other than profiles by the header, like `sso-session corp`.
+    pub fn section(&self, name: &str) -> Option<ProfileRef<'_>> {
+        self.sections
+            .get_key_value(name)
+            .map(|(name, items)| ProfileRef {
+                name,
+                default: false,
+                items,
+            })
+    }
+
+    /// Resolves the chain of `source_profile` from the profile down to one without `role_arn`,
+    /// or one referring to itself, whose credentials start the chain.
+    pub fn role_chain(&self, name: &str) -> Result<RoleChain, ctx::CTXError> {
+        let invalid = |message: String| ctx::CTXError::InvalidConfigurations {
+            message,
+            source: None,
+        };
+        let mut roles = vec![];
+        let mut visited = HashSet::new();
+        let mut current = name.to_string();
+        loop {
+            let profile = self.profile(&current).ok_or_else(|| {
+                invalid(format!(
+                    "source_profile ({}) of profile ({}) is not found",
+                    current, name
+                ))
+            })?;
+            let role_arn = match profile.get(ROLE_ARN_KEY) {
+                Some(role_arn) => role_arn,
+                None if roles.is_empty() => {
+                    return Err(invalid(format!(
+                        "profile ({}) has no role_arn to assume",
+                        name
+                    )))
+                }
+                None => break,
+            };
+            let source = profile.get(SOURCE_PROFILE_KEY).ok_or_else(|| {
+                invalid(format!(
+                    "profile ({}) has role_arn without source_profile",
+                    current
+                ))
+            })?;
+            if !visited.insert(current.clone()) {
+                return Err(invalid(format!(
+                    "source_profile of profile ({}) loops at ({})",
+                    name, current
+                )));
             }
-        })?;
-        file.write_all(self.to_string().as_bytes()).map_err(|e| {
-            ctx::CTXError::CannotWriteConfig {
-                source: Some(e.into()),
+            let duration_seconds = profile
+                .get("duration_seconds")
+                .map(|d| d.parse::<i64>())
+                .transpose()
+                .map_err(|e| ctx::CTXError::InvalidConfigurations {
+                    message: format!(
+                        "invalid duration_seconds of profile ({})",
+                        current
+                    ),
+                    source: Some(e.into()),
+                })?;
+            roles.push(AssumedRole {
+                profile: current.clone(),
+                role_arn: role_arn.to_string(),
+                external_id: profile.get("external_id").map(|e| e.to_string()),
+                mfa_serial: profile.get("mfa_serial").map(|s| s.to_string()),
+                role_session_name: profile
+                    .get("role_session_name")
+                    .map(|n| n.to_string()),
+                duration_seconds,
+            });
+            // a profile referring to itself assumes the role with its own credentials
+            if source == current {
+                break;
             }
-        })?;
-        file.flush().map_err(|e| ctx::CTXError::CannotWriteConfig {
-            source: Some(e.into()),
-        })?;
-        Ok(())
+            current = source.to_string();
+        }
+        roles.reverse();
+        Ok(RoleChain {
+            source: current,
+            roles,
+        })
+    }
+
+    /// Iterates over the profiles without cloning them, in no particular order.
+    pub fn iter_profiles(&self) -> impl Iterator<Item = ProfileRef<'_>> {
+        self.data.iter().map(|(name, items)| ProfileRef {
+            name,
+            default: self.is_default_profile(name),
+            items,
+        })
     }
 
     pub fn list_profiles(&self) -> Vec<Profile> {
@@ -192,19 +543,23 @@ impl Config {
     }
 }
 
-fn parse_aws_config(file: &File) -> Result<ConfigData, ctx::CTXError> {
+fn parse_aws_config(
+    file: &File,
+) -> Result<(ConfigData, TextEncoding), ctx::CTXError> {
     let mut buf_reader = BufReader::new(file);
-    let mut contents = String::new();
-    buf_reader.read_to_string(&mut contents).map_err(|e| {
+    let mut bytes = Vec::new();
+    buf_reader.read_to_end(&mut bytes).map_err(|e| {
         ctx::CTXError::CannotReadConfig {
             source: Some(e.into()),
         }
     })?;
+    let (contents, encoding) = TextEncoding::decode(bytes);
+    Ok((parse_aws_config_text(&contents)?, encoding))
+}
+
+fn parse_aws_config_text(contents: &str) -> Result<ConfigData, ctx::CTXError> {
     let c = config::Config::builder()
-        .add_source(config::File::from_str(
-            contents.as_str(),
-            config::FileFormat::Ini,
-        ))
+        .add_source(config::File::from_str(contents, config::FileFormat::Ini))
         .build()
         .context("failed to load aws config".to_string())
         .map_err(|e| ctx::CTXError::ConfigIsBroken { source: Some(e) })?;
@@ -217,21 +572,51 @@ fn parse_aws_config(file: &File) -> Result<ConfigData, ctx::CTXError> {
         )
 }
 
+/// Sections of profiles are `[profile foo]`, `[default]` or `[foo]` written like the credentials,
+/// while other sections like `[sso-session corp]` have their kinds before names.
+fn is_profile_section(section: &str) -> bool {
+    section.starts_with(PROFILE_PREFIX) || !section.contains(' ')
+}
+
+fn profile_name_of_section(section: &str) -> &str {
+    section.strip_prefix(PROFILE_PREFIX).unwrap_or(section)
+}
+
 fn find_default_from_parsed_aws_config(data: &ConfigData) -> Option<String> {
     let default_items = data.get(DEFAULT_PROFILE_NAME)?;
-    for (name, item) in data {
-        if name != DEFAULT_PROFILE_NAME && item == default_items {
-            if let Some(profile_name) = name.strip_prefix(PROFILE_PREFIX) {
-                return Some(profile_name.into());
-            }
-        }
-    }
-    None
+    let find = |matches: &dyn Fn(&HashMap<String, String>) -> bool| {
+        // the first in the order of names among equal profiles, not to depend on the order of the map
+        data.iter()
+            .filter(|(name, _)| name.as_str() != DEFAULT_PROFILE_NAME)
+            .filter(|(_, item)| matches(item))
+            .filter_map(|(name, _)| name.strip_prefix(PROFILE_PREFIX))
+            .min()
+            .map(|profile_name| profile_name.to_string())
+    };
+    // a profile whose region is overridden in the default section matches
+    // only when no profile matches exactly
+    find(&|item| item == &**default_items).or_else(|| {
+        find(&|item| without_region(item) == without_region(default_items))
+    })
+}
+
+fn without_region(items: &HashMap<String, String>) -> HashMap<&str, &str> {
+    items
+        .iter()
+        .filter(|(key, _)| key.as_str() != REGION_KEY)
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+fn find_default_region(data: &ConfigData, name: &str) -> Option<String> {
+    let region = data.get(DEFAULT_PROFILE_NAME)?.get(REGION_KEY)?;
+    let profile = data.get(&format!("{}{}", PROFILE_PREFIX, name))?;
+    (profile.get(REGION_KEY) != Some(region)).then(|| region.clone())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Seek;
+    use std::io::{Seek, Write};
 
     use maplit::hashmap;
     use rstest::*;
@@ -280,7 +665,7 @@ region=XXXXXXXXXXX
 
     #[fixture(aws_config = aws_config(aws_config_text()))]
     pub fn parsed_aws_config(aws_config: NamedTempFile) -> ConfigData {
-        parse_aws_config(aws_config.as_file()).unwrap()
+        parse_aws_config(aws_config.as_file()).unwrap().0
     }
 
     #[fixture]
@@ -307,6 +692,10 @@ region=XXXXXXXXXXX
                 "bar".to_string() => bar_profile_items(),
             },
             default_profile_name: Some("foo".to_string()),
+            default_region: None,
+            encoding: TextEncoding::Utf8,
+            fragments: HashSet::new(),
+            sections: HashMap::new(),
         }
     }
 
@@ -318,6 +707,10 @@ region=XXXXXXXXXXX
                 "bar".to_string() => bar_profile_items(),
             },
             default_profile_name: None,
+            default_region: None,
+            encoding: TextEncoding::Utf8,
+            fragments: HashSet::new(),
+            sections: HashMap::new(),
         }
     }
 
@@ -328,7 +721,7 @@ region=XXXXXXXXXXX
             "profile bar".to_string() => bar_profile_items(),
             "default".to_string() => foo_profile_items(),
         };
-        let actual = parse_aws_config(aws_config.as_file()).unwrap();
+        let (actual, _) = parse_aws_config(aws_config.as_file()).unwrap();
         assert_eq!(expect, actual);
     }
 
@@ -349,6 +742,47 @@ region=XXXXXXXXXXX
         assert_eq!(expect, actual);
     }
 
+    #[rstest(::trace)]
+    // equal apart from the region, which is kept as an override
+    #[case("[profile bar]\nregion=YYYYYYYYYYY\n\n[profile foo]\nregion=XXXXXXXXXXX\n\n[default]\nregion=YYYYYYYYYYY\n", "foo", Some("YYYYYYYYYYY"))]
+    #[case("[profile bar]\noutput=text\n\n[profile foo]\noutput=json\n\n[default]\noutput=text\n", "bar", None)]
+    // no region in the default section to override the one of the profile
+    #[case("[profile bar]\noutput=text\n\n[profile foo]\noutput=text\nregion=XXXXXXXXXXX\n\n[default]\noutput=text\n", "bar", None)]
+    fn test_config_prefer_default_profile(
+        #[case] text: &str,
+        #[case] expect: &str,
+        #[case] expect_region: Option<&str>,
+    ) {
+        let mut config = Config::from_text(text).unwrap();
+        assert_eq!(Some("bar"), config.default_profile_name());
+        let before = config.to_string();
+
+        config.prefer_default_profile("foo");
+        assert_eq!(Some(expect), config.default_profile_name());
+        assert_eq!(expect_region, config.get_default_region());
+        assert_eq!(before, config.to_string());
+    }
+
+    #[rstest]
+    fn test_config_default_region(mut config: Config) {
+        config.set_default_region("ZZZZZZZZZZZ").unwrap();
+        let namedfile = NamedTempFile::new().unwrap();
+        config.dump_config(namedfile.path()).unwrap();
+
+        let actual = Config::load_config(namedfile.path()).unwrap();
+        assert_eq!(Some("foo".to_string()), actual.default_profile_name);
+        assert_eq!(Some("ZZZZZZZZZZZ"), actual.get_default_region());
+        assert_eq!(config, actual);
+
+        // the region of the profile itself is not an override
+        config.set_default_region("XXXXXXXXXXX").unwrap();
+        assert_eq!(None, config.get_default_region());
+        assert!(matches!(
+            config_without_default().set_default_region("ZZZZZZZZZZZ"),
+            Err(ctx::CTXError::NoActiveContext { .. })
+        ));
+    }
+
     #[rstest(::trace)]
     #[case(aws_config(aws_config_text()), config())]
     #[case(
@@ -511,6 +945,206 @@ region=XXXXXXXXXXX
         assert_eq!(expect, actual);
     }
 
+    #[rstest]
+    fn test_iter_profiles(config: Config) {
+        let mut actual = config
+            .iter_profiles()
+            .map(|p| (p.name, p.default, p.get("region")))
+            .collect::<Vec<(&str, bool, Option<&str>)>>();
+        actual.sort();
+        assert_eq!(
+            vec![
+                ("bar", false, Some("YYYYYYYYYYY")),
+                ("foo", true, Some("XXXXXXXXXXX"))
+            ],
+            actual
+        );
+        assert_eq!(
+            Some("XXXXXXXXXXX"),
+            config.profile("foo").unwrap().get("region")
+        );
+        assert_eq!(None, config.profile("unknown"));
+    }
+
+    #[rstest]
+    fn test_config_load_config_with_fragments() {
+        let tmpdir = tempfile::TempDir::new().unwrap();
+        let write = |name: &str, text: &str| {
+            let path = tmpdir.path().join(name);
+            fs::write(&path, text).unwrap();
+            path
+        };
+        let config_path = write(
+            "config",
+            r#"[profile foo]
+region=XXXXXXXXXXX
+
+[default]
+region=YYYYYYYYYYY
+"#,
+        );
+        let fragment_paths = vec![
+            write(
+                "client-a",
+                r#"[profile bar]
+region=YYYYYYYYYYY
+
+[profile foo]
+region=ZZZZZZZZZZZ
+
+[default]
+region=ZZZZZZZZZZZ
+"#,
+            ),
+            write(
+                "client-b",
+                r#"[profile bar]
+region=ZZZZZZZZZZZ
+"#,
+            ),
+        ];
+        let config =
+            Config::load_config_with_fragments(&config_path, &fragment_paths)
+                .unwrap();
+        assert_eq!(
+            Some("XXXXXXXXXXX"),
+            config.profile("foo").unwrap().get("region")
+        );
+        // the default section of the config may match profiles of fragments
+        assert!(config.profile("bar").unwrap().default);
+        assert!(config.is_fragment("bar"));
+        assert!(!config.is_fragment("foo"));
+        assert_eq!(
+            r#"[profile foo]
+region=XXXXXXXXXXX
+
+[default]
+region=YYYYYYYYYYY
+"#,
+            config.to_string()
+        );
+    }
+
+    #[rstest]
+    fn test_config_other_sections() {
+        let text = r#"[profile foo]
+sso_account_id=123456789012
+sso_role_name=Admin
+sso_session=corp
+
+[sso-session corp]
+sso_region=us-east-1
+sso_start_url=https://example.awsapps.com/start
+
+[default]
+sso_account_id=123456789012
+sso_role_name=Admin
+sso_session=corp
+"#;
+        let config = Config::from_text(text).unwrap();
+        assert_eq!(
+            vec!["foo"],
+            config
+                .list_profiles()
+                .into_iter()
+                .map(|p| p.name)
+                .collect::<Vec<String>>()
+        );
+        assert_eq!(
+            Some("us-east-1"),
+            config
+                .section("sso-session corp")
+                .and_then(|s| s.get("sso_region"))
+        );
+        assert_eq!(text, config.to_string());
+    }
+
+    #[rstest(::trace)]
+    #[case("dev", Ok(("base", vec!["dev"])))]
+    #[case("prod", Ok(("base", vec!["dev", "prod"])))]
+    #[case("self", Ok(("self", vec!["self"])))]
+    #[case("base", Err(()))]
+    #[case("loop", Err(()))]
+    #[case("orphan", Err(()))]
+    fn test_config_role_chain(
+        #[case] name: &str,
+        #[case] expect: Result<(&str, Vec<&str>), ()>,
+    ) {
+        let config = Config::from_text(
+            r#"[profile base]
+region=us-east-1
+
+[profile dev]
+role_arn=arn:aws:iam::123456789012:role/dev
+source_profile=base
+
+[profile prod]
+role_arn=arn:aws:iam::210987654321:role/prod
+source_profile=dev
+external_id=secret
+duration_seconds=900
+
+[profile self]
+role_arn=arn:aws:iam::123456789012:role/self
+source_profile=self
+
+[profile loop]
+role_arn=arn:aws:iam::123456789012:role/loop
+source_profile=loop2
+
+[profile loop2]
+role_arn=arn:aws:iam::123456789012:role/loop2
+source_profile=loop
+
+[profile orphan]
+role_arn=arn:aws:iam::123456789012:role/orphan
+source_profile=unknown
+"#,
+        )
+        .unwrap();
+        let actual = config.role_chain(name);
+        match (expect, actual) {
+            (Ok((source, profiles)), Ok(chain)) => {
+                assert_eq!(source, chain.source);
+                assert_eq!(
+                    profiles,
+                    chain
+                        .roles
+                        .iter()
+                        .map(|r| r.profile.as_str())
+                        .collect::<Vec<&str>>()
+                );
+            }
+            (Err(_), Err(ctx::CTXError::InvalidConfigurations { .. })) => (),
+            (_, actual) => panic!("unexpected result: {:?}", actual),
+        }
+    }
+
+    #[rstest]
+    fn test_config_rename_profile() {
+        let mut config = Config::from_text(
+            r#"[profile foo]
+region=XXXXXXXXXXX
+
+[profile bar]
+role_arn=arn:aws:iam::123456789012:role/Admin
+source_profile=foo
+
+[default]
+region=XXXXXXXXXXX
+"#,
+        )
+        .unwrap();
+        assert!(config.rename_profile("foo", "qux"));
+        assert!(!config.rename_profile("foo", "quux"));
+        assert_eq!(None, config.profile("foo"));
+        assert!(config.profile("qux").unwrap().default);
+        assert_eq!(
+            Some("qux"),
+            config.profile("bar").unwrap().get("source_profile")
+        );
+    }
+
     #[rstest(::trace)]
     fn test_list_profiles(config: Config) {
         let expect = vec![
@@ -3,27 +3,655 @@ use maplit::hashmap;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
+use std::process::Command;
 use std::{collections::HashMap, path::Path};
 
 use anyhow::{anyhow, Context, Result};
 use config::{Config, File, FileFormat};
 use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use crate::ctx;
+use crate::i18n::Locale;
+use crate::state::awsctx_home;
 
 type ProfileName = String;
-type AuthScript = String;
 
-pub static CONFIGS_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    let mut path = home_dir().unwrap();
-    path.push(".awsctx/configs.yaml");
-    path
+pub static CONFIGS_PATH: Lazy<PathBuf> = Lazy::new(|| match awsctx_home() {
+    Some(home) => home.join("configs.yaml"),
+    None => {
+        let mut path = home_dir().unwrap();
+        path.push(".awsctx/configs.yaml");
+        path
+    }
 });
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Configs {
-    pub auth_commands: HashMap<ProfileName, AuthScript>,
+    pub auth_commands: HashMap<ProfileName, AuthCommandConfigs>,
+    /// Keys of `auth_commands` whose commands print credentials in the JSON of `credential_process`
+    /// on stdout, which are written to the credentials by awsctx
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credential_outputs: Vec<ProfileName>,
+    #[serde(default, skip_serializing_if = "SessionProfiles::is_default")]
+    pub session_profiles: SessionProfiles,
+    #[serde(default, skip_serializing_if = "AwsCliConfigs::is_default")]
+    pub aws_cli: AwsCliConfigs,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub saml: HashMap<ProfileName, SamlConfigs>,
+    /// Names of `sso-session` sections keyed by start URLs of IAM Identity Center,
+    /// so that profiles with `sso_start_url` share tokens of the sessions
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sso_sessions: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfigs>,
+    /// Disables every network access, features requiring it fail immediately
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub offline: bool,
+    /// Skips broken sections of the credentials instead of failing, which are written back as they are
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lenient: bool,
+    /// Calls GetCallerIdentity with credentials of contexts on switches,
+    /// refusing to switch to ones rejected by AWS
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verify_identity: bool,
+    /// Runs the auth of profiles whose credentials are expired on switches, before switching to them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_refresh: bool,
+    /// Refuses to write `~/.aws` files linked into working trees of version control, like dotfiles repositories
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protect_version_controlled: bool,
+    /// Files of credentials of profiles matching patterns, read together with `~/.aws/credentials`,
+    /// the first matching one applies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credentials_routes: Vec<CredentialsRoute>,
+    /// Directory of INI fragments of profiles read together with `~/.aws/config`, like `~/.aws/config.d`,
+    /// which are never written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_fragments_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "FileModesConfigs::is_default")]
+    pub file_modes: FileModesConfigs,
+    #[serde(default, skip_serializing_if = "BackupsConfigs::is_default")]
+    pub backups: BackupsConfigs,
+    #[serde(default, skip_serializing_if = "PromptConfigs::is_default")]
+    pub prompt: PromptConfigs,
+    /// Tags of profiles shown in listings, like `prod` or `sandbox`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<ProfileName, Vec<String>>,
+    /// Free-text notes of profiles, like owners or purposes of accounts
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub descriptions: HashMap<ProfileName, String>,
+    /// Short names accepted anywhere a profile name is, like `p: prod-admin`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, ProfileName>,
+    /// Labels of profiles or tags shown before names in listings and the finder,
+    /// the first matching one applies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<LabelConfigs>,
+    #[serde(default, skip_serializing_if = "ListingConfigs::is_default")]
+    pub listing: ListingConfigs,
+    /// Checks once a day whether a newer release exists, and prints a hint after commands
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub update_check: bool,
+    /// Profiles whose destructive operations require typing the name to confirm
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected_profiles: Vec<ProfileName>,
+    /// Language of messages, detected by `LC_ALL`, `LC_MESSAGES` and `LANG` unless it is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    /// Output for screen readers, without colors and symbols
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plain: bool,
+    #[serde(default, skip_serializing_if = "DaemonConfigs::is_default")]
+    pub daemon: DaemonConfigs,
+    #[serde(default, skip_serializing_if = "PickerConfigs::is_default")]
+    pub picker: PickerConfigs,
+    #[serde(default, skip_serializing_if = "MatchingConfigs::is_default")]
+    pub matching: MatchingConfigs,
+    #[serde(default, skip_serializing_if = "DiscoveryConfigs::is_default")]
+    pub discovery: DiscoveryConfigs,
+    /// URLs receiving JSON of switches, authentications and expiries of contexts, like audit logs or ChatOps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<WebhookConfigs>,
+    /// Chat channels notified of switches to and authentications of profiles with the tags,
+    /// like `prod: {service: slack, url: ...}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub chat_notifications: HashMap<String, ChatNotificationConfigs>,
+}
+
+/// Command of `auth` subcommand for a profile, in which `{{profile}}` is replaced by the profile name.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AuthCommand {
+    /// Script run by `sh -c`
+    Script(String),
+    /// Program and arguments executed directly without a shell,
+    /// e.g. `["aws", "sso", "login", "--profile", "{{profile}}"]`
+    Argv(Vec<String>),
+}
+
+impl From<&str> for AuthCommand {
+    fn from(script: &str) -> Self {
+        Self::Script(script.to_string())
+    }
+}
+
+impl From<String> for AuthCommand {
+    fn from(script: String) -> Self {
+        Self::Script(script)
+    }
+}
+
+/// Auth command of a profile with options of how it runs,
+/// written as the bare command unless any option is set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(from = "AuthCommandRepr", into = "AuthCommandRepr")]
+pub struct AuthCommandConfigs {
+    pub command: AuthCommand,
+    /// Kills the process group of the command after the seconds, like ones waiting on a browser forever
+    pub timeout_seconds: Option<u64>,
+    /// Runs the command again up to the times when it fails or times out
+    pub retries: u32,
+}
+
+impl From<AuthCommand> for AuthCommandConfigs {
+    fn from(command: AuthCommand) -> Self {
+        Self {
+            command,
+            timeout_seconds: None,
+            retries: 0,
+        }
+    }
+}
+
+impl From<&str> for AuthCommandConfigs {
+    fn from(script: &str) -> Self {
+        AuthCommand::from(script).into()
+    }
+}
+
+impl From<String> for AuthCommandConfigs {
+    fn from(script: String) -> Self {
+        AuthCommand::from(script).into()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AuthCommandRepr {
+    Command(AuthCommand),
+    Configs {
+        command: AuthCommand,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_seconds: Option<u64>,
+        #[serde(default)]
+        retries: u32,
+    },
+}
+
+impl From<AuthCommandRepr> for AuthCommandConfigs {
+    fn from(repr: AuthCommandRepr) -> Self {
+        match repr {
+            AuthCommandRepr::Command(command) => command.into(),
+            AuthCommandRepr::Configs {
+                command,
+                timeout_seconds,
+                retries,
+            } => Self {
+                command,
+                timeout_seconds,
+                retries,
+            },
+        }
+    }
+}
+
+impl From<AuthCommandConfigs> for AuthCommandRepr {
+    fn from(configs: AuthCommandConfigs) -> Self {
+        match configs {
+            AuthCommandConfigs {
+                command,
+                timeout_seconds: None,
+                retries: 0,
+            } => Self::Command(command),
+            AuthCommandConfigs {
+                command,
+                timeout_seconds,
+                retries,
+            } => Self::Configs {
+                command,
+                timeout_seconds,
+                retries,
+            },
+        }
+    }
+}
+
+/// Output of `list-contexts` subcommand in the table view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ListingConfigs {
+    /// Columns of the table in the order, overridden by `--columns`
+    pub columns: Vec<ctx::Column>,
+    /// Handlebars template of entries of the table and the finder instead of columns,
+    /// rendered with fields of contexts and `account_alias`
+    pub format: Option<String>,
+}
+
+impl Default for ListingConfigs {
+    fn default() -> Self {
+        Self {
+            columns: vec![ctx::Column::Name, ctx::Column::Description],
+            format: None,
+        }
+    }
+}
+
+impl ListingConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Output of `prompt` subcommand for shell prompts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PromptConfigs {
+    /// Handlebars template rendered with `name`, `expires_in` and `expired`
+    pub format: String,
+    /// Colored in yellow when the session expires within the minutes
+    pub warning_minutes: i64,
+    /// Colored in red when the session expires within the minutes
+    pub critical_minutes: i64,
+}
+
+impl Default for PromptConfigs {
+    fn default() -> Self {
+        Self {
+            format: "{{name}}{{#if expires_in}} ⏳{{expires_in}}{{/if}}"
+                .to_string(),
+            warning_minutes: 15,
+            critical_minutes: 5,
+        }
+    }
+}
+
+impl PromptConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Permission bits of written files, in octal like `"0600"`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct FileMode(pub u32);
+
+impl TryFrom<String> for FileMode {
+    type Error = String;
+
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|m| *m <= 0o777)
+            .map(Self)
+            .ok_or_else(|| format!("invalid file mode: {}", mode))
+    }
+}
+
+impl From<FileMode> for String {
+    fn from(mode: FileMode) -> Self {
+        format!("{:04o}", mode.0)
+    }
+}
+
+/// Modes of `~/.aws` files applied on every write, including the first creation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct FileModesConfigs {
+    pub credentials: FileMode,
+    pub config: FileMode,
+}
+
+impl Default for FileModesConfigs {
+    fn default() -> Self {
+        Self {
+            credentials: FileMode(0o600),
+            config: FileMode(0o644),
+        }
+    }
+}
+
+impl FileModesConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Backups of `~/.aws` files taken before they are overwritten.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct BackupsConfigs {
+    pub enabled: bool,
+    /// Number of backups kept for each file
+    pub keep_last: Option<usize>,
+    /// Backups older than the days are removed
+    pub max_age_days: Option<i64>,
+}
+
+impl Default for BackupsConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keep_last: Some(20),
+            max_age_days: None,
+        }
+    }
+}
+
+impl BackupsConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Daemon serving the status of awsctx to editors and status bars.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct DaemonConfigs {
+    /// Address of the HTTP endpoint, which must be a loopback address
+    pub listen: String,
+    /// Path of the control socket, `daemon.sock` in the state directory by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for DaemonConfigs {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:9457".to_string(),
+            socket: None,
+        }
+    }
+}
+
+impl DaemonConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Finder picking contexts and regions interactively.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PickerConfigs {
+    /// External fuzzy finder like `[fzf, --height=40%]`, which reads candidates from stdin
+    /// and prints the selected one, used instead of the built-in finder
+    pub command: Option<Vec<String>>,
+    /// Lists contexts whose credentials are expired or missing after the others
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub expired_last: bool,
+}
+
+impl PickerConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Templates of profiles generated for accounts of AWS Organizations by `discover`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct DiscoveryConfigs {
+    /// Handlebars template of profile names rendered with `account_name` and `account_id`,
+    /// where account names are lowercased and non-alphanumeric characters are replaced by `-`
+    pub name: String,
+    /// Role assumed in each account with credentials of the source profile
+    pub role_name: String,
+    pub region: Option<String>,
+}
+
+impl Default for DiscoveryConfigs {
+    fn default() -> Self {
+        Self {
+            name: "{{account_name}}".to_string(),
+            role_name: "OrganizationAccountAccessRole".to_string(),
+            region: None,
+        }
+    }
+}
+
+impl DiscoveryConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Credentials file which profiles of the names matching the pattern are written to
+/// instead of `~/.aws/credentials`, like client profiles kept on an encrypted volume.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CredentialsRoute {
+    /// Regular expression matching whole profile names, like `client-.*`
+    pub pattern: String,
+    pub path: PathBuf,
+}
+
+impl CredentialsRoute {
+    pub fn regex(&self) -> Result<Regex, ctx::CTXError> {
+        Regex::new(&format!("^(?:{})$", self.pattern)).map_err(|e| {
+            ctx::CTXError::InvalidConfigurations {
+                message: format!(
+                    "invalid pattern of credentials_routes: {}",
+                    self.pattern
+                ),
+                source: Some(e.into()),
+            }
+        })
+    }
+}
+
+/// Label of contexts of a profile or a tag, like `PROD` in red for the `prod` tag.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LabelConfigs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub text: String,
+    pub color: ctx::LabelColor,
+}
+
+/// Matching of profile names not given exactly to `use-context`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MatchingConfigs {
+    /// Switches to the only profile matched by the prefix or fuzzily, instead of failing
+    pub enabled: bool,
+    /// Asks before switching to the matched profile
+    pub confirm: bool,
+}
+
+impl Default for MatchingConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            confirm: false,
+        }
+    }
+}
+
+impl MatchingConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Proxy for AWS API calls and requests by `curl`,
+/// which overrides `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` of the environment.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProxyConfigs {
+    pub url: String,
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfigs {
+    /// Variables routing requests of child processes through the proxy.
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        let mut envs = vec![
+            ("HTTPS_PROXY", self.url.clone()),
+            ("HTTP_PROXY", self.url.clone()),
+        ];
+        if !self.no_proxy.is_empty() {
+            envs.push(("NO_PROXY", self.no_proxy.join(",")));
+        }
+        envs
+    }
+
+    /// Routes requests of the command through the proxy.
+    pub fn apply(&self, command: &mut Command) {
+        command.envs(self.envs());
+    }
+}
+
+/// Events of contexts posted to webhooks.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    /// The default profile is switched
+    Switch,
+    /// Credentials of a profile are refreshed by `auth`
+    Auth,
+    /// Credentials of a profile expire, noticed by the daemon
+    Expiry,
+}
+
+/// Webhook receiving events of contexts as JSON by `POST`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WebhookConfigs {
+    pub url: String,
+    /// Events posted to the URL, every event if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<WebhookEvent>,
+    /// Shared secret sent in the `X-Awsctx-Secret` header, for receivers to authenticate awsctx
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Retries of transient failures like timeouts and `5xx` responses
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+}
+
+fn default_webhook_retries() -> u32 {
+    2
+}
+
+impl WebhookConfigs {
+    /// Returns whether the event is posted to the URL.
+    pub fn accepts(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Chat services whose incoming webhooks receive notifications.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatService {
+    Slack,
+    Teams,
+}
+
+/// Incoming webhook of a chat channel.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChatNotificationConfigs {
+    pub service: ChatService,
+    pub url: String,
+}
+
+/// Settings of SAML login by [saml2aws](https://github.com/Versent/saml2aws) for a profile.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SamlConfigs {
+    /// URL of the identity provider
+    pub url: String,
+    /// Type of the identity provider supported by saml2aws, e.g. `Okta`, `ADFS` or `KeyCloak`
+    pub idp_provider: String,
+    pub role_arn: Option<String>,
+    pub username: Option<String>,
+    pub mfa: Option<String>,
+    pub session_duration: Option<i64>,
+    #[serde(default = "SamlConfigs::default_program")]
+    pub program: String,
+}
+
+impl SamlConfigs {
+    fn default_program() -> String {
+        "saml2aws".to_string()
+    }
+}
+
+/// Settings of the AWS CLI used by built-in features calling AWS APIs.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct AwsCliConfigs {
+    pub program: String,
+    pub connect_timeout_seconds: u64,
+    pub read_timeout_seconds: u64,
+    /// Max attempts of a request including the first one
+    pub max_attempts: u32,
+}
+
+impl Default for AwsCliConfigs {
+    fn default() -> Self {
+        Self {
+            program: "aws".to_string(),
+            connect_timeout_seconds: 10,
+            read_timeout_seconds: 30,
+            max_attempts: 3,
+        }
+    }
+}
+
+impl AwsCliConfigs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Writes temporary credentials to `<profile><suffix>` instead of the profile itself,
+/// so that long-lived keys of the base profile are never overwritten.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SessionProfiles {
+    pub enabled: bool,
+    pub suffix: String,
+}
+
+impl Default for SessionProfiles {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            suffix: "-session".to_string(),
+        }
+    }
+}
+
+impl SessionProfiles {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn profile_name(&self, profile: &str) -> String {
+        format!("{}{}", profile, self.suffix)
+    }
+
+    /// Returns the base profile name if `name` is a session profile.
+    pub fn base_profile_name<'a>(&self, name: &'a str) -> Option<&'a str> {
+        if !self.enabled || self.suffix.is_empty() {
+            return None;
+        }
+        name.strip_suffix(self.suffix.as_str())
+            .filter(|base| !base.is_empty())
+    }
 }
 
 impl Default for Configs {
@@ -33,8 +661,39 @@ impl Default for Configs {
             Self::DEFAULT_AUTH_COMMAND_KEY.to_string()  => r#"echo "This is default configuration for auth commands."
 echo "You can edit this configuration on ~/.awsctx/configs.yaml according to your needs."
 aws configure --profile {{profile}}
-"#.to_string(),
+"#.into(),
                 },
+            credential_outputs: Vec::new(),
+            session_profiles: SessionProfiles::default(),
+            aws_cli: AwsCliConfigs::default(),
+            saml: HashMap::new(),
+            sso_sessions: HashMap::new(),
+            proxy: None,
+            offline: false,
+            lenient: false,
+            verify_identity: false,
+            auto_refresh: false,
+            protect_version_controlled: false,
+            credentials_routes: vec![],
+            config_fragments_dir: None,
+            file_modes: FileModesConfigs::default(),
+            backups: BackupsConfigs::default(),
+            prompt: PromptConfigs::default(),
+            tags: HashMap::new(),
+            descriptions: HashMap::new(),
+            aliases: HashMap::new(),
+            labels: vec![],
+            listing: ListingConfigs::default(),
+            update_check: false,
+            protected_profiles: vec![],
+            locale: None,
+            plain: false,
+            daemon: DaemonConfigs::default(),
+            picker: PickerConfigs::default(),
+            matching: MatchingConfigs::default(),
+            discovery: DiscoveryConfigs::default(),
+            webhooks: vec![],
+            chat_notifications: HashMap::new(),
         }
     }
 }
@@ -54,6 +713,13 @@ impl Configs {
 #   bar: |
 #     # In this case, name of one-login configuration is same as `profile`
 #     onelogin-aws-login -C {{profile}} --profile {{profile}} -u user@example.com
+#   # configuration for `baz` profile executed directly without a shell
+#   baz: [aws, sso, login, --profile, "{{profile}}"]
+#   # configuration killed after `timeout_seconds` and run again up to `retries` times on failures
+#   qux:
+#     command: [aws, sso, login, --profile, "{{profile}}"]
+#     timeout_seconds: 300
+#     retries: 1
 #   # default configuration for profiles without auth configuration
 #   __default: |
 #     aws configure --profile {{profile}}
@@ -61,6 +727,82 @@ impl Configs {
 
     pub const DEFAULT_AUTH_COMMAND_KEY: &'static str = "__default";
 
+    /// Fails if network access is disabled by offline mode.
+    pub fn ensure_online(&self, operation: &str) -> Result<(), ctx::CTXError> {
+        if self.offline {
+            return Err(ctx::CTXError::Offline {
+                operation: operation.to_string(),
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Resolves an alias to the profile name, or returns the name as it is.
+    pub fn resolve_alias(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Returns the first label matching the profile or one of its tags.
+    pub fn label_of(
+        &self,
+        profile: &str,
+        tags: &[String],
+    ) -> Option<ctx::Label> {
+        self.labels
+            .iter()
+            .find(|l| {
+                l.profile.as_deref() == Some(profile)
+                    || l.tag.as_ref().is_some_and(|t| tags.contains(t))
+            })
+            .map(|l| ctx::Label {
+                text: l.text.clone(),
+                color: l.color,
+            })
+    }
+
+    /// Returns the aliases of the profile in the order of names.
+    pub fn aliases_of(&self, profile: &str) -> Vec<String> {
+        let mut aliases = self
+            .aliases
+            .iter()
+            .filter(|(_, p)| p.as_str() == profile)
+            .map(|(a, _)| a.clone())
+            .collect::<Vec<String>>();
+        aliases.sort();
+        aliases
+    }
+
+    /// Drops entries of the profile like tags, aliases and auth commands, once it is deleted.
+    /// Returns whether any entry is dropped.
+    pub fn remove_profile_entries(&mut self, profile: &str) -> bool {
+        let removed = [
+            self.auth_commands.remove(profile).is_some(),
+            self.saml.remove(profile).is_some(),
+            self.tags.remove(profile).is_some(),
+            self.descriptions.remove(profile).is_some(),
+        ]
+        .contains(&true);
+        let lengths = |c: &Self| {
+            (
+                c.credential_outputs.len(),
+                c.aliases.len(),
+                c.labels.len(),
+                c.protected_profiles.len(),
+            )
+        };
+        let before = lengths(self);
+        self.credential_outputs.retain(|p| p != profile);
+        self.aliases.retain(|_, p| p != profile);
+        self.labels
+            .retain(|l| l.profile.as_deref() != Some(profile));
+        self.protected_profiles.retain(|p| p != profile);
+        removed || before != lengths(self)
+    }
+
     pub fn load_configs<P: AsRef<Path>>(
         path: Option<P>,
     ) -> Result<Self, ctx::CTXError> {
@@ -101,6 +843,17 @@ impl Configs {
         if path.exists() {
             return Self::load_configs(Some(path));
         }
+        Self::initialize_configs(Some(path), &Configs::default())
+    }
+
+    /// Writes `configs` to a new configuration file with descriptions of the usage.
+    pub fn initialize_configs<P: AsRef<Path>>(
+        path: Option<P>,
+        configs: &Configs,
+    ) -> Result<Self, ctx::CTXError> {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| CONFIGS_PATH.clone());
         // if the config directory does not exist, create the directory recursively
         path.parent()
             .map_or_else(
@@ -117,7 +870,6 @@ impl Configs {
             )
             .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
 
-        let c = Configs::default();
         let mut file = fs::File::create(&path)
             .context("failed to create a configuration file")
             .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
@@ -126,7 +878,8 @@ impl Configs {
             .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
 
         let mut ser = serde_yaml::Serializer::new(&mut file);
-        c.serialize(&mut ser)
+        configs
+            .serialize(&mut ser)
             .context("failed to serialize configuration")
             .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
         file.flush()
@@ -166,9 +919,10 @@ mod tests {
     #[fixture]
     pub fn configs() -> Configs {
         Configs {
-            auth_commands: vec![("foo".to_string(), "echo 1".to_string())]
+            auth_commands: vec![("foo".to_string(), "echo 1".into())]
                 .into_iter()
-                .collect::<HashMap<String, String>>(),
+                .collect::<HashMap<String, AuthCommandConfigs>>(),
+            ..Default::default()
         }
     }
 
@@ -218,6 +972,161 @@ mod tests {
         }
     }
 
+    #[rstest]
+    fn test_configs_load_configs_with_argv() {
+        let input = configs_file(
+            r#"auth_commands:
+  foo: [aws, sso, login, --profile, "{{profile}}"]
+  bar: echo 1"#
+                .to_string(),
+        );
+        let actual = Configs::load_configs(Some(input.path())).unwrap();
+        assert_eq!(
+            AuthCommandConfigs::from(AuthCommand::Argv(
+                ["aws", "sso", "login", "--profile", "{{profile}}"]
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect()
+            )),
+            actual.auth_commands["foo"]
+        );
+        assert_eq!(
+            AuthCommandConfigs::from("echo 1"),
+            actual.auth_commands["bar"]
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case("foo: echo 1", None, 0)]
+    #[case("foo: {command: echo 1, timeout_seconds: 300}", Some(300), 0)]
+    #[case("foo: {command: [echo, '1'], retries: 2}", None, 2)]
+    fn test_configs_load_configs_with_auth_options(
+        #[case] text: &str,
+        #[case] timeout_seconds: Option<u64>,
+        #[case] retries: u32,
+    ) {
+        let input = configs_file(format!("auth_commands:\n  {}", text));
+        let actual = Configs::load_configs(Some(input.path())).unwrap();
+        let command = &actual.auth_commands["foo"];
+        assert_eq!(timeout_seconds, command.timeout_seconds);
+        assert_eq!(retries, command.retries);
+        // commands without options are written back as they are
+        let dumped = serde_yaml::to_string(command).unwrap();
+        assert_eq!(
+            timeout_seconds.is_none() && retries == 0,
+            !dumped.contains("command:")
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case("file_modes:\n  credentials: \"0640\"", Some((0o640, 0o644)))]
+    #[case("file_modes:\n  config: \"0o600\"", Some((0o600, 0o600)))]
+    #[case("file_modes:\n  config: \"0999\"", None)]
+    fn test_configs_load_configs_with_file_modes(
+        #[case] text: &str,
+        #[case] expect: Option<(u32, u32)>,
+    ) {
+        let input = configs_file(format!("auth_commands: {{}}\n{}", text));
+        let actual = Configs::load_configs(Some(input.path()))
+            .ok()
+            .map(|c| (c.file_modes.credentials.0, c.file_modes.config.0));
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest(::trace)]
+    #[case("p", "prod-admin")]
+    #[case("prod-admin", "prod-admin")]
+    #[case("unknown", "unknown")]
+    fn test_configs_resolve_alias(#[case] name: &str, #[case] expect: &str) {
+        let configs = Configs {
+            aliases: hashmap! {
+                "p".to_string() => "prod-admin".to_string(),
+                "s".to_string() => "staging-dev".to_string(),
+            },
+            ..Default::default()
+        };
+        assert_eq!(expect, configs.resolve_alias(name));
+    }
+
+    #[rstest]
+    fn test_configs_aliases_of() {
+        let configs = Configs {
+            aliases: hashmap! {
+                "prod".to_string() => "prod-admin".to_string(),
+                "p".to_string() => "prod-admin".to_string(),
+                "s".to_string() => "staging-dev".to_string(),
+            },
+            ..Default::default()
+        };
+        assert_eq!(vec!["p", "prod"], configs.aliases_of("prod-admin"));
+        assert!(configs.aliases_of("dev").is_empty());
+    }
+
+    #[rstest]
+    fn test_configs_remove_profile_entries() {
+        let mut configs = Configs {
+            aliases: hashmap! {
+                "p".to_string() => "prod-admin".to_string(),
+                "s".to_string() => "staging-dev".to_string(),
+            },
+            tags: hashmap! {
+                "prod-admin".to_string() => vec!["prod".to_string()],
+            },
+            protected_profiles: vec!["prod-admin".to_string()],
+            ..Default::default()
+        };
+        assert!(configs.remove_profile_entries("prod-admin"));
+        assert_eq!(vec!["s"], configs.aliases.keys().collect::<Vec<&String>>());
+        assert!(configs.tags.is_empty());
+        assert!(configs.protected_profiles.is_empty());
+        assert!(!configs.remove_profile_entries("prod-admin"));
+    }
+
+    #[rstest(::trace)]
+    #[case("prod-admin", vec![], Some("ADMIN"))]
+    #[case("prod-readonly", vec!["prod"], Some("PROD"))]
+    #[case("prod-admin", vec!["prod"], Some("ADMIN"))]
+    #[case("sandbox", vec!["dev"], None)]
+    fn test_configs_label_of(
+        #[case] profile: &str,
+        #[case] tags: Vec<&str>,
+        #[case] expect: Option<&str>,
+    ) {
+        let input = configs_file(
+            r#"auth_commands: {}
+labels:
+  - profile: prod-admin
+    text: ADMIN
+    color: magenta
+  - tag: prod
+    text: PROD
+    color: red"#
+                .to_string(),
+        );
+        let configs = Configs::load_configs(Some(input.path())).unwrap();
+        let tags = tags.iter().map(|t| t.to_string()).collect::<Vec<String>>();
+        let actual = configs.label_of(profile, &tags);
+        assert_eq!(expect, actual.as_ref().map(|l| l.text.as_str()));
+    }
+
+    #[rstest]
+    fn test_proxy_configs_apply() {
+        let proxy = ProxyConfigs {
+            url: "http://proxy.example.com:8080".to_string(),
+            no_proxy: vec!["localhost".to_string(), "10.0.0.0/8".to_string()],
+        };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo \"$HTTPS_PROXY $HTTP_PROXY $NO_PROXY\"");
+        proxy.apply(&mut command);
+        let output = command.output().unwrap();
+        assert_eq!(
+            "http://proxy.example.com:8080 http://proxy.example.com:8080 localhost,10.0.0.0/8\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
     #[rstest]
     fn test_initialize_default_configs() {
         let tmpdir = TempDir::new().unwrap();
@@ -237,6 +1146,13 @@ mod tests {
 #   bar: |
 #     # In this case, name of one-login configuration is same as `profile`
 #     onelogin-aws-login -C {{profile}} --profile {{profile}} -u user@example.com
+#   # configuration for `baz` profile executed directly without a shell
+#   baz: [aws, sso, login, --profile, "{{profile}}"]
+#   # configuration killed after `timeout_seconds` and run again up to `retries` times on failures
+#   qux:
+#     command: [aws, sso, login, --profile, "{{profile}}"]
+#     timeout_seconds: 300
+#     retries: 1
 #   # default configuration for profiles without auth configuration
 #   __default: |
 #     aws configure --profile {{profile}}
@@ -0,0 +1,115 @@
+use crate::ctx;
+
+use std::io::{BufRead, Write};
+
+use anyhow::Context;
+
+/// Shared confirmation of destructive operations, so that every command is guarded alike.
+///
+/// Operations on protected profiles require typing the name instead of `y`.
+#[derive(Debug, Clone, Default)]
+pub struct Confirmation {
+    /// Bypasses the questions, like `--yes`
+    pub yes: bool,
+    /// Questions are never asked in non-interactive mode, so it fails without `yes`
+    pub interactive: bool,
+    pub protected: Vec<String>,
+}
+
+impl Confirmation {
+    /// Asks whether to go on with the operation on the profile, like `delete profile (foo)`.
+    /// Fails with `InvalidInput` unless it is confirmed.
+    pub fn confirm<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+        operation: &str,
+        profile: &str,
+    ) -> Result<(), ctx::CTXError> {
+        if self.yes {
+            return Ok(());
+        }
+        let declined = |message: &str| ctx::CTXError::InvalidInput {
+            message: format!("{} ({}) {}", operation, profile, message),
+            source: None,
+        };
+        if !self.interactive {
+            return Err(declined("requires `--yes` in non-interactive mode"));
+        }
+        let protected = self.protected.iter().any(|p| p == profile);
+        let question = if protected {
+            format!(
+                "{} ({}), a protected profile? Type the name to confirm:",
+                operation, profile
+            )
+        } else {
+            format!("{} ({})? [y/N]", operation, profile)
+        };
+        let answer = ask(input, output, &question)
+            .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
+        let confirmed = if protected {
+            answer == profile
+        } else {
+            answer.eq_ignore_ascii_case("y")
+                || answer.eq_ignore_ascii_case("yes")
+        };
+        if confirmed {
+            Ok(())
+        } else {
+            Err(declined("is cancelled"))
+        }
+    }
+}
+
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+) -> anyhow::Result<String> {
+    write!(output, "{} ", question)
+        .and_then(|_| output.flush())
+        .context("failed to ask for confirmation")?;
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .context("failed to read confirmation")?;
+    Ok(answer.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(true, false, "foo", "", true)]
+    #[case(false, false, "foo", "y\n", false)]
+    #[case(false, true, "foo", "y\n", true)]
+    #[case(false, true, "foo", "\n", false)]
+    #[case(false, true, "prod", "y\n", false)]
+    #[case(false, true, "prod", "prod\n", true)]
+    fn test_confirmation_confirm(
+        #[case] yes: bool,
+        #[case] interactive: bool,
+        #[case] profile: &str,
+        #[case] answer: &str,
+        #[case] expect: bool,
+    ) {
+        let confirmation = Confirmation {
+            yes,
+            interactive,
+            protected: vec!["prod".to_string()],
+        };
+        let mut output = vec![];
+        let actual = confirmation.confirm(
+            &mut Cursor::new(answer),
+            &mut output,
+            "delete profile",
+            profile,
+        );
+        assert_eq!(expect, actual.is_ok());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::configs::ProxyConfigs;
+use crate::ctx;
+
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+const FEDERATION_ENDPOINT: &str = "https://signin.aws.amazon.com/federation";
+/// Shown in the console as the issuer of the sign-in
+const FEDERATION_ISSUER: &str = "awsctx";
+const FEDERATION_TIMEOUT_SECONDS: u64 = 30;
+
+/// Temporary credentials exchanged for a sign-in token of the console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+}
+
+impl SessionCredentials {
+    /// Picks credentials from variables given by `AWS::context_env`.
+    /// Long-lived keys without session tokens cannot sign in the console and give `None`.
+    pub fn from_env_vars(vars: &[(String, String)]) -> Option<Self> {
+        let get = |key: &str| {
+            vars.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.to_string())
+        };
+        Some(Self {
+            access_key_id: get("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: get("AWS_SECRET_ACCESS_KEY")?,
+            session_token: get("AWS_SESSION_TOKEN")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SigninToken {
+    #[serde(rename = "SigninToken")]
+    signin_token: String,
+}
+
+/// Home of the AWS console, in the region if it is known.
+pub fn home_url(region: Option<&str>) -> Url {
+    match region {
+        Some(region) => Url::parse_with_params(
+            &format!("https://{}.console.aws.amazon.com/console/home", region),
+            &[("region", region)],
+        )
+        .unwrap(),
+        None => {
+            Url::parse("https://console.aws.amazon.com/console/home").unwrap()
+        }
+    }
+}
+
+/// Page of the service in the console, like `s3` or `ec2`, in the region if it is known.
+pub fn service_url(service: &str, region: Option<&str>) -> Url {
+    let host = region.map_or_else(
+        || "console.aws.amazon.com".to_string(),
+        |r| format!("{}.console.aws.amazon.com", r),
+    );
+    let mut url =
+        Url::parse(&format!("https://{}/{}/home", host, service)).unwrap();
+    if let Some(region) = region {
+        url.query_pairs_mut().append_pair("region", region);
+    }
+    url
+}
+
+/// Goes to `destination` through the sign-in page of the account if the account is known,
+/// so that the console is not opened in another account signed in before.
+pub fn signin_url(account: Option<&str>, destination: Url) -> Url {
+    match account {
+        Some(account) => Url::parse_with_params(
+            &format!("https://{}.signin.aws.amazon.com/console", account),
+            &[("redirect_uri", destination.as_str())],
+        )
+        .unwrap(),
+        None => destination,
+    }
+}
+
+/// URL of the console for the context.
+pub fn context_url(context: &ctx::ContextMetadata) -> Url {
+    signin_url(
+        context.account.as_deref(),
+        home_url(context.region.as_deref()),
+    )
+}
+
+/// Builds a URL which signs in the console by [the federation endpoint](https://docs.aws.amazon.com/IAM/latest/UserGuide/id_roles_providers_enable-console-custom-url.html)
+/// and goes to `destination`.
+///
+/// The sign-in token is fetched by `curl`, since the endpoint is served only over HTTPS.
+/// The session is passed by stdin so that the credentials never appear in arguments.
+pub fn federation_url(
+    curl: &str,
+    credentials: &SessionCredentials,
+    destination: &Url,
+    proxy: Option<&ProxyConfigs>,
+) -> Result<Url, ctx::CTXError> {
+    let session = json!({
+        "sessionId": credentials.access_key_id,
+        "sessionKey": credentials.secret_access_key,
+        "sessionToken": credentials.session_token,
+    })
+    .to_string();
+    let mut command = Command::new(curl);
+    command
+        .args(["--silent", "--show-error", "--fail", "--get"])
+        .arg(format!("--max-time={}", FEDERATION_TIMEOUT_SECONDS))
+        .args(["--data-urlencode", "Action=getSigninToken"])
+        .args(["--data-urlencode", "Session@-"])
+        .arg(FEDERATION_ENDPOINT)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(proxy) = proxy {
+        proxy.apply(&mut command);
+    }
+    debug!("fetch a sign-in token of the console by {}", curl);
+    let output = command
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(session.as_bytes())?;
+            child.wait_with_output()
+        })
+        .map_err(|e| ctx::CTXError::AWSRequestFailed {
+            message: format!("failed to execute `{}`", curl),
+            source: Some(e.into()),
+        })?;
+    if !output.status.success() {
+        return Err(ctx::CTXError::AWSRequestFailed {
+            message: "failed to fetch a sign-in token of the console"
+                .to_string(),
+            source: Some(anyhow!(
+                "{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+        });
+    }
+    let token = serde_json::from_slice::<SigninToken>(&output.stdout)
+        .context("failed to parse a sign-in token of the console")
+        .map_err(|e| ctx::CTXError::AWSRequestFailed {
+            message: "unexpected response of the federation endpoint"
+                .to_string(),
+            source: Some(e),
+        })?;
+    Ok(Url::parse_with_params(
+        FEDERATION_ENDPOINT,
+        &[
+            ("Action", "login"),
+            ("Issuer", FEDERATION_ISSUER),
+            ("Destination", destination.as_str()),
+            ("SigninToken", token.signin_token.as_str()),
+        ],
+    )
+    .unwrap())
+}
+
+/// Opens the URL by `$BROWSER` or the default browser of the platform.
+pub fn open_browser(url: &Url) -> Result<(), ctx::CTXError> {
+    let (program, args) = match env::var("BROWSER") {
+        Ok(browser) if !browser.is_empty() => (browser, vec![]),
+        _ if cfg!(target_os = "macos") => ("open".to_string(), vec![]),
+        _ if cfg!(windows) => ("cmd".to_string(), vec!["/C", "start", ""]),
+        _ => ("xdg-open".to_string(), vec![]),
+    };
+    Command::new(&program)
+        .args(args)
+        .arg(url.as_str())
+        .stdin(Stdio::null())
+        .status()
+        .context(format!("failed to execute `{}`", program))
+        .and_then(|status| {
+            status
+                .success()
+                .then_some(())
+                .ok_or_else(|| anyhow!("`{}` exited with {}", program, status))
+        })
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    #[fixture]
+    pub fn credentials() -> SessionCredentials {
+        SessionCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "xx/xx+xx=".to_string(),
+            session_token: "token".to_string(),
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case(None, "https://console.aws.amazon.com/s3/home")]
+    #[case(
+        Some("us-east-1"),
+        "https://us-east-1.console.aws.amazon.com/s3/home?region=us-east-1"
+    )]
+    fn test_service_url(#[case] region: Option<&str>, #[case] expect: &str) {
+        assert_eq!(expect, service_url("s3", region).as_str());
+    }
+
+    #[rstest]
+    fn test_session_credentials_from_env_vars(credentials: SessionCredentials) {
+        let mut vars = vec![
+            ("AWS_ACCESS_KEY_ID".to_string(), "XXXXXXXXXXX".to_string()),
+            ("AWS_SECRET_ACCESS_KEY".to_string(), "xx/xx+xx=".to_string()),
+            ("AWS_REGION".to_string(), "us-east-1".to_string()),
+        ];
+        assert_eq!(None, SessionCredentials::from_env_vars(&vars));
+        vars.push(("AWS_SESSION_TOKEN".to_string(), "token".to_string()));
+        assert_eq!(Some(credentials), SessionCredentials::from_env_vars(&vars));
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        r#"session=$(cat)
+case "$*:$session" in
+    *Action=getSigninToken*'"sessionToken":"token"'*) echo '{"SigninToken": "signin"}' ;;
+    *) echo "unexpected request" >&2; exit 22 ;;
+esac"#,
+        Some("https://signin.aws.amazon.com/federation?Action=login&Issuer=awsctx&Destination=https%3A%2F%2Fconsole.aws.amazon.com%2Fs3%2Fhome&SigninToken=signin")
+    )]
+    #[case("echo 'curl: (22) 400' >&2; exit 22", None)]
+    #[case("echo 'not json'", None)]
+    fn test_federation_url(
+        credentials: SessionCredentials,
+        #[case] script: &str,
+        #[case] expect: Option<&str>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let curl = fake_executable(&tmpdir, "curl", script);
+        let actual = federation_url(
+            curl.to_str().unwrap(),
+            &credentials,
+            &service_url("s3", None),
+            None,
+        );
+        assert_eq!(expect, actual.ok().as_ref().map(|u| u.as_str()));
+    }
+
+    #[rstest(::trace)]
+    #[case(None, None, "https://console.aws.amazon.com/console/home")]
+    #[case(
+        None,
+        Some("ap-northeast-1"),
+        "https://ap-northeast-1.console.aws.amazon.com/console/home?region=ap-northeast-1"
+    )]
+    #[case(
+        Some("123456789012"),
+        Some("us-east-1"),
+        "https://123456789012.signin.aws.amazon.com/console?redirect_uri=https%3A%2F%2Fus-east-1.console.aws.amazon.com%2Fconsole%2Fhome%3Fregion%3Dus-east-1"
+    )]
+    fn test_context_url(
+        #[case] account: Option<&str>,
+        #[case] region: Option<&str>,
+        #[case] expect: &str,
+    ) {
+        let context = ctx::ContextMetadata {
+            name: "foo".to_string(),
+            account: account.map(|a| a.to_string()),
+            region: region.map(|r| r.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(expect, context_url(&context).as_str());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::awscli::{run_concurrently, AwsCli};
+use crate::ctx;
+use crate::state::{self, COSTS_CACHE_PATH};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Cost Explorer is served only in us-east-1
+const COST_EXPLORER_REGION: &str = "us-east-1";
+/// Cost Explorer updates costs a few times a day and charges every request,
+/// so fetched costs are reused this long
+const COST_TTL_HOURS: i64 = 12;
+
+/// Month-to-date spend of an account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cost {
+    pub amount: f64,
+    pub unit: String,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl Cost {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        // costs of the last month are never reused in a new month
+        self.fetched_at.year() == now.year()
+            && self.fetched_at.month() == now.month()
+            && now - self.fetched_at < Duration::hours(COST_TTL_HOURS)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextCost {
+    pub profile: String,
+    pub account: Option<String>,
+    pub cost: Option<Cost>,
+    /// Error message reported by AWS for failed requests
+    pub message: Option<String>,
+}
+
+/// Month-to-date spend keyed by profile names.
+#[derive(Default, Debug, PartialEq)]
+pub struct CostsCache {
+    path: PathBuf,
+    entries: HashMap<String, Cost>,
+}
+
+impl CostsCache {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| COSTS_CACHE_PATH.clone());
+        Self {
+            entries: state::load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    /// Returns the cost of the profile unless it is stale.
+    pub fn get(&self, profile: &str, now: DateTime<Utc>) -> Option<&Cost> {
+        self.entries.get(profile).filter(|c| c.is_fresh(now))
+    }
+
+    pub fn insert(&mut self, profile: &str, cost: Cost) {
+        self.entries.insert(profile.to_string(), cost);
+    }
+
+    pub fn dump(&mut self) -> Result<(), ctx::CTXError> {
+        let now = Utc::now();
+        self.entries.retain(|_, c| c.is_fresh(now));
+
+        state::save(&self.path, &self.entries)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetCostAndUsageOutput {
+    results_by_time: Vec<ResultByTime>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResultByTime {
+    total: HashMap<String, MetricValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MetricValue {
+    amount: String,
+    unit: String,
+}
+
+fn first_day_of_month(now: DateTime<Utc>) -> NaiveDate {
+    NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap()
+}
+
+/// Calls Cost Explorer GetCostAndUsage for month-to-date spend with credentials of the profile.
+/// Spend is filtered by the account if it is known,
+/// since the management account of an organization sees spend of all the member accounts.
+pub fn fetch_month_to_date_cost(
+    cli: &AwsCli,
+    profile: &str,
+    account: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Cost, ctx::CTXError> {
+    // the end is exclusive and must be after the start even on the first day of a month
+    let time_period = format!(
+        "Start={},End={}",
+        first_day_of_month(now).format("%Y-%m-%d"),
+        (now + Duration::days(1)).format("%Y-%m-%d")
+    );
+    let filter = account.map(|a| {
+        json!({"Dimensions": {"Key": "LINKED_ACCOUNT", "Values": [a]}})
+            .to_string()
+    });
+    let mut args = vec![
+        "ce",
+        "get-cost-and-usage",
+        "--time-period",
+        &time_period,
+        "--granularity",
+        "MONTHLY",
+        "--metrics",
+        "UnblendedCost",
+        "--profile",
+        profile,
+        "--region",
+        COST_EXPLORER_REGION,
+    ];
+    if let Some(filter) = filter.as_ref() {
+        args.extend(["--filter", filter]);
+    }
+    let output = cli.run::<GetCostAndUsageOutput>(&args)?;
+    let unexpected = |message: &str| ctx::CTXError::AWSRequestFailed {
+        message: "unexpected output of `aws ce get-cost-and-usage`".to_string(),
+        source: Some(anyhow!("{}", message)),
+    };
+    let metric = output
+        .results_by_time
+        .into_iter()
+        .next()
+        .and_then(|mut r| r.total.remove("UnblendedCost"))
+        .ok_or_else(|| unexpected("no UnblendedCost in the results"))?;
+    Ok(Cost {
+        amount: metric
+            .amount
+            .parse()
+            .map_err(|_| unexpected("amount is not a number"))?,
+        unit: metric.unit,
+        fetched_at: now,
+    })
+}
+
+/// Returns month-to-date spend of the contexts, fetching costs missing in the cache
+/// with at most `parallelism` concurrent requests.
+/// Fetched costs are stored in the cache, which the caller should dump.
+pub fn month_to_date_costs(
+    cli: &AwsCli,
+    cache: &mut CostsCache,
+    contexts: &[ctx::ContextMetadata],
+    refresh: bool,
+    parallelism: usize,
+    now: DateTime<Utc>,
+) -> Vec<ContextCost> {
+    let stale = contexts
+        .iter()
+        .filter(|c| refresh || cache.get(&c.name, now).is_none())
+        .collect::<Vec<&ctx::ContextMetadata>>();
+    let fetched = run_concurrently(&stale, parallelism, |c| {
+        fetch_month_to_date_cost(cli, &c.name, c.account.as_deref(), now)
+    });
+    let mut messages = HashMap::new();
+    for (context, result) in stale.iter().zip(fetched) {
+        match result {
+            Ok(cost) => cache.insert(&context.name, cost),
+            Err(e) => {
+                let message = match &e {
+                    ctx::CTXError::AWSRequestFailed {
+                        source: Some(source),
+                        ..
+                    } => source.to_string(),
+                    _ => e.to_string(),
+                };
+                messages.insert(context.name.as_str(), message);
+            }
+        }
+    }
+    contexts
+        .iter()
+        .map(|c| ContextCost {
+            profile: c.name.clone(),
+            account: c.account.clone(),
+            cost: messages
+                .get(c.name.as_str())
+                .map_or_else(|| cache.get(&c.name, now).cloned(), |_| None),
+            message: messages.get(c.name.as_str()).map(|m| m.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[fixture]
+    pub fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2022-08-31T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[rstest]
+    fn test_fetch_month_to_date_cost(now: DateTime<Utc>) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"case "$*" in
+    *"Start=2022-08-01,End=2022-09-01"*"--profile foo"*'"Values":["123456789012"]'*)
+        echo '{"ResultsByTime": [{"Total": {"UnblendedCost": {"Amount": "12.3456", "Unit": "USD"}}}]}' ;;
+    *) echo "unexpected arguments: $*" >&2; exit 255 ;;
+esac"#,
+        );
+        let actual =
+            fetch_month_to_date_cost(&cli, "foo", Some("123456789012"), now)
+                .unwrap();
+        assert_eq!(
+            Cost {
+                amount: 12.3456,
+                unit: "USD".to_string(),
+                fetched_at: now,
+            },
+            actual
+        );
+    }
+
+    #[rstest]
+    fn test_month_to_date_costs() {
+        let now = Utc::now();
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("cache/costs.json");
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"case "$*" in
+    *"--profile bar"*) echo '{"ResultsByTime": [{"Total": {"UnblendedCost": {"Amount": "1.5", "Unit": "USD"}}}]}' ;;
+    *) echo "An error occurred (AccessDeniedException)" >&2; exit 255 ;;
+esac"#,
+        );
+        let cached = Cost {
+            amount: 3.0,
+            unit: "USD".to_string(),
+            fetched_at: now,
+        };
+        let mut cache = CostsCache::load(Some(&path));
+        cache.insert("foo", cached.clone());
+        // stale in a new month
+        cache.insert(
+            "baz",
+            Cost {
+                fetched_at: now - Duration::days(31),
+                ..cached.clone()
+            },
+        );
+        let contexts = ["bar", "baz", "foo"]
+            .into_iter()
+            .map(|name| ctx::ContextMetadata {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<ctx::ContextMetadata>>();
+
+        let actual =
+            month_to_date_costs(&cli, &mut cache, &contexts, false, 2, now);
+        assert_eq!(Some(1.5), actual[0].cost.as_ref().map(|c| c.amount));
+        assert_eq!(None, actual[1].cost);
+        assert_eq!(
+            Some("An error occurred (AccessDeniedException)"),
+            actual[1].message.as_deref()
+        );
+        assert_eq!(Some(&cached), actual[2].cost.as_ref());
+
+        cache.dump().unwrap();
+        let actual = CostsCache::load(Some(&path));
+        assert!(actual.get("bar", now).is_some());
+        assert!(actual.get("baz", now).is_none());
+    }
+}
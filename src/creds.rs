@@ -1,4 +1,6 @@
+use crate::configs::{FileMode, FileModesConfigs};
 use crate::ctx;
+use crate::file::{write_atomic, TextEncoding};
 
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
@@ -6,31 +8,161 @@ use std::fmt;
 use std::fmt::Debug;
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::BufReader;
 use std::io::{BufWriter, Read};
 use std::path::Path;
 use std::rc::Rc;
 
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ArgEnum;
 use config;
 use ini::Ini;
+use serde::{Deserialize, Serialize};
 
-const DEFAULT_PROFILE_NAME: &str = "default";
+pub const DEFAULT_PROFILE_NAME: &str = "default";
+pub const ACCESS_KEY_ID_KEY: &str = "aws_access_key_id";
+pub const SECRET_ACCESS_KEY_KEY: &str = "aws_secret_access_key";
+pub const SESSION_TOKEN_KEY: &str = "aws_session_token";
+pub const EXPIRATION_KEY: &str = "x_security_token_expires";
+/// Keys which tools writing temporary credentials use to record their expiration
+const EXPIRATION_KEYS: [&str; 2] = [EXPIRATION_KEY, "expiration"];
+/// Keys replaced together by merged credentials, not to mix up old and new ones
+const CREDENTIAL_KEYS: [&str; 3] =
+    [ACCESS_KEY_ID_KEY, SECRET_ACCESS_KEY_KEY, SESSION_TOKEN_KEY];
 
 #[derive(Default, Debug, PartialEq, Eq)]
 pub struct Profile {
     pub name: String,
     pub default: bool,
-    #[allow(dead_code)]
     items: Rc<HashMap<String, String>>,
 }
 
+/// Short-lived credentials issued by STS, in the shape returned by the AWS APIs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TemporaryCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    pub expiration: DateTime<Utc>,
+}
+
+/// Formats of credentials printed by `creds` subcommand.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredsFormat {
+    /// JSON read by AWS SDKs and CLI from `credential_process`
+    Process,
+}
+
+/// Credentials in the output format of `credential_process`, whose version is always 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProcessCredentials {
+    pub version: u8,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    /// Long-lived keys have no expiration, and are never refreshed by SDKs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<DateTime<Utc>>,
+}
+
+impl Profile {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns when the credentials of the profile expire, if it is recorded.
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        expiration_of(&self.items)
+    }
+
+    /// Returns the credentials of the profile for `credential_process`.
+    pub fn process_credentials(
+        &self,
+    ) -> Result<ProcessCredentials, ctx::CTXError> {
+        let get = |key: &str| {
+            self.get(key)
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string())
+                .ok_or_else(|| ctx::CTXError::CredentialsIsBroken {
+                    source: Some(anyhow!(
+                        "no {} in profile ({})",
+                        key,
+                        self.name
+                    )),
+                })
+        };
+        Ok(ProcessCredentials {
+            version: 1,
+            access_key_id: get(ACCESS_KEY_ID_KEY)?,
+            secret_access_key: get(SECRET_ACCESS_KEY_KEY)?,
+            session_token: get(SESSION_TOKEN_KEY).ok(),
+            expiration: self.expiration(),
+        })
+    }
+}
+
+/// Borrowed view of a profile, which never clones the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileRef<'a> {
+    pub name: &'a str,
+    pub default: bool,
+    items: &'a HashMap<String, String>,
+}
+
+impl<'a> ProfileRef<'a> {
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.items.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns when the credentials of the profile expire, if it is recorded.
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        expiration_of(self.items)
+    }
+}
+
+fn expiration_of(items: &HashMap<String, String>) -> Option<DateTime<Utc>> {
+    EXPIRATION_KEYS
+        .iter()
+        .filter_map(|key| items.get(*key))
+        .find_map(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|d| d.with_timezone(&Utc))
+}
+
 type CredentialData = HashMap<String, Rc<HashMap<String, String>>>;
 
 #[derive(Default, Debug, PartialEq, Eq)]
 pub struct Credentials {
     data: CredentialData,
     default_profile_name: Option<String>,
+    /// Items of the default section which match no profile
+    drifted_default: Option<Rc<HashMap<String, String>>>,
+    /// Broken sections skipped by lenient parsing, written back as they are
+    ignored: Vec<IgnoredLines>,
+    encoding: TextEncoding,
+}
+
+/// Lines of a broken section skipped by lenient parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoredLines {
+    /// First line of the section, counted from 1
+    pub first_line: usize,
+    /// Last non-blank line of the section
+    pub last_line: usize,
+    pub reason: String,
+    text: String,
+}
+
+/// Default section edited by hand, which matches no profile exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultDrift {
+    /// Profile sharing the most items with the default section
+    pub closest: Option<String>,
+    /// Keys whose values differ between the default section and the closest profile
+    pub differing_keys: Vec<String>,
 }
 
 impl fmt::Display for Credentials {
@@ -53,11 +185,15 @@ impl fmt::Display for Credentials {
         }
 
         // write default profile to section first to write last
-        if let Some(default_profile_name) = &self.default_profile_name {
+        let default_data = match &self.default_profile_name {
+            Some(default_profile_name) => self.data.get(default_profile_name),
+            // keep the drifted default section until it is repaired
+            None => self.drifted_default.as_ref(),
+        };
+        if let Some(data) = default_data {
             let mut sec = conf.with_section(Some(DEFAULT_PROFILE_NAME));
             // NOTE: to use method chain of `&mut SectionSetter`, declare `s` before
             let mut s = sec.borrow_mut();
-            let data = self.data.get(default_profile_name).unwrap();
             let mut data_keys = Vec::from_iter(data.keys());
             data_keys.sort();
             for data_key in data_keys {
@@ -71,7 +207,12 @@ impl fmt::Display for Credentials {
             let mut f = BufWriter::new(&mut buf);
             conf.write_to(&mut f).unwrap();
         }
-        write!(fmt, "{}", String::from_utf8(buf).unwrap())
+        write!(fmt, "{}", String::from_utf8(buf).unwrap())?;
+        // never lose broken sections, which users may still repair by hand
+        for ignored in &self.ignored {
+            write!(fmt, "\n{}\n", ignored.text)?;
+        }
+        Ok(())
     }
 }
 
@@ -85,14 +226,156 @@ impl Credentials {
             }
         })?;
 
-        let mut data = parse_aws_credentials(&file)?;
+        let (data, encoding) = parse_aws_credentials(&file)?;
+        Ok(Self {
+            encoding,
+            ..Self::from_data(data)
+        })
+    }
+
+    /// Loads the credentials skipping broken sections instead of failing,
+    /// so that the healthy profiles are still switched.
+    pub fn load_credentials_lenient<P: AsRef<Path>>(
+        credentials_path: P,
+    ) -> Result<Self, ctx::CTXError> {
+        let file = fs::File::open(credentials_path).map_err(|e| {
+            ctx::CTXError::CannotReadCredentials {
+                source: Some(e.into()),
+            }
+        })?;
+
+        let (contents, encoding) = read_aws_credentials(&file)?;
+        Ok(Self {
+            encoding,
+            ..Self::from_text_lenient(&contents)
+        })
+    }
+
+    /// Parses the credentials like `from_text`, skipping broken sections.
+    pub fn from_text_lenient(contents: &str) -> Self {
+        let (profiles, ignored) = parse_profiles_lenient(contents);
+        Self {
+            ignored,
+            ..Self::from_data(
+                profiles.into_iter().map(|(k, v)| (k, Rc::new(v))).collect(),
+            )
+        }
+    }
+
+    /// Returns the broken sections skipped by lenient parsing.
+    pub fn ignored_lines(&self) -> &[IgnoredLines] {
+        &self.ignored
+    }
+
+    /// Parses the credentials in the INI format of `~/.aws/credentials`, for storages other than files.
+    pub fn from_text(contents: &str) -> Result<Self, ctx::CTXError> {
+        let data = parse_profiles(contents)?
+            .into_iter()
+            .map(|(k, v)| (k, Rc::new(v)))
+            .collect();
+        Ok(Self::from_data(data))
+    }
+
+    fn from_data(mut data: CredentialData) -> Self {
         let ck = find_default_from_parsed_aws_credentials(&data);
         // remove DEFAULT_KEY after retrain current key
-        data.remove(DEFAULT_PROFILE_NAME);
+        let default_items = data.remove(DEFAULT_PROFILE_NAME);
 
-        Ok(Credentials {
+        Credentials {
+            drifted_default: default_items.filter(|_| ck.is_none()),
             data,
             default_profile_name: ck,
+            ignored: Vec::new(),
+            encoding: TextEncoding::Utf8,
+        }
+    }
+
+    /// Merges profiles of another credentials file, which never replace profiles of this one.
+    /// The default section matching no profile of this file is resolved against the merged ones.
+    pub fn merge_file(&mut self, other: Credentials) {
+        for (name, items) in other.data {
+            self.data.entry(name).or_insert(items);
+        }
+        let default_name = self.drifted_default.as_ref().and_then(|d| {
+            self.data
+                .iter()
+                .find(|(_, items)| *items == d)
+                .map(|(name, _)| name.clone())
+        });
+        if default_name.is_some() {
+            self.default_profile_name = default_name;
+            self.drifted_default = None;
+        }
+    }
+
+    /// Splits the profiles whose names satisfy `routed` off into credentials of another file,
+    /// without the default section, which stays in the rest even if the profile is routed.
+    pub fn split_off<F: Fn(&str) -> bool>(&self, routed: F) -> (Self, Self) {
+        let (routed_data, data): (CredentialData, CredentialData) = self
+            .data
+            .iter()
+            .map(|(name, items)| (name.clone(), items.clone()))
+            .partition(|(name, _)| routed(name));
+        let default_routed = self
+            .default_profile_name
+            .as_ref()
+            .filter(|name| routed_data.contains_key(*name));
+        let rest = Credentials {
+            drifted_default: match default_routed {
+                // written as a default section matching no profile of the file
+                Some(name) => Some(routed_data[name].clone()),
+                None => self.drifted_default.clone(),
+            },
+            default_profile_name: self
+                .default_profile_name
+                .clone()
+                .filter(|_| default_routed.is_none()),
+            data,
+            ignored: self.ignored.clone(),
+            encoding: self.encoding,
+        };
+        let routed = Credentials {
+            data: routed_data,
+            encoding: self.encoding,
+            ..Default::default()
+        };
+        (rest, routed)
+    }
+
+    /// Reports the default section drifted from every profile, with the profile closest to it.
+    pub fn default_drift(&self) -> Option<DefaultDrift> {
+        let default_items = self.drifted_default.as_ref()?;
+        let matches = |items: &HashMap<String, String>| {
+            default_items
+                .iter()
+                .filter(|(k, v)| items.get(*k) == Some(v))
+                .count()
+        };
+        let closest = self
+            .data
+            .iter()
+            .map(|(name, items)| (matches(items), name))
+            .filter(|(count, _)| *count > 0)
+            // prefer the first name in order among the same number of matches
+            .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+            .map(|(_, name)| name.to_string());
+        let mut differing_keys = match &closest {
+            Some(name) => {
+                let items = &self.data[name];
+                default_items
+                    .keys()
+                    .chain(items.keys())
+                    .filter(|k| default_items.get(*k) != items.get(*k))
+                    .cloned()
+                    .collect::<Vec<String>>()
+            }
+            None => vec![],
+        };
+        differing_keys.sort();
+        differing_keys.dedup();
+        Some(DefaultDrift {
+            closest,
+            differing_keys,
         })
     }
 
@@ -120,10 +403,16 @@ impl Credentials {
     }
 
     pub fn get_default_profile(&self) -> Result<Profile, ctx::CTXError> {
-        let name = self
-            .default_profile_name
-            .as_ref()
-            .ok_or(ctx::CTXError::NoActiveContext { source: None })?;
+        let name =
+            self.default_profile_name.as_ref().ok_or_else(|| {
+                match self.default_drift() {
+                    Some(drift) => ctx::CTXError::DefaultDrifted {
+                        closest: drift.closest,
+                        source: None,
+                    },
+                    None => ctx::CTXError::NoActiveContext { source: None },
+                }
+            })?;
         self.get_profile(name)
     }
 
@@ -140,6 +429,7 @@ impl Credentials {
                 ))),
             })?;
         self.default_profile_name = Some(name.to_string());
+        self.drifted_default = None;
         Ok(Profile {
             name: name.into(),
             items: items.clone(),
@@ -147,25 +437,155 @@ impl Credentials {
         })
     }
 
+    /// Writes temporary credentials to the profile, creating it if it does not exist.
+    /// Keys other than credentials in the profile are kept as they are.
+    pub fn set_profile_credentials(
+        &mut self,
+        name: &str,
+        credentials: &TemporaryCredentials,
+    ) -> Profile {
+        let mut items = self
+            .data
+            .get(name)
+            .map(|items| items.as_ref().clone())
+            .unwrap_or_default();
+        items.insert(
+            ACCESS_KEY_ID_KEY.to_string(),
+            credentials.access_key_id.clone(),
+        );
+        items.insert(
+            SECRET_ACCESS_KEY_KEY.to_string(),
+            credentials.secret_access_key.clone(),
+        );
+        items.insert(
+            SESSION_TOKEN_KEY.to_string(),
+            credentials.session_token.clone(),
+        );
+        items.insert(
+            EXPIRATION_KEY.to_string(),
+            credentials
+                .expiration
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+        let items = Rc::new(items);
+        self.data.insert(name.to_string(), items.clone());
+        Profile {
+            name: name.into(),
+            items,
+            default: self.is_default_profile(name),
+        }
+    }
+
+    /// Merges items into the profile, creating it if it does not exist.
+    /// Credentials in the profile are replaced as a whole, while the other keys are kept.
+    /// Removes the profile, and the default section too if it is the default profile.
+    pub fn remove_profile(
+        &mut self,
+        name: &str,
+    ) -> Option<HashMap<String, String>> {
+        let items = self.data.remove(name)?;
+        if self.is_default_profile(name) {
+            self.default_profile_name = None;
+        }
+        Some(items.as_ref().clone())
+    }
+
+    /// Renames the profile, keeping it as the default profile if it is.
+    /// Returns whether the profile exists.
+    pub fn rename_profile(&mut self, from: &str, to: &str) -> bool {
+        let items = match self.data.remove(from) {
+            Some(items) => items,
+            None => return false,
+        };
+        self.data.insert(to.to_string(), items);
+        if self.is_default_profile(from) {
+            self.default_profile_name = Some(to.to_string());
+        }
+        true
+    }
+
+    /// Adds the profile as it is, replacing the one of the same name.
+    pub fn insert_profile(
+        &mut self,
+        name: &str,
+        items: HashMap<String, String>,
+    ) {
+        self.data.insert(name.to_string(), Rc::new(items));
+    }
+
+    pub fn merge_profile(
+        &mut self,
+        name: &str,
+        items: HashMap<String, String>,
+    ) -> Profile {
+        let mut merged = self
+            .data
+            .get(name)
+            .map(|items| items.as_ref().clone())
+            .unwrap_or_default();
+        merged.retain(|key, _| {
+            !CREDENTIAL_KEYS.contains(&key.as_str())
+                && !EXPIRATION_KEYS.contains(&key.as_str())
+        });
+        merged.extend(items);
+        let items = Rc::new(merged);
+        self.data.insert(name.to_string(), items.clone());
+        Profile {
+            name: name.into(),
+            items,
+            default: self.is_default_profile(name),
+        }
+    }
+
+    /// Writes the credentials in the default mode of `FileModesConfigs`.
     pub fn dump_credentials<P: AsRef<Path>>(
         &self,
         credentials_path: P,
     ) -> Result<(), ctx::CTXError> {
-        let mut file = fs::File::create(credentials_path).map_err(|e| {
-            ctx::CTXError::CannotWriteCredentials {
-                source: Some(e.into()),
-            }
-        })?;
-        file.write_all(self.to_string().as_bytes()).map_err(|e| {
-            ctx::CTXError::CannotWriteCredentials {
-                source: Some(e.into()),
-            }
-        })?;
-        file.flush()
+        self.dump_credentials_with_mode(
+            credentials_path,
+            FileModesConfigs::default().credentials,
+        )
+    }
+
+    pub fn dump_credentials_with_mode<P: AsRef<Path>>(
+        &self,
+        credentials_path: P,
+        mode: FileMode,
+    ) -> Result<(), ctx::CTXError> {
+        self.encoding
+            .encode(&self.to_string())
+            .and_then(|bytes| {
+                write_atomic(credentials_path.as_ref(), &bytes, mode.0)
+            })
             .map_err(|e| ctx::CTXError::CannotWriteCredentials {
                 source: Some(e.into()),
-            })?;
-        Ok(())
+            })
+    }
+
+    /// Returns the encoding of the file, which it is written back in.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Looks up the profile by the name without cloning it.
+    pub fn profile(&self, name: &str) -> Option<ProfileRef<'_>> {
+        self.data
+            .get_key_value(name)
+            .map(|(name, items)| ProfileRef {
+                name,
+                default: self.is_default_profile(name),
+                items,
+            })
+    }
+
+    /// Iterates over the profiles without cloning them, in no particular order.
+    pub fn iter_profiles(&self) -> impl Iterator<Item = ProfileRef<'_>> {
+        self.data.iter().map(|(name, items)| ProfileRef {
+            name,
+            default: self.is_default_profile(name),
+            items,
+        })
     }
 
     pub fn list_profiles(&self) -> Vec<Profile> {
@@ -183,29 +603,88 @@ impl Credentials {
     }
 }
 
-fn parse_aws_credentials(file: &File) -> Result<CredentialData, ctx::CTXError> {
+fn read_aws_credentials(
+    file: &File,
+) -> Result<(String, TextEncoding), ctx::CTXError> {
     let mut buf_reader = BufReader::new(file);
-    let mut contents = String::new();
-    buf_reader.read_to_string(&mut contents).map_err(|e| {
+    let mut bytes = Vec::new();
+    buf_reader.read_to_end(&mut bytes).map_err(|e| {
         ctx::CTXError::CannotReadCredentials {
             source: Some(e.into()),
         }
     })?;
+    Ok(TextEncoding::decode(bytes))
+}
+
+fn parse_aws_credentials(
+    file: &File,
+) -> Result<(CredentialData, TextEncoding), ctx::CTXError> {
+    let (contents, encoding) = read_aws_credentials(file)?;
+    let data = parse_profiles(&contents)?
+        .into_iter()
+        .map(|(k, v)| (k, Rc::new(v)))
+        .collect();
+    Ok((data, encoding))
+}
+
+/// Parses sections of credentials files into items keyed by section names.
+pub fn parse_profiles(
+    contents: &str,
+) -> Result<HashMap<String, HashMap<String, String>>, ctx::CTXError> {
     let c = config::Config::builder()
-        .add_source(config::File::from_str(
-            contents.as_str(),
-            config::FileFormat::Ini,
-        ))
+        .add_source(config::File::from_str(contents, config::FileFormat::Ini))
         .build()
         .context("failed to load aws credentials".to_string())
         .map_err(|e| ctx::CTXError::CredentialsIsBroken { source: Some(e) })?;
 
     c.try_deserialize::<HashMap<String, HashMap<String, String>>>()
         .context("failed to deserialize credentials".to_string())
-        .map_or_else(
-            |e| Err(ctx::CTXError::CredentialsIsBroken { source: Some(e) }),
-            |h| Ok(h.into_iter().map(|(k, v)| (k, Rc::new(v))).collect()),
-        )
+        .map_err(|e| ctx::CTXError::CredentialsIsBroken { source: Some(e) })
+}
+
+/// Parses sections like `parse_profiles`, but skips broken ones and reports their lines.
+pub fn parse_profiles_lenient(
+    contents: &str,
+) -> (HashMap<String, HashMap<String, String>>, Vec<IgnoredLines>) {
+    if let Ok(profiles) = parse_profiles(contents) {
+        return (profiles, Vec::new());
+    }
+    // split into sections by headers, to parse each of them alone
+    let mut sections: Vec<(usize, Vec<&str>)> = vec![(1, Vec::new())];
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim_start().starts_with('[') {
+            sections.push((i + 1, Vec::new()));
+        }
+        sections.last_mut().unwrap().1.push(line);
+    }
+
+    let mut profiles = HashMap::new();
+    let mut ignored = Vec::new();
+    for (first_line, lines) in sections {
+        let text = lines.join("\n");
+        match parse_profiles(&text) {
+            Ok(parsed) => profiles.extend(parsed),
+            Err(e) => {
+                let last_line = first_line
+                    + lines
+                        .iter()
+                        .rposition(|l| !l.trim().is_empty())
+                        .unwrap_or(0);
+                ignored.push(IgnoredLines {
+                    first_line,
+                    last_line,
+                    reason: match e {
+                        ctx::CTXError::CredentialsIsBroken {
+                            source: Some(source),
+                        } => format!("{:#}", source),
+                        e => e.to_string(),
+                    },
+                    text: lines[..=last_line - first_line].join("\n"),
+                });
+            }
+        }
+    }
+    (profiles, ignored)
 }
 
 fn find_default_from_parsed_aws_credentials(
@@ -222,7 +701,7 @@ fn find_default_from_parsed_aws_credentials(
 
 #[cfg(test)]
 mod tests {
-    use std::io::Seek;
+    use std::io::{Seek, Write};
 
     use maplit::hashmap;
     use rstest::*;
@@ -278,7 +757,7 @@ aws_session_token=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
     pub fn parsed_aws_credentials(
         aws_credentials: NamedTempFile,
     ) -> CredentialData {
-        parse_aws_credentials(aws_credentials.as_file()).unwrap()
+        parse_aws_credentials(aws_credentials.as_file()).unwrap().0
     }
 
     #[fixture]
@@ -307,6 +786,7 @@ aws_session_token=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
                 "bar".to_string() => bar_profile_items(),
             },
             default_profile_name: Some("foo".to_string()),
+            ..Default::default()
         }
     }
 
@@ -318,6 +798,7 @@ aws_session_token=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
                 "bar".to_string() => bar_profile_items(),
             },
             default_profile_name: None,
+            ..Default::default()
         }
     }
 
@@ -328,7 +809,8 @@ aws_session_token=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
             "bar".to_string() => bar_profile_items(),
             "default".to_string() => foo_profile_items(),
         };
-        let actual = parse_aws_credentials(aws_credentials.as_file()).unwrap();
+        let (actual, _) =
+            parse_aws_credentials(aws_credentials.as_file()).unwrap();
         assert_eq!(expect, actual);
     }
 
@@ -521,6 +1003,269 @@ aws_session_token=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
         assert_eq!(expect, actual);
     }
 
+    #[rstest]
+    fn test_credentials_keep_non_utf8_bytes() {
+        let namedfile = NamedTempFile::new().unwrap();
+        let bytes = b"[bar]\naws_access_key_id=YYYYYYYYYYY\nowner=Jos\xe9\n\n[foo]\naws_access_key_id=XXXXXXXXXXX\n";
+        fs::write(namedfile.path(), bytes).unwrap();
+
+        let mut credentials =
+            Credentials::load_credentials(namedfile.path()).unwrap();
+        assert_eq!(TextEncoding::Latin1, credentials.encoding());
+        assert_eq!(
+            Some("José"),
+            credentials.profile("bar").unwrap().get("owner")
+        );
+
+        credentials.set_default_profile("bar").unwrap();
+        credentials.dump_credentials(namedfile.path()).unwrap();
+        let actual = fs::read(namedfile.path()).unwrap();
+        assert!(actual
+            .windows(b"owner=Jos\xe9\n".len())
+            .any(|w| w == b"owner=Jos\xe9\n"));
+        assert!(String::from_utf8(actual).is_err());
+    }
+
+    #[rstest(::trace)]
+    #[case("foo")]
+    #[case("foo-session")]
+    fn test_credentials_set_profile_credentials(
+        mut credentials: Credentials,
+        #[case] name: &str,
+    ) {
+        let temporary_credentials = TemporaryCredentials {
+            access_key_id: "ZZZZZZZZZZZ".to_string(),
+            secret_access_key: "ZZZZZZZZZZZ".to_string(),
+            session_token: "ZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZ"
+                .to_string(),
+            expiration: DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+                .unwrap()
+                .into(),
+        };
+        let expect = Profile {
+            name: name.to_string(),
+            default: name == "foo",
+            items: Rc::new(hashmap! {
+                "aws_access_key_id".to_string() => "ZZZZZZZZZZZ".to_string(),
+                "aws_secret_access_key".to_string() => "ZZZZZZZZZZZ".to_string(),
+                "aws_session_token".to_string() => "ZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZ".to_string(),
+                "x_security_token_expires".to_string() => "2022-01-01T00:00:00Z".to_string(),
+            }),
+        };
+
+        let actual =
+            credentials.set_profile_credentials(name, &temporary_credentials);
+        assert_eq!(expect, actual);
+        assert_eq!(expect, credentials.get_profile(name).unwrap());
+        // the other profile is left untouched
+        assert_eq!(
+            bar_profile_items(),
+            credentials.get_profile("bar").unwrap().items
+        );
+    }
+
+    #[rstest]
+    fn test_credentials_split_off_and_merge_file(credentials: Credentials) {
+        let names = |c: &Credentials| {
+            c.list_profiles()
+                .into_iter()
+                .map(|p| p.name)
+                .collect::<Vec<String>>()
+        };
+        let (rest, routed) = credentials.split_off(|name| name == "foo");
+        assert_eq!(vec!["bar"], names(&rest));
+        assert_eq!(vec!["foo"], names(&routed));
+        // the default section stays in the rest, matching none of its profiles
+        assert!(rest.to_string().contains("[default]"));
+        assert!(!routed.to_string().contains("[default]"));
+
+        let mut merged = Credentials::from_text(&rest.to_string()).unwrap();
+        merged.merge_file(Credentials::from_text(&routed.to_string()).unwrap());
+        assert_eq!("foo", merged.get_default_profile().unwrap().name);
+        assert_eq!(credentials.list_profiles(), merged.list_profiles());
+    }
+
+    #[rstest]
+    fn test_credentials_merge_profile(mut credentials: Credentials) {
+        let items = hashmap! {
+            "aws_access_key_id".to_string() => "ZZZZZZZZZZZ".to_string(),
+            "aws_secret_access_key".to_string() => "ZZZZZZZZZZZ".to_string(),
+            "region".to_string() => "ZZZZZZZZZZZ".to_string(),
+        };
+        // the session token of the old credentials is dropped
+        let actual = credentials.merge_profile("foo", items.clone());
+        assert_eq!(Rc::new(items.clone()), actual.items);
+        assert!(actual.default);
+
+        let actual = credentials.merge_profile("qux", items.clone());
+        assert_eq!(Rc::new(items), actual.items);
+        assert!(!actual.default);
+    }
+
+    #[rstest(::trace)]
+    #[case(hashmap! {}, None)]
+    #[case(
+        hashmap! {"x_security_token_expires".to_string() => "2022-01-01T09:00:00+09:00".to_string()},
+        Some("2022-01-01T00:00:00Z")
+    )]
+    #[case(
+        hashmap! {"expiration".to_string() => "2022-01-01T00:00:00Z".to_string()},
+        Some("2022-01-01T00:00:00Z")
+    )]
+    #[case(hashmap! {"expiration".to_string() => "unknown".to_string()}, None)]
+    fn test_profile_expiration(
+        #[case] items: HashMap<String, String>,
+        #[case] expect: Option<&str>,
+    ) {
+        let profile = Profile {
+            name: "foo".to_string(),
+            default: false,
+            items: Rc::new(items),
+        };
+        let expect = expect.map(|e| {
+            DateTime::parse_from_rfc3339(e).unwrap().with_timezone(&Utc)
+        });
+        assert_eq!(expect, profile.expiration());
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        hashmap! {
+            "aws_access_key_id".to_string() => "XXXXXXXXXXX".to_string(),
+            "aws_secret_access_key".to_string() => "YYYYYYYYYYY".to_string(),
+        },
+        Some(r#"{"Version":1,"AccessKeyId":"XXXXXXXXXXX","SecretAccessKey":"YYYYYYYYYYY"}"#)
+    )]
+    #[case(
+        hashmap! {
+            "aws_access_key_id".to_string() => "XXXXXXXXXXX".to_string(),
+            "aws_secret_access_key".to_string() => "YYYYYYYYYYY".to_string(),
+            "aws_session_token".to_string() => "ZZZZZZZZZZZ".to_string(),
+            "expiration".to_string() => "2022-01-01T09:00:00+09:00".to_string(),
+        },
+        Some(r#"{"Version":1,"AccessKeyId":"XXXXXXXXXXX","SecretAccessKey":"YYYYYYYYYYY","SessionToken":"ZZZZZZZZZZZ","Expiration":"2022-01-01T00:00:00Z"}"#)
+    )]
+    #[case(
+        hashmap! {"aws_access_key_id".to_string() => "XXXXXXXXXXX".to_string()},
+        None
+    )]
+    fn test_profile_process_credentials(
+        #[case] items: HashMap<String, String>,
+        #[case] expect: Option<&str>,
+    ) {
+        let profile = Profile {
+            name: "foo".to_string(),
+            default: false,
+            items: Rc::new(items),
+        };
+        match (expect, profile.process_credentials()) {
+            (Some(expect), Ok(actual)) => {
+                assert_eq!(expect, serde_json::to_string(&actual).unwrap())
+            }
+            (None, Err(ctx::CTXError::CredentialsIsBroken { .. })) => (),
+            (_, actual) => panic!("unexpected result: {:?}", actual),
+        }
+    }
+
+    #[rstest]
+    fn test_credentials_default_drift() {
+        let f = aws_credentials(
+            r#"[bar]
+aws_access_key_id=YYYYYYYYYYY
+aws_secret_access_key=YYYYYYYYYYY
+
+[foo]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+
+[default]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=ZZZZZZZZZZZ
+"#
+            .to_string(),
+        );
+        let credentials = Credentials::load_credentials(f.path()).unwrap();
+        let expect = DefaultDrift {
+            closest: Some("foo".to_string()),
+            differing_keys: vec!["aws_secret_access_key".to_string()],
+        };
+        assert_eq!(Some(expect), credentials.default_drift());
+        match credentials.get_default_profile() {
+            Err(ctx::CTXError::DefaultDrifted { closest, .. }) => {
+                assert_eq!(Some("foo".to_string()), closest)
+            }
+            actual => panic!("unexpected result: {:?}", actual),
+        }
+        // the drifted section is kept until it is repaired
+        assert!(credentials.to_string().contains(
+            "[default]\naws_access_key_id=XXXXXXXXXXX\naws_secret_access_key=ZZZZZZZZZZZ"
+        ));
+    }
+
+    #[rstest]
+    fn test_credentials_without_default_has_no_drift(
+        credentials_without_default: Credentials,
+    ) {
+        assert_eq!(None, credentials_without_default.default_drift());
+    }
+
+    #[rstest]
+    fn test_credentials_load_credentials_lenient() {
+        let text = r#"[bar]
+aws_access_key_id=YYYYYYYYYYY
+aws_secret_access_key=YYYYYYYYYYY
+
+[baz]
+aws_session_token="pasted
+
+[foo]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+"#;
+        let f = aws_credentials(text.to_string());
+        assert!(matches!(
+            Credentials::load_credentials(f.path()),
+            Err(ctx::CTXError::CredentialsIsBroken { .. })
+        ));
+
+        let actual = Credentials::load_credentials_lenient(f.path()).unwrap();
+        assert_eq!(
+            vec!["bar", "foo"],
+            actual
+                .list_profiles()
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        let ignored = actual.ignored_lines();
+        assert_eq!(1, ignored.len());
+        assert_eq!((5, 6), (ignored[0].first_line, ignored[0].last_line));
+        // broken sections are written back as they are
+        assert!(actual
+            .to_string()
+            .ends_with("\n[baz]\naws_session_token=\"pasted\n"));
+
+        // healthy files are parsed as usual
+        let f = aws_credentials(aws_credentials_text());
+        let actual = Credentials::load_credentials_lenient(f.path()).unwrap();
+        assert_eq!(credentials(), actual);
+    }
+
+    #[rstest]
+    fn test_iter_profiles(credentials: Credentials) {
+        let mut actual = credentials
+            .iter_profiles()
+            .map(|p| (p.name, p.default))
+            .collect::<Vec<(&str, bool)>>();
+        actual.sort();
+        assert_eq!(vec![("bar", false), ("foo", true)], actual);
+        assert_eq!(
+            Some("XXXXXXXXXXX"),
+            credentials.profile("foo").unwrap().get("aws_access_key_id")
+        );
+        assert_eq!(None, credentials.profile("unknown"));
+    }
+
     #[rstest(::trace)]
     fn test_list_profiles(credentials: Credentials) {
         let expect = vec![
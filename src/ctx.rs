@@ -1,10 +1,29 @@
-use anyhow::Result;
-use skim::SkimOptions;
+use crate::state::AccountAliases;
+use crate::template;
+
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use skim::prelude::Key;
+use skim::{AnsiString, DisplayContext, SkimItem, SkimOptions};
 use thiserror::Error;
 
 pub trait CTX {
     fn auth(&mut self, profile: &str) -> Result<Context, CTXError>;
+    /// Authenticates the profiles with at most `parallelism` auth commands running concurrently,
+    /// which leaves the last authenticated one active.
+    /// Results are returned in the same order as `profiles`.
+    fn auth_all(
+        &mut self,
+        profiles: &[String],
+        parallelism: usize,
+    ) -> Vec<Result<Context, CTXError>>;
+    fn assume(&mut self, profile: &str) -> Result<Context, CTXError>;
     fn list_contexts(&self) -> Result<Vec<Context>, CTXError>;
+    fn describe_contexts(&self) -> Result<Vec<ContextMetadata>, CTXError>;
     fn get_active_context(&self) -> Result<Context, CTXError>;
     fn set_default_profile(
         &mut self,
@@ -13,53 +32,223 @@ pub trait CTX {
     fn dump_credentials(&self) -> Result<(), CTXError>;
     fn dump_config(&self) -> Result<(), CTXError>;
     fn use_context(&mut self, profile: &str) -> Result<Context, CTXError>;
+    fn use_context_in_region(
+        &mut self,
+        profile: &str,
+        region: &str,
+    ) -> Result<Context, CTXError>;
+    fn pick_context_interactive(
+        &self,
+        skim_options: &SkimOptions,
+    ) -> Result<String, CTXError>;
     fn use_context_interactive(
         &mut self,
         skim_options: SkimOptions,
     ) -> Result<Context, CTXError>;
 }
 
+/// Errors of awsctx.
+///
+/// Causes are chained to `std::error::Error::source`, and more variants may be added later.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum CTXError {
+    #[error("AWS request failed")]
+    AWSRequestFailed {
+        message: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Auth command timed out")]
+    AuthTimedOut {
+        profile: String,
+        timeout_seconds: u64,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Cannot read credentials")]
-    CannotReadCredentials { source: Option<anyhow::Error> },
+    CannotReadCredentials {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Cannot write credentials")]
-    CannotWriteCredentials { source: Option<anyhow::Error> },
+    CannotWriteCredentials {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Credentials is broken")]
-    CredentialsIsBroken { source: Option<anyhow::Error> },
+    CredentialsIsBroken {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Credentials is rejected by AWS")]
+    CredentialsInvalid {
+        profile: String,
+        message: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Cannot read config")]
-    CannotReadConfig { source: Option<anyhow::Error> },
+    CannotReadConfig {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Cannot write config")]
-    CannotWriteConfig { source: Option<anyhow::Error> },
+    CannotWriteConfig {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Config is broken")]
-    ConfigIsBroken { source: Option<anyhow::Error> },
+    ConfigIsBroken {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Context is locked")]
+    ContextLocked {
+        context: String,
+        holder: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Default profile matches no profile")]
+    DefaultDrifted {
+        closest: Option<String>,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("File was changed by another process")]
+    FileChangedOnDisk {
+        path: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Invalid input")]
+    InvalidInput {
+        message: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("Invalid configurations")]
     InvalidConfigurations {
         message: String,
+        #[source]
         source: Option<anyhow::Error>,
     },
     #[error("No active context found")]
-    NoActiveContext { source: Option<anyhow::Error> },
+    NoActiveContext {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("No auth configuration found for the profile")]
     NoAuthConfiguration {
         profile: String,
+        #[source]
         source: Option<anyhow::Error>,
     },
     #[error("No context is selected")]
-    NoContextIsSelected { source: Option<anyhow::Error> },
+    NoContextIsSelected {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("Network access is disabled in offline mode")]
+    Offline {
+        operation: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
     #[error("No such profile")]
     NoSuchProfile {
         profile: String,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+    #[error("No such region")]
+    NoSuchRegion {
+        region: String,
+        #[source]
         source: Option<anyhow::Error>,
     },
     #[error("Unexpected error")]
-    UnexpectedError { source: Option<anyhow::Error> },
+    UnexpectedError {
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+}
+
+/// Kind of `CTXError` without details, to tell errors apart without matching fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    AWSRequestFailed,
+    AuthTimedOut,
+    CannotReadCredentials,
+    CannotWriteCredentials,
+    CredentialsIsBroken,
+    CredentialsInvalid,
+    CannotReadConfig,
+    CannotWriteConfig,
+    ConfigIsBroken,
+    ContextLocked,
+    DefaultDrifted,
+    FileChangedOnDisk,
+    InvalidInput,
+    InvalidConfigurations,
+    NoActiveContext,
+    NoAuthConfiguration,
+    NoContextIsSelected,
+    Offline,
+    NoSuchProfile,
+    NoSuchRegion,
+    UnexpectedError,
 }
 
-#[derive(Default, Debug, PartialEq, Eq)]
+impl CTXError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::AWSRequestFailed { .. } => ErrorKind::AWSRequestFailed,
+            Self::AuthTimedOut { .. } => ErrorKind::AuthTimedOut,
+            Self::CannotReadCredentials { .. } => {
+                ErrorKind::CannotReadCredentials
+            }
+            Self::CannotWriteCredentials { .. } => {
+                ErrorKind::CannotWriteCredentials
+            }
+            Self::CredentialsIsBroken { .. } => ErrorKind::CredentialsIsBroken,
+            Self::CredentialsInvalid { .. } => ErrorKind::CredentialsInvalid,
+            Self::CannotReadConfig { .. } => ErrorKind::CannotReadConfig,
+            Self::CannotWriteConfig { .. } => ErrorKind::CannotWriteConfig,
+            Self::ConfigIsBroken { .. } => ErrorKind::ConfigIsBroken,
+            Self::ContextLocked { .. } => ErrorKind::ContextLocked,
+            Self::DefaultDrifted { .. } => ErrorKind::DefaultDrifted,
+            Self::FileChangedOnDisk { .. } => ErrorKind::FileChangedOnDisk,
+            Self::InvalidInput { .. } => ErrorKind::InvalidInput,
+            Self::InvalidConfigurations { .. } => {
+                ErrorKind::InvalidConfigurations
+            }
+            Self::NoActiveContext { .. } => ErrorKind::NoActiveContext,
+            Self::NoAuthConfiguration { .. } => ErrorKind::NoAuthConfiguration,
+            Self::NoContextIsSelected { .. } => ErrorKind::NoContextIsSelected,
+            Self::Offline { .. } => ErrorKind::Offline,
+            Self::NoSuchProfile { .. } => ErrorKind::NoSuchProfile,
+            Self::NoSuchRegion { .. } => ErrorKind::NoSuchRegion,
+            Self::UnexpectedError { .. } => ErrorKind::UnexpectedError,
+        }
+    }
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Serialize)]
 pub struct Context {
     pub name: String,
     pub active: bool,
+    /// Account of the credentials, known only when the identity is verified on switches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// ARN of the caller, known only when the identity is verified on switches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arn: Option<String>,
+    /// Expiration of the session, by `expiration` or `x_security_token_expires` of the credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl AsRef<str> for Context {
@@ -67,3 +256,579 @@ impl AsRef<str> for Context {
         &self.name
     }
 }
+
+/// Details of a context resolved from the config and credentials.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContextMetadata {
+    pub name: String,
+    pub active: bool,
+    pub account: Option<String>,
+    pub region: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Tags of the profile set in the configurations
+    pub tags: Vec<String>,
+    /// Name of the role or the user of the credentials, resolved only on demand
+    pub identity: Option<String>,
+    /// Description of the profile set in the configurations
+    pub description: Option<String>,
+    /// Aliases of the profile set in the configurations
+    pub aliases: Vec<String>,
+    /// Label of the profile or its tags set in the configurations
+    pub label: Option<Label>,
+    /// Whether the profile has no access key yet, like placeholders of adopted SSO profiles
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub missing_credentials: bool,
+    /// How the profile gets credentials, `sso`, `role`, `process` or `static`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Colors of labels of contexts, shown as backgrounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelColor {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl LabelColor {
+    /// Name of the color in markups of logs, like `red`
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabelColor::Red => "red",
+            LabelColor::Yellow => "yellow",
+            LabelColor::Green => "green",
+            LabelColor::Blue => "blue",
+            LabelColor::Magenta => "magenta",
+            LabelColor::Cyan => "cyan",
+        }
+    }
+
+    /// ANSI escape sequence of the background color with a readable foreground
+    fn ansi(&self) -> &'static str {
+        match self {
+            LabelColor::Red => "\x1b[97;41m",
+            LabelColor::Yellow => "\x1b[30;43m",
+            LabelColor::Green => "\x1b[30;42m",
+            LabelColor::Blue => "\x1b[97;44m",
+            LabelColor::Magenta => "\x1b[97;45m",
+            LabelColor::Cyan => "\x1b[30;46m",
+        }
+    }
+}
+
+/// Label shown before names of contexts, like `PROD` in red, so that dangerous ones stand out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Label {
+    pub text: String,
+    pub color: LabelColor,
+}
+
+impl Label {
+    /// Returns the text padded by a space on both sides, drawn on the background color.
+    pub fn banner(&self) -> String {
+        format!(" {} ", self.text)
+    }
+}
+
+/// Reasons why credentials of a context fail until it is authenticated again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unusable {
+    Expired,
+    Missing,
+}
+
+impl Unusable {
+    /// Suffix of entries of the finder, like `[expired]`
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Expired => "[expired]",
+            Self::Missing => "[no credentials]",
+        }
+    }
+}
+
+impl ContextMetadata {
+    /// Returns why the credentials of the context cannot be used at `now`, if so.
+    pub fn unusable(&self, now: DateTime<Utc>) -> Option<Unusable> {
+        if self.missing_credentials {
+            Some(Unusable::Missing)
+        } else if self.expires_at.is_some_and(|e| e <= now) {
+            Some(Unusable::Expired)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name followed by its aliases, like `prod-admin (p, prod)`.
+    pub fn name_with_aliases(&self) -> String {
+        if self.aliases.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.aliases.join(", "))
+        }
+    }
+}
+
+/// Item of the finder, which shows and matches `line` and selects the context of `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickerItem {
+    pub name: String,
+    pub line: String,
+    /// Label whose banner starts `line`, drawn in its color
+    pub label: Option<Label>,
+    /// Whether `line` is drawn dimmed, for contexts whose credentials cannot be used
+    pub dimmed: bool,
+}
+
+impl PickerItem {
+    /// Builds items showing aliases and descriptions next to names aligned,
+    /// so that contexts can be found by their aliases too.
+    pub fn from_contexts(contexts: &[ContextMetadata]) -> Vec<Self> {
+        let width = contexts
+            .iter()
+            .filter(|c| c.description.is_some())
+            .map(|c| c.name_with_aliases().chars().count())
+            .max()
+            .unwrap_or_default();
+        contexts
+            .iter()
+            .map(|c| Self {
+                name: c.name.clone(),
+                line: match &c.description {
+                    Some(description) => format!(
+                        "{:<width$}  {}",
+                        c.name_with_aliases(),
+                        description,
+                        width = width
+                    ),
+                    None => c.name_with_aliases(),
+                },
+                label: None,
+                dimmed: false,
+            })
+            .collect::<Vec<Self>>()
+            .with_labels(contexts)
+    }
+
+    /// Builds items rendered by the handlebars template of `listing.format`,
+    /// like `{{name}} [{{account_alias}}] {{region}}`.
+    pub fn render(
+        contexts: &[ContextMetadata],
+        format: &str,
+        aliases: &AccountAliases,
+    ) -> Result<Vec<Self>, CTXError> {
+        let reg = template::registry();
+        contexts
+            .iter()
+            .map(|c| {
+                let mut data = serde_json::to_value(c).unwrap();
+                // accounts without aliases are cached as empty ones
+                let alias = c
+                    .account
+                    .as_deref()
+                    .and_then(|a| aliases.get(a))
+                    .filter(|a| !a.is_empty());
+                data["account_alias"] = alias.into();
+                let line = reg.render_template(format, &data).map_err(|e| {
+                    CTXError::InvalidConfigurations {
+                        message: "failed to render listing.format".to_string(),
+                        source: Some(anyhow!("failed to render entry {}", e)),
+                    }
+                })?;
+                Ok(Self {
+                    name: c.name.clone(),
+                    line,
+                    label: None,
+                    dimmed: false,
+                })
+            })
+            .collect::<Result<Vec<Self>, CTXError>>()
+            .map(|items| items.with_labels(contexts))
+    }
+
+    /// Dims items of the contexts whose credentials are expired or missing at `now`,
+    /// suffixed like `[expired]`, and moves them after the others if `last` is set.
+    pub fn mark_unusable(
+        items: Vec<Self>,
+        contexts: &[ContextMetadata],
+        now: DateTime<Utc>,
+        last: bool,
+    ) -> Vec<Self> {
+        let mut items = items
+            .into_iter()
+            .zip(contexts.iter())
+            .map(|(item, c)| match c.unusable(now) {
+                Some(unusable) => Self {
+                    line: format!("{} {}", item.line, unusable.suffix()),
+                    dimmed: true,
+                    ..item
+                },
+                None => item,
+            })
+            .collect::<Vec<Self>>();
+        if last {
+            // stable, so the order is kept among each of them
+            items.sort_by_key(|i| i.dimmed);
+        }
+        items
+    }
+}
+
+trait WithLabels {
+    fn with_labels(self, contexts: &[ContextMetadata]) -> Self;
+}
+
+impl WithLabels for Vec<PickerItem> {
+    /// Prefixes lines by banners of labels of the contexts, aligned if any context has one.
+    fn with_labels(self, contexts: &[ContextMetadata]) -> Self {
+        let width = contexts
+            .iter()
+            .filter_map(|c| c.label.as_ref())
+            .map(|l| l.banner().chars().count())
+            .max();
+        let width = match width {
+            Some(width) => width,
+            None => return self,
+        };
+        self.into_iter()
+            .zip(contexts.iter())
+            .map(|(item, c)| PickerItem {
+                line: format!(
+                    "{:<width$} {}",
+                    c.label.as_ref().map(|l| l.banner()).unwrap_or_default(),
+                    item.line,
+                    width = width
+                ),
+                label: c.label.clone(),
+                ..item
+            })
+            .collect()
+    }
+}
+
+impl SkimItem for PickerItem {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.line)
+    }
+
+    fn output(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.name)
+    }
+
+    fn display<'a>(&'a self, context: DisplayContext<'a>) -> AnsiString<'a> {
+        if self.label.is_none() && !self.dimmed {
+            return context.into();
+        }
+        // matched characters are highlighted over the color of the banner
+        let highlighted = AnsiString::from(context);
+        let matches = highlighted
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, attr))| *attr != Default::default())
+            .map(|(i, (_, attr))| (attr, (i as u32, i as u32 + 1)))
+            .collect::<Vec<_>>();
+        let (banner, rest) = match &self.label {
+            Some(label) => {
+                let banner = label.banner();
+                (
+                    format!("{}{}\x1b[0m", label.color.ansi(), banner),
+                    &self.line[banner.len()..],
+                )
+            }
+            None => (String::new(), self.line.as_str()),
+        };
+        let mut display = AnsiString::parse(&if self.dimmed {
+            format!("{}\x1b[2m{}\x1b[0m", banner, rest)
+        } else {
+            format!("{}{}", banner, rest)
+        });
+        display.override_attrs(matches);
+        display
+    }
+}
+
+/// Actions of the finder by the keys accepting the highlighted context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerAction {
+    /// Switches to the context by `enter`
+    Switch,
+    /// Auths the profile, then switches to it by `ctrl-a`
+    Auth,
+    /// Reloads the contexts by `ctrl-r`, for ones changed while the finder is open
+    Reload,
+}
+
+impl PickerAction {
+    /// Keys given to `expect` of the finder, which accept items like `enter`
+    pub const EXPECTED_KEYS: &'static str = "ctrl-a,ctrl-r";
+    pub const HEADER: &'static str =
+        "enter: switch, ctrl-a: auth and switch, ctrl-r: reload";
+
+    /// Returns the action of the key, and `None` for keys aborting the finder.
+    pub fn from_key(key: &Key) -> Option<Self> {
+        match key {
+            Key::Enter => Some(Self::Switch),
+            Key::Ctrl('a') => Some(Self::Auth),
+            Key::Ctrl('r') => Some(Self::Reload),
+            _ => None,
+        }
+    }
+}
+
+/// Columns of context listings.
+#[derive(
+    ArgEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Name,
+    Active,
+    Account,
+    Region,
+    Expiry,
+    Tags,
+    Identity,
+    Description,
+}
+
+impl Column {
+    pub fn header(&self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Active => "active",
+            Column::Account => "account",
+            Column::Region => "region",
+            Column::Expiry => "expiry",
+            Column::Tags => "tags",
+            Column::Identity => "identity",
+            Column::Description => "description",
+        }
+    }
+
+    pub fn value(&self, context: &ContextMetadata) -> String {
+        match self {
+            Column::Name => context.name.clone(),
+            Column::Active => context.active.to_string(),
+            Column::Account => context.account.clone().unwrap_or_default(),
+            Column::Region => context.region.clone().unwrap_or_default(),
+            Column::Expiry => context
+                .expires_at
+                .map(|e| e.to_rfc3339_opts(SecondsFormat::Secs, true))
+                .unwrap_or_default(),
+            Column::Tags => context.tags.join(","),
+            Column::Identity => context.identity.clone().unwrap_or_default(),
+            Column::Description => {
+                context.description.clone().unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Columns of csv output when none are given
+pub const DEFAULT_COLUMNS: [Column; 7] = [
+    Column::Name,
+    Column::Active,
+    Column::Account,
+    Column::Region,
+    Column::Expiry,
+    Column::Tags,
+    Column::Description,
+];
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_ctx_error_source_and_kind() {
+        let error = CTXError::CannotReadConfig {
+            source: Some(anyhow!("no such file")),
+        };
+        assert_eq!(ErrorKind::CannotReadConfig, error.kind());
+        assert_eq!(
+            Some("no such file".to_string()),
+            std::error::Error::source(&error).map(|e| e.to_string())
+        );
+        let error = CTXError::NoActiveContext { source: None };
+        assert_eq!(ErrorKind::NoActiveContext, error.kind());
+        assert!(std::error::Error::source(&error).is_none());
+    }
+
+    #[rstest(::trace)]
+    #[case(Key::Enter, Some(PickerAction::Switch))]
+    #[case(Key::Ctrl('a'), Some(PickerAction::Auth))]
+    #[case(Key::Ctrl('r'), Some(PickerAction::Reload))]
+    #[case(Key::ESC, None)]
+    #[case(Key::Ctrl('c'), None)]
+    fn test_picker_action_from_key(
+        #[case] key: Key,
+        #[case] expect: Option<PickerAction>,
+    ) {
+        assert_eq!(expect, PickerAction::from_key(&key));
+    }
+
+    #[rstest]
+    fn test_picker_item_from_contexts() {
+        let contexts = ["bar", "staging", "foo"]
+            .into_iter()
+            .map(|name| ContextMetadata {
+                name: name.to_string(),
+                description: (name != "staging")
+                    .then(|| format!("{} account", name)),
+                ..Default::default()
+            })
+            .collect::<Vec<ContextMetadata>>();
+        let actual = PickerItem::from_contexts(&contexts);
+        assert_eq!(
+            vec!["bar  bar account", "staging", "foo  foo account"],
+            actual
+                .iter()
+                .map(|i| i.text().to_string())
+                .collect::<Vec<String>>()
+        );
+        assert_eq!("bar", actual[0].output());
+    }
+
+    #[rstest]
+    fn test_picker_item_from_contexts_with_labels() {
+        let contexts = vec![
+            ContextMetadata {
+                name: "prod".to_string(),
+                label: Some(Label {
+                    text: "PROD".to_string(),
+                    color: LabelColor::Red,
+                }),
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "dev".to_string(),
+                ..Default::default()
+            },
+        ];
+        let actual = PickerItem::from_contexts(&contexts);
+        assert_eq!(
+            vec![" PROD  prod", "       dev"],
+            actual
+                .iter()
+                .map(|i| i.text().to_string())
+                .collect::<Vec<String>>()
+        );
+        assert_eq!("prod", actual[0].output());
+    }
+
+    #[rstest]
+    fn test_picker_item_from_contexts_with_aliases() {
+        let contexts = vec![
+            ContextMetadata {
+                name: "prod-admin".to_string(),
+                aliases: vec!["p".to_string()],
+                description: Some("production".to_string()),
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "dev".to_string(),
+                description: Some("development".to_string()),
+                ..Default::default()
+            },
+        ];
+        let actual = PickerItem::from_contexts(&contexts);
+        assert_eq!(
+            vec!["prod-admin (p)  production", "dev             development"],
+            actual
+                .iter()
+                .map(|i| i.text().to_string())
+                .collect::<Vec<String>>()
+        );
+        assert_eq!("prod-admin", actual[0].output());
+    }
+
+    #[rstest(::trace)]
+    #[case(false, vec!["bar [expired]", "baz", "foo [no credentials]", "qux"])]
+    #[case(true, vec!["baz", "qux", "bar [expired]", "foo [no credentials]"])]
+    fn test_picker_item_mark_unusable(
+        #[case] last: bool,
+        #[case] expect: Vec<&str>,
+    ) {
+        let now = Utc::now();
+        let contexts = vec![
+            ContextMetadata {
+                name: "bar".to_string(),
+                expires_at: Some(now - chrono::Duration::minutes(1)),
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "baz".to_string(),
+                expires_at: Some(now + chrono::Duration::minutes(1)),
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "foo".to_string(),
+                missing_credentials: true,
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "qux".to_string(),
+                ..Default::default()
+            },
+        ];
+        let actual = PickerItem::mark_unusable(
+            PickerItem::from_contexts(&contexts),
+            &contexts,
+            now,
+            last,
+        );
+        assert_eq!(
+            expect,
+            actual
+                .iter()
+                .map(|i| i.line.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(
+            expect
+                .iter()
+                .map(|e| e.ends_with(']'))
+                .collect::<Vec<bool>>(),
+            actual.iter().map(|i| i.dimmed).collect::<Vec<bool>>()
+        );
+    }
+
+    #[rstest]
+    fn test_picker_item_render() {
+        let contexts = vec![
+            ContextMetadata {
+                name: "bar".to_string(),
+                ..Default::default()
+            },
+            ContextMetadata {
+                name: "foo".to_string(),
+                account: Some("123456789012".to_string()),
+                region: Some("us-east-1".to_string()),
+                ..Default::default()
+            },
+        ];
+        let mut aliases = AccountAliases::default();
+        aliases.insert("123456789012", "example-prod");
+        let actual = PickerItem::render(
+            &contexts,
+            "{{name}}{{#if account_alias}} [{{account_alias}}]{{/if}} {{region}}",
+            &aliases,
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["bar ", "foo [example-prod] us-east-1"],
+            actual
+                .iter()
+                .map(|i| i.line.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert!(PickerItem::render(&contexts, "{{#if}}", &aliases).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+use crate::ctx;
+use crate::state::HistoryEntry;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Switches reported in the status, the most recent first
+const MAX_RECENT_SWITCHES: usize = 10;
+/// Clients of the control socket must send a request in time, not to block other requests
+const CONTROL_READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// Clients of HTTP must send a request and read the response in time, not to block other requests
+const HTTP_TIMEOUT: Duration = Duration::from_secs(1);
+/// Interval of periodic work of the daemon between requests
+const TICK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Expiry of credentials of a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProfileStatus {
+    pub name: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Negative once expired
+    pub seconds_to_expiry: Option<i64>,
+}
+
+/// Status served by the daemon, for editor plugins and status bars.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub active_context: Option<String>,
+    pub profiles: Vec<ProfileStatus>,
+    pub recent_switches: Vec<HistoryEntry>,
+}
+
+impl Status {
+    pub fn new(
+        contexts: &[ctx::ContextMetadata],
+        history: &[HistoryEntry],
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            active_context: contexts
+                .iter()
+                .find(|c| c.active)
+                .map(|c| c.name.clone()),
+            profiles: contexts
+                .iter()
+                .map(|c| ProfileStatus {
+                    name: c.name.clone(),
+                    expires_at: c.expires_at,
+                    seconds_to_expiry: c
+                        .expires_at
+                        .map(|e| (e - now).num_seconds()),
+                })
+                .collect(),
+            recent_switches: history
+                .iter()
+                .take(MAX_RECENT_SWITCHES)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Response of the HTTP endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json<T: Serialize>(data: &T) -> Self {
+        Self::ok("application/json", serde_json::to_string(data).unwrap())
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            content_type: "text/plain",
+            body: "not found\n".to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Request of the control socket, a line like `switch foo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Lists the contexts with their details
+    List,
+    /// Shows the active context with its details
+    Whoami,
+    /// Switches to the context of the profile
+    Switch(String),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("list"), None, _) => Ok(Self::List),
+            (Some("whoami"), None, _) => Ok(Self::Whoami),
+            (Some("switch"), Some(profile), None) => {
+                Ok(Self::Switch(profile.to_string()))
+            }
+            _ => Err(format!("unknown command: {}", line.trim())),
+        }
+    }
+}
+
+/// Reads a request of the control socket and writes the reply of `handler` as a line of JSON,
+/// `{"ok":true,"result":..}` or `{"ok":false,"error":".."}`.
+pub fn control<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    handler: &mut F,
+) -> std::io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(Command) -> Result<Value, String>,
+{
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let reply = match Command::parse(&line).and_then(handler) {
+        Ok(result) => json!({"ok": true, "result": result}),
+        Err(error) => json!({"ok": false, "error": error}),
+    };
+    writeln!(writer, "{}", reply)?;
+    writer.flush()
+}
+
+/// Binds the control socket readable only by the user, since it switches contexts.
+/// A socket left by a daemon which is gone is replaced.
+pub fn bind_socket(path: &Path) -> Result<UnixListener, ctx::CTXError> {
+    let failed =
+        |e: anyhow::Error| ctx::CTXError::UnexpectedError { source: Some(e) };
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(ctx::CTXError::InvalidConfigurations {
+                message: format!(
+                    "another daemon listens on the socket ({})",
+                    path.display()
+                ),
+                source: None,
+            });
+        }
+        fs::remove_file(path)
+            .context(format!("failed to remove {}", path.display()))
+            .map_err(failed)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .context(format!("failed to create {}", dir.display()))
+            .map_err(failed)?;
+    }
+    let listener = UnixListener::bind(path)
+        .context(format!("failed to listen on {}", path.display()))
+        .map_err(failed)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .context(format!("failed to restrict {}", path.display()))
+        .map_err(failed)?;
+    Ok(listener)
+}
+
+/// Binds the address, refusing ones other than loopback addresses
+/// since the status tells which accounts the user works on.
+pub fn bind(address: &str) -> Result<TcpListener, ctx::CTXError> {
+    let parsed = address.parse::<SocketAddr>().map_err(|e| {
+        ctx::CTXError::InvalidConfigurations {
+            message: format!("invalid address of the daemon ({})", address),
+            source: Some(e.into()),
+        }
+    })?;
+    if !parsed.ip().is_loopback() {
+        return Err(ctx::CTXError::InvalidConfigurations {
+            message: format!(
+                "the daemon listens only on loopback addresses, not ({})",
+                address
+            ),
+            source: None,
+        });
+    }
+    TcpListener::bind(parsed)
+        .context(format!("failed to listen on {}", address))
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+/// Returns whether the `Host` header names a loopback address, like `localhost:9876`,
+/// which refuses pages of other sites resolving their names to loopback addresses.
+fn is_loopback_host(host: &str) -> bool {
+    let host = match host.parse::<SocketAddr>() {
+        Ok(address) => return address.ip().is_loopback(),
+        Err(_) => host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Reads a request and writes the response of `handler` for its path.
+/// Only `GET` with a loopback `Host` is served, and connections are closed after each response.
+pub fn respond<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    handler: &mut F,
+) -> std::io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str) -> Response,
+{
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // headers other than `Host` are never used
+    let mut host = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some(_), Some(_))
+            if !host.as_deref().is_some_and(is_loopback_host) =>
+        {
+            Response {
+                status: 403,
+                content_type: "text/plain",
+                body: "forbidden host\n".to_string(),
+            }
+        }
+        (Some("GET"), Some(target)) => {
+            // queries are never used
+            handler(target.split('?').next().unwrap_or(target))
+        }
+        (Some(_), Some(_)) => Response {
+            status: 405,
+            content_type: "text/plain",
+            body: "method not allowed\n".to_string(),
+        },
+        _ => Response {
+            status: 400,
+            content_type: "text/plain",
+            body: "bad request\n".to_string(),
+        },
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    writer.flush()
+}
+
+enum Connection {
+    Http(TcpStream),
+    Control(UnixStream),
+    /// Periodic wake-up of the serving thread, not a connection indeed
+    Tick,
+}
+
+/// Accepts connections by `next` in a thread, passing them to the thread serving requests.
+fn accept<F>(mut next: F, tx: mpsc::Sender<Connection>)
+where
+    F: FnMut() -> std::io::Result<Connection> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        match next() {
+            Ok(connection) => {
+                if tx.send(connection).is_err() {
+                    break;
+                }
+            }
+            Err(e) => debug!("failed to accept a connection: {:?}", e),
+        }
+    });
+}
+
+/// Serves requests of HTTP and the control socket one by one, which keeps states of awsctx free from locks.
+/// `tick` runs every `TICK_INTERVAL` between requests, like checking expiries.
+pub fn serve<F, G, H>(
+    listener: TcpListener,
+    socket: Option<UnixListener>,
+    mut handler: F,
+    mut control_handler: G,
+    mut tick: H,
+) where
+    F: FnMut(&str) -> Response,
+    G: FnMut(Command) -> Result<Value, String>,
+    H: FnMut(),
+{
+    let (tx, rx) = mpsc::channel();
+    if let Some(socket) = socket {
+        accept(
+            move || socket.accept().map(|(s, _)| Connection::Control(s)),
+            tx.clone(),
+        );
+    }
+    accept(
+        || {
+            thread::sleep(TICK_INTERVAL);
+            Ok(Connection::Tick)
+        },
+        tx.clone(),
+    );
+    accept(
+        move || listener.accept().map(|(s, _)| Connection::Http(s)),
+        tx,
+    );
+    for connection in rx {
+        let result = match connection {
+            Connection::Http(stream) => stream
+                .set_read_timeout(Some(HTTP_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(HTTP_TIMEOUT)))
+                .and_then(|_| stream.try_clone())
+                .and_then(|reader| {
+                    let mut writer = stream;
+                    respond(
+                        &mut BufReader::new(reader),
+                        &mut writer,
+                        &mut handler,
+                    )
+                }),
+            Connection::Control(stream) => stream
+                .set_read_timeout(Some(CONTROL_READ_TIMEOUT))
+                .and_then(|_| stream.try_clone())
+                .and_then(|reader| {
+                    let mut writer = stream;
+                    control(
+                        &mut BufReader::new(reader),
+                        &mut writer,
+                        &mut control_handler,
+                    )
+                }),
+            Connection::Tick => {
+                tick();
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            debug!("failed to serve a request: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::Duration;
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_status_new() {
+        let now = Utc::now();
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "bar".to_string(),
+                expires_at: Some(now + Duration::minutes(5)),
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "foo".to_string(),
+                active: true,
+                ..Default::default()
+            },
+        ];
+        let history = (0..20)
+            .map(|i| HistoryEntry {
+                context: "foo".to_string(),
+                used_at: now - Duration::minutes(i),
+            })
+            .collect::<Vec<HistoryEntry>>();
+        let actual = Status::new(&contexts, &history, now);
+        assert_eq!(Some("foo".to_string()), actual.active_context);
+        assert_eq!(Some(300), actual.profiles[0].seconds_to_expiry);
+        assert_eq!(None, actual.profiles[1].seconds_to_expiry);
+        assert_eq!(MAX_RECENT_SWITCHES, actual.recent_switches.len());
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        "GET /status?pretty HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "HTTP/1.1 200 OK",
+        "/status"
+    )]
+    #[case(
+        "GET /unknown HTTP/1.1\r\nHost: 127.0.0.1:9876\r\n\r\n",
+        "HTTP/1.1 404 Not Found",
+        ""
+    )]
+    #[case(
+        "POST /status HTTP/1.1\r\nhost: [::1]:9876\r\n\r\n",
+        "HTTP/1.1 405 Method Not Allowed",
+        ""
+    )]
+    // pages of other sites resolving their names to loopback addresses are refused
+    #[case(
+        "GET /status HTTP/1.1\r\nHost: evil.example.com:9876\r\n\r\n",
+        "HTTP/1.1 403 Forbidden",
+        ""
+    )]
+    #[case("GET /status HTTP/1.1\r\n\r\n", "HTTP/1.1 403 Forbidden", "")]
+    #[case("\r\n", "HTTP/1.1 400 Bad Request", "")]
+    fn test_respond(
+        #[case] request: &str,
+        #[case] expect_status_line: &str,
+        #[case] expect_body: &str,
+    ) {
+        let mut output = vec![];
+        respond(
+            &mut Cursor::new(request),
+            &mut output,
+            &mut |path| match path {
+                "/status" => Response::json(&path),
+                _ => Response::not_found(),
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(expect_status_line));
+        if !expect_body.is_empty() {
+            assert!(output.ends_with(&format!("\"{}\"", expect_body)));
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case("list\n", Ok(Command::List))]
+    #[case(" whoami ", Ok(Command::Whoami))]
+    #[case("switch foo\n", Ok(Command::Switch("foo".to_string())))]
+    #[case("switch\n", Err("unknown command: switch".to_string()))]
+    #[case("switch foo bar", Err("unknown command: switch foo bar".to_string()))]
+    #[case("list foo", Err("unknown command: list foo".to_string()))]
+    fn test_command_parse(
+        #[case] line: &str,
+        #[case] expect: Result<Command, String>,
+    ) {
+        assert_eq!(expect, Command::parse(line));
+    }
+
+    #[rstest(::trace)]
+    #[case("switch foo\n", r#"{"ok":true,"result":"foo"}"#)]
+    #[case("switch bar\n", r#"{"error":"no such profile","ok":false}"#)]
+    #[case("\n", r#"{"error":"unknown command: ","ok":false}"#)]
+    fn test_control(#[case] request: &str, #[case] expect: &str) {
+        let mut output = vec![];
+        control(&mut Cursor::new(request), &mut output, &mut |command| {
+            match command {
+                Command::Switch(profile) if profile == "foo" => {
+                    Ok(json!(profile))
+                }
+                _ => Err("no such profile".to_string()),
+            }
+        })
+        .unwrap();
+        assert_eq!(format!("{}\n", expect), String::from_utf8(output).unwrap());
+    }
+
+    #[rstest]
+    fn test_bind_socket() {
+        let tmpdir = tempfile::TempDir::new().unwrap();
+        let path = tmpdir.path().join("awsctx/daemon.sock");
+        let listener = bind_socket(&path).unwrap();
+        assert_eq!(
+            0o600,
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        );
+        // the socket of a running daemon is never taken over
+        assert!(bind_socket(&path).is_err());
+        drop(listener);
+        assert!(bind_socket(&path).is_ok());
+    }
+
+    #[rstest(::trace)]
+    #[case("127.0.0.1:0", true)]
+    #[case("0.0.0.0:0", false)]
+    #[case("localhost", false)]
+    fn test_bind(#[case] address: &str, #[case] expect_ok: bool) {
+        assert_eq!(expect_ok, bind(address).is_ok());
+    }
+}
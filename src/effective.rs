@@ -0,0 +1,164 @@
+use serde::Serialize;
+
+/// Variables naming the profile used by the AWS CLI and SDKs instead of the default sections,
+/// the first one set applies.
+const PROFILE_VARS: [&str; 2] = ["AWS_PROFILE", "AWS_DEFAULT_PROFILE"];
+const ACCESS_KEY_ID_VAR: &str = "AWS_ACCESS_KEY_ID";
+const SECRET_ACCESS_KEY_VAR: &str = "AWS_SECRET_ACCESS_KEY";
+const SESSION_TOKEN_VAR: &str = "AWS_SESSION_TOKEN";
+
+/// Environment variables taking precedence over the active context of the default sections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Override {
+    /// Profile named by a variable like `AWS_PROFILE`
+    Profile { variable: String, name: String },
+    /// Credentials of `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, preferred to any profile
+    Credentials { access_key_id: String },
+}
+
+impl Override {
+    /// Detects the override by the variables in the order of precedence of the AWS CLI.
+    /// `AWS_PROFILE=default` names the default sections, so it overrides nothing.
+    pub fn detect<F: Fn(&str) -> Option<String>>(var: F) -> Option<Self> {
+        let set = |k: &str| var(k).filter(|v| !v.is_empty());
+        if let (Some(access_key_id), Some(_)) =
+            (set(ACCESS_KEY_ID_VAR), set(SECRET_ACCESS_KEY_VAR))
+        {
+            return Some(Self::Credentials { access_key_id });
+        }
+        PROFILE_VARS
+            .iter()
+            .find_map(|k| set(k).map(|v| (k, v)))
+            .filter(|(_, name)| name != "default")
+            .map(|(k, name)| Self::Profile {
+                variable: k.to_string(),
+                name,
+            })
+    }
+
+    /// Returns the variable of the override, shown to users to unset it.
+    pub fn variable(&self) -> &str {
+        match self {
+            Self::Profile { variable, .. } => variable,
+            Self::Credentials { .. } => ACCESS_KEY_ID_VAR,
+        }
+    }
+}
+
+/// Returns the variables exported in the environment shadowing the default sections,
+/// which must be unset for switches of contexts to take effect in the shell.
+pub fn shadowing_variables<F: Fn(&str) -> Option<String>>(
+    var: F,
+) -> Vec<&'static str> {
+    [ACCESS_KEY_ID_VAR, SECRET_ACCESS_KEY_VAR, SESSION_TOKEN_VAR]
+        .into_iter()
+        .chain(PROFILE_VARS)
+        .filter(|k| var(k).is_some_and(|v| !v.is_empty() && v != "default"))
+        .collect()
+}
+
+/// Context used by the AWS CLI and SDKs in the environment, which may differ from the active one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EffectiveContext {
+    /// Name of the context in effect, `None` for credentials of the environment matching no profile
+    pub name: Option<String>,
+    /// Name of the active context of the default sections
+    pub active: Option<String>,
+    #[serde(rename = "override", skip_serializing_if = "Option::is_none")]
+    pub overridden_by: Option<Override>,
+}
+
+impl EffectiveContext {
+    /// Returns whether the environment puts another context than the active one in effect.
+    pub fn is_discrepant(&self) -> bool {
+        self.overridden_by.is_some() && self.name != self.active
+    }
+
+    /// Returns the name of the context in effect, or the access key of unknown credentials.
+    pub fn display_name(&self) -> &str {
+        match (&self.name, &self.overridden_by) {
+            (Some(name), _) => name,
+            (None, Some(Override::Credentials { access_key_id })) => {
+                access_key_id
+            }
+            (None, _) => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(vec![], None)]
+    #[case(
+        vec![("AWS_PROFILE", "bar")],
+        Some(Override::Profile { variable: "AWS_PROFILE".to_string(), name: "bar".to_string() })
+    )]
+    #[case(
+        vec![("AWS_PROFILE", "bar"), ("AWS_DEFAULT_PROFILE", "baz")],
+        Some(Override::Profile { variable: "AWS_PROFILE".to_string(), name: "bar".to_string() })
+    )]
+    #[case(
+        vec![("AWS_PROFILE", ""), ("AWS_DEFAULT_PROFILE", "baz")],
+        Some(Override::Profile { variable: "AWS_DEFAULT_PROFILE".to_string(), name: "baz".to_string() })
+    )]
+    #[case(vec![("AWS_PROFILE", "default")], None)]
+    // a secret access key is required together
+    #[case(vec![("AWS_ACCESS_KEY_ID", "XXXX")], None)]
+    #[case(
+        vec![("AWS_PROFILE", "bar"), ("AWS_ACCESS_KEY_ID", "XXXX"), ("AWS_SECRET_ACCESS_KEY", "YYYY")],
+        Some(Override::Credentials { access_key_id: "XXXX".to_string() })
+    )]
+    fn test_override_detect(
+        #[case] vars: Vec<(&str, &str)>,
+        #[case] expect: Option<Override>,
+    ) {
+        let vars = vars.into_iter().collect::<HashMap<&str, &str>>();
+        assert_eq!(
+            expect,
+            Override::detect(|k| vars.get(k).map(|v| v.to_string()))
+        );
+    }
+
+    #[rstest]
+    fn test_shadowing_variables() {
+        let vars = HashMap::from([
+            ("AWS_PROFILE", "bar"),
+            ("AWS_DEFAULT_PROFILE", "default"),
+            ("AWS_ACCESS_KEY_ID", "XXXX"),
+            ("AWS_SESSION_TOKEN", ""),
+            ("AWS_REGION", "us-east-1"),
+        ]);
+        assert_eq!(
+            vec!["AWS_ACCESS_KEY_ID", "AWS_PROFILE"],
+            shadowing_variables(|k| vars.get(k).map(|v| v.to_string()))
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(Some("foo"), false, false)]
+    #[case(Some("foo"), true, false)]
+    #[case(Some("bar"), true, true)]
+    #[case(None, true, true)]
+    fn test_effective_context_is_discrepant(
+        #[case] name: Option<&str>,
+        #[case] overridden: bool,
+        #[case] expect: bool,
+    ) {
+        let effective = EffectiveContext {
+            name: name.map(|n| n.to_string()),
+            active: Some("foo".to_string()),
+            overridden_by: overridden.then(|| Override::Credentials {
+                access_key_id: "XXXX".to_string(),
+            }),
+        };
+        assert_eq!(expect, effective.is_discrepant());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::ctx;
+use crate::file::create_private_file;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context;
+use clap::ArgEnum;
+
+/// Formats of environment variables printed by `env` subcommand.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvFormat {
+    /// `export` statements for POSIX shells
+    Shell,
+    /// `.env` files read by docker compose and dotenv libraries
+    Dotenv,
+    /// Environment files of GitHub Actions, `$GITHUB_ENV`
+    Github,
+}
+
+/// Variables masked in logs of GitHub Actions
+const SECRET_KEYS: [&str; 3] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+];
+/// Delimiter of multiline values in environment files of GitHub Actions,
+/// which never appears in credentials
+const GITHUB_DELIMITER: &str = "AWSCTX_EOF";
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn dotenv_quote(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/+=_-.:".contains(c));
+    if plain {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', r"\\").replace('"', "\\\""))
+}
+
+/// Renders environment variables in the format, one variable per line.
+pub fn render(vars: &[(String, String)], format: EnvFormat) -> String {
+    vars.iter()
+        .map(|(key, value)| match format {
+            EnvFormat::Shell => {
+                format!("export {}={}\n", key, shell_quote(value))
+            }
+            EnvFormat::Dotenv => format!("{}={}\n", key, dotenv_quote(value)),
+            EnvFormat::Github => format!(
+                "{}<<{}\n{}\n{}\n",
+                key, GITHUB_DELIMITER, value, GITHUB_DELIMITER
+            ),
+        })
+        .collect()
+}
+
+/// Renders workflow commands of GitHub Actions which mask the secrets in logs.
+/// They must be printed before the variables are exported.
+pub fn github_masks(vars: &[(String, String)]) -> String {
+    vars.iter()
+        .filter(|(key, _)| SECRET_KEYS.contains(&key.as_str()))
+        .map(|(_, value)| format!("::add-mask::{}\n", value))
+        .collect()
+}
+
+/// Appends rendered environment variables to the file, like `$GITHUB_ENV`.
+pub fn append<P: AsRef<Path>>(
+    path: P,
+    contents: &str,
+) -> Result<(), ctx::CTXError> {
+    let path = path.as_ref();
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.flush()
+        })
+        .context(format!("failed to append to {}", path.to_str().unwrap()))
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+/// Writes rendered environment variables to a file readable only by the user,
+/// since they contain secrets.
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    contents: &str,
+) -> Result<(), ctx::CTXError> {
+    let path = path.as_ref();
+    create_private_file(path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.flush()
+        })
+        .context(format!("failed to write {}", path.to_str().unwrap()))
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[fixture]
+    pub fn vars() -> Vec<(String, String)> {
+        vec![
+            ("AWS_ACCESS_KEY_ID".to_string(), "XXXXXXXXXXX".to_string()),
+            ("AWS_SECRET_ACCESS_KEY".to_string(), "xx/xx+xx=".to_string()),
+            (
+                "AWS_SESSION_TOKEN".to_string(),
+                "it's \"quoted\"".to_string(),
+            ),
+        ]
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        EnvFormat::Shell,
+        r#"export AWS_ACCESS_KEY_ID='XXXXXXXXXXX'
+export AWS_SECRET_ACCESS_KEY='xx/xx+xx='
+export AWS_SESSION_TOKEN='it'\''s "quoted"'
+"#
+    )]
+    #[case(
+        EnvFormat::Github,
+        r#"AWS_ACCESS_KEY_ID<<AWSCTX_EOF
+XXXXXXXXXXX
+AWSCTX_EOF
+AWS_SECRET_ACCESS_KEY<<AWSCTX_EOF
+xx/xx+xx=
+AWSCTX_EOF
+AWS_SESSION_TOKEN<<AWSCTX_EOF
+it's "quoted"
+AWSCTX_EOF
+"#
+    )]
+    #[case(
+        EnvFormat::Dotenv,
+        r#"AWS_ACCESS_KEY_ID=XXXXXXXXXXX
+AWS_SECRET_ACCESS_KEY=xx/xx+xx=
+AWS_SESSION_TOKEN="it's \"quoted\""
+"#
+    )]
+    fn test_render(
+        vars: Vec<(String, String)>,
+        #[case] format: EnvFormat,
+        #[case] expect: &str,
+    ) {
+        assert_eq!(expect, render(&vars, format));
+    }
+
+    #[rstest]
+    fn test_github_masks(mut vars: Vec<(String, String)>) {
+        vars.push(("AWS_REGION".to_string(), "us-east-1".to_string()));
+        let expect = r#"::add-mask::XXXXXXXXXXX
+::add-mask::xx/xx+xx=
+::add-mask::it's "quoted"
+"#;
+        assert_eq!(expect, github_masks(&vars));
+    }
+
+    #[rstest]
+    fn test_append(vars: Vec<(String, String)>) {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("github_env");
+        fs::write(&path, "FOO=bar\n").unwrap();
+        let contents = render(&vars[..1], EnvFormat::Github);
+        append(&path, &contents).unwrap();
+        assert_eq!(
+            format!("FOO=bar\n{}", contents),
+            fs::read_to_string(&path).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_write(vars: Vec<(String, String)>) {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join(".env");
+        let contents = render(&vars, EnvFormat::Dotenv);
+        write(&path, &contents).unwrap();
+        assert_eq!(contents, fs::read_to_string(&path).unwrap());
+    }
+}
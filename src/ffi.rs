@@ -0,0 +1,184 @@
+//! C ABI of switching contexts, for tools written in other languages like Python by `ctypes`.
+//!
+//! Every function loads `~/.awsctx/configs.yaml` and `~/.aws` files as the binary does.
+//! Functions returning `int` give 0 on success and -1 on failure, whose message is taken by
+//! `awsctx_last_error`. Strings returned by the functions must be freed by `awsctx_string_free`.
+use crate::aws::{AWS, CONFIG_PATH, CREDENTIALS_PATH};
+use crate::configs::{Configs, CONFIGS_PATH};
+use crate::ctx::{CTXError, CTX};
+use crate::state::{CREDENTIALS_CACHE_PATH, LOCK_PATH};
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::ptr;
+use std::rc::Rc;
+
+use serde::Serialize;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Keeps the error with its details and causes, like `InvalidInput { message: .., source: .. }`.
+fn set_last_error(e: &CTXError) {
+    let message = format!("{:?}", e);
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = CString::new(message.replace('\0', "")).ok();
+    });
+}
+
+fn load_aws() -> Result<AWS<'static>, CTXError> {
+    let configs = if CONFIGS_PATH.exists() {
+        Configs::load_configs::<PathBuf>(None)?
+    } else {
+        Configs::default()
+    };
+    AWS::new(
+        Rc::new(configs),
+        CREDENTIALS_PATH.clone(),
+        CONFIG_PATH.clone(),
+    )
+    .map(|aws| {
+        aws.with_lock(LOCK_PATH.as_path())
+            .with_credentials_cache(CREDENTIALS_CACHE_PATH.as_path())
+    })
+    .map_err(|e| CTXError::UnexpectedError { source: Some(e) })
+}
+
+/// Reads the argument of a C string, which must be valid UTF-8.
+///
+/// # Safety
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn read_str(s: *const c_char) -> Result<String, CTXError> {
+    if s.is_null() {
+        return Err(CTXError::InvalidInput {
+            message: "null string is given".to_string(),
+            source: None,
+        });
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(|s| s.to_string())
+        .map_err(|e| CTXError::InvalidInput {
+            message: "string of invalid UTF-8 is given".to_string(),
+            source: Some(e.into()),
+        })
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<CString, CTXError> {
+    serde_json::to_string(value)
+        .map_err(|e| e.into())
+        .and_then(|json| CString::new(json).map_err(|e| e.into()))
+        .map_err(|e: anyhow::Error| CTXError::UnexpectedError {
+            source: Some(e),
+        })
+}
+
+fn into_status(result: Result<(), CTXError>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(&e);
+            -1
+        }
+    }
+}
+
+fn into_raw(result: Result<CString, CTXError>) -> *mut c_char {
+    match result {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns a JSON array of contexts with their details like `expires_at`, or null on failure.
+#[no_mangle]
+pub extern "C" fn awsctx_list_contexts() -> *mut c_char {
+    into_raw(load_aws().and_then(|aws| to_json(&aws.describe_contexts()?)))
+}
+
+/// Returns a JSON object of the active context, or null on failure including no active context.
+#[no_mangle]
+pub extern "C" fn awsctx_active_context() -> *mut c_char {
+    into_raw(load_aws().and_then(|aws| to_json(&aws.get_active_context()?)))
+}
+
+/// Switches to the context of the profile.
+///
+/// # Safety
+/// `profile` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn awsctx_use_context(profile: *const c_char) -> c_int {
+    into_status(
+        read_str(profile)
+            .and_then(|profile| load_aws()?.use_context(&profile).map(|_| ())),
+    )
+}
+
+/// Runs the auth command of the profile, which writes its credentials.
+///
+/// # Safety
+/// `profile` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn awsctx_auth(profile: *const c_char) -> c_int {
+    into_status(
+        read_str(profile)
+            .and_then(|profile| load_aws()?.auth(&profile).map(|_| ())),
+    )
+}
+
+/// Returns the message of the last failure in the thread, or null if nothing failed.
+/// The string is owned by awsctx and valid until the next failure, so it must not be freed.
+#[no_mangle]
+pub extern "C" fn awsctx_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|s| s.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Frees a string returned by awsctx.
+///
+/// # Safety
+/// `s` must be null or a string returned by awsctx, which is never freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn awsctx_string_free(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    drop(CString::from_raw(s));
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_awsctx_use_context_null() {
+        assert_eq!(-1, unsafe { awsctx_use_context(ptr::null()) });
+        let message = unsafe { CStr::from_ptr(awsctx_last_error()) };
+        assert_eq!(
+            r#"InvalidInput { message: "null string is given", source: None }"#,
+            message.to_str().unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_to_json() {
+        let json = to_json(&vec!["foo"]).unwrap().into_raw();
+        assert_eq!(
+            r#"["foo"]"#,
+            unsafe { CStr::from_ptr(json) }.to_str().unwrap()
+        );
+        unsafe { awsctx_string_free(json) };
+    }
+}
@@ -0,0 +1,305 @@
+use crate::ctx;
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// Max number of symlinks followed to resolve a path, the same as `MAXSYMLINKS` of Linux
+const MAX_SYMLINK_HOPS: usize = 40;
+/// Directories marking roots of working trees of version control systems
+const VERSION_CONTROL_DIRS: [&str; 4] = [".git", ".hg", ".svn", ".jj"];
+
+/// Snapshot of a file to detect changes made by other processes after it was loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    modified: Option<SystemTime>,
+    hash: u64,
+}
+
+impl Fingerprint {
+    /// Takes a fingerprint of the file, `None` if it cannot be read.
+    pub fn of<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        let contents = fs::read(path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        Some(Self {
+            modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+            hash: hasher.finish(),
+        })
+    }
+
+    /// Fails if the file was changed since the fingerprint was taken.
+    /// A different modified time is taken as a change without reading the file, while an equal one
+    /// is not trusted, as edits within a tick of coarse modified times keep it.
+    pub fn ensure_unchanged<P: AsRef<Path>>(
+        expect: Option<&Self>,
+        path: P,
+    ) -> Result<(), ctx::CTXError> {
+        let path = path.as_ref();
+        let unchanged = match expect {
+            Some(expect) => {
+                let modified =
+                    fs::metadata(path).and_then(|m| m.modified()).ok();
+                modified == expect.modified
+                    && Self::of(path).map(|f| f.hash) == Some(expect.hash)
+            }
+            // created by another process if it did not exist
+            None => !path.exists(),
+        };
+        if unchanged {
+            return Ok(());
+        }
+        Err(ctx::CTXError::FileChangedOnDisk {
+            path: path.to_str().unwrap().to_string(),
+            source: None,
+        })
+    }
+}
+
+/// Encoding of a text file detected on reading, to write it back in the same bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    /// Files with bytes out of UTF-8, like stray Latin-1 characters in comments.
+    /// Latin-1 maps every byte to a character, so they are decoded and encoded losslessly.
+    Latin1,
+}
+
+impl TextEncoding {
+    /// Decodes the bytes as UTF-8, or as Latin-1 if they are not valid UTF-8.
+    pub fn decode(bytes: Vec<u8>) -> (String, Self) {
+        match String::from_utf8(bytes) {
+            Ok(text) => (text, Self::Utf8),
+            Err(e) => (
+                e.into_bytes().into_iter().map(char::from).collect(),
+                Self::Latin1,
+            ),
+        }
+    }
+
+    /// Encodes the text in the encoding.
+    /// Fails on characters Latin-1 cannot represent, added after decoding, not to corrupt
+    /// the other characters of the file by writing it in UTF-8.
+    pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Utf8 => Ok(text.as_bytes().to_vec()),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{:?} (U+{:04X}) cannot be written in Latin-1, the encoding of the file",
+                                c, c as u32
+                            ),
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Follows symlinks of the path to the file which they point to, even if it does not exist yet.
+pub fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_HOPS {
+        match fs::read_link(&path) {
+            // relative targets are relative to the directory of the link
+            Ok(target) => {
+                path = path.parent().map_or(target.clone(), |p| p.join(&target))
+            }
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Returns the root of the working tree of git, Mercurial, Subversion or Jujutsu
+/// which the file resolved from the path belongs to.
+pub fn version_control_root(path: &Path) -> Option<PathBuf> {
+    let target = resolve_symlinks(path);
+    let dir = fs::canonicalize(target.parent()?).ok()?;
+    dir.ancestors()
+        .find(|d| VERSION_CONTROL_DIRS.iter().any(|v| d.join(v).exists()))
+        .map(|d| d.to_path_buf())
+}
+
+/// Writes the contents to a temporary file renamed over the path, so that readers never see
+/// half-written files.
+///
+/// Symlinks are resolved and the file which they point to is replaced, keeping the links
+/// into dotfiles repositories. The file gets the mode even if it is created for the first time.
+pub fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let target = resolve_symlinks(path);
+    let file_name = target.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no file name in the path")
+    })?;
+    let tmp = target.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let result = options
+        .open(&tmp)
+        .and_then(|mut file| {
+            // the mode on creation is masked by umask
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, &target));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Creates a file readable only by the user, truncating it if it exists.
+#[cfg(unix)]
+pub fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Creates a file readable only by the user, truncating it if it exists.
+#[cfg(not(unix))]
+pub fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[rstest]
+    fn test_write_atomic_through_symlink() {
+        let tmpdir = TempDir::new().unwrap();
+        let dotfiles = tmpdir.path().join("dotfiles");
+        fs::create_dir_all(dotfiles.join(".git")).unwrap();
+        let target = dotfiles.join("config");
+        fs::write(&target, "[foo]\n").unwrap();
+        let link = tmpdir.path().join("config");
+        std::os::unix::fs::symlink("dotfiles/config", &link).unwrap();
+
+        assert_eq!(target, resolve_symlinks(&link));
+        write_atomic(&link, b"[bar]\n", 0o640).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!("[bar]\n", fs::read_to_string(&target).unwrap());
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode =
+            |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(0o640, mode(&target));
+        let created = tmpdir.path().join("credentials");
+        write_atomic(&created, b"[foo]\n", 0o600).unwrap();
+        assert_eq!(0o600, mode(&created));
+
+        assert_eq!(
+            Some(fs::canonicalize(&dotfiles).unwrap()),
+            version_control_root(&link)
+        );
+        assert_eq!(None, version_control_root(&tmpdir.path().join("other")));
+    }
+
+    #[rstest(::trace)]
+    #[case(b"[foo]\n# caf\xc3\xa9\n", TextEncoding::Utf8)]
+    #[case(b"[foo]\n# caf\xe9\n", TextEncoding::Latin1)]
+    fn test_text_encoding(#[case] bytes: &[u8], #[case] expect: TextEncoding) {
+        let (text, encoding) = TextEncoding::decode(bytes.to_vec());
+        assert_eq!(expect, encoding);
+        assert_eq!("[foo]\n# café\n", text);
+        // written back in the same bytes
+        assert_eq!(bytes, encoding.encode(&text).unwrap());
+    }
+
+    #[rstest]
+    fn test_text_encoding_unrepresentable() {
+        let actual = TextEncoding::Latin1.encode("# café\n[東京]\n");
+        assert!(actual.unwrap_err().to_string().contains("'東' (U+6771)"));
+        assert_eq!(
+            "[東京]\n".as_bytes(),
+            TextEncoding::Utf8.encode("[東京]\n").unwrap()
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(Some("[foo]\n"), Some("[foo]\n"), true, true)]
+    #[case(Some("[foo]\n"), Some("[foo]\n"), false, false)]
+    #[case(Some("[foo]\n"), Some("[bar]\n"), false, false)]
+    // edited within a tick of the modified time
+    #[case(Some("[foo]\n"), Some("[bar]\n"), true, false)]
+    #[case(Some("[foo]\n"), None, false, false)]
+    #[case(None, None, false, true)]
+    #[case(None, Some("[foo]\n"), false, false)]
+    fn test_fingerprint_ensure_unchanged(
+        #[case] before: Option<&str>,
+        #[case] after: Option<&str>,
+        #[case] keep_modified: bool,
+        #[case] expect_ok: bool,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("credentials");
+        if let Some(before) = before {
+            fs::write(&path, before).unwrap();
+        }
+        let fingerprint = Fingerprint::of(&path);
+        match after {
+            Some(after) => {
+                let modified =
+                    fs::metadata(&path).and_then(|m| m.modified()).ok();
+                // rewrite even with the same contents to update the modified time
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                fs::write(&path, after).unwrap();
+                if let Some(modified) = modified.filter(|_| keep_modified) {
+                    fs::File::options()
+                        .write(true)
+                        .open(&path)
+                        .and_then(|f| f.set_modified(modified))
+                        .unwrap();
+                }
+            }
+            None => {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        let actual = Fingerprint::ensure_unchanged(fingerprint.as_ref(), &path);
+        match actual {
+            Ok(()) => assert!(expect_ok),
+            Err(ctx::CTXError::FileChangedOnDisk { .. }) => assert!(!expect_ok),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use crate::ctx;
+use crate::setting::glob_regex;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+/// Keys of contexts compared in filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Tag,
+    Account,
+    Region,
+    /// How the profile gets credentials, like `sso` or `static`
+    Source,
+}
+
+/// States of contexts written alone in filters, like `expired`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flag {
+    Active,
+    Expired,
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Equal(String),
+    NotEqual(String),
+    /// Glob like `prod-*` matching whole values
+    Glob(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Flag(Flag),
+    Compare(Field, Matcher),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Equal,
+    NotEqual,
+    Glob,
+    Word(String),
+}
+
+/// Filter of contexts like `tag=prod && (expired || missing)`, evaluated over their metadata.
+///
+/// `name`, `tag`, `account`, `region` and `source` are compared by `=`, `!=` or a glob of `~`,
+/// and `active`, `expired` and `missing` stand alone. A context matches `tag=x` if any of its tags is `x`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    /// Parses the expression, failing with `InvalidInput` for syntax errors and unknown keys.
+    pub fn parse(text: &str) -> Result<Self, ctx::CTXError> {
+        let invalid = |reason: String| ctx::CTXError::InvalidInput {
+            message: format!("invalid filter ({}): {}", text, reason),
+            source: None,
+        };
+        let tokens = tokenize(text).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+        };
+        let expr = parser.or().map_err(invalid)?;
+        match parser.tokens.next() {
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+            None => Ok(Self { expr }),
+        }
+    }
+
+    /// Returns whether the context matches the filter at `now`.
+    pub fn matches(
+        &self,
+        context: &ctx::ContextMetadata,
+        now: DateTime<Utc>,
+    ) -> bool {
+        evaluate(&self.expr, context, now)
+    }
+}
+
+fn evaluate(
+    expr: &Expr,
+    context: &ctx::ContextMetadata,
+    now: DateTime<Utc>,
+) -> bool {
+    match expr {
+        Expr::And(l, r) => {
+            evaluate(l, context, now) && evaluate(r, context, now)
+        }
+        Expr::Or(l, r) => {
+            evaluate(l, context, now) || evaluate(r, context, now)
+        }
+        Expr::Not(e) => !evaluate(e, context, now),
+        Expr::Flag(Flag::Active) => context.active,
+        Expr::Flag(Flag::Expired) => {
+            context.unusable(now) == Some(ctx::Unusable::Expired)
+        }
+        Expr::Flag(Flag::Missing) => context.missing_credentials,
+        Expr::Compare(field, matcher) => {
+            let values = match field {
+                Field::Name => vec![context.name.as_str()],
+                Field::Tag => context.tags.iter().map(|t| t.as_str()).collect(),
+                Field::Account => {
+                    context.account.iter().map(|a| a.as_str()).collect()
+                }
+                Field::Region => {
+                    context.region.iter().map(|r| r.as_str()).collect()
+                }
+                Field::Source => {
+                    context.source.iter().map(|s| s.as_str()).collect()
+                }
+            };
+            match matcher {
+                Matcher::Equal(v) => values.contains(&v.as_str()),
+                Matcher::NotEqual(v) => !values.contains(&v.as_str()),
+                Matcher::Glob(r) => values.iter().any(|v| r.is_match(v)),
+            }
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Equal,
+            '~' => Token::Glob,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::NotEqual,
+            '!' => Token::Not,
+            '&' | '|' => {
+                if chars.next_if_eq(&c).is_none() {
+                    return Err(format!("`{}` must be doubled", c));
+                }
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            '\'' | '"' => Token::Word(quoted(&mut chars, c)?),
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"()=~!&|'\"".contains(c)
+}
+
+fn quoted(chars: &mut Peekable<Chars>, quote: char) -> Result<String, String> {
+    let mut word = String::new();
+    for c in chars.by_ref() {
+        if c == quote {
+            return Ok(word);
+        }
+        word.push(c);
+    }
+    Err(format!("unclosed `{}`", quote))
+}
+
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("unclosed `(`".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => self.term(&word),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn term(&mut self, word: &str) -> Result<Expr, String> {
+        let flag = match word {
+            "active" => Some(Flag::Active),
+            "expired" => Some(Flag::Expired),
+            "missing" => Some(Flag::Missing),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            return Ok(Expr::Flag(flag));
+        }
+        let field = match word {
+            "name" => Field::Name,
+            "tag" => Field::Tag,
+            "account" => Field::Account,
+            "region" => Field::Region,
+            "source" => Field::Source,
+            _ => return Err(format!("unknown key `{}`", word)),
+        };
+        let op = self.tokens.next();
+        let value = match self.tokens.next() {
+            Some(Token::Word(value)) => value,
+            _ => return Err(format!("`{}` requires a value", word)),
+        };
+        let matcher = match op {
+            Some(Token::Equal) => Matcher::Equal(value),
+            Some(Token::NotEqual) => Matcher::NotEqual(value),
+            Some(Token::Glob) => Matcher::Glob(
+                glob_regex(&value).map_err(|e| format!("{:?}", e))?,
+            ),
+            _ => return Err(format!("`{}` requires `=`, `!=` or `~`", word)),
+        };
+        Ok(Expr::Compare(field, matcher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn context() -> ctx::ContextMetadata {
+        ctx::ContextMetadata {
+            name: "prod-admin".to_string(),
+            active: true,
+            account: Some("123456789012".to_string()),
+            region: Some("us-east-1".to_string()),
+            expires_at: Some(Utc::now() - Duration::minutes(1)),
+            tags: vec!["prod".to_string(), "admin".to_string()],
+            source: Some("sso".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case("tag=prod", true)]
+    #[case("tag=dev", false)]
+    #[case("tag!=dev", true)]
+    #[case("tag = prod && expired", true)]
+    #[case("tag=dev || account=123456789012", true)]
+    #[case("!active", false)]
+    #[case("name~'prod-*' && region=us-east-1", true)]
+    #[case("name~\"*-readonly\"", false)]
+    #[case("source=sso && !(missing || region!=us-east-1)", true)]
+    #[case("tag=dev || tag=qa && active", false)]
+    fn test_filter_matches(
+        context: ctx::ContextMetadata,
+        #[case] text: &str,
+        #[case] expect: bool,
+    ) {
+        let filter = Filter::parse(text).unwrap();
+        assert_eq!(expect, filter.matches(&context, Utc::now()));
+    }
+
+    #[rstest(::trace)]
+    #[case("")]
+    #[case("tag")]
+    #[case("tag=")]
+    #[case("owner=me")]
+    #[case("tag=prod & expired")]
+    #[case("(tag=prod")]
+    #[case("tag=prod)")]
+    #[case("name='prod")]
+    fn test_filter_parse_invalid(#[case] text: &str) {
+        assert!(matches!(
+            Filter::parse(text),
+            Err(ctx::CTXError::InvalidInput { .. })
+        ));
+    }
+}
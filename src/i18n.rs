@@ -0,0 +1,254 @@
+use std::env;
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static LOCALE: OnceCell<Locale> = OnceCell::new();
+
+/// Language of messages shown to users.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ja,
+}
+
+impl Locale {
+    /// Detects the locale by `LC_ALL`, `LC_MESSAGES` and `LANG` in the order, like `ja_JP.UTF-8`.
+    /// Unknown languages fall back to English.
+    pub fn detect<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+        let lang = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|k| var(k))
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        if lang.starts_with("ja") {
+            Self::Ja
+        } else {
+            Self::En
+        }
+    }
+}
+
+/// Sets the locale of messages once, before any message is shown.
+pub fn set_locale(locale: Locale) {
+    if LOCALE.set(locale).is_err() {
+        debug!("locale is already set");
+    }
+}
+
+/// Returns the locale set by `set_locale`, or the one of the environment.
+pub fn locale() -> Locale {
+    LOCALE
+        .get()
+        .copied()
+        .unwrap_or_else(|| Locale::detect(|k| env::var(k).ok()))
+}
+
+/// Keys of the message catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Message {
+    AWSRequestFailed,
+    AuthTimedOut,
+    TimeoutHint,
+    CannotReadCredentials,
+    CannotWriteCredentials,
+    CredentialsIsBroken,
+    CredentialsInvalid,
+    AuthHint,
+    LenientHint,
+    CannotReadConfig,
+    CannotWriteConfig,
+    ConfigIsBroken,
+    ContextLocked,
+    UnlockHint,
+    DefaultDrifted,
+    ClosestProfile,
+    RepairHint,
+    DefaultMismatch,
+    PickDefaultProfile,
+    FileChangedOnDisk,
+    ReloadHint,
+    InvalidConfigurations,
+    EditConfigsHint,
+    ExampleUsage,
+    InvalidInput,
+    NoActiveContext,
+    NoAuthConfiguration,
+    NoContextIsSelected,
+    Offline,
+    NoSuchProfile,
+    NoSuchRegion,
+    UnexpectedError,
+    NoBackups,
+    NoDeletedProfiles,
+    NoProblems,
+    NoMatchingProfiles,
+    NoManifestChanges,
+    NoSettingChanges,
+    NoUnusedProfiles,
+    NoAdoptableProfiles,
+    NoLockHeld,
+    EnvOverridesContext,
+    SwitchShadowed,
+}
+
+impl Message {
+    /// Returns the template of the message, whose `{}` are replaced by arguments in order.
+    fn template(self, locale: Locale) -> &'static str {
+        use Message::*;
+        match locale {
+            Locale::En => match self {
+                AWSRequestFailed => "failed to call AWS API: {}",
+                AuthTimedOut => "auth command of profile ({}) timed out after {} seconds",
+                TimeoutHint => "raise `timeout_seconds` of the profile in ~/.awsctx/configs.yaml if it needs more time",
+                CannotReadCredentials => "failed to read credentials, check your ~/.aws/credentials file",
+                CannotWriteCredentials => "failed to write credentials to ~/.aws/credentials file",
+                CredentialsIsBroken => "broken credentials, check your ~/.aws/credentials file",
+                CredentialsInvalid => "credentials of profile ({}) are rejected by AWS: {}",
+                AuthHint => "run `awsctx auth {}` to renew them",
+                LenientHint => "broken sections can be skipped by `--lenient` option",
+                CannotReadConfig => "failed to read config, check your ~/.aws/config file",
+                CannotWriteConfig => "failed to write config to ~/.aws/config file",
+                ConfigIsBroken => "broken config, check your ~/.aws/config file",
+                ContextLocked => "context ({}) is locked by {}",
+                UnlockHint => "wait for it to finish, or run `awsctx unlock` to release the lock",
+                DefaultDrifted => "the default profile was edited and matches no profile",
+                ClosestProfile => "the closest profile is ({})",
+                RepairHint => "run `awsctx repair` or `awsctx repair --profile <name>` to re-sync the default profile",
+                DefaultMismatch => "the default profile is ({}) in ~/.aws/credentials but ({}) in ~/.aws/config",
+                PickDefaultProfile => "Which default profile wins? (number, empty to leave them)",
+                FileChangedOnDisk => "{} was changed by another process after awsctx loaded it",
+                ReloadHint => "run the command again to reload it, or pass `--force` to overwrite it",
+                InvalidConfigurations => "invalid configurations: {}",
+                EditConfigsHint => "modify ~/.awsctx/configs.yaml manually and try again",
+                ExampleUsage => "Example Usage",
+                InvalidInput => "invalid input: {}",
+                NoActiveContext => "no active context",
+                NoAuthConfiguration => "no auth configuration found for the profile: {}",
+                NoContextIsSelected => "no context is selected",
+                Offline => "{} requires network access, which is disabled in offline mode",
+                NoSuchProfile => "no such profile: {}, check your ~/.aws/credentials file",
+                NoSuchRegion => "no such region: {}, run `awsctx regions --refresh` if it is a new region",
+                UnexpectedError => "unexpected error occurred, you can check detailed error by `verbose` option",
+                NoBackups => "no backups found, enable them by `backups.enabled` of configs",
+                NoDeletedProfiles => "no deleted profiles found",
+                NoProblems => "no problems found",
+                NoMatchingProfiles => "no profiles match the pattern",
+                NoManifestChanges => "no changes, the profiles match the manifest",
+                NoSettingChanges => "no changes, the profiles have the value already",
+                NoUnusedProfiles => "no unused profiles found",
+                NoAdoptableProfiles => "no SSO profiles to adopt found",
+                NoLockHeld => "no lock is held",
+                EnvOverridesContext => "{} overrides the active context in this shell, ({}) is in effect, unset it to use the active context",
+                SwitchShadowed => "the switch has no effect in this shell, which exports {}, run `unset {}` first",
+            },
+            Locale::Ja => match self {
+                AWSRequestFailed => "AWS API の呼び出しに失敗しました: {}",
+                AuthTimedOut => "プロファイル ({}) の認証コマンドが {} 秒でタイムアウトしました",
+                TimeoutHint => "時間が足りない場合は ~/.awsctx/configs.yaml でプロファイルの `timeout_seconds` を増やしてください",
+                CannotReadCredentials => "credentials を読み込めませんでした。~/.aws/credentials を確認してください",
+                CannotWriteCredentials => "~/.aws/credentials に credentials を書き込めませんでした",
+                CredentialsIsBroken => "credentials が壊れています。~/.aws/credentials を確認してください",
+                CredentialsInvalid => "プロファイル ({}) の credentials が AWS に拒否されました: {}",
+                AuthHint => "`awsctx auth {}` で更新してください",
+                LenientHint => "壊れたセクションは `--lenient` オプションで読み飛ばせます",
+                CannotReadConfig => "config を読み込めませんでした。~/.aws/config を確認してください",
+                CannotWriteConfig => "~/.aws/config に config を書き込めませんでした",
+                ConfigIsBroken => "config が壊れています。~/.aws/config を確認してください",
+                ContextLocked => "コンテキスト ({}) は {} によってロックされています",
+                UnlockHint => "終了を待つか、`awsctx unlock` でロックを解除してください",
+                DefaultDrifted => "default プロファイルが編集され、どのプロファイルとも一致しません",
+                ClosestProfile => "最も近いプロファイルは ({}) です",
+                RepairHint => "`awsctx repair` または `awsctx repair --profile <name>` で default プロファイルを同期し直してください",
+                DefaultMismatch => "default プロファイルが ~/.aws/credentials では ({}) ですが、~/.aws/config では ({}) です",
+                PickDefaultProfile => "どちらの default プロファイルに揃えますか? (番号、空欄でそのまま)",
+                FileChangedOnDisk => "{} は awsctx が読み込んだ後に別のプロセスによって変更されました",
+                ReloadHint => "コマンドを再実行して読み込み直すか、`--force` を指定して上書きしてください",
+                InvalidConfigurations => "設定が不正です: {}",
+                EditConfigsHint => "~/.awsctx/configs.yaml を修正してから再実行してください",
+                ExampleUsage => "設定例",
+                InvalidInput => "入力が不正です: {}",
+                NoActiveContext => "アクティブなコンテキストがありません",
+                NoAuthConfiguration => "プロファイルの認証設定が見つかりません: {}",
+                NoContextIsSelected => "コンテキストが選択されていません",
+                Offline => "{} にはネットワークアクセスが必要ですが、オフラインモードでは無効です",
+                NoSuchProfile => "プロファイルが存在しません: {}。~/.aws/credentials を確認してください",
+                NoSuchRegion => "リージョンが存在しません: {}。新しいリージョンの場合は `awsctx regions --refresh` を実行してください",
+                UnexpectedError => "予期しないエラーが発生しました。`verbose` オプションで詳細を確認できます",
+                NoBackups => "バックアップがありません。configs の `backups.enabled` で有効にできます",
+                NoDeletedProfiles => "削除されたプロファイルはありません",
+                NoProblems => "問題は見つかりませんでした",
+                NoMatchingProfiles => "パターンに一致するプロファイルはありません",
+                NoManifestChanges => "変更はありません。プロファイルはマニフェストと一致しています",
+                NoSettingChanges => "変更はありません。プロファイルには既にその値が設定されています",
+                NoUnusedProfiles => "使われていないプロファイルはありません",
+                NoAdoptableProfiles => "取り込む SSO プロファイルはありません",
+                NoLockHeld => "ロックはかかっていません",
+                EnvOverridesContext => "このシェルでは {} がアクティブなコンテキストを上書きしており、({}) が有効です。アクティブなコンテキストを使うには解除してください",
+                SwitchShadowed => "{} がエクスポートされているため、このシェルでは切り替えが反映されません。`unset {}` を実行してください",
+            },
+        }
+    }
+}
+
+/// Renders the message in the locale, replacing `{}` by `args` in order.
+pub fn render(message: Message, locale: Locale, args: &[&str]) -> String {
+    let mut args = args.iter();
+    let mut parts = message.template(locale).split("{}");
+    let mut rendered = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        rendered.push_str(args.next().copied().unwrap_or_default());
+        rendered.push_str(part);
+    }
+    rendered
+}
+
+/// Renders the message in the current locale.
+pub fn tr(message: Message, args: &[&str]) -> String {
+    render(message, locale(), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(vec![], Locale::En)]
+    #[case(vec![("LANG", "ja_JP.UTF-8")], Locale::Ja)]
+    #[case(vec![("LANG", "ja_JP.UTF-8"), ("LC_ALL", "C")], Locale::En)]
+    #[case(vec![("LANG", "en_US.UTF-8"), ("LC_MESSAGES", "ja_JP")], Locale::Ja)]
+    #[case(vec![("LC_ALL", ""), ("LANG", "ja")], Locale::Ja)]
+    fn test_locale_detect(
+        #[case] vars: Vec<(&str, &str)>,
+        #[case] expect: Locale,
+    ) {
+        let vars = vars.into_iter().collect::<HashMap<&str, &str>>();
+        assert_eq!(
+            expect,
+            Locale::detect(|k| vars.get(k).map(|v| v.to_string()))
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(Message::NoSuchProfile, Locale::En, vec!["foo"], "no such profile: foo, check your ~/.aws/credentials file")]
+    #[case(Message::NoSuchProfile, Locale::Ja, vec!["foo"], "プロファイルが存在しません: foo。~/.aws/credentials を確認してください")]
+    #[case(Message::NoActiveContext, Locale::Ja, vec![], "アクティブなコンテキストがありません")]
+    #[case(Message::InvalidInput, Locale::En, vec![], "invalid input: ")]
+    fn test_render(
+        #[case] message: Message,
+        #[case] locale: Locale,
+        #[case] args: Vec<&str>,
+        #[case] expect: &str,
+    ) {
+        assert_eq!(expect, render(message, locale, &args));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::awscli::AwsCli;
+use crate::ctx;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListAccountAliasesOutput {
+    account_aliases: Vec<String>,
+}
+
+/// Calls IAM ListAccountAliases with credentials of the profile.
+/// An account has at most one alias.
+pub fn get_account_alias(
+    cli: &AwsCli,
+    profile: &str,
+) -> Result<Option<String>, ctx::CTXError> {
+    cli.run::<ListAccountAliasesOutput>(&[
+        "iam",
+        "list-account-aliases",
+        "--profile",
+        profile,
+    ])
+    .map(|o| o.account_aliases.into_iter().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[rstest(::trace)]
+    #[case(r#"{"AccountAliases": ["example-prod"]}"#, Some("example-prod"))]
+    #[case(r#"{"AccountAliases": []}"#, None)]
+    fn test_get_account_alias(
+        #[case] output: &str,
+        #[case] expect: Option<&str>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(&tmpdir, &format!("echo '{}'", output));
+        let actual = get_account_alias(&cli, "foo").unwrap();
+        assert_eq!(expect, actual.as_deref());
+    }
+}
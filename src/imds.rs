@@ -0,0 +1,248 @@
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+
+use std::env;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use ureq::{Agent, AgentBuilder};
+
+const EC2_METADATA_ENDPOINT: &str = "http://169.254.169.254";
+const ECS_METADATA_ENDPOINT: &str = "http://169.254.170.2";
+const TOKEN_TTL_SECONDS: &str = "21600";
+
+/// Credentials served by the EC2 instance metadata service or the ECS task metadata endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MetadataCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: DateTime<Utc>,
+}
+
+impl From<MetadataCredentials> for TemporaryCredentials {
+    fn from(c: MetadataCredentials) -> Self {
+        Self {
+            access_key_id: c.access_key_id,
+            secret_access_key: c.secret_access_key,
+            session_token: c.token,
+            expiration: c.expiration,
+        }
+    }
+}
+
+/// Where credentials of the instance or task role are served.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MetadataSource {
+    /// EC2 instance metadata service (IMDSv2)
+    Instance { endpoint: String },
+    /// ECS task metadata endpoint
+    Container {
+        uri: String,
+        authorization: Option<String>,
+    },
+}
+
+impl MetadataSource {
+    /// Detects the metadata source from the environment the same way as AWS SDKs.
+    pub fn from_env() -> Self {
+        let authorization = env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN").ok();
+        if let Ok(uri) = env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+            return Self::Container {
+                uri: format!("{}{}", ECS_METADATA_ENDPOINT, uri),
+                authorization,
+            };
+        }
+        if let Ok(uri) = env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI") {
+            return Self::Container { uri, authorization };
+        }
+        Self::Instance {
+            endpoint: env::var("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+                .unwrap_or_else(|_| EC2_METADATA_ENDPOINT.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+        }
+    }
+
+    pub fn fetch_credentials(
+        &self,
+    ) -> Result<TemporaryCredentials, ctx::CTXError> {
+        // metadata endpoints are link-local, fail fast when they are unreachable
+        let agent = AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(1))
+            .timeout(Duration::from_secs(5))
+            .build();
+        match self {
+            Self::Instance { endpoint } => {
+                fetch_instance_credentials(&agent, endpoint)
+            }
+            Self::Container { uri, authorization } => {
+                let mut request = agent.get(uri);
+                if let Some(authorization) = authorization {
+                    request = request.set("Authorization", authorization);
+                }
+                request
+                    .call()
+                    .map_err(|e| request_failed("task metadata", e))?
+                    .into_json::<MetadataCredentials>()
+                    .map(Into::into)
+                    .map_err(|e| request_failed("task metadata", e))
+            }
+        }
+    }
+}
+
+fn request_failed<E>(target: &str, e: E) -> ctx::CTXError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    ctx::CTXError::AWSRequestFailed {
+        message: format!("failed to fetch credentials from {}", target),
+        source: Some(e.into()),
+    }
+}
+
+fn fetch_instance_credentials(
+    agent: &Agent,
+    endpoint: &str,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let target = "instance metadata";
+    let token = agent
+        .put(&format!("{}/latest/api/token", endpoint))
+        .set("X-aws-ec2-metadata-token-ttl-seconds", TOKEN_TTL_SECONDS)
+        .call()
+        .map_err(|e| request_failed(target, e))?
+        .into_string()
+        .map_err(|e| request_failed(target, e))?;
+    let base =
+        format!("{}/latest/meta-data/iam/security-credentials/", endpoint);
+    let roles = agent
+        .get(&base)
+        .set("X-aws-ec2-metadata-token", &token)
+        .call()
+        .map_err(|e| request_failed(target, e))?
+        .into_string()
+        .map_err(|e| request_failed(target, e))?;
+    let role =
+        roles
+            .lines()
+            .next()
+            .filter(|r| !r.is_empty())
+            .ok_or_else(|| ctx::CTXError::AWSRequestFailed {
+                message: "no instance role is attached to this instance"
+                    .to_string(),
+                source: Some(anyhow!("empty response from {}", base)),
+            })?;
+    agent
+        .get(&format!("{}{}", base, role))
+        .set("X-aws-ec2-metadata-token", &token)
+        .call()
+        .map_err(|e| request_failed(target, e))?
+        .into_json::<MetadataCredentials>()
+        .map(Into::into)
+        .map_err(|e| request_failed(target, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rstest::*;
+
+    use super::*;
+
+    /// Serves `responses` in order and returns the endpoint and received request lines.
+    fn serve(
+        responses: Vec<&'static str>,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for body in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim().to_string());
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (endpoint, handle)
+    }
+
+    #[fixture]
+    pub fn expect() -> TemporaryCredentials {
+        TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"
+                .to_string(),
+            expiration: DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+                .unwrap()
+                .into(),
+        }
+    }
+
+    const CREDENTIALS_RESPONSE: &str = r#"{
+  "Code" : "Success",
+  "Type" : "AWS-HMAC",
+  "AccessKeyId" : "XXXXXXXXXXX",
+  "SecretAccessKey" : "XXXXXXXXXXX",
+  "Token" : "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX",
+  "Expiration" : "2022-01-01T00:00:00Z"
+}"#;
+
+    #[rstest]
+    fn test_fetch_instance_credentials(expect: TemporaryCredentials) {
+        let (endpoint, handle) =
+            serve(vec!["token", "instance-role\n", CREDENTIALS_RESPONSE]);
+        let source = MetadataSource::Instance { endpoint };
+
+        let actual = source.fetch_credentials().unwrap();
+        assert_eq!(expect, actual);
+        assert_eq!(
+            vec![
+                "PUT /latest/api/token HTTP/1.1",
+                "GET /latest/meta-data/iam/security-credentials/ HTTP/1.1",
+                "GET /latest/meta-data/iam/security-credentials/instance-role HTTP/1.1",
+            ],
+            handle.join().unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_fetch_container_credentials(expect: TemporaryCredentials) {
+        let (endpoint, handle) = serve(vec![CREDENTIALS_RESPONSE]);
+        let source = MetadataSource::Container {
+            uri: format!("{}/v2/credentials/xxx", endpoint),
+            authorization: None,
+        };
+
+        let actual = source.fetch_credentials().unwrap();
+        assert_eq!(expect, actual);
+        assert_eq!(
+            vec!["GET /v2/credentials/xxx HTTP/1.1"],
+            handle.join().unwrap()
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::creds::{
+    parse_profiles, ACCESS_KEY_ID_KEY, DEFAULT_PROFILE_NAME, EXPIRATION_KEY,
+    SECRET_ACCESS_KEY_KEY, SESSION_TOKEN_KEY,
+};
+use crate::ctx;
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+
+const PROFILE_PREFIX: &str = "profile ";
+
+/// Name and items of a profile to import
+pub type ImportedProfile = (String, HashMap<String, String>);
+
+/// Credentials in the JSON of `credential_process`.
+/// Long-lived access keys come without a session token nor an expiration.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProcessCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expiration: Option<DateTime<Utc>>,
+}
+
+impl ProcessCredentials {
+    fn into_items(self) -> HashMap<String, String> {
+        let mut items = HashMap::from([
+            (ACCESS_KEY_ID_KEY.to_string(), self.access_key_id),
+            (SECRET_ACCESS_KEY_KEY.to_string(), self.secret_access_key),
+        ]);
+        if let Some(token) = self.session_token {
+            items.insert(SESSION_TOKEN_KEY.to_string(), token);
+        }
+        if let Some(expiration) = self.expiration {
+            items.insert(
+                EXPIRATION_KEY.to_string(),
+                expiration.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+        }
+        items
+    }
+}
+
+fn invalid(message: &str) -> ctx::CTXError {
+    ctx::CTXError::InvalidInput {
+        message: message.to_string(),
+        source: None,
+    }
+}
+
+/// Parses profiles to import, given as sections of credentials files or
+/// credentials in the JSON of `credential_process`.
+///
+/// `profile` names the profile of JSON, and renames the section if only one is given.
+pub fn parse(
+    contents: &str,
+    profile: Option<&str>,
+) -> Result<Vec<ImportedProfile>, ctx::CTXError> {
+    if contents.trim_start().starts_with('{') {
+        let profile = profile.ok_or_else(|| {
+            invalid("--profile is required to import credentials of JSON")
+        })?;
+        let credentials = serde_json::from_str::<ProcessCredentials>(contents)
+            .context("failed to parse credentials of JSON")
+            .map_err(|e| ctx::CTXError::InvalidInput {
+                message: "no credentials of credential_process found"
+                    .to_string(),
+                source: Some(e),
+            })?;
+        return Ok(vec![(profile.to_string(), credentials.into_items())]);
+    }
+
+    let mut profiles = parse_profiles(contents)?
+        .into_iter()
+        .map(|(name, items)| {
+            let name = name.strip_prefix(PROFILE_PREFIX).unwrap_or(&name);
+            (name.to_string(), items)
+        })
+        .collect::<Vec<ImportedProfile>>();
+    profiles.sort_by(|a, b| a.0.cmp(&b.0));
+    match (profile, profiles.len()) {
+        (_, 0) => return Err(invalid("no profiles found in the input")),
+        (Some(profile), 1) => profiles[0].0 = profile.to_string(),
+        (Some(_), _) => {
+            return Err(invalid(
+                "--profile is given but the input has multiple profiles",
+            ))
+        }
+        (None, _) => (),
+    }
+    // the default section is managed by awsctx as the active context
+    if profiles
+        .iter()
+        .any(|(name, _)| name == DEFAULT_PROFILE_NAME)
+    {
+        return Err(ctx::CTXError::InvalidInput {
+            message: "the default profile cannot be imported, give another name by --profile".to_string(),
+            source: Some(anyhow!("input has a section of {}", DEFAULT_PROFILE_NAME)),
+        });
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(
+        r#"{"Version": 1, "AccessKeyId": "XXXXXXXXXXX", "SecretAccessKey": "XXXXXXXXXXX", "SessionToken": "XXXXXXXXXXX", "Expiration": "2022-01-01T00:00:00+00:00"}"#,
+        Some("foo"),
+        vec![("foo", hashmap! {
+            "aws_access_key_id" => "XXXXXXXXXXX",
+            "aws_secret_access_key" => "XXXXXXXXXXX",
+            "aws_session_token" => "XXXXXXXXXXX",
+            "x_security_token_expires" => "2022-01-01T00:00:00Z",
+        })]
+    )]
+    #[case(
+        r#"{"Version": 1, "AccessKeyId": "XXXXXXXXXXX", "SecretAccessKey": "XXXXXXXXXXX"}"#,
+        Some("foo"),
+        vec![("foo", hashmap! {
+            "aws_access_key_id" => "XXXXXXXXXXX",
+            "aws_secret_access_key" => "XXXXXXXXXXX",
+        })]
+    )]
+    #[case(
+        "[profile bar]\naws_access_key_id = YYYYYYYYYYY\n\n[baz]\naws_access_key_id = ZZZZZZZZZZZ\n",
+        None,
+        vec![
+            ("bar", hashmap! {"aws_access_key_id" => "YYYYYYYYYYY"}),
+            ("baz", hashmap! {"aws_access_key_id" => "ZZZZZZZZZZZ"}),
+        ]
+    )]
+    #[case(
+        "[default]\naws_access_key_id = YYYYYYYYYYY\n",
+        Some("foo"),
+        vec![("foo", hashmap! {"aws_access_key_id" => "YYYYYYYYYYY"})]
+    )]
+    fn test_parse(
+        #[case] contents: &str,
+        #[case] profile: Option<&str>,
+        #[case] expect: Vec<(&str, HashMap<&str, &str>)>,
+    ) {
+        let expect = expect
+            .into_iter()
+            .map(|(name, items)| {
+                (
+                    name.to_string(),
+                    items
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                )
+            })
+            .collect::<Vec<ImportedProfile>>();
+        assert_eq!(expect, parse(contents, profile).unwrap());
+    }
+
+    #[rstest(::trace)]
+    #[case(r#"{"AccessKeyId": "XXXXXXXXXXX"}"#, Some("foo"))]
+    #[case(
+        r#"{"AccessKeyId": "XXXXXXXXXXX", "SecretAccessKey": "XXXXXXXXXXX"}"#,
+        None
+    )]
+    #[case("", None)]
+    #[case("[default]\naws_access_key_id = YYYYYYYYYYY\n", None)]
+    #[case("[bar]\na = b\n\n[baz]\na = b\n", Some("foo"))]
+    fn test_parse_invalid(
+        #[case] contents: &str,
+        #[case] profile: Option<&str>,
+    ) {
+        assert!(matches!(
+            parse(contents, profile),
+            Err(ctx::CTXError::InvalidInput { .. })
+        ));
+    }
+}
@@ -1,9 +1,57 @@
 pub mod aws;
+pub mod awscli;
+pub mod backup;
+pub mod cache;
+pub mod cleanup;
 pub mod config;
 pub mod configs;
+pub mod confirm;
+pub mod console;
+pub mod costs;
 pub mod creds;
 pub mod ctx;
+pub mod daemon;
+pub mod diff;
+pub mod effective;
+pub mod env;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod file;
+pub mod filter;
+pub mod i18n;
+pub mod iam;
+pub mod imds;
+pub mod import;
+pub mod lint;
+pub mod lock;
+pub mod manifest;
+pub mod metrics;
+pub mod mfa;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod onboard;
+pub mod organizations;
+pub mod picker;
+pub mod prompt;
+pub mod region;
+pub mod rename;
+pub mod saml;
+pub mod setting;
+pub mod shell;
+pub mod ssm;
+pub mod sso;
+pub mod sso_cache;
+pub mod state;
+pub mod statusbar;
+pub mod storage;
+pub mod sts;
+pub mod template;
+pub mod trash;
+pub mod tree;
+pub mod update;
+pub mod validate;
 pub mod view;
+pub mod webhook;
 
 #[macro_use]
-extern crate simplelog;
+extern crate log;
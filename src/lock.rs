@@ -0,0 +1,302 @@
+use crate::ctx;
+use crate::file;
+use crate::state;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Lock of the active context, refusing switches to other contexts until it is released.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextLock {
+    /// Context kept active while locked
+    pub context: String,
+    /// Command line of the holder, like `awsctx exec --lock -- make deploy`
+    pub command: String,
+    pub pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub locked_at: DateTime<Utc>,
+    /// Whether the lock lives only as long as the process of `pid`, as by `exec --lock`.
+    /// Otherwise it is held until `unlock`.
+    #[serde(default)]
+    pub transient: bool,
+}
+
+impl ContextLock {
+    /// Creates a lock of the context held by the current process.
+    pub fn new(context: &str, reason: Option<String>, transient: bool) -> Self {
+        Self {
+            context: context.to_string(),
+            command: env::args().collect::<Vec<String>>().join(" "),
+            pid: process::id(),
+            user: env::var("USER").ok().filter(|u| !u.is_empty()),
+            reason,
+            locked_at: Utc::now(),
+            transient,
+        }
+    }
+
+    /// Describes who holds the lock, shown when a switch is refused.
+    pub fn holder(&self) -> String {
+        let mut holder = format!("`{}` (pid {}", self.command, self.pid);
+        if let Some(user) = &self.user {
+            holder.push_str(&format!(", {}", user));
+        }
+        holder.push_str(&format!(
+            ") since {}",
+            self.locked_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+        if let Some(reason) = &self.reason {
+            holder.push_str(&format!(": {}", reason));
+        }
+        holder
+    }
+
+    fn is_stale<F: Fn(u32) -> bool>(&self, is_alive: F) -> bool {
+        self.transient && !is_alive(self.pid)
+    }
+}
+
+/// Max number of attempts to replace stale locks, which other processes may replace at the same time
+const MAX_ACQUIRE_ATTEMPTS: usize = 3;
+
+/// Returns whether the process is running, by the signal 0 not to depend on `/proc`.
+fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // 0 would signal the process group of the caller
+    if pid == 0 {
+        return false;
+    }
+    // SAFETY: the signal 0 only checks whether the process exists
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // the process exists but is owned by another user
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Loads the lock held now. Transient locks left by processes which died are ignored.
+pub fn load(path: &Path) -> Option<ContextLock> {
+    state::load::<ContextLock>(path).filter(|l| !l.is_stale(is_alive))
+}
+
+/// Places the lock, failing fast if another one is held.
+///
+/// The lock file is created only if it does not exist, so that one of racing processes wins.
+/// Existing files are replaced only if they are stale or broken.
+pub fn acquire(path: &Path, lock: &ContextLock) -> Result<(), ctx::CTXError> {
+    for _ in 0..MAX_ACQUIRE_ATTEMPTS {
+        if state::save_new(path, lock)? {
+            return Ok(());
+        }
+        let fingerprint = file::Fingerprint::of(path);
+        if let Some(held) = load(path) {
+            return Err(locked(&held));
+        }
+        // not to remove a lock placed by another process after the stale one was loaded
+        if file::Fingerprint::ensure_unchanged(fingerprint.as_ref(), path)
+            .is_err()
+        {
+            continue;
+        }
+        match fs::remove_file(path) {
+            Ok(_) => {}
+            // released by another process meanwhile
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(ctx::CTXError::UnexpectedError {
+                    source: Some(anyhow::Error::from(e).context(format!(
+                        "failed to remove {}",
+                        path.to_str().unwrap()
+                    ))),
+                })
+            }
+        }
+    }
+    Err(load(path).map_or_else(
+        || ctx::CTXError::UnexpectedError {
+            source: Some(anyhow!(
+                "failed to place {}, replaced by other processes",
+                path.to_str().unwrap()
+            )),
+        },
+        |held| locked(&held),
+    ))
+}
+
+/// Removes the lock. With `pid`, only the lock held by the process is removed,
+/// not to release one placed by another process after it.
+/// Returns the removed lock.
+pub fn release(
+    path: &Path,
+    pid: Option<u32>,
+) -> Result<Option<ContextLock>, ctx::CTXError> {
+    let held = match state::load::<ContextLock>(path) {
+        Some(held) if pid.is_none_or(|pid| held.pid == pid) => held,
+        _ => return Ok(None),
+    };
+    match fs::remove_file(path) {
+        Ok(_) => Ok(Some(held)),
+        // released by another process meanwhile
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ctx::CTXError::UnexpectedError {
+            source: Some(anyhow::Error::from(e).context(format!(
+                "failed to remove {}",
+                path.to_str().unwrap()
+            ))),
+        }),
+    }
+}
+
+/// Fails if a lock of another context is held, before switching to `name`.
+pub fn ensure_switchable(path: &Path, name: &str) -> Result<(), ctx::CTXError> {
+    match load(path) {
+        Some(held) if held.context != name => Err(locked(&held)),
+        _ => Ok(()),
+    }
+}
+
+fn locked(held: &ContextLock) -> ctx::CTXError {
+    ctx::CTXError::ContextLocked {
+        context: held.context.clone(),
+        holder: held.holder(),
+        source: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn lock_of(context: &str, pid: u32, transient: bool) -> ContextLock {
+        ContextLock {
+            pid,
+            ..ContextLock::new(context, Some("deploy".to_string()), transient)
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case(false, false, false)]
+    #[case(true, true, false)]
+    #[case(true, false, true)]
+    fn test_context_lock_is_stale(
+        #[case] transient: bool,
+        #[case] alive: bool,
+        #[case] expect: bool,
+    ) {
+        let lock = lock_of("foo", 1, transient);
+        assert_eq!(expect, lock.is_stale(|_| alive));
+    }
+
+    #[rstest]
+    fn test_context_lock_holder() {
+        let lock = ContextLock {
+            command: "awsctx lock".to_string(),
+            pid: 42,
+            user: Some("alice".to_string()),
+            locked_at: "2022-01-02T03:04:05Z".parse().unwrap(),
+            ..lock_of("foo", 42, false)
+        };
+        assert_eq!(
+            "`awsctx lock` (pid 42, alice) since 2022-01-02T03:04:05Z: deploy",
+            lock.holder()
+        );
+    }
+
+    #[rstest]
+    fn test_acquire_and_release() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state/lock.json");
+        let lock = lock_of("foo", process::id(), true);
+
+        acquire(&path, &lock).unwrap();
+        assert_eq!(Some(lock.clone()), load(&path));
+        assert!(acquire(&path, &lock_of("bar", process::id(), false)).is_err());
+        assert!(ensure_switchable(&path, "foo").is_ok());
+        assert!(ensure_switchable(&path, "bar").is_err());
+
+        // locks of other processes are kept
+        assert_eq!(None, release(&path, Some(process::id() + 1)).unwrap());
+        assert_eq!(Some(lock), release(&path, Some(process::id())).unwrap());
+        assert_eq!(None, load(&path));
+        assert!(ensure_switchable(&path, "bar").is_ok());
+        assert_eq!(None, release(&path, None).unwrap());
+    }
+
+    #[rstest]
+    fn test_load_ignores_stale_lock() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("lock.json");
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        state::save(&path, &lock_of("foo", child.id(), true)).unwrap();
+        assert_eq!(None, load(&path));
+        assert!(acquire(&path, &lock_of("bar", process::id(), true)).is_ok());
+    }
+
+    #[rstest]
+    fn test_is_alive() {
+        assert!(is_alive(process::id()));
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        assert!(!is_alive(child.id()));
+        assert!(!is_alive(0));
+        assert!(!is_alive(u32::MAX));
+    }
+
+    #[rstest]
+    fn test_acquire_replaces_broken_lock() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("lock.json");
+        fs::write(&path, "{").unwrap();
+        let lock = lock_of("foo", process::id(), false);
+        acquire(&path, &lock).unwrap();
+        assert_eq!(Some(lock), load(&path));
+    }
+
+    #[rstest]
+    fn test_acquire_races() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state/lock.json");
+        let acquired = std::thread::scope(|s| {
+            (0..8)
+                .map(|i| {
+                    let path = &path;
+                    s.spawn(move || {
+                        acquire(
+                            path,
+                            &lock_of(
+                                &format!("ctx{}", i),
+                                process::id(),
+                                false,
+                            ),
+                        )
+                        .is_ok()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|acquired| *acquired)
+                .count()
+        });
+        assert_eq!(1, acquired);
+        assert_eq!(1, fs::read_dir(path.parent().unwrap()).unwrap().count());
+    }
+}
@@ -1,16 +1,78 @@
-use std::{io, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    env, fs,
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+    rc::Rc,
+    thread,
+};
 
 use awsctx::{
     aws::{AWS, CONFIG_PATH, CREDENTIALS_PATH},
-    configs::Configs,
-    ctx::{CTXError, CTX},
-    view::{fatal_ctxerr, show_context, show_contexts},
+    awscli::{run_concurrently, AwsCli},
+    backup,
+    cleanup::find_unused,
+    config::Config,
+    configs::{Configs, WebhookEvent, CONFIGS_PATH},
+    confirm::Confirmation,
+    console::{self, SessionCredentials},
+    costs::{month_to_date_costs, CostsCache},
+    creds::{Credentials, CredsFormat},
+    ctx::{self, CTXError, Context, PickerItem, CTX},
+    daemon,
+    effective::{shadowing_variables, EffectiveContext, Override},
+    env::{
+        append as append_env, github_masks, render as render_env,
+        write as write_env, EnvFormat,
+    },
+    filter::Filter,
+    i18n,
+    iam::get_account_alias,
+    imds::MetadataSource,
+    import::parse as parse_import,
+    lint::lint,
+    lock::{self, ContextLock},
+    manifest::{self, Change, Manifest},
+    metrics::{self, Counters},
+    mfa::MfaCode,
+    onboard::{detect_profiles, run as run_onboarding, ProfileKind},
+    organizations::{generate_profiles, list_accounts},
+    picker::{match_name, pick_by_command, pick_by_line, pick_many, NameMatch},
+    prompt::{render as render_prompt, PromptCache},
+    region::Regions,
+    rename, setting,
+    shell::{render_init as render_shell_init, ShellKind},
+    ssm,
+    state::{
+        AccountAliases, History, BACKUPS_DIR, CREDENTIALS_CACHE_PATH,
+        DAEMON_SOCKET_PATH, LOCK_PATH,
+    },
+    statusbar::{render as render_status, StatusFormat},
+    sts::{resolve_identities, IdentitiesCache},
+    trash,
+    tree::RoleTree,
+    update::{self, UpdateCheck},
+    validate::{validate_profiles, CredentialsStatus},
+    view::{
+        fatal_ctxerr, plain_output, set_plain_output, show_adoptable_profiles,
+        show_auth_results, show_backups, show_context, show_context_json,
+        show_contexts_alfred, show_contexts_csv, show_contexts_json,
+        show_contexts_jsonl, show_contexts_lines, show_contexts_table,
+        show_costs, show_deleted_profiles, show_effective_context,
+        show_findings, show_manifest_changes, show_regions, show_renames,
+        show_settings, show_tree, show_unused_profiles, show_validations,
+        supports_hyperlinks, unused_profile_lines,
+    },
+    webhook,
 };
 
-use clap::{IntoApp, Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{ArgEnum, ArgGroup, IntoApp, Parser, Subcommand};
 use clap_complete::{generate, Generator, Shell};
+use paris::formatter::colorize_string;
+use serde_json::json;
 use simplelog as sl;
-use skim::prelude::SkimOptionsBuilder;
+use skim::prelude::{SkimOptions, SkimOptionsBuilder};
 
 #[derive(Parser)]
 #[clap(
@@ -25,45 +87,653 @@ struct Cli {
     /// Enable verbose output
     #[clap(long, short = 'v', parse(from_occurrences), global = true)]
     verbose: i8,
+    /// Disable all network access
+    #[clap(long, global = true)]
+    offline: bool,
+    /// Overwrite ~/.aws files even if another process changed them
+    #[clap(long, global = true)]
+    force: bool,
+    /// Show diffs of ~/.aws files with secrets masked, and confirm them before writing
+    #[clap(long, global = true)]
+    show_diff: bool,
+    /// Skip broken sections of ~/.aws/credentials instead of failing
+    #[clap(long, global = true)]
+    lenient: bool,
+    /// Code of the MFA device for profiles with `mfa_serial`, which is asked on the terminal otherwise
+    #[clap(long, global = true, value_name = "CODE")]
+    mfa_code: Option<String>,
+    /// Run non-interactively for pipelines, with JSON output on stdout and logs on stderr
+    #[clap(long, global = true, env = "AWSCTX_CI")]
+    ci: bool,
+    /// Output for screen readers, without colors and symbols, and pick by lines instead of the finder
+    #[clap(long, global = true, env = "AWSCTX_PLAIN")]
+    plain: bool,
+    /// Skip confirmations of destructive commands like `delete`, and of profiles matched by names
+    #[clap(long, short = 'y', global = true)]
+    yes: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Opts {
     /// Show active context in the credentials.
+    ///
+    /// Variables like `AWS_PROFILE` and `AWS_ACCESS_KEY_ID` overriding it in the shell are warned.
+    #[clap(arg_required_else_help = false, alias = "current")]
+    ActiveContext {
+        #[clap(
+            long,
+            value_name = "NAME",
+            help = "exit with 0 if the active context is NAME, otherwise 1, without output unless -v"
+        )]
+        check: Option<String>,
+        #[clap(
+            long,
+            help = "show the context in effect under variables of the environment instead"
+        )]
+        effective: bool,
+    },
+    /// Adopt SSO profiles created by `aws configure sso`, registering their auth commands.
+    ///
+    /// Adopted profiles are listed as contexts, and `auth` signs in by `aws sso login`.
     #[clap(arg_required_else_help = false)]
-    ActiveContext {},
+    Adopt {
+        #[clap(long, help = "list the profiles without adopting them")]
+        dry_run: bool,
+    },
+    /// Assume a role with credentials of the active context, then make it active as an ad-hoc context.
+    ///
+    /// The ad-hoc context is named like `AdminRole@123456789012` unless `--name` is given,
+    /// and removed once its credentials expire.
+    /// With `--profile`, roles of the profile and its `source_profile` chain are assumed in turn instead.
+    #[clap(arg_required_else_help = true)]
+    Assume {
+        #[clap(
+            value_name = "ROLE_ARN",
+            help = "ARN of the role to assume",
+            required_unless_present = "profile",
+            conflicts_with = "profile"
+        )]
+        role_arn: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "profile with role_arn and source_profile to assume",
+            conflicts_with_all = &["name", "duration"]
+        )]
+        profile: Option<String>,
+        #[clap(long, short, help = "name of the ad-hoc context")]
+        name: Option<String>,
+        #[clap(
+            long,
+            short,
+            value_name = "SECONDS",
+            help = "duration of the session, 1 hour by default"
+        )]
+        duration: Option<i64>,
+    },
     /// Auth awscli with the specified profile by pre-defined scripts, then make it active.
     ///
     /// This function requires the configuration set up for the specified profile before use.
+    /// With `--all`, every context matching `--filter` is authed in turn and the active one is kept.
     #[clap(arg_required_else_help = true)]
     Auth {
+        #[clap(
+            long,
+            short,
+            env = "AWSCTX_PROFILE",
+            help = "profile name",
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "auth all the contexts with auth configurations"
+        )]
+        all: bool,
+        #[clap(
+            long,
+            requires = "all",
+            value_name = "EXPR",
+            help = "auth only contexts matching the filter, like `tag=prod && expired`"
+        )]
+        filter: Option<String>,
+        #[clap(
+            long,
+            short,
+            requires = "all",
+            default_value_t = 4,
+            help = "number of auth commands run concurrently, 1 to leave them on the terminal"
+        )]
+        jobs: usize,
+    },
+    /// Manage backups of ~/.aws files taken before they are overwritten.
+    Backups {
+        #[clap(subcommand)]
+        opts: BackupsOpts,
+    },
+    /// Archive profiles not used for days, selected interactively, with their entries of configs.
+    ///
+    /// Archived profiles can be brought back by `restore`.
+    #[clap(arg_required_else_help = false)]
+    Cleanup {
+        #[clap(
+            long,
+            default_value = "90",
+            help = "days since the profiles were used last time"
+        )]
+        days: i64,
+        #[clap(
+            long,
+            help = "archive all the unused profiles without selecting them"
+        )]
+        all: bool,
+        #[clap(long, help = "list the unused profiles without archiving them")]
+        dry_run: bool,
+    },
+    /// Show month-to-date spend of the active context or all the contexts by Cost Explorer.
+    ///
+    /// Costs are cached for hours since every request of Cost Explorer is charged.
+    #[clap(arg_required_else_help = false)]
+    Costs {
+        #[clap(long, short, help = "profile name", conflicts_with = "all")]
+        profile: Option<String>,
+        #[clap(long, short, help = "show costs of all the contexts")]
+        all: bool,
+        #[clap(long, help = "fetch costs even if they are cached")]
+        refresh: bool,
+        #[clap(
+            long,
+            short,
+            default_value_t = 8,
+            help = "number of contexts fetched concurrently"
+        )]
+        jobs: usize,
+    },
+    /// Print credentials of the active context or a profile, without writing ~/.aws files.
+    ///
+    /// Reference it from ~/.aws/config by
+    /// `credential_process = awsctx creds --profile foo --format process`.
+    #[clap(arg_required_else_help = false)]
+    Creds {
+        #[clap(
+            long,
+            short,
+            help = "profile name, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(long, short, arg_enum, default_value = "process")]
+        format: CredsFormat,
+    },
+    /// Serve the status of awsctx as JSON at `/status` of a localhost HTTP endpoint.
+    ///
+    /// Editor plugins and status bars can query it without spawning processes,
+    /// and Prometheus can scrape metrics of expiry, switches and refreshes at `/metrics`.
+    /// Contexts are listed and switched by lines like `switch foo` on the control socket.
+    #[clap(arg_required_else_help = false)]
+    Daemon {
+        #[clap(
+            long,
+            value_name = "ADDRESS",
+            help = "loopback address to listen on, `daemon.listen` of configs by default"
+        )]
+        listen: Option<String>,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "path of the control socket, `daemon.socket` of configs by default"
+        )]
+        socket: Option<PathBuf>,
+    },
+    /// Generate profiles assuming a role in accounts of AWS Organizations, or of an OU.
+    ///
+    /// Accounts are listed with credentials of the management account, and profiles are named
+    /// by the template of `discovery` configurations. Existing profiles are never overwritten.
+    #[clap(arg_required_else_help = false)]
+    Discover {
+        #[clap(
+            long,
+            value_name = "OU_ID",
+            help = "OU or root whose direct accounts are discovered, all the accounts by default"
+        )]
+        ou: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "source profile of the management account, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            value_name = "TEMPLATE",
+            help = "template of profile names like `{{account_name}}-admin`, `discovery.name` by default"
+        )]
+        name: Option<String>,
+        #[clap(
+            long,
+            value_name = "ROLE_NAME",
+            help = "role assumed in the accounts, `discovery.role_name` by default"
+        )]
+        role: Option<String>,
+        #[clap(
+            long,
+            help = "region of the profiles, `discovery.region` by default"
+        )]
+        region: Option<String>,
+        #[clap(long, help = "preview the profiles without writing them")]
+        dry_run: bool,
+    },
+    /// Delete a profile from ~/.aws files, which can be brought back by `restore`.
+    #[clap(arg_required_else_help = true)]
+    Delete {
+        #[clap(long, short, help = "profile name")]
+        profile: String,
+    },
+    /// Print environment variables of credentials of the active context or a profile.
+    #[clap(arg_required_else_help = false)]
+    Env {
+        #[clap(
+            long,
+            short,
+            help = "profile name, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(long, short, arg_enum, default_value = "shell")]
+        format: EnvFormat,
+        #[clap(long, short, help = "write to the file instead of stdout")]
+        output: Option<PathBuf>,
+        #[clap(
+            long,
+            short,
+            help = "region of AWS_REGION and AWS_DEFAULT_REGION, the one of the profile by default"
+        )]
+        region: Option<String>,
+    },
+    /// Run a command with credentials of the active context or a profile.
+    ///
+    /// With `--lock`, the active context is locked until the command exits,
+    /// so that other invocations of awsctx cannot switch it during deployments.
+    #[clap(arg_required_else_help = true)]
+    Exec {
+        #[clap(
+            long,
+            short,
+            help = "profile name, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "region of AWS_REGION and AWS_DEFAULT_REGION, the one of the profile by default"
+        )]
+        region: Option<String>,
+        #[clap(long, help = "lock the active context until the command exits")]
+        lock: bool,
+        #[clap(
+            long,
+            requires = "lock",
+            help = "reason of the lock, shown to invocations refused by it"
+        )]
+        reason: Option<String>,
+        #[clap(
+            last = true,
+            required = true,
+            value_name = "COMMAND",
+            help = "command and its arguments"
+        )]
+        command: Vec<String>,
+    },
+    /// Import profiles of credentials files or credentials of JSON from a file or stdin.
+    ///
+    /// JSON is read in the format of `credential_process`, like `aws configure export-credentials`.
+    #[clap(arg_required_else_help = true)]
+    Import {
+        #[clap(value_name = "FILE", help = "file to import, `-` for stdin")]
+        source: PathBuf,
+        #[clap(
+            long,
+            short,
+            help = "profile name of JSON, or a new name of a single profile"
+        )]
+        profile: Option<String>,
+    },
+    /// Import credentials of the instance or task role from the metadata endpoint as a profile.
+    #[clap(arg_required_else_help = true)]
+    ImportImds {
         #[clap(long, short, help = "profile name")]
         profile: String,
     },
+    /// Check the credentials for mistakes like access keys shared by profiles.
+    #[clap(arg_required_else_help = false)]
+    Lint {},
     /// List all the contexts in the credentials.
     #[clap(arg_required_else_help = false)]
-    ListContexts {},
+    ListContexts {
+        #[clap(
+            long,
+            short,
+            arg_enum,
+            help = "text by default, json in CI mode"
+        )]
+        output: Option<OutputFormat>,
+        #[clap(
+            long,
+            arg_enum,
+            use_value_delimiter = true,
+            help = "columns of text or csv output, configured ones for text and all for csv by default"
+        )]
+        columns: Vec<ctx::Column>,
+        #[clap(
+            long,
+            help = "resolve identities of the contexts by STS and show them next to names"
+        )]
+        details: bool,
+        #[clap(
+            long,
+            short,
+            default_value_t = 8,
+            help = "number of identities resolved concurrently"
+        )]
+        jobs: usize,
+        #[clap(
+            long,
+            value_name = "EXPR",
+            help = "list only contexts matching the filter, like `tag=prod && expired`"
+        )]
+        filter: Option<String>,
+    },
+    /// Lock the active context, refusing switches to other contexts until `unlock`.
+    #[clap(arg_required_else_help = false)]
+    Lock {
+        #[clap(
+            long,
+            short,
+            help = "reason of the lock, shown to invocations refused by it"
+        )]
+        reason: Option<String>,
+    },
+    /// Manage profiles declaratively by a manifest of YAML.
+    Manifest {
+        #[clap(subcommand)]
+        opts: ManifestOpts,
+    },
+    /// Open the AWS console in the account and region of the active context or a profile.
+    ///
+    /// Temporary credentials are exchanged for a sign-in of the console, which requires `curl`.
+    #[clap(arg_required_else_help = false)]
+    Open {
+        #[clap(
+            help = "service of the console like s3 or ec2, the home by default"
+        )]
+        service: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "profile name, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(long, help = "print the URL instead of opening a browser")]
+        print: bool,
+    },
+    /// Manage keys of profiles in ~/.aws/config.
+    Profile {
+        #[clap(subcommand)]
+        opts: ProfileOpts,
+    },
+    /// List AWS regions, which are bundled or fetched from AWS by `--refresh`.
+    #[clap(arg_required_else_help = false)]
+    Regions {
+        #[clap(long, help = "fetch the latest regions from AWS")]
+        refresh: bool,
+    },
+    /// Show which profiles assume roles with credentials of which profiles by `source_profile`.
+    #[clap(arg_required_else_help = false)]
+    Tree {},
     /// Auth awscli for the active profile by pre-defined scripts
     ///
     /// This function requires the configuration set up for the specified profile before use.
     #[clap(arg_required_else_help = false)]
     Refresh {},
-    /// Updates a default profile by a profile name.
+    /// Rename profiles whose names match a pattern in both ~/.aws files, fixing `source_profile`.
+    ///
+    /// Renames are previewed, then applied once confirmed.
     #[clap(arg_required_else_help = true)]
-    UseContext {
+    Rename {
+        #[clap(
+            long,
+            value_name = "REGEX",
+            help = "regular expression matching whole names, like `old-(.*)`"
+        )]
+        pattern: String,
+        #[clap(
+            long,
+            value_name = "NAME",
+            help = "new name with groups of the pattern, like `client-$1`"
+        )]
+        to: String,
+        #[clap(long, help = "preview the renames without applying them")]
+        dry_run: bool,
+    },
+    /// Re-sync the default profile edited by hand to the closest profile.
+    #[clap(arg_required_else_help = false)]
+    Repair {
+        #[clap(
+            long,
+            short,
+            help = "profile to re-sync to instead of the closest one"
+        )]
+        profile: Option<String>,
+    },
+    /// Restore a profile deleted by `delete`, or list deleted profiles if no profile is given.
+    Restore {
         #[clap(long, short, help = "profile name")]
-        profile: String,
+        profile: Option<String>,
+    },
+    /// Start a session of SSM Session Manager to an instance with credentials of the active context or a profile.
+    ///
+    /// This requires the Session Manager plugin of the AWS CLI.
+    #[clap(arg_required_else_help = true)]
+    Ssm {
+        #[clap(
+            value_name = "INSTANCE_ID",
+            help = "target instance like i-0123456789abcdef0"
+        )]
+        target: String,
+        #[clap(
+            long,
+            short,
+            help = "profile name, the active context by default"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            short,
+            help = "region of the instance, the one of the profile by default"
+        )]
+        region: Option<String>,
+        #[clap(
+            last = true,
+            value_name = "ARGS",
+            help = "arguments passed to `aws ssm start-session`, like `--document-name`"
+        )]
+        args: Vec<String>,
+    },
+    /// Print the active context for status bars like waybar, i3blocks and xbar.
+    ///
+    /// The class or the color is chosen by remaining time of the session,
+    /// by thresholds of `prompt` configurations.
+    #[clap(arg_required_else_help = false)]
+    Status {
+        #[clap(long, short, arg_enum, default_value = "waybar")]
+        format: StatusFormat,
     },
+    /// Updates a default profile by a profile name.
+    ///
+    /// With `--region`, the region in the default section is switched together.
+    /// Either of them is picked by the interactive finder if no value is given.
+    /// Names not given exactly switch to the only profile matched by the prefix or fuzzily.
+    #[clap(arg_required_else_help = true, alias = "use")]
+    UseContext {
+        #[clap(
+            value_name = "PROFILE",
+            help = "profile name, preferred to `--profile`"
+        )]
+        name: Option<String>,
+        #[clap(
+            long,
+            short,
+            env = "AWSCTX_PROFILE",
+            help = "profile name, picked by the finder if omitted"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            short,
+            value_name = "REGION",
+            help = "override the region of the profile, picked by the finder if no value is given"
+        )]
+        region: Option<Option<String>>,
+    },
+    /// Release the lock of the active context placed by `lock` or `exec --lock`.
+    #[clap(arg_required_else_help = false)]
+    Unlock {},
     /// Update a default profile by interactive finder.
     #[clap(skip = true)]
     UseContextByInteractiveFinder {},
-    /// Generate completion script.
+    /// Print the active context for shell prompts, formatted by `prompt` configurations.
+    #[clap(arg_required_else_help = false)]
+    Prompt {
+        #[clap(
+            long,
+            help = "disable colors for remaining time of the session"
+        )]
+        no_color: bool,
+    },
+    /// Validate credentials of the active context or profiles by STS.
+    #[clap(arg_required_else_help = false)]
+    Validate {
+        #[clap(long, short, help = "profile name", conflicts_with = "all")]
+        profile: Option<String>,
+        #[clap(long, short, help = "validate all the profiles")]
+        all: bool,
+        #[clap(
+            long,
+            requires = "all",
+            value_name = "EXPR",
+            help = "validate only profiles matching the filter, like `tag=prod && !missing`"
+        )]
+        filter: Option<String>,
+        #[clap(
+            long,
+            short,
+            default_value_t = 8,
+            help = "number of profiles validated concurrently"
+        )]
+        jobs: usize,
+    },
+    /// Generate completion script of bash, elvish, fish, powershell or zsh.
     Completion {
-        #[clap(long, short, arg_enum)]
-        shell: Shell,
+        #[clap(
+            arg_enum,
+            required_unless_present = "shell-option",
+            conflicts_with = "shell-option"
+        )]
+        shell: Option<Shell>,
+        // `--shell` is kept for scripts written for older versions
+        #[clap(long = "shell", short = 's', arg_enum, hide = true)]
+        shell_option: Option<Shell>,
+    },
+    /// Generate shell integration of bash, zsh or fish, evaluated in rc files like `eval "$(awsctx shell-init bash)"`.
+    ///
+    /// It defines `awsctx-env` and `awsctx-exec`, switches to the profile of `.awsctx-profile`
+    /// on entering directories, and keeps the active context in `AWSCTX_PROMPT` for prompts.
+    #[clap(arg_required_else_help = true)]
+    ShellInit {
+        #[clap(arg_enum)]
+        shell: ShellKind,
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum BackupsOpts {
+    /// List backups, the newest first.
+    List {},
+}
+
+#[derive(Subcommand, Debug)]
+enum ManifestOpts {
+    /// Reconcile ~/.aws files and configurations with the manifest, reporting the changes.
+    ///
+    /// Profiles applied before but no longer in the manifest are removed, keeping them in the trash.
+    #[clap(arg_required_else_help = true)]
+    Apply {
+        #[clap(value_name = "FILE", help = "manifest of YAML")]
+        path: PathBuf,
+        #[clap(long, help = "preview the changes without applying them")]
+        dry_run: bool,
+    },
+    /// Print a manifest of the profiles in ~/.aws files and configurations, without secrets.
+    #[clap(arg_required_else_help = false)]
+    Export {},
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileOpts {
+    /// Set a key of profiles in ~/.aws/config, like `output json`, or `cli_pager ""` to disable the pager.
+    ///
+    /// Profiles are given by `--profile`, or selected by `--all`, `--tag` and `--match` together.
+    #[clap(
+        arg_required_else_help = true,
+        group(
+            ArgGroup::new("profiles")
+                .required(true)
+                .multiple(true)
+                .args(&["profile", "all", "tag", "glob"])
+        )
+    )]
+    Set {
+        #[clap(value_name = "KEY", help = "key like output or cli_pager")]
+        key: String,
+        #[clap(value_name = "VALUE", help = "value, which may be empty")]
+        value: String,
+        #[clap(
+            long,
+            short,
+            conflicts_with_all = &["all", "tag", "glob"],
+            help = "profile name"
+        )]
+        profile: Option<String>,
+        #[clap(
+            long,
+            help = "all the profiles, except ones of config fragments"
+        )]
+        all: bool,
+        #[clap(long, help = "profiles tagged by TAG in configurations")]
+        tag: Option<String>,
+        #[clap(
+            long = "match",
+            value_name = "GLOB",
+            help = "profiles whose names match the glob, like `prod-*`"
+        )]
+        glob: Option<String>,
+        #[clap(long, help = "preview the changes without applying them")]
+        dry_run: bool,
+    },
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// CSV with a header row
+    Csv,
+    /// Script filter JSON of Alfred
+    Alfred,
+}
+
 fn level_enum(verbosity: i8) -> log::Level {
     match verbosity {
         std::i8::MIN..=-1 => log::Level::Info,
@@ -74,61 +744,1285 @@ fn level_enum(verbosity: i8) -> log::Level {
     }
 }
 
+/// Asks whether to reload the file changed by another process and retry.
+fn confirm_reload(interactive: bool, path: &str) -> bool {
+    if !interactive {
+        return false;
+    }
+    eprint!(
+        "{} was changed by another process, reload it and retry? [Y/n] ",
+        path
+    );
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim(), "" | "y" | "Y")
+}
+
+/// Replaces aliases of profiles given to the subcommand by the profile names.
+/// Names of new profiles, like ones of `import`, are left as they are.
+fn resolve_aliases(mut opts: Opts, configs: &Configs) -> Opts {
+    match &mut opts {
+        Opts::Delete { profile } => {
+            *profile = configs.resolve_alias(profile);
+        }
+        Opts::ActiveContext {
+            check: Some(profile),
+            ..
+        }
+        | Opts::Costs {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Creds {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Assume {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Auth {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Discover {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Env {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Exec {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Open {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Profile {
+            opts:
+                ProfileOpts::Set {
+                    profile: Some(profile),
+                    ..
+                },
+        }
+        | Opts::Ssm {
+            profile: Some(profile),
+            ..
+        }
+        | Opts::Repair {
+            profile: Some(profile),
+        }
+        | Opts::Restore {
+            profile: Some(profile),
+        }
+        | Opts::Validate {
+            profile: Some(profile),
+            ..
+        } => {
+            *profile = configs.resolve_alias(profile);
+        }
+        Opts::UseContext { name, profile, .. } => {
+            for profile in [name, profile].into_iter().flatten() {
+                *profile = configs.resolve_alias(profile);
+            }
+        }
+        _ => {}
+    }
+    opts
+}
+
+/// Resolves a profile name not given exactly to the only profile matched by the prefix or fuzzily.
+/// Names matching nothing are left as they are, to fail as no such profile.
+/// Ambiguous names open the finder filtered by them in interactive mode, and fail otherwise.
+fn match_profile(
+    aws: &AWS,
+    configs: &Configs,
+    confirmation: &Confirmation,
+    interactive: bool,
+    profile: String,
+) -> Result<String, CTXError> {
+    let names = aws
+        .list_contexts()?
+        .into_iter()
+        .map(|c| c.name)
+        .collect::<Vec<String>>();
+    match match_name(&names, &profile) {
+        NameMatch::Unique(name) => {
+            if configs.matching.confirm {
+                confirmation.confirm(
+                    &mut io::stdin().lock(),
+                    &mut io::stderr(),
+                    "switch to profile",
+                    &name,
+                )?;
+            }
+            log::debug!("({}) matches profile ({})", profile, name);
+            Ok(name)
+        }
+        NameMatch::Ambiguous(candidates) if interactive => {
+            if plain_output() {
+                return pick_by_lines(candidates, "profile:");
+            }
+            if let Some(command) = &configs.picker.command {
+                return pick_by_command(command, &candidates)
+                    .map(|i| candidates[i].clone());
+            }
+            // the query is kept editable, and the only match is accepted without the finder
+            let mut skim_options = finder_options();
+            skim_options.query = Some(&profile);
+            skim_options.select1 = true;
+            aws.pick_context_interactive(&skim_options)
+        }
+        NameMatch::Ambiguous(candidates) => Err(CTXError::InvalidInput {
+            message: format!(
+                "ambiguous profile ({}), candidates: {}",
+                profile,
+                candidates.join(", ")
+            ),
+            source: None,
+        }),
+        NameMatch::Exact(_) | NameMatch::Nothing => Ok(profile),
+    }
+}
+
+/// Reports a switched context, as JSON on stdout in CI mode.
+/// Variables of the shell still taking precedence over the switched context are warned.
+fn report_switch(aws: &AWS, ci: bool, context: &Context, message: &str) {
+    if ci {
+        show_context_json(context);
+    }
+    log::info!("<green>{}</>", message);
+    if let Some(arn) = &context.arn {
+        log::info!("<green>verified as {}</>", arn);
+    }
+    if aws.effective_context(env_override()).is_discrepant() {
+        warn_shadowed(&shadowing_variables(|k| env::var(k).ok()));
+    }
+}
+
+/// Detects variables of the environment overriding the active context, like `AWS_PROFILE`.
+fn env_override() -> Option<Override> {
+    Override::detect(|k| env::var(k).ok())
+}
+
+/// Counts refreshes of credentials for metrics of the daemon, passing the result through.
+/// Failures of counting are not fatal since they never change the result.
+fn record_refresh<T>(
+    profile: &str,
+    result: Result<T, CTXError>,
+) -> Result<T, CTXError> {
+    let mut counters = Counters::load::<PathBuf>(None);
+    counters.record_refresh(profile, result.is_ok());
+    if let Err(e) = counters.dump() {
+        log::debug!("failed to record metrics: {:?}", e);
+    }
+    result
+}
+
+/// Keeps the profiles whose contexts match the filter, all of them without one.
+fn filter_profiles(
+    aws: &AWS,
+    names: Vec<String>,
+    filter: Option<&str>,
+) -> Vec<String> {
+    let filter = match filter {
+        Some(filter) => fatal_ctxerr(Filter::parse(filter)),
+        None => return names,
+    };
+    let active = aws.get_active_context().ok().map(|c| c.name);
+    let now = Utc::now();
+    names
+        .into_iter()
+        .filter(|name| {
+            let context = aws.describe_context(Context {
+                name: name.clone(),
+                active: active.as_ref() == Some(name),
+                ..Default::default()
+            });
+            filter.matches(&context, now)
+        })
+        .collect()
+}
+
+/// Posts the event of the active context to the webhooks accepting it.
+fn notify_active(
+    configs: &Configs,
+    aws: &AWS,
+    event: WebhookEvent,
+    context: &str,
+) {
+    if configs.webhooks.is_empty() && configs.chat_notifications.is_empty() {
+        return;
+    }
+    let metadata = aws.describe_context(Context {
+        name: context.to_string(),
+        active: true,
+        ..Default::default()
+    });
+    notify_webhooks(configs, event, &metadata);
+}
+
+fn notify_webhooks(
+    configs: &Configs,
+    event: WebhookEvent,
+    context: &ctx::ContextMetadata,
+) {
+    // webhooks are network access, which offline mode disables
+    if configs.offline {
+        log::debug!("skip webhooks of {:?} in offline mode", event);
+        return;
+    }
+    let payload = webhook::Payload::new(event, context, Utc::now());
+    webhook::notify(
+        "curl",
+        &configs.webhooks,
+        &payload,
+        configs.proxy.as_ref(),
+    );
+    webhook::notify_chats(
+        "curl",
+        &configs.chat_notifications,
+        &payload,
+        configs.proxy.as_ref(),
+    );
+}
+
+/// Records a switched context, failures are not fatal since the switch itself succeeded.
+fn record_switch(configs: &Configs, aws: &AWS, context: &str) {
+    let mut history = History::load::<PathBuf>(None);
+    history.record(context, Utc::now());
+    if let Err(e) = history.dump() {
+        log::debug!("failed to record history: {:?}", e);
+    }
+    let mut counters = Counters::load::<PathBuf>(None);
+    counters.record_switch(context, Utc::now());
+    if let Err(e) = counters.dump() {
+        log::debug!("failed to record metrics: {:?}", e);
+    }
+    cache_prompt(aws, context);
+    notify_active(configs, aws, WebhookEvent::Switch, context);
+}
+
+/// Fills identities of the contexts, and accounts unknown from the config, by cached or fetched caller identities.
+fn resolve_context_identities(
+    configs: &Configs,
+    contexts: &mut [ctx::ContextMetadata],
+    jobs: usize,
+) {
+    let now = Utc::now();
+    let mut cache = IdentitiesCache::load::<PathBuf>(None);
+    let profiles = contexts
+        .iter()
+        .map(|c| c.name.clone())
+        .collect::<Vec<String>>();
+    if profiles.iter().any(|p| cache.get(p, now).is_none()) {
+        fatal_ctxerr(configs.ensure_online("--details"));
+    }
+    let identities = resolve_identities(
+        &AwsCli::from_configs(configs),
+        &mut cache,
+        &profiles,
+        jobs,
+        now,
+    );
+    if let Err(e) = cache.dump() {
+        log::debug!("failed to cache identities: {:?}", e);
+    }
+    for (context, identity) in contexts.iter_mut().zip(identities) {
+        if let Some(identity) = identity {
+            context.identity = Some(identity.name().to_string());
+            context.account.get_or_insert(identity.account);
+        }
+    }
+    resolve_account_aliases(configs, contexts, jobs);
+}
+
+/// Caches aliases of accounts of the contexts for `listing.format`, by a profile of each account.
+fn resolve_account_aliases(
+    configs: &Configs,
+    contexts: &[ctx::ContextMetadata],
+    jobs: usize,
+) {
+    let mut aliases = AccountAliases::load::<PathBuf>(None);
+    let mut targets: Vec<(&str, &str)> = vec![];
+    for c in contexts.iter().filter(|c| c.identity.is_some()) {
+        if let Some(account) = c.account.as_deref() {
+            if aliases.get(account).is_none()
+                && !targets.iter().any(|(a, _)| *a == account)
+            {
+                targets.push((account, c.name.as_str()));
+            }
+        }
+    }
+    if targets.is_empty() {
+        return;
+    }
+    let cli = AwsCli::from_configs(configs);
+    let fetched = run_concurrently(&targets, jobs, |(_, profile)| {
+        get_account_alias(&cli, profile)
+    });
+    for ((account, profile), result) in targets.iter().zip(fetched) {
+        match result {
+            // accounts without aliases are cached as empty ones not to ask again
+            Ok(alias) => aliases.insert(account, &alias.unwrap_or_default()),
+            Err(e) => log::debug!(
+                "failed to resolve account alias by profile ({}): {:?}",
+                profile,
+                e
+            ),
+        }
+    }
+    if let Err(e) = aliases.dump() {
+        log::debug!("failed to cache account aliases: {:?}", e);
+    }
+}
+
+fn cache_prompt(aws: &AWS, context: &str) {
+    let cache = PromptCache::new(
+        context,
+        aws.get_expiration(context),
+        CREDENTIALS_PATH.as_path(),
+    );
+    if let Some(Err(e)) = cache.map(|c| c.dump::<PathBuf>(None)) {
+        log::debug!("failed to cache prompt: {:?}", e);
+    }
+}
+
+fn print_prompt(
+    configs: &Configs,
+    context: &str,
+    expiration: Option<DateTime<Utc>>,
+    no_color: bool,
+) {
+    let prompt = fatal_ctxerr(render_prompt(
+        &configs.prompt,
+        context,
+        expiration,
+        Utc::now(),
+        !no_color && !plain_output(),
+    ));
+    println!("{}", prompt);
+}
+
+/// Generates configurations from existing AWS profiles on the first run.
+fn onboard() -> Configs {
+    let config = Config::load_config(CONFIG_PATH.as_path()).ok();
+    let credentials =
+        Credentials::load_credentials(CREDENTIALS_PATH.as_path()).ok();
+    let profiles = detect_profiles(config.as_ref(), credentials.as_ref());
+    let configs = fatal_ctxerr(run_onboarding(
+        &mut io::stdin().lock(),
+        &mut io::stderr(),
+        &profiles,
+    ));
+    let configs =
+        fatal_ctxerr(Configs::initialize_configs::<PathBuf>(None, &configs));
+    log::info!(
+        "<green>generated configurations on {}</>",
+        CONFIGS_PATH.to_str().unwrap()
+    );
+    configs
+}
+
+/// Renders markups of colors like `<red>..</>`, which are dropped in plain output.
+fn render_markup(message: String) -> String {
+    let colored = colorize_string(message);
+    if !plain_output() {
+        return colored;
+    }
+    let mut plain = String::with_capacity(colored.len());
+    let mut chars = colored.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip escape sequences like `\x1b[31m` up to the final letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Logger rendering markups of colors like `<red>..</>`, which messages of awsctx contain
+struct MarkupLogger(Box<sl::TermLogger>);
+
+impl log::Log for MarkupLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let message = render_markup(record.args().to_string());
+            self.0.log(
+                &log::Record::builder()
+                    .metadata(record.metadata().clone())
+                    .args(format_args!("{}", message))
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
-    sl::TermLogger::init(
-        level_enum(cli.verbose).to_level_filter(),
+    set_plain_output(cli.plain);
+    // in CI mode, stdout is kept for machine-readable output
+    let (terminal_mode, color_choice) = if cli.ci {
+        (sl::TerminalMode::Stderr, sl::ColorChoice::Never)
+    } else {
+        (sl::TerminalMode::Mixed, sl::ColorChoice::Auto)
+    };
+    let level = level_enum(cli.verbose).to_level_filter();
+    let logger = sl::TermLogger::new(
+        level,
         sl::ConfigBuilder::new()
             .set_time_level(log::LevelFilter::Off)
             .set_target_level(log::LevelFilter::Debug)
             .set_max_level(log::LevelFilter::Debug)
-            .set_write_log_enable_colors(true)
+            .set_write_log_enable_colors(!cli.ci)
             .build(),
-        sl::TerminalMode::Mixed,
-        sl::ColorChoice::Auto,
-    )
-    .unwrap();
-
-    let configs = Rc::new(fatal_ctxerr(Configs::initialize_default_configs::<
-        PathBuf,
-    >(None)));
-    let mut aws = AWS::new(
-        Rc::clone(&configs),
-        CREDENTIALS_PATH.clone(),
-        CONFIG_PATH.clone(),
-    )
-    .unwrap();
-    let opts = cli.opts.unwrap_or(Opts::UseContextByInteractiveFinder {});
-    let skim_options = SkimOptionsBuilder::default()
-        .height(Some("30%"))
-        .multi(false)
-        .build()
-        .unwrap();
+        terminal_mode,
+        color_choice,
+    );
+    log::set_boxed_logger(Box::new(MarkupLogger(logger))).unwrap();
+    log::set_max_level(level);
+    // completion scripts come from the CLI definition only, so they never need the setup
+    if let Some(Opts::Completion {
+        shell,
+        shell_option,
+    }) = cli.opts
+    {
+        // either of them is given, as required by clap
+        print_completions(shell.or(shell_option).unwrap());
+        return;
+    }
+    if let Some(Opts::ShellInit { shell }) = cli.opts {
+        print!("{}", render_shell_init(shell));
+        return;
+    }
+    let interactive = !cli.ci && io::stdin().is_terminal();
+
+    let mut configs = if !CONFIGS_PATH.exists() && interactive {
+        onboard()
+    } else {
+        fatal_ctxerr(Configs::initialize_default_configs::<PathBuf>(None))
+    };
+    configs.offline |= cli.offline;
+    configs.lenient |= cli.lenient;
+    set_plain_output(cli.plain || configs.plain);
+    if let Some(locale) = configs.locale {
+        i18n::set_locale(locale);
+    }
+    let confirmation = Confirmation {
+        yes: cli.yes,
+        interactive,
+        protected: configs.protected_profiles.clone(),
+    };
+    let configs = Rc::new(configs);
+    // fast path of prompts, which skips parsing the AWS files
+    if let Some(Opts::Prompt { no_color }) = cli.opts {
+        if let Some(cache) =
+            PromptCache::load::<PathBuf, _>(None, CREDENTIALS_PATH.as_path())
+        {
+            print_prompt(&configs, &cache.context, cache.expiration, no_color);
+            return;
+        }
+    }
+    let load_aws = || {
+        fatal_ctxerr(
+            AWS::new(
+                Rc::clone(&configs),
+                CREDENTIALS_PATH.clone(),
+                CONFIG_PATH.clone(),
+            )
+            .map_err(|e| {
+                e.downcast::<CTXError>().unwrap_or_else(|e| {
+                    CTXError::UnexpectedError { source: Some(e) }
+                })
+            }),
+        )
+        .with_force(cli.force)
+        .with_lock(LOCK_PATH.as_path())
+        .with_credentials_cache(CREDENTIALS_CACHE_PATH.as_path())
+        .with_diff_confirmation(cli.show_diff.then(|| confirmation.clone()))
+        .with_mfa_code(
+            cli.mfa_code
+                .clone()
+                .map(MfaCode::Given)
+                .or_else(|| interactive.then_some(MfaCode::Prompt)),
+        )
+    };
+    let mut aws = load_aws();
+    let opts = match cli.opts {
+        Some(opts) => opts,
+        // never open the finder in CI mode, the profile is given by the environment
+        None if cli.ci => match env::var("AWSCTX_PROFILE") {
+            Ok(profile) => Opts::UseContext {
+                name: None,
+                profile: Some(profile),
+                region: None,
+            },
+            Err(_) => {
+                log::error!("<red>AWSCTX_PROFILE or a subcommand is required in CI mode</>");
+                std::process::exit(2);
+            }
+        },
+        None => Opts::UseContextByInteractiveFinder {},
+    };
+    let opts = resolve_aliases(opts, &configs);
+    let skim_options = finder_options();
+    // releases are checked while the command runs, and the hint is printed after it
+    let update_check = (configs.update_check
+        && !configs.offline
+        && !cli.ci
+        && !matches!(
+            opts,
+            Opts::Prompt { .. } | Opts::Daemon { .. } | Opts::Creds { .. }
+        ))
+    .then(|| {
+        let proxy = configs.proxy.clone();
+        thread::spawn(move || {
+            update::check::<PathBuf>(None, "curl", proxy.as_ref(), Utc::now())
+        })
+    });
+
+    // only commands switching contexts ask, which write the default sections of both files
+    if matches!(
+        opts,
+        Opts::UseContext { .. }
+            | Opts::UseContextByInteractiveFinder {}
+            | Opts::Auth { .. }
+            | Opts::Assume { .. }
+            | Opts::Refresh {}
+    ) {
+        resolve_default_mismatch(&mut aws, interactive);
+    }
 
     match opts {
-        Opts::ActiveContext {} => {
-            let context = fatal_ctxerr(aws.get_active_context());
-            show_context(&context)
+        Opts::ActiveContext {
+            check: Some(name),
+            effective,
+        } => {
+            let current = if effective {
+                aws.effective_context(env_override())
+                    .name
+                    .ok_or(CTXError::NoActiveContext { source: None })
+            } else {
+                aws.get_active_context().map(|c| c.name)
+            };
+            match current {
+                Ok(current) if current == name => {
+                    log::debug!("active context is ({})", name);
+                }
+                Ok(current) => {
+                    log::debug!(
+                        "active context is ({}), not ({})",
+                        current,
+                        name
+                    );
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    log::debug!("no active context: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Opts::ActiveContext {
+            check: None,
+            effective,
+        } => {
+            let effective_context = aws.effective_context(env_override());
+            if effective {
+                if effective_context.display_name().is_empty() {
+                    fatal_ctxerr::<()>(Err(CTXError::NoActiveContext {
+                        source: None,
+                    }));
+                }
+                show_effective_context(&effective_context);
+            } else {
+                show_context(&fatal_ctxerr(aws.get_active_context()));
+            }
+            warn_overridden(&effective_context);
+        }
+        Opts::Adopt { dry_run } => {
+            let names = aws.adoptable_profiles();
+            show_adoptable_profiles(&names);
+            if !names.is_empty() && !dry_run {
+                // load the file again, never to write options given by the command line
+                let mut stored =
+                    fatal_ctxerr(Configs::load_configs::<PathBuf>(None));
+                for name in names.iter() {
+                    stored.auth_commands.insert(
+                        name.clone(),
+                        ProfileKind::Sso.auth_command().into(),
+                    );
+                }
+                fatal_ctxerr(Configs::initialize_configs::<PathBuf>(
+                    None, &stored,
+                ));
+                fatal_ctxerr(aws.adopt_profiles(&names));
+                log::info!(
+                    "<green>adopt {} SSO profiles, sign in by `awsctx auth -p <name>`</>",
+                    names.len()
+                );
+            }
         }
-        Opts::Auth { profile } => {
-            let context = fatal_ctxerr(aws.auth(profile.as_str()));
-            sl::info!(
-                "<green>successfully auth with profile ({}) and make it active</>",
+        Opts::Assume {
+            profile: Some(profile),
+            ..
+        } => {
+            let context =
+                fatal_ctxerr(record_refresh(&profile, aws.assume(&profile)));
+            record_switch(&configs, &aws, &context.name);
+            report_switch(
+                &aws,
+                cli.ci,
+                &context,
+                &format!(
+                    "successfully assume roles of profile ({}) and make it active",
+                    context.name
+                ),
+            );
+        }
+        Opts::Assume {
+            role_arn: None,
+            profile: None,
+            ..
+        } => unreachable!("clap requires either a role ARN or a profile"),
+        Opts::Assume {
+            role_arn: Some(role_arn),
+            name,
+            duration,
+            ..
+        } => {
+            let context = fatal_ctxerr(aws.assume_role::<PathBuf>(
+                &role_arn,
+                name.as_deref(),
+                duration,
+                None,
+            ));
+            record_switch(&configs, &aws, &context.name);
+            report_switch(
+                &aws,
+                cli.ci,
+                &context,
+                &format!(
+                    "successfully assume role ({}) and make ({}) active",
+                    role_arn, context.name
+                ),
+            );
+        }
+        Opts::Auth {
+            profile: None,
+            filter,
+            jobs,
+            ..
+        } => {
+            let active = aws.get_active_context().ok();
+            let names = fatal_ctxerr(aws.list_contexts())
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<String>>();
+            let names = filter_profiles(&aws, names, filter.as_deref());
+            let results = names
+                .iter()
+                .zip(aws.auth_all(&names, jobs))
+                .map(|(name, result)| {
+                    (name.clone(), record_refresh(name, result))
+                })
+                .collect::<Vec<_>>();
+            for context in results.iter().filter_map(|(_, r)| r.as_ref().ok()) {
+                notify_active(
+                    &configs,
+                    &aws,
+                    WebhookEvent::Auth,
+                    &context.name,
+                );
+            }
+            show_auth_results(&results);
+            // auth makes each profile active, so the one active before is brought back
+            if let Some(active) = active {
+                fatal_ctxerr(aws.use_context(&active.name));
+            }
+            if results.iter().any(|(_, r)| {
+                r.as_ref().is_err_and(|e| {
+                    !matches!(e, CTXError::NoAuthConfiguration { .. })
+                })
+            }) {
+                std::process::exit(1);
+            }
+        }
+        Opts::Auth {
+            profile: Some(profile),
+            ..
+        } => {
+            let context =
+                fatal_ctxerr(record_refresh(&profile, aws.auth(&profile)));
+            notify_active(&configs, &aws, WebhookEvent::Auth, &context.name);
+            record_switch(&configs, &aws, &context.name);
+            report_switch(
+                &aws,
+                cli.ci,
+                &context,
+                &format!(
+                    "successfully auth with profile ({}) and make it active",
+                    context.name
+                ),
+            );
+        }
+        Opts::Cleanup { days, all, dry_run } => {
+            let active = aws.get_active_context().ok().map(|c| c.name);
+            let names = fatal_ctxerr(aws.profile_names())
+                .into_iter()
+                .filter(|n| Some(n) != active.as_ref() && !aws.is_read_only(n))
+                .collect::<Vec<String>>();
+            // history keeps only recent entries, so the last switches of metrics are used too
+            let history = History::load::<PathBuf>(None);
+            let counters = Counters::load::<PathBuf>(None);
+            let unused = find_unused(
+                &names,
+                |n| history.last_used(n).max(counters.last_switched(n)),
+                Utc::now(),
+                days,
+            );
+            if dry_run || unused.is_empty() {
+                show_unused_profiles(&unused);
+                return;
+            }
+            let selected = if all {
+                unused
+            } else if interactive {
+                match pick_many(&unused_profile_lines(&unused), "archive> ") {
+                    Err(CTXError::NoContextIsSelected { .. }) => return,
+                    result => fatal_ctxerr(result)
+                        .into_iter()
+                        .map(|i| unused[i].clone())
+                        .collect(),
+                }
+            } else {
+                fatal_ctxerr(Err(CTXError::InvalidInput {
+                    message: "select profiles interactively or pass `--all`"
+                        .to_string(),
+                    source: None,
+                }))
+            };
+            if selected.is_empty() {
+                return;
+            }
+            show_unused_profiles(&selected);
+            fatal_ctxerr(confirmation.confirm(
+                &mut io::stdin().lock(),
+                &mut io::stderr(),
+                "archive unused profiles",
+                &selected.len().to_string(),
+            ));
+            // protected profiles are confirmed one by one by their names
+            for p in selected
+                .iter()
+                .filter(|p| configs.protected_profiles.contains(&p.name))
+            {
+                fatal_ctxerr(confirmation.confirm(
+                    &mut io::stdin().lock(),
+                    &mut io::stderr(),
+                    "archive profile",
+                    &p.name,
+                ));
+            }
+            // load the file again, never to write options given by the command line
+            let mut stored =
+                fatal_ctxerr(Configs::load_configs::<PathBuf>(None));
+            let mut changed = false;
+            for p in selected.iter() {
+                fatal_ctxerr(aws.delete_profile::<PathBuf>(&p.name, None));
+                changed |= stored.remove_profile_entries(&p.name);
+            }
+            if changed {
+                fatal_ctxerr(Configs::initialize_configs::<PathBuf>(
+                    None, &stored,
+                ));
+            }
+            log::info!(
+                "<green>archive {} profiles, restore them by `awsctx restore -p <name>`</>",
+                selected.len()
+            );
+        }
+        Opts::Costs {
+            profile,
+            all,
+            refresh,
+            jobs,
+        } => {
+            let contexts = if all {
+                fatal_ctxerr(aws.describe_contexts())
+            } else {
+                let context = match profile {
+                    Some(name) => Context {
+                        name,
+                        ..Default::default()
+                    },
+                    None => fatal_ctxerr(aws.get_active_context()),
+                };
+                vec![aws.describe_context(context)]
+            };
+            let now = Utc::now();
+            let mut cache = CostsCache::load::<PathBuf>(None);
+            if refresh
+                || contexts.iter().any(|c| cache.get(&c.name, now).is_none())
+            {
+                fatal_ctxerr(configs.ensure_online("costs"));
+            }
+            let costs = month_to_date_costs(
+                &AwsCli::from_configs(&configs),
+                &mut cache,
+                &contexts,
+                refresh,
+                jobs,
+                now,
+            );
+            if let Err(e) = cache.dump() {
+                log::debug!("failed to cache costs: {:?}", e);
+            }
+            show_costs(&costs);
+            if costs.iter().any(|c| c.cost.is_none()) {
+                std::process::exit(1);
+            }
+        }
+        Opts::Creds { profile, format } => {
+            let name = profile
+                .unwrap_or_else(|| fatal_ctxerr(aws.get_active_context()).name);
+            // stdout is read by AWS SDKs and CLI, so nothing but the JSON is printed
+            match format {
+                CredsFormat::Process => println!(
+                    "{}",
+                    serde_json::to_string(&fatal_ctxerr(
+                        aws.process_credentials(&name)
+                    ))
+                    .unwrap()
+                ),
+            }
+        }
+        Opts::Env {
+            profile,
+            format,
+            output,
+            region,
+        } => {
+            let name = profile
+                .unwrap_or_else(|| fatal_ctxerr(aws.get_active_context()).name);
+            if let Some(region) = &region {
+                fatal_ctxerr(Regions::load::<PathBuf>(None).validate(region));
+            }
+            let vars = fatal_ctxerr(aws.context_env(&name, region.as_deref()));
+            let contents = render_env(&vars, format);
+            match (format, output) {
+                (EnvFormat::Github, output) => {
+                    // secrets have to be masked before they reach the environment of later steps
+                    print!("{}", github_masks(&vars));
+                    let path = output
+                        .or_else(|| env::var_os("GITHUB_ENV").map(PathBuf::from))
+                        .unwrap_or_else(|| {
+                            fatal_ctxerr(Err(CTXError::InvalidConfigurations {
+                                message: "GITHUB_ENV is not set, run in GitHub Actions or pass --output".to_string(),
+                                source: None,
+                            }))
+                        });
+                    fatal_ctxerr(append_env(&path, &contents));
+                    log::info!(
+                        "<green>export environment variables of profile ({}) to later steps</>",
+                        name
+                    );
+                }
+                (_, Some(path)) => {
+                    fatal_ctxerr(write_env(&path, &contents));
+                    log::info!(
+                        "<green>write environment variables of profile ({}) to {}</>",
+                        name,
+                        path.to_str().unwrap()
+                    );
+                }
+                (_, None) => print!("{}", contents),
+            }
+        }
+        Opts::Exec {
+            profile,
+            region,
+            lock,
+            reason,
+            command,
+        } => {
+            let name = profile
+                .unwrap_or_else(|| fatal_ctxerr(aws.get_active_context()).name);
+            if lock {
+                let active = fatal_ctxerr(aws.get_active_context()).name;
+                if active != name {
+                    fatal_ctxerr::<()>(Err(CTXError::InvalidInput {
+                        message: format!(
+                            "--lock locks the active context ({}), switch to ({}) first",
+                            active, name
+                        ),
+                        source: None,
+                    }));
+                }
+            }
+            if let Some(region) = &region {
+                fatal_ctxerr(Regions::load::<PathBuf>(None).validate(region));
+            }
+            let vars = fatal_ctxerr(aws.context_env(&name, region.as_deref()));
+            if lock {
+                fatal_ctxerr(lock::acquire(
+                    LOCK_PATH.as_path(),
+                    &ContextLock::new(&name, reason, true),
+                ));
+            }
+            let status = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .envs(vars)
+                .status();
+            if lock {
+                fatal_ctxerr(lock::release(
+                    LOCK_PATH.as_path(),
+                    Some(std::process::id()),
+                ));
+            }
+            let status =
+                fatal_ctxerr(status.map_err(|e| CTXError::InvalidInput {
+                    message: format!("failed to execute `{}`", command[0]),
+                    source: Some(e.into()),
+                }));
+            if !status.success() {
+                std::process::exit(status.code().unwrap_or(1));
+            }
+        }
+        Opts::Import { source, profile } => {
+            let contents = if source.as_os_str() == "-" {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents).map(|_| contents)
+            } else {
+                fs::read_to_string(&source)
+            };
+            let contents =
+                fatal_ctxerr(contents.map_err(|e| CTXError::InvalidInput {
+                    message: format!("cannot read {}", source.display()),
+                    source: Some(e.into()),
+                }));
+            let profiles =
+                fatal_ctxerr(parse_import(&contents, profile.as_deref()));
+            let names = profiles
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<String>>();
+            fatal_ctxerr(aws.import_profiles(profiles));
+            log::info!("<green>import profiles ({})</>", names.join(", "));
+        }
+        Opts::ImportImds { profile } => {
+            fatal_ctxerr(configs.ensure_online("import-imds"));
+            let credentials =
+                fatal_ctxerr(MetadataSource::from_env().fetch_credentials());
+            fatal_ctxerr(
+                aws.import_credentials(profile.as_str(), &credentials),
+            );
+            log::info!(
+                "<green>import credentials from the metadata endpoint as profile ({})</>",
+                profile
+            );
+        }
+        Opts::Tree {} => {
+            let config =
+                fatal_ctxerr(Config::load_config(CONFIG_PATH.as_path()));
+            let tree = RoleTree::from_config(&config);
+            show_tree(&if plain_output() {
+                tree.render_plain()
+            } else {
+                tree.render()
+            });
+            if !tree.cycles().is_empty() {
+                log::error!("<red>source_profile of some profiles loops</>");
+                std::process::exit(1);
+            }
+        }
+        Opts::Lint {} => {
+            let credentials = fatal_ctxerr(Credentials::load_credentials(
+                CREDENTIALS_PATH.as_path(),
+            ));
+            let config = Config::load_config(CONFIG_PATH.as_path()).ok();
+            let findings = lint(&credentials, config.as_ref());
+            show_findings(&findings);
+            if !findings.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Opts::ListContexts {
+            output,
+            columns,
+            details,
+            jobs,
+            filter,
+        } => {
+            let output = output.unwrap_or(if cli.ci {
+                OutputFormat::Json
+            } else {
+                OutputFormat::Text
+            });
+            let filter = filter.map(|f| fatal_ctxerr(Filter::parse(&f)));
+            let mut contexts = fatal_ctxerr(aws.describe_contexts());
+            if let Some(filter) = filter {
+                let now = Utc::now();
+                contexts.retain(|c| filter.matches(c, now));
+            }
+            if details {
+                resolve_context_identities(&configs, &mut contexts, jobs);
+            }
+            // the template replaces the configured columns, but not ones given by `--columns`
+            let format = configs
+                .listing
+                .format
+                .as_ref()
+                .filter(|_| columns.is_empty());
+            let mut columns = match (columns.is_empty(), &output) {
+                (false, _) => columns,
+                (true, OutputFormat::Csv) => ctx::DEFAULT_COLUMNS.to_vec(),
+                (true, _) => configs.listing.columns.clone(),
+            };
+            if details && !columns.contains(&ctx::Column::Identity) {
+                let at = columns
+                    .iter()
+                    .position(|c| c == &ctx::Column::Name)
+                    .map_or(0, |i| i + 1);
+                columns.insert(at, ctx::Column::Identity);
+            }
+            match output {
+                OutputFormat::Text => {
+                    match format {
+                        Some(format) => {
+                            let lines = fatal_ctxerr(PickerItem::render(
+                                &contexts,
+                                format,
+                                &AccountAliases::load::<PathBuf>(None),
+                            ))
+                            .into_iter()
+                            .map(|i| i.line)
+                            .collect::<Vec<String>>();
+                            show_contexts_lines(&contexts, &lines)
+                        }
+                        None => show_contexts_table(
+                            &contexts,
+                            &columns,
+                            !cli.ci && supports_hyperlinks(),
+                        ),
+                    }
+                    warn_overridden(&aws.effective_context(env_override()));
+                }
+                OutputFormat::Json => show_contexts_json(&contexts),
+                OutputFormat::Jsonl => show_contexts_jsonl(&contexts),
+                OutputFormat::Csv => show_contexts_csv(&contexts, &columns),
+                OutputFormat::Alfred => show_contexts_alfred(&contexts),
+            }
+        }
+        Opts::Lock { reason } => {
+            let context = fatal_ctxerr(aws.get_active_context());
+            fatal_ctxerr(lock::acquire(
+                LOCK_PATH.as_path(),
+                &ContextLock::new(&context.name, reason, false),
+            ));
+            log::info!(
+                "<green>lock context ({}), run `awsctx unlock` to release it</>",
                 context.name
             );
         }
-        Opts::ListContexts {} => {
-            let contexts = fatal_ctxerr(aws.list_contexts());
-            show_contexts(&contexts)
+        Opts::Unlock {} => {
+            match fatal_ctxerr(lock::release(LOCK_PATH.as_path(), None)) {
+                Some(released) => log::info!(
+                    "<green>unlock context ({}) locked by {}</>",
+                    released.context,
+                    released.holder()
+                ),
+                None => {
+                    log::info!("{}", i18n::tr(i18n::Message::NoLockHeld, &[]))
+                }
+            }
+        }
+        Opts::Open {
+            service,
+            profile,
+            print,
+        } => {
+            let context = match profile {
+                Some(name) => Context {
+                    name,
+                    ..Default::default()
+                },
+                None => fatal_ctxerr(aws.get_active_context()),
+            };
+            let name = context.name.clone();
+            let metadata = aws.describe_context(context);
+            let region = metadata.region.as_deref();
+            let destination = match &service {
+                Some(service) => console::service_url(service, region),
+                None => console::home_url(region),
+            };
+            let vars = fatal_ctxerr(aws.context_env(&name, None));
+            let url = match SessionCredentials::from_env_vars(&vars) {
+                Some(credentials) => {
+                    fatal_ctxerr(configs.ensure_online("open"));
+                    fatal_ctxerr(console::federation_url(
+                        "curl",
+                        &credentials,
+                        &destination,
+                        configs.proxy.as_ref(),
+                    ))
+                }
+                None => {
+                    log::warn!(
+                        "profile ({}) has no session token, sign in the console by yourself",
+                        name
+                    );
+                    console::signin_url(
+                        metadata.account.as_deref(),
+                        destination,
+                    )
+                }
+            };
+            if print {
+                println!("{}", url);
+            } else {
+                fatal_ctxerr(console::open_browser(&url));
+                log::info!("<green>open the console of profile ({})</>", name);
+            }
         }
-        Opts::UseContext { profile } => {
-            let context = fatal_ctxerr(aws.use_context(profile.as_str()));
-            sl::info!("<green>switch to profile ({})</>", context.name);
+        Opts::UseContext {
+            name,
+            profile,
+            region,
+        } => {
+            let profile = name.or(profile);
+            let picked = profile.is_none() || matches!(region, Some(None));
+            if picked && !interactive {
+                log::error!("<red>the profile and the region must be given in non-interactive mode</>");
+                std::process::exit(2);
+            }
+            let profile = match profile {
+                // names in CI mode are exact, never switching to a wrong account by a typo
+                Some(profile) if configs.matching.enabled && !cli.ci => {
+                    match match_profile(
+                        &aws,
+                        &configs,
+                        &confirmation,
+                        interactive,
+                        profile,
+                    ) {
+                        Err(CTXError::NoContextIsSelected { .. }) => return,
+                        result => fatal_ctxerr(result),
+                    }
+                }
+                Some(profile) => profile,
+                None if plain_output() => {
+                    let names = fatal_ctxerr(aws.list_contexts())
+                        .into_iter()
+                        .map(|c| c.name)
+                        .collect();
+                    match pick_by_lines(names, "profile:") {
+                        Err(CTXError::NoContextIsSelected { .. }) => return,
+                        result => fatal_ctxerr(result),
+                    }
+                }
+                None => match aws.pick_context_interactive(&skim_options) {
+                    Err(CTXError::NoContextIsSelected { .. }) => return,
+                    result => fatal_ctxerr(result),
+                },
+            };
+            let region = match region {
+                Some(Some(region)) => Some(region),
+                Some(None) if plain_output() => {
+                    let names = Regions::load::<PathBuf>(None).names().to_vec();
+                    match pick_by_lines(names, "region:") {
+                        Err(CTXError::NoContextIsSelected { .. }) => return,
+                        result => Some(fatal_ctxerr(result)),
+                    }
+                }
+                Some(None) => {
+                    let regions = Regions::load::<PathBuf>(None);
+                    let picked = match &configs.picker.command {
+                        Some(command) => {
+                            pick_by_command(command, regions.names())
+                                .map(|i| regions.names()[i].clone())
+                        }
+                        None => regions.pick_interactive(&skim_options),
+                    };
+                    match picked {
+                        Err(CTXError::NoContextIsSelected { .. }) => return,
+                        result => Some(fatal_ctxerr(result)),
+                    }
+                }
+                None => None,
+            };
+            if let Some(region) = &region {
+                fatal_ctxerr(Regions::load::<PathBuf>(None).validate(region));
+            }
+            if picked {
+                // the finder may stay open for a long time, switch on the latest files
+                fatal_ctxerr(aws.reload_if_changed());
+            }
+            let switch = |aws: &mut AWS| match &region {
+                Some(region) => aws.use_context_in_region(&profile, region),
+                None => aws.use_context(&profile),
+            };
+            let context = match switch(&mut aws) {
+                Err(CTXError::FileChangedOnDisk { path, .. })
+                    if confirm_reload(interactive, &path) =>
+                {
+                    aws = load_aws();
+                    fatal_ctxerr(switch(&mut aws))
+                }
+                result => fatal_ctxerr(result),
+            };
+            record_switch(&configs, &aws, &context.name);
+            report_switch(
+                &aws,
+                cli.ci,
+                &context,
+                &match &region {
+                    Some(region) => format!(
+                        "switch to profile ({}) in region ({})",
+                        context.name, region
+                    ),
+                    None => format!("switch to profile ({})", context.name),
+                },
+            );
         }
         Opts::UseContextByInteractiveFinder {} => {
-            match aws.use_context_interactive(skim_options) {
+            let result = if plain_output() {
+                aws.list_contexts()
+                    .and_then(|contexts| {
+                        pick_by_lines(
+                            contexts.into_iter().map(|c| c.name).collect(),
+                            "profile:",
+                        )
+                    })
+                    .and_then(|name| aws.use_context(&name))
+            } else {
+                aws.use_context_interactive(skim_options)
+            };
+            match result {
                 Ok(context) => {
-                    sl::info!("<green>switch to profile ({})</>", context.name)
+                    record_switch(&configs, &aws, &context.name);
+                    report_switch(
+                        &aws,
+                        cli.ci,
+                        &context,
+                        &format!("switch to profile ({})", context.name),
+                    );
                 }
                 Err(err) => match err {
                     CTXError::NoContextIsSelected { source: _ } => (),
@@ -136,21 +2030,590 @@ fn main() {
                 },
             };
         }
+        Opts::Backups {
+            opts: BackupsOpts::List {},
+        } => {
+            show_backups(&backup::list(&BACKUPS_DIR));
+        }
+        Opts::Daemon { listen, socket } => {
+            let listen =
+                listen.unwrap_or_else(|| configs.daemon.listen.clone());
+            let socket = socket
+                .or_else(|| configs.daemon.socket.clone())
+                .unwrap_or_else(|| DAEMON_SOCKET_PATH.clone());
+            let listener = fatal_ctxerr(daemon::bind(&listen));
+            let socket_listener = fatal_ctxerr(daemon::bind_socket(&socket));
+            log::info!(
+                "<green>serve the status on http://{}/status, and control on {}</>",
+                listen,
+                socket.display()
+            );
+            // requests are served one by one, so the files are never borrowed twice
+            let aws = RefCell::new(aws);
+            // expiries before the daemon started are never notified
+            let mut checked_at = Utc::now();
+            let reload = |aws: &mut AWS| {
+                // the files may be changed by other awsctx processes meanwhile
+                if let Err(e) = aws.reload_if_changed() {
+                    log::debug!("failed to reload the AWS files: {:?}", e);
+                }
+            };
+            daemon::serve(
+                listener,
+                Some(socket_listener),
+                |path| {
+                    let mut aws = aws.borrow_mut();
+                    reload(&mut aws);
+                    let contexts = aws.describe_contexts().unwrap_or_default();
+                    match path {
+                        "/status" => {
+                            daemon::Response::json(&daemon::Status::new(
+                                &contexts,
+                                History::load::<PathBuf>(None).entries(),
+                                Utc::now(),
+                            ))
+                        }
+                        "/metrics" => daemon::Response::ok(
+                            "text/plain; version=0.0.4",
+                            metrics::render(
+                                &contexts,
+                                &Counters::load::<PathBuf>(None),
+                                Utc::now(),
+                            ),
+                        ),
+                        _ => daemon::Response::not_found(),
+                    }
+                },
+                |command| {
+                    let mut aws = aws.borrow_mut();
+                    reload(&mut aws);
+                    match command {
+                        daemon::Command::List => {
+                            aws.describe_contexts().map(|c| json!(c))
+                        }
+                        daemon::Command::Whoami => aws
+                            .describe_contexts()
+                            .and_then(|contexts| {
+                                contexts.into_iter().find(|c| c.active).ok_or(
+                                    CTXError::NoActiveContext { source: None },
+                                )
+                            })
+                            .map(|c| json!(c)),
+                        daemon::Command::Switch(profile) => {
+                            let profile = configs.resolve_alias(&profile);
+                            // the daemon has no terminal to prompt on, nor to run auth commands on
+                            match aws.interaction_needed(&profile) {
+                                Some(reason) => Err(CTXError::InvalidInput {
+                                    message: format!(
+                                        "switching to ({}) needs a terminal, {}, run `awsctx use-context {}` instead",
+                                        profile, reason, profile
+                                    ),
+                                    source: None,
+                                }),
+                                None => aws.use_context(&profile).map(|context| {
+                                    record_switch(&configs, &aws, &context.name);
+                                    json!(context)
+                                }),
+                            }
+                        }
+                    }
+                    .map_err(|e| format!("{:?}", e))
+                },
+                || {
+                    if configs.webhooks.is_empty() {
+                        return;
+                    }
+                    let mut aws = aws.borrow_mut();
+                    reload(&mut aws);
+                    let now = Utc::now();
+                    let contexts = aws.describe_contexts().unwrap_or_default();
+                    for context in
+                        webhook::expired_between(&contexts, checked_at, now)
+                    {
+                        notify_webhooks(
+                            &configs,
+                            WebhookEvent::Expiry,
+                            context,
+                        );
+                    }
+                    checked_at = now;
+                },
+            );
+        }
+        Opts::Discover {
+            ou,
+            profile,
+            name,
+            role,
+            region,
+            dry_run,
+        } => {
+            let source_profile = match profile {
+                Some(profile) => profile,
+                None => fatal_ctxerr(aws.get_active_context()).name,
+            };
+            let mut discovery = configs.discovery.clone();
+            discovery.name = name.unwrap_or(discovery.name);
+            discovery.role_name = role.unwrap_or(discovery.role_name);
+            discovery.region = region.or(discovery.region);
+            let accounts = fatal_ctxerr(list_accounts(
+                &AwsCli::from_configs(&configs),
+                &source_profile,
+                ou.as_deref(),
+            ));
+            let profiles = fatal_ctxerr(generate_profiles(
+                &accounts,
+                &discovery,
+                &source_profile,
+            ));
+            if dry_run {
+                for (name, items) in profiles.iter() {
+                    log::info!("{}  {}", name, items["role_arn"]);
+                }
+            } else {
+                let added = fatal_ctxerr(aws.add_config_profiles(profiles));
+                for name in added.iter() {
+                    log::info!("{}", name);
+                }
+                log::info!(
+                    "<green>add {} profiles of {} accounts</>",
+                    added.len(),
+                    accounts.len()
+                );
+            }
+        }
+        Opts::Delete { profile } => {
+            fatal_ctxerr(confirmation.confirm(
+                &mut io::stdin().lock(),
+                &mut io::stderr(),
+                "delete profile",
+                &profile,
+            ));
+            fatal_ctxerr(aws.delete_profile::<PathBuf>(&profile, None));
+            log::info!(
+                "<green>delete profile ({}), restore it by `awsctx restore -p {}`</>",
+                profile,
+                profile
+            );
+        }
+        Opts::Rename {
+            pattern,
+            to,
+            dry_run,
+        } => {
+            let names = fatal_ctxerr(aws.profile_names());
+            let renames = fatal_ctxerr(rename::plan(&names, &pattern, &to));
+            show_renames(&renames);
+            if !renames.is_empty() && !dry_run {
+                fatal_ctxerr(confirmation.confirm(
+                    &mut io::stdin().lock(),
+                    &mut io::stderr(),
+                    &format!("rename {} profiles matching", renames.len()),
+                    &pattern,
+                ));
+                // protected profiles are confirmed one by one by their names
+                for r in renames
+                    .iter()
+                    .filter(|r| configs.protected_profiles.contains(&r.from))
+                {
+                    fatal_ctxerr(confirmation.confirm(
+                        &mut io::stdin().lock(),
+                        &mut io::stderr(),
+                        "rename profile",
+                        &r.from,
+                    ));
+                }
+                fatal_ctxerr(aws.rename_profiles(&renames));
+                log::info!("<green>rename {} profiles</>", renames.len());
+            }
+        }
+        Opts::Profile {
+            opts:
+                ProfileOpts::Set {
+                    key,
+                    value,
+                    profile,
+                    all: _,
+                    tag,
+                    glob,
+                    dry_run,
+                },
+        } => {
+            fatal_ctxerr(setting::validate_key(&key));
+            let names = match profile {
+                Some(profile) => vec![profile],
+                None => {
+                    let names = fatal_ctxerr(aws.profile_names())
+                        .into_iter()
+                        .filter(|n| !aws.is_read_only(n))
+                        .collect::<Vec<String>>();
+                    fatal_ctxerr(setting::select(
+                        &names,
+                        &configs.tags,
+                        tag.as_deref(),
+                        glob.as_deref(),
+                    ))
+                }
+            };
+            let settings =
+                fatal_ctxerr(aws.plan_settings(&names, &key, &value));
+            show_settings(&settings);
+            if !settings.is_empty() && !dry_run {
+                if settings.len() > 1 {
+                    fatal_ctxerr(confirmation.confirm(
+                        &mut io::stdin().lock(),
+                        &mut io::stderr(),
+                        &format!(
+                            "set {} of {} profiles to",
+                            key,
+                            settings.len()
+                        ),
+                        &value,
+                    ));
+                }
+                // protected profiles are confirmed one by one by their names
+                for s in settings
+                    .iter()
+                    .filter(|s| configs.protected_profiles.contains(&s.profile))
+                {
+                    fatal_ctxerr(confirmation.confirm(
+                        &mut io::stdin().lock(),
+                        &mut io::stderr(),
+                        &format!("set {} of profile", key),
+                        &s.profile,
+                    ));
+                }
+                fatal_ctxerr(aws.apply_settings(&settings));
+                log::info!(
+                    "<green>set {} of {} profiles</>",
+                    key,
+                    settings.len()
+                );
+            }
+        }
+        Opts::Manifest {
+            opts: ManifestOpts::Apply { path, dry_run },
+        } => {
+            let manifest = fatal_ctxerr(Manifest::load(&path));
+            let managed = manifest::load_managed::<PathBuf>(None);
+            let changes = aws.plan_manifest(&manifest, &managed);
+            show_manifest_changes(&changes);
+            if !changes.is_empty() && !dry_run {
+                fatal_ctxerr(confirmation.confirm(
+                    &mut io::stdin().lock(),
+                    &mut io::stderr(),
+                    &format!("apply {} changes of the manifest", changes.len()),
+                    path.to_str().unwrap(),
+                ));
+                let removed = changes
+                    .iter()
+                    .filter_map(|c| match c {
+                        Change::Remove(name) => Some(name.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<String>>();
+                // protected profiles are confirmed one by one by their names
+                for name in removed
+                    .iter()
+                    .filter(|n| configs.protected_profiles.contains(n))
+                {
+                    fatal_ctxerr(confirmation.confirm(
+                        &mut io::stdin().lock(),
+                        &mut io::stderr(),
+                        "remove profile",
+                        name,
+                    ));
+                }
+                fatal_ctxerr(
+                    aws.apply_manifest::<PathBuf>(&manifest, &changes, None),
+                );
+                // load the file again, never to write options given by the command line
+                let mut stored =
+                    fatal_ctxerr(Configs::load_configs::<PathBuf>(None));
+                if manifest.apply_configs(&mut stored, &removed) {
+                    fatal_ctxerr(Configs::initialize_configs::<PathBuf>(
+                        None, &stored,
+                    ));
+                }
+                log::info!(
+                    "<green>apply {} changes of the manifest</>",
+                    changes.len()
+                );
+            }
+            // profiles matching the manifest already are managed from now on too
+            if !dry_run {
+                let names = manifest
+                    .profiles
+                    .iter()
+                    .map(|p| p.name.clone())
+                    .collect::<Vec<String>>();
+                fatal_ctxerr(manifest::save_managed::<PathBuf>(None, &names));
+            }
+        }
+        Opts::Manifest {
+            opts: ManifestOpts::Export {},
+        } => {
+            let manifest = fatal_ctxerr(aws.export_manifest::<PathBuf>(None));
+            print!("{}", fatal_ctxerr(manifest.to_yaml()));
+        }
+        Opts::Restore { profile: None } => {
+            show_deleted_profiles(&trash::list::<PathBuf>(None));
+        }
+        Opts::Restore {
+            profile: Some(profile),
+        } => {
+            fatal_ctxerr(aws.restore_profile::<PathBuf>(&profile, None));
+            log::info!("<green>restore profile ({})</>", profile);
+        }
+        Opts::Regions { refresh } => {
+            let regions = if refresh {
+                fatal_ctxerr(Regions::refresh::<PathBuf>(
+                    &AwsCli::from_configs(&configs),
+                    None,
+                ))
+            } else {
+                Regions::load::<PathBuf>(None)
+            };
+            show_regions(regions.names());
+        }
+        Opts::Repair { profile } => {
+            let context =
+                fatal_ctxerr(aws.repair_default_profile(profile.as_deref()));
+            report_switch(
+                &aws,
+                cli.ci,
+                &context,
+                &format!("re-sync the default profile to ({})", context.name),
+            );
+        }
         Opts::Refresh {} => {
             let active_context = fatal_ctxerr(aws.get_active_context());
-            fatal_ctxerr(aws.auth(active_context.name.as_str()));
-            sl::info!(
+            fatal_ctxerr(record_refresh(
+                &active_context.name,
+                aws.auth(&active_context.name),
+            ));
+            notify_active(
+                &configs,
+                &aws,
+                WebhookEvent::Auth,
+                &active_context.name,
+            );
+            log::info!(
                 "<green>successfully refresh credentials for profile ({})</>",
                 active_context.name
             );
         }
+        Opts::Prompt { no_color } => {
+            // prompts are rendered on every command, print nothing without an active context
+            if let Ok(context) = aws.get_active_context() {
+                cache_prompt(&aws, &context.name);
+                print_prompt(
+                    &configs,
+                    &context.name,
+                    aws.get_expiration(&context.name),
+                    no_color,
+                );
+            } else if cli.ci {
+                std::process::exit(1);
+            }
+        }
+        Opts::Ssm {
+            target,
+            profile,
+            region,
+            args,
+        } => {
+            let name = profile
+                .unwrap_or_else(|| fatal_ctxerr(aws.get_active_context()).name);
+            if let Some(region) = &region {
+                fatal_ctxerr(Regions::load::<PathBuf>(None).validate(region));
+            }
+            let vars = fatal_ctxerr(aws.context_env(&name, region.as_deref()));
+            log::info!(
+                "<green>start a session to {} with profile ({})</>",
+                target,
+                name
+            );
+            let status = fatal_ctxerr(ssm::start_session(
+                &AwsCli::from_configs(&configs),
+                &target,
+                &args,
+                &vars,
+            ));
+            if !status.success() {
+                std::process::exit(status.code().unwrap_or(1));
+            }
+        }
+        Opts::Status { format } => {
+            let context = aws
+                .get_active_context()
+                .ok()
+                .map(|c| aws.describe_context(c));
+            println!(
+                "{}",
+                render_status(
+                    context.as_ref(),
+                    format,
+                    &configs.prompt,
+                    Utc::now()
+                )
+            );
+        }
+        Opts::Validate {
+            profile,
+            all,
+            filter,
+            jobs,
+        } => {
+            fatal_ctxerr(configs.ensure_online("validate"));
+            let profiles = if all {
+                filter_profiles(
+                    &aws,
+                    fatal_ctxerr(aws.credential_profile_names()),
+                    filter.as_deref(),
+                )
+            } else {
+                vec![profile.unwrap_or_else(|| {
+                    fatal_ctxerr(aws.get_active_context()).name
+                })]
+            };
+            let validations = validate_profiles(
+                &AwsCli::from_configs(&configs),
+                &profiles,
+                jobs,
+            );
+            show_validations(&validations);
+            if validations
+                .iter()
+                .any(|v| v.status != CredentialsStatus::Ok)
+            {
+                std::process::exit(1);
+            }
+        }
+
+        Opts::Completion { .. } => {
+            unreachable!("completion is handled before loading the AWS files")
+        }
+        Opts::ShellInit { .. } => {
+            unreachable!("shell-init is handled before loading the AWS files")
+        }
+    }
+    if let Some(check) = update_check.and_then(|h| h.join().ok().flatten()) {
+        print_update_hint(&check);
+    }
+}
+
+/// Warns on stderr that variables of the environment put another context than the active one
+/// in effect, never to break outputs of commands.
+fn warn_overridden(effective: &EffectiveContext) {
+    if let Some(overridden_by) = effective
+        .overridden_by
+        .as_ref()
+        .filter(|_| effective.is_discrepant())
+    {
+        eprintln!(
+            "{}",
+            render_markup(format!(
+                "<yellow>{}</>",
+                i18n::tr(
+                    i18n::Message::EnvOverridesContext,
+                    &[overridden_by.variable(), effective.display_name()]
+                )
+            ))
+        );
+    }
+}
+
+/// Warns of the default profiles of the credentials and the config differing,
+/// and asks which one wins in interactive mode. They are left as they are unless picked.
+fn resolve_default_mismatch(aws: &mut AWS, interactive: bool) {
+    let mismatch = match aws.default_mismatch() {
+        Some(mismatch) => mismatch,
+        None => return,
+    };
+    log::warn!(
+        "<yellow>{}</>",
+        i18n::tr(
+            i18n::Message::DefaultMismatch,
+            &[&mismatch.credentials, &mismatch.config]
+        )
+    );
+    let candidates = [mismatch.credentials, mismatch.config];
+    let picked = interactive
+        .then(|| {
+            pick_by_line(
+                &mut io::stdin().lock(),
+                &mut io::stderr(),
+                &[
+                    format!("{} (~/.aws/credentials)", candidates[0]),
+                    format!("{} (~/.aws/config)", candidates[1]),
+                ],
+                &i18n::tr(i18n::Message::PickDefaultProfile, &[]),
+            )
+            .ok()
+        })
+        .flatten();
+    match picked {
+        Some(i) => {
+            let context =
+                fatal_ctxerr(aws.repair_default_profile(Some(&candidates[i])));
+            log::info!(
+                "<green>re-sync the default profile to ({})</>",
+                context.name
+            );
+        }
+        None => log::warn!("{}", i18n::tr(i18n::Message::RepairHint, &[])),
+    }
+}
 
-        Opts::Completion { shell } => {
-            print_completions(shell);
+/// Warns on stderr that the switch has no effect in the shell, with the variables to unset.
+fn warn_shadowed(variables: &[&str]) {
+    eprintln!(
+        "{}",
+        render_markup(format!(
+            "<bold><yellow>{}</>",
+            i18n::tr(
+                i18n::Message::SwitchShadowed,
+                &[&variables.join(", "), &variables.join(" ")]
+            )
+        ))
+    );
+}
+
+/// Prints a hint of a newer release to stderr, so that outputs of commands are never broken.
+fn print_update_hint(check: &UpdateCheck) {
+    let current = env!("CARGO_PKG_VERSION");
+    if let Some(latest) = check.newer_than(current) {
+        if io::stderr().is_terminal() {
+            eprintln!(
+                "{}",
+                render_markup(format!(
+                    "<yellow>awsctx {} is available (current: {}), see https://github.com/hiro-o918/awsctx/releases</>",
+                    latest, current
+                ))
+            );
         }
     }
 }
 
+/// Options of the built-in finder, opened in the lower part of the terminal.
+fn finder_options<'a>() -> SkimOptions<'a> {
+    SkimOptionsBuilder::default()
+        .height(Some("30%"))
+        .multi(false)
+        .build()
+        .unwrap()
+}
+
+/// Picks one of the items by a line of its number or name in plain output.
+fn pick_by_lines(
+    items: Vec<String>,
+    question: &str,
+) -> Result<String, CTXError> {
+    pick_by_line(&mut io::stdin().lock(), &mut io::stderr(), &items, question)
+        .map(|i| items[i].clone())
+}
+
 fn print_completions<G: Generator>(gen: G) {
     let cmd = &mut Cli::command();
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
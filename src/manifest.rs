@@ -0,0 +1,425 @@
+use crate::config::Config;
+use crate::configs::{AuthCommandConfigs, Configs};
+use crate::creds::{
+    Credentials, ACCESS_KEY_ID_KEY, EXPIRATION_KEY, SECRET_ACCESS_KEY_KEY,
+    SESSION_TOKEN_KEY,
+};
+use crate::ctx;
+use crate::state::{self, MANIFEST_STATE_PATH};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Desired profiles, which `awsctx manifest apply` reconciles the files and configurations with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub profiles: Vec<ManifestProfile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestProfile {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sso: Option<SsoSettings>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthCommandConfigs>,
+    /// Other items of the config, like `output`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub items: BTreeMap<String, String>,
+}
+
+/// Settings of IAM Identity Center, written as `sso_*` items of the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SsoSettings {
+    pub start_url: String,
+    pub region: String,
+    pub account_id: String,
+    pub role_name: String,
+}
+
+impl ManifestProfile {
+    /// Returns items of the section of the config.
+    pub fn config_items(&self) -> HashMap<String, String> {
+        let mut items = self
+            .items
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<HashMap<String, String>>();
+        let mut set = |key: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                items.insert(key.to_string(), value.clone());
+            }
+        };
+        set("region", &self.region);
+        set("role_arn", &self.role_arn);
+        set("source_profile", &self.source_profile);
+        if let Some(sso) = &self.sso {
+            items.insert("sso_start_url".to_string(), sso.start_url.clone());
+            items.insert("sso_region".to_string(), sso.region.clone());
+            items.insert("sso_account_id".to_string(), sso.account_id.clone());
+            items.insert("sso_role_name".to_string(), sso.role_name.clone());
+        }
+        items
+    }
+
+    /// Builds the profile from items of the section of the config, with tags and the auth command
+    /// of the configurations. Secrets like access keys are left out.
+    pub fn from_items(
+        name: &str,
+        items: &HashMap<String, String>,
+        configs: &Configs,
+    ) -> Self {
+        let mut items = items
+            .iter()
+            .filter(|(k, _)| {
+                ![
+                    ACCESS_KEY_ID_KEY,
+                    SECRET_ACCESS_KEY_KEY,
+                    SESSION_TOKEN_KEY,
+                    EXPIRATION_KEY,
+                ]
+                .contains(&k.as_str())
+            })
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<BTreeMap<String, String>>();
+        // settings of SSO are taken only if complete, otherwise they stay as items
+        let sso_keys = [
+            "sso_start_url",
+            "sso_region",
+            "sso_account_id",
+            "sso_role_name",
+        ];
+        let sso = sso_keys.iter().all(|k| items.contains_key(*k)).then(|| {
+            let mut take = |key: &str| items.remove(key).unwrap_or_default();
+            SsoSettings {
+                start_url: take("sso_start_url"),
+                region: take("sso_region"),
+                account_id: take("sso_account_id"),
+                role_name: take("sso_role_name"),
+            }
+        });
+        Self {
+            name: name.to_string(),
+            region: items.remove("region"),
+            role_arn: items.remove("role_arn"),
+            source_profile: items.remove("source_profile"),
+            sso,
+            tags: configs.tags.get(name).cloned().unwrap_or_default(),
+            auth: configs.auth_commands.get(name).cloned(),
+            items,
+        }
+    }
+
+    /// Profiles neither assuming roles nor signing in by SSO need access keys in the credentials,
+    /// which are left empty for the auth command or users to fill in.
+    pub fn needs_credentials(&self) -> bool {
+        self.role_arn.is_none() && self.sso.is_none()
+    }
+}
+
+/// Returns items of the placeholder section of the credentials.
+pub fn placeholder_credentials() -> HashMap<String, String> {
+    HashMap::from([
+        (ACCESS_KEY_ID_KEY.to_string(), String::new()),
+        (SECRET_ACCESS_KEY_KEY.to_string(), String::new()),
+    ])
+}
+
+/// Change of a profile made by applying a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Add(String),
+    Update(String),
+    Remove(String),
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ctx::CTXError> {
+        let path = path.as_ref();
+        let manifest = fs::read_to_string(path)
+            .context(format!("failed to read {}", path.to_str().unwrap()))
+            .and_then(|contents| {
+                serde_yaml::from_str::<Self>(&contents).context(format!(
+                    "failed to parse {}",
+                    path.to_str().unwrap()
+                ))
+            })
+            .map_err(|e| ctx::CTXError::InvalidInput {
+                message: "broken manifest".to_string(),
+                source: Some(e),
+            })?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, ctx::CTXError> {
+        serde_yaml::to_string(self)
+            .context("failed to serialize manifest")
+            .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+    }
+
+    fn validate(&self) -> Result<(), ctx::CTXError> {
+        let mut names = HashSet::new();
+        for profile in self.profiles.iter() {
+            let problem = if profile.name.is_empty() {
+                Some("an empty name")
+            } else if profile.name == "default" {
+                Some("the default profile, which awsctx manages by itself")
+            } else if !names.insert(profile.name.as_str()) {
+                Some("a duplicate name")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                return Err(ctx::CTXError::InvalidInput {
+                    message: format!(
+                        "manifest has {} ({})",
+                        problem, profile.name
+                    ),
+                    source: None,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&ManifestProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Plans changes reconciling the files and configurations with the manifest.
+    /// `managed` lists the profiles applied last time, which are removed unless in the manifest,
+    /// so that profiles never written by manifests are left untouched.
+    pub fn plan(
+        &self,
+        config: &Config,
+        credentials: &Credentials,
+        configs: &Configs,
+        managed: &[String],
+    ) -> Vec<Change> {
+        let mut changes = self
+            .profiles
+            .iter()
+            .filter_map(|p| {
+                let section = config.profile(&p.name);
+                let has_credentials = credentials.profile(&p.name).is_some();
+                if section.is_none() && !has_credentials {
+                    return Some(Change::Add(p.name.clone()));
+                }
+                // profiles only in the credentials have no items of the config
+                let changed = section
+                    .map(|s| s.items().clone())
+                    .unwrap_or_default()
+                    != p.config_items()
+                    || (p.needs_credentials() && !has_credentials)
+                    || configs.tags.get(&p.name).cloned().unwrap_or_default()
+                        != p.tags
+                    || configs.auth_commands.get(&p.name) != p.auth.as_ref();
+                changed.then(|| Change::Update(p.name.clone()))
+            })
+            .collect::<Vec<Change>>();
+        let mut removed = managed
+            .iter()
+            .filter(|name| self.profile(name).is_none())
+            .filter(|name| {
+                config.profile(name).is_some()
+                    || credentials.profile(name).is_some()
+            })
+            .collect::<Vec<&String>>();
+        removed.sort();
+        changes.extend(removed.into_iter().map(|n| Change::Remove(n.clone())));
+        changes
+    }
+
+    /// Applies tags and auth commands of the profiles to the configurations,
+    /// dropping ones of the removed profiles. Returns whether the configurations changed.
+    pub fn apply_configs(
+        &self,
+        configs: &mut Configs,
+        removed: &[String],
+    ) -> bool {
+        let mut changed = false;
+        for profile in self.profiles.iter() {
+            let tags = if profile.tags.is_empty() {
+                configs.tags.remove(&profile.name)
+            } else {
+                configs
+                    .tags
+                    .insert(profile.name.clone(), profile.tags.clone())
+            };
+            changed |= tags.unwrap_or_default() != profile.tags;
+            let auth = match &profile.auth {
+                Some(auth) => configs
+                    .auth_commands
+                    .insert(profile.name.clone(), auth.clone()),
+                None => configs.auth_commands.remove(&profile.name),
+            };
+            changed |= auth != profile.auth;
+        }
+        for name in removed.iter() {
+            changed |= configs.tags.remove(name).is_some();
+            changed |= configs.auth_commands.remove(name).is_some();
+        }
+        changed
+    }
+}
+
+/// Loads names of the profiles applied by manifests last time.
+pub fn load_managed<P: AsRef<Path>>(path: Option<P>) -> Vec<String> {
+    let path = path
+        .map(|p| p.as_ref().to_path_buf())
+        .unwrap_or_else(|| MANIFEST_STATE_PATH.clone());
+    state::load(&path).unwrap_or_default()
+}
+
+pub fn save_managed<P: AsRef<Path>>(
+    path: Option<P>,
+    names: &[String],
+) -> Result<(), ctx::CTXError> {
+    let path: PathBuf = path
+        .map(|p| p.as_ref().to_path_buf())
+        .unwrap_or_else(|| MANIFEST_STATE_PATH.clone());
+    state::save(&path, &names)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    fn manifest() -> Manifest {
+        serde_yaml::from_str(
+            r#"profiles:
+  - name: foo
+    region: us-east-1
+    tags: [prod]
+  - name: bar
+    role_arn: arn:aws:iam::123456789012:role/Admin
+    source_profile: foo
+  - name: qux
+    sso:
+      start_url: https://example.awsapps.com/start
+      region: us-east-1
+      account_id: "123456789012"
+      role_name: ReadOnly
+"#,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    fn test_manifest_plan() {
+        let config = Config::from_text(
+            r#"[profile foo]
+region=us-east-1
+
+[profile bar]
+role_arn=arn:aws:iam::123456789012:role/Admin
+source_profile=baz
+
+[profile old]
+region=us-east-1
+"#,
+        )
+        .unwrap();
+        let credentials = Credentials::from_text(
+            r#"[foo]
+aws_access_key_id=XXXXXXXXXXXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+"#,
+        )
+        .unwrap();
+        let configs = Configs {
+            tags: HashMap::from([(
+                "foo".to_string(),
+                vec!["prod".to_string()],
+            )]),
+            ..Default::default()
+        };
+        let actual = manifest().plan(
+            &config,
+            &credentials,
+            &configs,
+            &["foo".to_string(), "old".to_string(), "gone".to_string()],
+        );
+        assert_eq!(
+            vec![
+                Change::Update("bar".to_string()),
+                Change::Add("qux".to_string()),
+                Change::Remove("old".to_string()),
+            ],
+            actual
+        );
+    }
+
+    #[rstest]
+    fn test_manifest_profile_from_items() {
+        let items = HashMap::from([
+            ("region".to_string(), "us-east-1".to_string()),
+            ("output".to_string(), "json".to_string()),
+            ("aws_secret_access_key".to_string(), "XXXXXXXX".to_string()),
+            (
+                "sso_start_url".to_string(),
+                "https://example.awsapps.com/start".to_string(),
+            ),
+            ("sso_region".to_string(), "us-east-1".to_string()),
+            ("sso_account_id".to_string(), "123456789012".to_string()),
+            ("sso_role_name".to_string(), "ReadOnly".to_string()),
+        ]);
+        let configs = Configs {
+            tags: HashMap::from([(
+                "qux".to_string(),
+                vec!["prod".to_string()],
+            )]),
+            ..Default::default()
+        };
+        let actual = ManifestProfile::from_items("qux", &items, &configs);
+        assert_eq!(manifest().profile("qux").unwrap().sso, actual.sso);
+        assert_eq!(vec!["prod".to_string()], actual.tags);
+        assert_eq!(
+            BTreeMap::from([("output".to_string(), "json".to_string())]),
+            actual.items
+        );
+        // secrets never appear, and the rest is written back as it is
+        let mut expect = items.clone();
+        expect.remove("aws_secret_access_key");
+        assert_eq!(expect, actual.config_items());
+    }
+
+    #[rstest(::trace)]
+    #[case("profiles: [{name: foo}, {name: foo}]", false)]
+    #[case("profiles: [{name: default}]", false)]
+    #[case("profiles: [{name: foo}, {name: bar}]", true)]
+    fn test_manifest_validate(#[case] text: &str, #[case] expect: bool) {
+        let manifest = serde_yaml::from_str::<Manifest>(text).unwrap();
+        assert_eq!(expect, manifest.validate().is_ok());
+    }
+
+    #[rstest]
+    fn test_manifest_apply_configs() {
+        let mut configs = Configs {
+            tags: HashMap::from([("old".to_string(), vec!["dev".to_string()])]),
+            ..Default::default()
+        };
+        assert!(manifest().apply_configs(&mut configs, &["old".to_string()]));
+        assert_eq!(
+            HashMap::from([("foo".to_string(), vec!["prod".to_string()])]),
+            configs.tags
+        );
+        assert!(!manifest().apply_configs(&mut configs, &[]));
+    }
+}
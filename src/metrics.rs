@@ -0,0 +1,198 @@
+use crate::ctx;
+use crate::state::{self, METRICS_PATH};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefreshCounts {
+    pub successes: u64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Counts {
+    switches: BTreeMap<String, u64>,
+    refreshes: BTreeMap<String, RefreshCounts>,
+    #[serde(default)]
+    last_switched: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// Counters of switches and refreshes of credentials by profiles, kept over processes
+/// since every command runs in its own process.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    path: PathBuf,
+    counts: Counts,
+}
+
+impl Counters {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| METRICS_PATH.clone());
+        Self {
+            counts: state::load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    pub fn record_switch(&mut self, profile: &str, switched_at: DateTime<Utc>) {
+        *self.counts.switches.entry(profile.to_string()).or_default() += 1;
+        self.counts
+            .last_switched
+            .insert(profile.to_string(), switched_at);
+    }
+
+    /// Returns when the active context was switched to the profile last time,
+    /// which is kept for every profile unlike the history.
+    pub fn last_switched(&self, profile: &str) -> Option<DateTime<Utc>> {
+        self.counts.last_switched.get(profile).copied()
+    }
+
+    pub fn record_refresh(&mut self, profile: &str, success: bool) {
+        let counts = self
+            .counts
+            .refreshes
+            .entry(profile.to_string())
+            .or_default();
+        if success {
+            counts.successes += 1;
+        } else {
+            counts.failures += 1;
+        }
+    }
+
+    pub fn dump(&self) -> Result<(), ctx::CTXError> {
+        state::save(&self.path, &self.counts)
+    }
+}
+
+/// Escapes a value of labels in the text format of Prometheus.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders metrics in the [text format](https://prometheus.io/docs/instrumenting/exposition_formats/) of Prometheus.
+pub fn render(
+    contexts: &[ctx::ContextMetadata],
+    counters: &Counters,
+    now: DateTime<Utc>,
+) -> String {
+    let mut text = String::new();
+    text.push_str("# HELP awsctx_credentials_expiry_seconds Seconds until credentials of the profile expire, negative once expired.\n");
+    text.push_str("# TYPE awsctx_credentials_expiry_seconds gauge\n");
+    for c in contexts.iter() {
+        if let Some(expires_at) = c.expires_at {
+            writeln!(
+                text,
+                "awsctx_credentials_expiry_seconds{{profile=\"{}\"}} {}",
+                label(&c.name),
+                (expires_at - now).num_seconds()
+            )
+            .unwrap();
+        }
+    }
+    text.push_str("# HELP awsctx_active_context Whether the profile is the active context.\n");
+    text.push_str("# TYPE awsctx_active_context gauge\n");
+    for c in contexts.iter() {
+        writeln!(
+            text,
+            "awsctx_active_context{{profile=\"{}\"}} {}",
+            label(&c.name),
+            u8::from(c.active)
+        )
+        .unwrap();
+    }
+    text.push_str(
+        "# HELP awsctx_switches_total Switches of the active context to the profile.\n",
+    );
+    text.push_str("# TYPE awsctx_switches_total counter\n");
+    for (profile, count) in counters.counts.switches.iter() {
+        writeln!(
+            text,
+            "awsctx_switches_total{{profile=\"{}\"}} {}",
+            label(profile),
+            count
+        )
+        .unwrap();
+    }
+    text.push_str("# HELP awsctx_refreshes_total Refreshes of credentials of the profile by results.\n");
+    text.push_str("# TYPE awsctx_refreshes_total counter\n");
+    for (profile, counts) in counters.counts.refreshes.iter() {
+        for (result, count) in
+            [("success", counts.successes), ("failure", counts.failures)]
+        {
+            writeln!(
+                text,
+                "awsctx_refreshes_total{{profile=\"{}\",result=\"{}\"}} {}",
+                label(profile),
+                result,
+                count
+            )
+            .unwrap();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[rstest]
+    fn test_render() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state/metrics.json");
+        let now = Utc::now();
+        let mut counters = Counters::load(Some(&path));
+        counters.record_switch("foo", now);
+        counters.record_switch("foo", now);
+        counters.record_refresh("bar", true);
+        counters.record_refresh("bar", false);
+        counters.record_refresh("bar", false);
+        counters.dump().unwrap();
+
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "bar".to_string(),
+                expires_at: Some(now + Duration::minutes(5)),
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "foo\"".to_string(),
+                active: true,
+                ..Default::default()
+            },
+        ];
+        let counters = Counters::load(Some(&path));
+        assert_eq!(Some(now), counters.last_switched("foo"));
+        let actual = render(&contexts, &counters, now);
+        let samples = actual
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec![
+                r#"awsctx_credentials_expiry_seconds{profile="bar"} 300"#,
+                r#"awsctx_active_context{profile="bar"} 0"#,
+                r#"awsctx_active_context{profile="foo\""} 1"#,
+                r#"awsctx_switches_total{profile="foo"} 2"#,
+                r#"awsctx_refreshes_total{profile="bar",result="success"} 1"#,
+                r#"awsctx_refreshes_total{profile="bar",result="failure"} 2"#,
+            ],
+            samples
+        );
+    }
+}
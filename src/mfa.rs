@@ -0,0 +1,93 @@
+use crate::ctx;
+
+use std::io::{BufRead, Write};
+
+use anyhow::Context;
+
+/// Source of one-time codes of MFA devices for profiles with `mfa_serial`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MfaCode {
+    /// Code given beforehand, like `--mfa-code` of scripts
+    Given(String),
+    /// Code typed on the terminal when it is needed
+    Prompt,
+}
+
+impl MfaCode {
+    /// Returns the code for the device, asking for it on `output` for `Prompt`.
+    /// Fails with `InvalidInput` unless it is 6 digits.
+    pub fn read<R: BufRead, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+        serial: &str,
+    ) -> Result<String, ctx::CTXError> {
+        let code = match self {
+            Self::Given(code) => code.trim().to_string(),
+            Self::Prompt => ask(input, output, serial).map_err(|e| {
+                ctx::CTXError::UnexpectedError { source: Some(e) }
+            })?,
+        };
+        if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+            Ok(code)
+        } else {
+            Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "invalid MFA code of {}, give 6 digits",
+                    serial
+                ),
+                source: None,
+            })
+        }
+    }
+}
+
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    serial: &str,
+) -> anyhow::Result<String> {
+    write!(output, "MFA code of {}: ", serial)
+        .and_then(|_| output.flush())
+        .context("failed to ask for MFA code")?;
+    let mut code = String::new();
+    input
+        .read_line(&mut code)
+        .context("failed to read MFA code")?;
+    Ok(code.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    const SERIAL: &str = "arn:aws:iam::123456789012:mfa/alice";
+
+    #[rstest(::trace)]
+    #[case(MfaCode::Given("123456".to_string()), "", Some("123456"))]
+    #[case(MfaCode::Given("12345".to_string()), "", None)]
+    #[case(MfaCode::Prompt, "654321\n", Some("654321"))]
+    #[case(MfaCode::Prompt, "abcdef\n", None)]
+    #[case(MfaCode::Prompt, "", None)]
+    fn test_mfa_code_read(
+        #[case] source: MfaCode,
+        #[case] input: &str,
+        #[case] expect: Option<&str>,
+    ) {
+        let mut output = vec![];
+        let actual = source.read(&mut input.as_bytes(), &mut output, SERIAL);
+        match (expect, actual) {
+            (Some(expect), Ok(actual)) => assert_eq!(expect, actual),
+            (None, Err(ctx::CTXError::InvalidInput { .. })) => (),
+            (_, actual) => panic!("unexpected result: {:?}", actual),
+        }
+        if source == MfaCode::Prompt {
+            assert_eq!(
+                format!("MFA code of {}: ", SERIAL),
+                String::from_utf8(output).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use crate::ctx::{CTXError, Context, ContextMetadata, CTX};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::anyhow;
+use skim::SkimOptions;
+
+/// Call of a method of `CTX` recorded by `MockCtx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Auth {
+        profile: String,
+    },
+    Assume {
+        profile: String,
+    },
+    ListContexts,
+    DescribeContexts,
+    GetActiveContext,
+    SetDefaultProfile {
+        profile: String,
+    },
+    DumpCredentials,
+    DumpConfig,
+    UseContext {
+        profile: String,
+        region: Option<String>,
+    },
+    PickContextInteractive,
+    UseContextInteractive,
+}
+
+impl Call {
+    /// Name of the method of `CTX`, which keys failures scripted by `MockCtx::fail`.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::Auth { .. } => "auth",
+            Self::Assume { .. } => "assume",
+            Self::ListContexts => "list_contexts",
+            Self::DescribeContexts => "describe_contexts",
+            Self::GetActiveContext => "get_active_context",
+            Self::SetDefaultProfile { .. } => "set_default_profile",
+            Self::DumpCredentials => "dump_credentials",
+            Self::DumpConfig => "dump_config",
+            Self::UseContext { region: None, .. } => "use_context",
+            Self::UseContext { .. } => "use_context_in_region",
+            Self::PickContextInteractive => "pick_context_interactive",
+            Self::UseContextInteractive => "use_context_interactive",
+        }
+    }
+}
+
+/// In-memory `CTX` for tests of applications embedding awsctx, which never touches files.
+///
+/// Contexts are switched in memory, every call is recorded, and errors or picks of
+/// the finder are returned as scripted in advance.
+#[derive(Debug, Default)]
+pub struct MockCtx {
+    contexts: Vec<ContextMetadata>,
+    picks: RefCell<VecDeque<String>>,
+    failures: RefCell<HashMap<&'static str, VecDeque<CTXError>>>,
+    calls: RefCell<Vec<Call>>,
+}
+
+impl MockCtx {
+    pub fn new(names: &[&str]) -> Self {
+        Self {
+            contexts: names
+                .iter()
+                .map(|name| ContextMetadata {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a context with the details, replacing the one of the same name.
+    pub fn with_context(mut self, context: ContextMetadata) -> Self {
+        self.contexts.retain(|c| c.name != context.name);
+        if context.active {
+            self.contexts.iter_mut().for_each(|c| c.active = false);
+        }
+        self.contexts.push(context);
+        self.contexts.sort_by(|a, b| a.name.cmp(&b.name));
+        self
+    }
+
+    pub fn with_active(mut self, name: &str) -> Self {
+        self.contexts
+            .iter_mut()
+            .for_each(|c| c.active = c.name == name);
+        self
+    }
+
+    /// Scripts the context picked by the next call of the finder.
+    /// The finder is cancelled if no pick is left.
+    pub fn pick(self, name: &str) -> Self {
+        self.picks.borrow_mut().push_back(name.to_string());
+        self
+    }
+
+    /// Scripts the error returned by the next call of the method, like `use_context`.
+    pub fn fail(self, method: &'static str, error: CTXError) -> Self {
+        self.failures
+            .borrow_mut()
+            .entry(method)
+            .or_default()
+            .push_back(error);
+        self
+    }
+
+    /// Returns the calls recorded so far in the order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    pub fn contexts(&self) -> &[ContextMetadata] {
+        &self.contexts
+    }
+
+    fn record(&self, call: Call) -> Result<(), CTXError> {
+        let method = call.method();
+        self.calls.borrow_mut().push(call);
+        match self
+            .failures
+            .borrow_mut()
+            .get_mut(method)
+            .and_then(|errors| errors.pop_front())
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn switch(
+        &mut self,
+        profile: &str,
+        region: Option<&str>,
+    ) -> Result<Context, CTXError> {
+        if !self.contexts.iter().any(|c| c.name == profile) {
+            return Err(CTXError::NoSuchProfile {
+                profile: profile.to_string(),
+                source: Some(anyhow!("unknown context name: {}", profile)),
+            });
+        }
+        for context in self.contexts.iter_mut() {
+            context.active = context.name == profile;
+            if let (true, Some(region)) = (context.active, region) {
+                context.region = Some(region.to_string());
+            }
+        }
+        Ok(Context {
+            name: profile.to_string(),
+            active: true,
+            ..Default::default()
+        })
+    }
+}
+
+impl CTX for MockCtx {
+    fn auth(&mut self, profile: &str) -> Result<Context, CTXError> {
+        self.record(Call::Auth {
+            profile: profile.to_string(),
+        })?;
+        self.switch(profile, None)
+    }
+
+    fn auth_all(
+        &mut self,
+        profiles: &[String],
+        _parallelism: usize,
+    ) -> Vec<Result<Context, CTXError>> {
+        profiles.iter().map(|p| self.auth(p)).collect()
+    }
+
+    fn assume(&mut self, profile: &str) -> Result<Context, CTXError> {
+        self.record(Call::Assume {
+            profile: profile.to_string(),
+        })?;
+        self.switch(profile, None)
+    }
+
+    fn list_contexts(&self) -> Result<Vec<Context>, CTXError> {
+        self.record(Call::ListContexts)?;
+        Ok(self
+            .contexts
+            .iter()
+            .map(|c| Context {
+                name: c.name.clone(),
+                active: c.active,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn describe_contexts(&self) -> Result<Vec<ContextMetadata>, CTXError> {
+        self.record(Call::DescribeContexts)?;
+        Ok(self.contexts.clone())
+    }
+
+    fn get_active_context(&self) -> Result<Context, CTXError> {
+        self.record(Call::GetActiveContext)?;
+        self.contexts
+            .iter()
+            .find(|c| c.active)
+            .map(|c| Context {
+                name: c.name.clone(),
+                active: true,
+                ..Default::default()
+            })
+            .ok_or(CTXError::NoActiveContext { source: None })
+    }
+
+    fn set_default_profile(
+        &mut self,
+        profile: &str,
+    ) -> Result<Context, CTXError> {
+        self.record(Call::SetDefaultProfile {
+            profile: profile.to_string(),
+        })?;
+        self.switch(profile, None)
+    }
+
+    fn dump_credentials(&self) -> Result<(), CTXError> {
+        self.record(Call::DumpCredentials)
+    }
+
+    fn dump_config(&self) -> Result<(), CTXError> {
+        self.record(Call::DumpConfig)
+    }
+
+    fn use_context(&mut self, profile: &str) -> Result<Context, CTXError> {
+        self.record(Call::UseContext {
+            profile: profile.to_string(),
+            region: None,
+        })?;
+        self.switch(profile, None)
+    }
+
+    fn use_context_in_region(
+        &mut self,
+        profile: &str,
+        region: &str,
+    ) -> Result<Context, CTXError> {
+        self.record(Call::UseContext {
+            profile: profile.to_string(),
+            region: Some(region.to_string()),
+        })?;
+        self.switch(profile, Some(region))
+    }
+
+    fn pick_context_interactive(
+        &self,
+        _skim_options: &SkimOptions,
+    ) -> Result<String, CTXError> {
+        self.record(Call::PickContextInteractive)?;
+        self.picks
+            .borrow_mut()
+            .pop_front()
+            .ok_or(CTXError::NoContextIsSelected { source: None })
+    }
+
+    fn use_context_interactive(
+        &mut self,
+        _skim_options: SkimOptions,
+    ) -> Result<Context, CTXError> {
+        self.record(Call::UseContextInteractive)?;
+        let name = self
+            .picks
+            .borrow_mut()
+            .pop_front()
+            .ok_or(CTXError::NoContextIsSelected { source: None })?;
+        self.switch(&name, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    pub fn mock() -> MockCtx {
+        MockCtx::new(&["bar", "foo"]).with_active("foo")
+    }
+
+    #[rstest]
+    fn test_mock_ctx_use_context(mut mock: MockCtx) {
+        let ctx: &mut dyn CTX = &mut mock;
+        assert_eq!("foo", ctx.get_active_context().unwrap().name);
+        assert_eq!(
+            "bar",
+            ctx.use_context_in_region("bar", "xx-1").unwrap().name
+        );
+        assert_eq!("bar", ctx.get_active_context().unwrap().name);
+        assert!(matches!(
+            ctx.use_context("baz"),
+            Err(CTXError::NoSuchProfile { .. })
+        ));
+
+        assert_eq!(Some("xx-1".to_string()), mock.contexts()[0].region);
+        assert_eq!(
+            vec![
+                Call::GetActiveContext,
+                Call::UseContext {
+                    profile: "bar".to_string(),
+                    region: Some("xx-1".to_string())
+                },
+                Call::GetActiveContext,
+                Call::UseContext {
+                    profile: "baz".to_string(),
+                    region: None
+                },
+            ],
+            mock.calls()
+        );
+    }
+
+    #[rstest]
+    fn test_mock_ctx_scripted(mock: MockCtx) {
+        let mut mock = mock
+            .pick("bar")
+            .fail("use_context", CTXError::NoActiveContext { source: None });
+
+        assert!(matches!(
+            mock.use_context("bar"),
+            Err(CTXError::NoActiveContext { .. })
+        ));
+        // failures are consumed one by one
+        assert!(mock.use_context("foo").is_ok());
+        assert_eq!(
+            "bar",
+            mock.use_context_interactive(SkimOptions::default())
+                .unwrap()
+                .name
+        );
+        assert!(matches!(
+            mock.pick_context_interactive(&SkimOptions::default()),
+            Err(CTXError::NoContextIsSelected { .. })
+        ));
+    }
+}
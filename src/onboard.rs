@@ -0,0 +1,298 @@
+use crate::config::Config;
+use crate::configs::{AuthCommandConfigs, Configs};
+use crate::creds::Credentials;
+use crate::ctx;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use anyhow::Context;
+
+/// Exports credentials resolved by the AWS CLI into the credentials file,
+/// so that the profile can be used as a context.
+const EXPORT_CREDENTIALS_SCRIPT: &str = r#"eval "$(aws configure export-credentials --profile {{profile}} --format env)"
+aws configure set aws_access_key_id "$AWS_ACCESS_KEY_ID" --profile {{profile}}
+aws configure set aws_secret_access_key "$AWS_SECRET_ACCESS_KEY" --profile {{profile}}
+aws configure set aws_session_token "$AWS_SESSION_TOKEN" --profile {{profile}}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileKind {
+    Sso,
+    AssumeRole,
+    CredentialProcess,
+    Static,
+}
+
+impl fmt::Display for ProfileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Sso => "SSO",
+            Self::AssumeRole => "assume-role",
+            Self::CredentialProcess => "credential process",
+            Self::Static => "static",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl ProfileKind {
+    /// Detects the kind of a profile of the config by whether it has the keys.
+    /// Profiles with none of them have static credentials, if any.
+    pub fn of_config<F: Fn(&str) -> bool>(has_key: F) -> Option<Self> {
+        if has_key("sso_start_url") || has_key("sso_session") {
+            Some(Self::Sso)
+        } else if has_key("role_arn") {
+            Some(Self::AssumeRole)
+        } else if has_key("credential_process") {
+            Some(Self::CredentialProcess)
+        } else {
+            None
+        }
+    }
+
+    /// Keyword of the kind in filters and JSON, like `sso`
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Self::Sso => "sso",
+            Self::AssumeRole => "role",
+            Self::CredentialProcess => "process",
+            Self::Static => "static",
+        }
+    }
+
+    /// Returns the auth command suggested for profiles of the kind.
+    pub fn auth_command(&self) -> String {
+        match self {
+            Self::Sso => format!(
+                "aws sso login --profile {{{{profile}}}}\n{}",
+                EXPORT_CREDENTIALS_SCRIPT
+            ),
+            Self::AssumeRole | Self::CredentialProcess => {
+                EXPORT_CREDENTIALS_SCRIPT.to_string()
+            }
+            Self::Static => "aws configure --profile {{profile}}\n".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedProfile {
+    pub name: String,
+    pub kind: ProfileKind,
+}
+
+/// Detects existing profiles in the config and credentials, classified by how they get credentials.
+pub fn detect_profiles(
+    config: Option<&Config>,
+    credentials: Option<&Credentials>,
+) -> Vec<DetectedProfile> {
+    let mut kinds = HashMap::new();
+    if let Some(credentials) = credentials {
+        for profile in credentials.list_profiles() {
+            if profile.get("aws_access_key_id").is_some() {
+                kinds.insert(profile.name, ProfileKind::Static);
+            }
+        }
+    }
+    if let Some(config) = config {
+        for profile in config.list_profiles() {
+            if let Some(kind) =
+                ProfileKind::of_config(|k| profile.get(k).is_some())
+            {
+                kinds.insert(profile.name, kind);
+            }
+        }
+    }
+    let mut profiles = kinds
+        .into_iter()
+        .map(|(name, kind)| DetectedProfile { name, kind })
+        .collect::<Vec<DetectedProfile>>();
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    profiles
+}
+
+/// Builds configurations with auth commands suggested for the profiles.
+/// Static profiles are covered by the default command, so they get no command of their own.
+pub fn build_configs(
+    profiles: &[DetectedProfile],
+    default_command: Option<String>,
+) -> Configs {
+    let mut auth_commands = profiles
+        .iter()
+        .filter(|p| p.kind != ProfileKind::Static)
+        .map(|p| (p.name.clone(), p.kind.auth_command().into()))
+        .collect::<HashMap<String, AuthCommandConfigs>>();
+    if let Some(command) = default_command {
+        auth_commands.insert(
+            Configs::DEFAULT_AUTH_COMMAND_KEY.to_string(),
+            command.into(),
+        );
+    }
+    Configs {
+        auth_commands,
+        ..Default::default()
+    }
+}
+
+fn ask<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    question: &str,
+) -> anyhow::Result<String> {
+    write!(output, "{} ", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Walks through generating configurations for the detected profiles.
+pub fn run<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    profiles: &[DetectedProfile],
+) -> Result<Configs, ctx::CTXError> {
+    let mut walk_through = || -> anyhow::Result<Configs> {
+        writeln!(output, "No configurations of awsctx found.")?;
+        if profiles.is_empty() {
+            writeln!(
+                output,
+                "No AWS profiles found, use the default configurations."
+            )?;
+            return Ok(Configs::default());
+        }
+        writeln!(output, "Found AWS profiles:")?;
+        for profile in profiles {
+            writeln!(output, "  {} ({})", profile.name, profile.kind)?;
+        }
+        let answer = ask(
+            input,
+            output,
+            "Generate auth commands for SSO, assume-role and credential process profiles? [Y/n]",
+        )?;
+        let targets = if answer.is_empty() || answer.eq_ignore_ascii_case("y") {
+            profiles.to_vec()
+        } else {
+            vec![]
+        };
+
+        writeln!(output, "Default auth command for other profiles:")?;
+        writeln!(output, "  1) aws configure")?;
+        writeln!(output, "  2) aws sso login")?;
+        writeln!(output, "  3) none")?;
+        let default_command = loop {
+            match ask(input, output, "Choose [1]:")?.as_str() {
+                "" | "1" => break Some(ProfileKind::Static.auth_command()),
+                "2" => break Some(ProfileKind::Sso.auth_command()),
+                "3" => break None,
+                _ => writeln!(output, "Choose 1, 2 or 3.")?,
+            }
+        };
+        Ok(build_configs(&targets, default_command))
+    };
+    walk_through()
+        .context("failed to walk through the onboarding")
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rstest::*;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::configs::AuthCommand;
+
+    #[fixture]
+    pub fn profiles() -> Vec<DetectedProfile> {
+        vec![
+            DetectedProfile {
+                name: "bar".to_string(),
+                kind: ProfileKind::AssumeRole,
+            },
+            DetectedProfile {
+                name: "baz".to_string(),
+                kind: ProfileKind::Static,
+            },
+            DetectedProfile {
+                name: "foo".to_string(),
+                kind: ProfileKind::Sso,
+            },
+        ]
+    }
+
+    #[rstest(::trace)]
+    #[case("\n\n", vec!["__default", "bar", "foo"])]
+    #[case("n\n3\n", vec![])]
+    #[case("y\nx\n2\n", vec!["__default", "bar", "foo"])]
+    fn test_run(
+        profiles: Vec<DetectedProfile>,
+        #[case] answers: &str,
+        #[case] expect: Vec<&str>,
+    ) {
+        let mut output = vec![];
+        let actual =
+            run(&mut Cursor::new(answers), &mut output, &profiles).unwrap();
+        let mut keys = actual
+            .auth_commands
+            .keys()
+            .map(|k| k.as_str())
+            .collect::<Vec<&str>>();
+        keys.sort();
+        assert_eq!(expect, keys);
+        assert!(String::from_utf8(output).unwrap().contains("foo (SSO)"));
+    }
+
+    #[rstest]
+    fn test_detect_profiles() {
+        let mut config = NamedTempFile::new().unwrap();
+        write!(
+            config,
+            r#"[profile foo]
+sso_start_url=https://example.awsapps.com/start
+region=us-east-1
+
+[profile bar]
+role_arn=arn:aws:iam::123456789012:role/bar
+source_profile=baz
+
+[profile baz]
+region=us-east-1
+"#
+        )
+        .unwrap();
+        let mut credentials = NamedTempFile::new().unwrap();
+        write!(
+            credentials,
+            r#"[baz]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+"#
+        )
+        .unwrap();
+        let config = Config::load_config(config.path()).unwrap();
+        let credentials =
+            Credentials::load_credentials(credentials.path()).unwrap();
+
+        let actual = detect_profiles(Some(&config), Some(&credentials));
+        assert_eq!(profiles(), actual);
+    }
+
+    #[rstest]
+    fn test_build_configs(profiles: Vec<DetectedProfile>) {
+        let actual = build_configs(&profiles, None);
+        let script = match &actual.auth_commands["foo"].command {
+            AuthCommand::Script(script) => script,
+            command => panic!("unexpected command: {:?}", command),
+        };
+        assert_eq!(
+            "aws sso login --profile {{profile}}\neval \"$(aws configure export-credentials --profile {{profile}} --format env)\"",
+            script.lines().take(2).collect::<Vec<&str>>().join("\n")
+        );
+        assert!(!actual.auth_commands.contains_key("baz"));
+    }
+}
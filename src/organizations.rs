@@ -0,0 +1,180 @@
+use crate::awscli::AwsCli;
+use crate::configs::DiscoveryConfigs;
+use crate::ctx;
+use crate::template;
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Account of AWS Organizations.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    /// `ACTIVE`, `SUSPENDED` or `PENDING_CLOSURE`
+    #[serde(default)]
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListAccountsOutput {
+    accounts: Vec<Account>,
+}
+
+/// Profile generated for an account, with items of the config.
+pub type GeneratedProfile = (String, HashMap<String, String>);
+
+/// Calls Organizations ListAccounts, or ListAccountsForParent for the OU or the root,
+/// with credentials of the profile, usually of the management account.
+/// Accounts directly under the OU are listed, and ones not active are skipped.
+pub fn list_accounts(
+    cli: &AwsCli,
+    profile: &str,
+    parent_id: Option<&str>,
+) -> Result<Vec<Account>, ctx::CTXError> {
+    let output = match parent_id {
+        Some(parent_id) => cli.run::<ListAccountsOutput>(&[
+            "organizations",
+            "list-accounts-for-parent",
+            "--parent-id",
+            parent_id,
+            "--profile",
+            profile,
+        ])?,
+        None => cli.run::<ListAccountsOutput>(&[
+            "organizations",
+            "list-accounts",
+            "--profile",
+            profile,
+        ])?,
+    };
+    let mut accounts = output
+        .accounts
+        .into_iter()
+        .filter(|a| a.status.is_empty() || a.status == "ACTIVE")
+        .collect::<Vec<Account>>();
+    accounts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(accounts)
+}
+
+/// Generates profiles assuming the role of the configurations in the accounts
+/// with credentials of the source profile, named by the template.
+pub fn generate_profiles(
+    accounts: &[Account],
+    configs: &DiscoveryConfigs,
+    source_profile: &str,
+) -> Result<Vec<GeneratedProfile>, ctx::CTXError> {
+    let reg = template::registry();
+    accounts
+        .iter()
+        .map(|account| {
+            let name = reg
+                .render_template(
+                    &configs.name,
+                    &json!({
+                        "account_name": slug(&account.name),
+                        "account_id": account.id,
+                    }),
+                )
+                .map_err(|e| ctx::CTXError::InvalidConfigurations {
+                    message: "failed to render discovery.name".to_string(),
+                    source: Some(anyhow!("failed to render name {}", e)),
+                })?;
+            let mut items = HashMap::from([
+                (
+                    "role_arn".to_string(),
+                    format!(
+                        "arn:aws:iam::{}:role/{}",
+                        account.id, configs.role_name
+                    ),
+                ),
+                ("source_profile".to_string(), source_profile.to_string()),
+            ]);
+            if let Some(region) = &configs.region {
+                items.insert("region".to_string(), region.clone());
+            }
+            Ok((name, items))
+        })
+        .collect()
+}
+
+/// Makes an account name usable in profile names, like `Client X (Prod)` to `client-x-prod`.
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[rstest(::trace)]
+    #[case(None, "list-accounts --profile mgmt")]
+    #[case(
+        Some("ou-ab12-cdef3456"),
+        "list-accounts-for-parent --parent-id ou-ab12-cdef3456 --profile mgmt"
+    )]
+    fn test_list_accounts(#[case] parent_id: Option<&str>, #[case] args: &str) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            &format!(
+                r#"case "$*" in "organizations {} "*) ;; *) exit 1;; esac
+echo '{{"Accounts": [
+  {{"Id": "222222222222", "Name": "Web", "Status": "ACTIVE"}},
+  {{"Id": "333333333333", "Name": "Old", "Status": "SUSPENDED"}},
+  {{"Id": "111111111111", "Name": "Billing", "Status": "ACTIVE"}}
+]}}'"#,
+                args
+            ),
+        );
+        let actual = list_accounts(&cli, "mgmt", parent_id)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.id)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["111111111111", "222222222222"], actual);
+    }
+
+    #[rstest]
+    fn test_generate_profiles() {
+        let accounts = vec![Account {
+            id: "123456789012".to_string(),
+            name: "Client X (Prod)".to_string(),
+            status: "ACTIVE".to_string(),
+        }];
+        let configs = DiscoveryConfigs {
+            name: "{{account_name}}-admin".to_string(),
+            region: Some("us-east-1".to_string()),
+            ..Default::default()
+        };
+        let actual = generate_profiles(&accounts, &configs, "mgmt").unwrap();
+        assert_eq!(
+            vec![(
+                "client-x-prod-admin".to_string(),
+                HashMap::from([
+                    (
+                        "role_arn".to_string(),
+                        "arn:aws:iam::123456789012:role/OrganizationAccountAccessRole"
+                            .to_string()
+                    ),
+                    ("source_profile".to_string(), "mgmt".to_string()),
+                    ("region".to_string(), "us-east-1".to_string()),
+                ])
+            )],
+            actual
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::ctx;
+
+use std::cmp::Reverse;
+use std::io::{BufRead, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use skim::prelude::{
+    unbounded, SkimItemReceiver, SkimItemSender, SkimOptionsBuilder,
+};
+use skim::Skim;
+
+/// Result of matching a name given by users against names of contexts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameMatch {
+    Exact(String),
+    /// The only name matched by the prefix or fuzzily
+    Unique(String),
+    /// Candidates in the order of the scores
+    Ambiguous(Vec<String>),
+    Nothing,
+}
+
+/// Matches the query exactly, by the prefix, then fuzzily, like `prod-ad` for `prod-admin`.
+/// Fuzzy matching applies only when no name starts with the query.
+pub fn match_name(names: &[String], query: &str) -> NameMatch {
+    if names.iter().any(|n| n == query) {
+        return NameMatch::Exact(query.to_string());
+    }
+    let mut candidates = names
+        .iter()
+        .filter(|n| n.starts_with(query))
+        .cloned()
+        .collect::<Vec<String>>();
+    if candidates.is_empty() {
+        let matcher = SkimMatcherV2::default();
+        let mut scored = names
+            .iter()
+            .filter_map(|n| matcher.fuzzy_match(n, query).map(|s| (s, n)))
+            .collect::<Vec<(i64, &String)>>();
+        scored.sort_by_key(|(s, _)| Reverse(*s));
+        candidates = scored.into_iter().map(|(_, n)| n.clone()).collect();
+    }
+    match candidates.len() {
+        0 => NameMatch::Nothing,
+        1 => NameMatch::Unique(candidates.remove(0)),
+        _ => NameMatch::Ambiguous(candidates),
+    }
+}
+
+/// Picks one of the items by a line of its number or name, for terminals where
+/// the interactive finder does not work like screen readers.
+/// Returns the index of the picked item, and an empty line picks nothing.
+pub fn pick_by_line<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    items: &[String],
+    question: &str,
+) -> Result<usize, ctx::CTXError> {
+    let unexpected =
+        |e: anyhow::Error| ctx::CTXError::UnexpectedError { source: Some(e) };
+    for (i, item) in items.iter().enumerate() {
+        writeln!(output, "{}. {}", i + 1, item)
+            .context("failed to show items")
+            .map_err(unexpected)?;
+    }
+    write!(output, "{} ", question)
+        .and_then(|_| output.flush())
+        .context("failed to ask for an item")
+        .map_err(unexpected)?;
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .context("failed to read an item")
+        .map_err(unexpected)?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Err(ctx::CTXError::NoContextIsSelected { source: None });
+    }
+    answer
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=items.len()).contains(n))
+        .map(|n| n - 1)
+        .or_else(|| items.iter().position(|i| i == answer))
+        .ok_or_else(|| ctx::CTXError::InvalidInput {
+            message: format!("no such item: {}", answer),
+            source: None,
+        })
+}
+
+/// Picks any of the items by the interactive finder, marked by TAB.
+/// Returns indices of the picked items, and aborting picks nothing.
+pub fn pick_many(
+    items: &[String],
+    prompt: &str,
+) -> Result<Vec<usize>, ctx::CTXError> {
+    let options = SkimOptionsBuilder::default()
+        .height(Some("30%"))
+        .multi(true)
+        .prompt(Some(prompt))
+        .build()
+        .map_err(|e| ctx::CTXError::UnexpectedError {
+            source: Some(anyhow!("failed to build options of skim: {}", e)),
+        })?;
+    let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+    // skim shows reverse order
+    for item in items.iter().rev() {
+        tx_item
+            .send(Arc::new(item.clone()))
+            .context("failed to send an item to skim")
+            .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
+    }
+    drop(tx_item);
+    let out = Skim::run_with(&options, Some(rx_item))
+        .filter(|out| !out.is_abort)
+        .ok_or(ctx::CTXError::NoContextIsSelected { source: None })?;
+    Ok(out
+        .selected_items
+        .iter()
+        .filter_map(|picked| items.iter().position(|i| *i == picked.output()))
+        .collect())
+}
+
+/// Picks one of the items by an external fuzzy finder like fzf, which reads the items
+/// from stdin and prints the selected one. The finder draws on the terminal by itself.
+/// Returns the index of the picked item.
+pub fn pick_by_command(
+    command: &[String],
+    items: &[String],
+) -> Result<usize, ctx::CTXError> {
+    let (program, args) = command.split_first().ok_or_else(|| {
+        ctx::CTXError::InvalidConfigurations {
+            message: "picker.command is empty, check configurations"
+                .to_string(),
+            source: None,
+        }
+    })?;
+    let failed = |e: anyhow::Error| ctx::CTXError::InvalidConfigurations {
+        message: format!("failed to run the picker ({})", program),
+        source: Some(e),
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| failed(e.into()))?;
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for item in items.iter() {
+            // the finder may quit before reading all the items
+            if writeln!(stdin, "{}", item).is_err() {
+                break;
+            }
+        }
+    }
+    let output = child.wait_with_output().map_err(|e| failed(e.into()))?;
+    let selected = String::from_utf8_lossy(&output.stdout);
+    let selected = selected.lines().next().unwrap_or_default();
+    // finders exit with non-zero codes when they are cancelled or nothing matches
+    if !output.status.success() || selected.is_empty() {
+        return Err(ctx::CTXError::NoContextIsSelected { source: None });
+    }
+    items.iter().position(|i| i == selected).ok_or_else(|| {
+        failed(anyhow!("unknown item is selected: {}", selected))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    #[rstest(::trace)]
+    #[case("prod-admin", NameMatch::Exact("prod-admin".to_string()))]
+    #[case("prod-ad", NameMatch::Unique("prod-admin".to_string()))]
+    #[case("stgdev", NameMatch::Unique("staging-dev".to_string()))]
+    #[case("prod", NameMatch::Ambiguous(vec!["prod-admin".to_string(), "prod-readonly".to_string()]))]
+    #[case("qux", NameMatch::Nothing)]
+    fn test_match_name(#[case] query: &str, #[case] expect: NameMatch) {
+        let names = ["prod-admin", "prod-readonly", "staging-dev"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(expect, match_name(&names, query));
+    }
+
+    #[rstest(::trace)]
+    #[case("2\n", Some(1))]
+    #[case("foo\n", Some(2))]
+    #[case("\n", None)]
+    #[case("4\n", None)]
+    #[case("qux\n", None)]
+    fn test_pick_by_line(#[case] answer: &str, #[case] expect: Option<usize>) {
+        let items =
+            vec!["bar".to_string(), "baz".to_string(), "foo".to_string()];
+        let mut output = vec![];
+        let actual = pick_by_line(
+            &mut Cursor::new(answer),
+            &mut output,
+            &items,
+            "profile:",
+        );
+        assert_eq!(expect, actual.ok());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("1. bar\n2. baz\n3. foo\nprofile: "));
+    }
+
+    #[rstest(::trace)]
+    #[case("sed -n 2p", Some(1))]
+    // cancelled by ESC in fzf
+    #[case("cat >/dev/null; exit 130", None)]
+    #[case("echo qux", None)]
+    fn test_pick_by_command(
+        #[case] script: &str,
+        #[case] expect: Option<usize>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let finder = fake_executable(&tmpdir, "fzf", script);
+        let items =
+            vec!["bar".to_string(), "baz".to_string(), "foo".to_string()];
+        let actual = pick_by_command(
+            &[
+                finder.to_str().unwrap().to_string(),
+                "--height=40%".to_string(),
+            ],
+            &items,
+        );
+        assert_eq!(expect, actual.ok());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::configs::PromptConfigs;
+use crate::ctx;
+use crate::state::{self, PROMPT_CACHE_PATH};
+use crate::template;
+use crate::view::format_remaining;
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Active context recorded at switch time, so that prompts are rendered without parsing
+/// the credentials on every command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptCache {
+    pub context: String,
+    pub expiration: Option<DateTime<Utc>>,
+    /// Modified time of the credentials when the cache was written
+    credentials_modified: SystemTime,
+}
+
+fn modified<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl PromptCache {
+    pub fn new<P: AsRef<Path>>(
+        context: &str,
+        expiration: Option<DateTime<Utc>>,
+        credentials_path: P,
+    ) -> Option<Self> {
+        Some(Self {
+            context: context.to_string(),
+            expiration,
+            credentials_modified: modified(credentials_path)?,
+        })
+    }
+
+    /// Loads the cache unless the credentials were modified after it was written.
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: Option<P>,
+        credentials_path: Q,
+    ) -> Option<Self> {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| PROMPT_CACHE_PATH.clone());
+        state::load::<Self>(&path).filter(|c| {
+            modified(&credentials_path) == Some(c.credentials_modified)
+        })
+    }
+
+    pub fn dump<P: AsRef<Path>>(
+        &self,
+        path: Option<P>,
+    ) -> Result<(), ctx::CTXError> {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| PROMPT_CACHE_PATH.clone());
+        state::save(&path, self)
+    }
+}
+
+/// Renders the prompt for the active context, colored by remaining time of its session.
+///
+/// Raw escape sequences are used for colors since prompts are rendered in command substitutions,
+/// where the logger disables colors.
+pub fn render(
+    configs: &PromptConfigs,
+    name: &str,
+    expiration: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    color: bool,
+) -> Result<String, ctx::CTXError> {
+    let remaining = expiration.map(|e| (e - now).num_minutes());
+    let expired = expiration.map(|e| e <= now).unwrap_or_default();
+    let expires_in = match remaining {
+        Some(_) if expired => "expired".to_string(),
+        Some(r) => format_remaining(r),
+        None => "".to_string(),
+    };
+    let reg = template::registry();
+    let prompt = reg
+        .render_template(
+            &configs.format,
+            &json!({ "name": name, "expires_in": expires_in, "expired": expired }),
+        )
+        .map_err(|e| ctx::CTXError::InvalidConfigurations {
+            message: "failed to render prompt.format".to_string(),
+            source: Some(anyhow!("failed to render prompt {}", e)),
+        })?;
+
+    let color_code = match remaining {
+        Some(r) if color && r < configs.critical_minutes => Some(RED),
+        Some(r) if color && r < configs.warning_minutes => Some(YELLOW),
+        _ => None,
+    };
+    Ok(match color_code {
+        Some(code) => format!("{}{}{}", code, prompt, RESET),
+        None => prompt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(None, false, "foo")]
+    #[case(Some(Duration::minutes(125) + Duration::seconds(30)), false, "foo ⏳2h05m")]
+    #[case(Some(Duration::minutes(30) + Duration::seconds(30)), true, "foo ⏳30m")]
+    #[case(
+        Some(Duration::minutes(12) + Duration::seconds(30)),
+        true,
+        "\x1b[33mfoo ⏳12m\x1b[0m"
+    )]
+    #[case(
+        Some(Duration::minutes(3) + Duration::seconds(30)),
+        true,
+        "\x1b[31mfoo ⏳3m\x1b[0m"
+    )]
+    #[case(Some(Duration::minutes(3) + Duration::seconds(30)), false, "foo ⏳3m")]
+    #[case(Some(Duration::minutes(-10)), true, "\x1b[31mfoo ⏳expired\x1b[0m")]
+    fn test_render(
+        #[case] expires_in: Option<Duration>,
+        #[case] color: bool,
+        #[case] expect: &str,
+    ) {
+        let now = Utc::now();
+        let actual = render(
+            &PromptConfigs::default(),
+            "foo",
+            expires_in.map(|e| now + e),
+            now,
+            color,
+        )
+        .unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest]
+    fn test_render_with_format() {
+        let configs = PromptConfigs {
+            format: "aws:{{name}}{{#if expired}}!{{/if}}".to_string(),
+            ..Default::default()
+        };
+        let now = Utc::now();
+        let actual = render(
+            &configs,
+            "foo",
+            Some(now - Duration::minutes(1)),
+            now,
+            false,
+        )
+        .unwrap();
+        assert_eq!("aws:foo!", actual);
+    }
+
+    #[rstest]
+    fn test_prompt_cache() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("prompt.json");
+        let credentials_path = tmpdir.path().join("credentials");
+        fs::write(&credentials_path, "[foo]\n").unwrap();
+
+        let cache = PromptCache::new("foo", None, &credentials_path).unwrap();
+        cache.dump(Some(&path)).unwrap();
+        assert_eq!(
+            Some(cache),
+            PromptCache::load(Some(&path), &credentials_path)
+        );
+
+        // switching by other tools invalidates the cache
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(&credentials_path, "[bar]\n").unwrap();
+        assert_eq!(None, PromptCache::load(Some(&path), &credentials_path));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::awscli::AwsCli;
+use crate::ctx;
+use crate::state::{self, REGIONS_CACHE_PATH};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use skim::prelude::{unbounded, Key};
+use skim::{Skim, SkimItemReceiver, SkimItemSender, SkimOptions};
+
+/// Regions known at the release, used until the list is refreshed from AWS
+const BUNDLED_REGIONS: &[&str] = &[
+    "af-south-1",
+    "ap-east-1",
+    "ap-east-2",
+    "ap-northeast-1",
+    "ap-northeast-2",
+    "ap-northeast-3",
+    "ap-south-1",
+    "ap-south-2",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "ap-southeast-3",
+    "ap-southeast-4",
+    "ap-southeast-5",
+    "ap-southeast-6",
+    "ap-southeast-7",
+    "ca-central-1",
+    "ca-west-1",
+    "cn-north-1",
+    "cn-northwest-1",
+    "eu-central-1",
+    "eu-central-2",
+    "eu-north-1",
+    "eu-south-1",
+    "eu-south-2",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "il-central-1",
+    "me-central-1",
+    "me-south-1",
+    "mx-central-1",
+    "sa-east-1",
+    "us-east-1",
+    "us-east-2",
+    "us-gov-east-1",
+    "us-gov-west-1",
+    "us-west-1",
+    "us-west-2",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Regions {
+    names: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DescribeRegionsOutput {
+    regions: Vec<RegionOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RegionOutput {
+    region_name: String,
+}
+
+impl Default for Regions {
+    fn default() -> Self {
+        Self {
+            names: BUNDLED_REGIONS.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+}
+
+impl Regions {
+    /// Loads the refreshed list of regions, falling back to the bundled one.
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| REGIONS_CACHE_PATH.clone());
+        state::load::<Vec<String>>(&path)
+            .filter(|names| !names.is_empty())
+            .map(|names| Self { names })
+            .unwrap_or_default()
+    }
+
+    /// Fetches all the regions from AWS and saves them for later loads.
+    pub fn refresh<P: AsRef<Path>>(
+        cli: &AwsCli,
+        path: Option<P>,
+    ) -> Result<Self, ctx::CTXError> {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| REGIONS_CACHE_PATH.clone());
+        let output = cli.run::<DescribeRegionsOutput>(&[
+            "ec2",
+            "describe-regions",
+            "--all-regions",
+        ])?;
+        let mut names = output
+            .regions
+            .into_iter()
+            .map(|r| r.region_name)
+            .collect::<Vec<String>>();
+        names.sort();
+        let regions = Self { names };
+
+        state::save(&path, &regions.names)?;
+        Ok(regions)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Picks a region by the interactive finder.
+    pub fn pick_interactive(
+        &self,
+        skim_options: &SkimOptions,
+    ) -> Result<String, ctx::CTXError> {
+        let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) =
+            unbounded();
+        // skim shows reverse order
+        for name in self.names.iter().rev() {
+            tx_item
+                .send(Arc::new(name.clone()))
+                .context("failed to send an item to skim")
+                .map_err(|e| ctx::CTXError::UnexpectedError {
+                    source: Some(e),
+                })?;
+        }
+        drop(tx_item);
+
+        let selected_items = Skim::run_with(skim_options, Some(rx_item))
+            .map(|out| match out.final_key {
+                Key::Enter => Ok(out.selected_items),
+                _ => Err(ctx::CTXError::NoContextIsSelected { source: None }),
+            })
+            .unwrap_or(Ok(Vec::new()))?;
+        selected_items
+            .first()
+            .map(|item| item.output().to_string())
+            .ok_or(ctx::CTXError::NoContextIsSelected { source: None })
+    }
+
+    pub fn validate(&self, region: &str) -> Result<(), ctx::CTXError> {
+        if self.names.iter().any(|r| r == region) {
+            return Ok(());
+        }
+        Err(ctx::CTXError::NoSuchRegion {
+            region: region.to_string(),
+            source: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[rstest(::trace)]
+    #[case("us-east-1", true)]
+    #[case("ap-northeast-1", true)]
+    #[case("us-east-9", false)]
+    #[case("", false)]
+    fn test_regions_validate(#[case] region: &str, #[case] expect_ok: bool) {
+        let actual = Regions::default().validate(region);
+        match actual {
+            Ok(()) => assert!(expect_ok),
+            Err(ctx::CTXError::NoSuchRegion { region: actual, .. }) => {
+                assert!(!expect_ok);
+                assert_eq!(region, actual);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[rstest]
+    fn test_regions_refresh_and_load() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("cache/regions.json");
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"echo '{"Regions": [{"RegionName": "us-west-2", "OptInStatus": "opt-in-not-required"}, {"RegionName": "xx-new-1", "OptInStatus": "not-opted-in"}]}'"#,
+        );
+        let expect = vec!["us-west-2".to_string(), "xx-new-1".to_string()];
+
+        let refreshed = Regions::refresh(&cli, Some(&path)).unwrap();
+        assert_eq!(expect, refreshed.names());
+        let loaded = Regions::load(Some(&path));
+        assert_eq!(expect, loaded.names());
+    }
+
+    #[rstest]
+    fn test_regions_load_without_cache() {
+        let tmpdir = TempDir::new().unwrap();
+        let actual = Regions::load(Some(tmpdir.path().join("regions.json")));
+        assert_eq!(Regions::default(), actual);
+    }
+}
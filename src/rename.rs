@@ -0,0 +1,105 @@
+use crate::ctx;
+
+use std::collections::HashSet;
+
+use regex::Regex;
+
+/// Rename of a profile planned by `plan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Plans renames of the profiles whose whole names match the pattern, replaced by `to`
+/// with groups like `$1`, e.g. `old-(.*)` and `client-$1`.
+///
+/// Fails if a new name is empty, taken by another profile, or given to several profiles.
+pub fn plan(
+    names: &[String],
+    pattern: &str,
+    to: &str,
+) -> Result<Vec<Rename>, ctx::CTXError> {
+    let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+        ctx::CTXError::InvalidInput {
+            message: format!("invalid pattern: {}", pattern),
+            source: Some(e.into()),
+        }
+    })?;
+    let renames = names
+        .iter()
+        .filter(|n| regex.is_match(n))
+        .map(|n| Rename {
+            from: n.clone(),
+            to: regex.replace(n, to).into_owned(),
+        })
+        .filter(|r| r.from != r.to)
+        .collect::<Vec<Rename>>();
+    let renamed = renames
+        .iter()
+        .map(|r| r.from.as_str())
+        .collect::<HashSet<&str>>();
+    let mut taken = names
+        .iter()
+        .map(|n| n.as_str())
+        .filter(|n| !renamed.contains(n))
+        .collect::<HashSet<&str>>();
+    for rename in renames.iter() {
+        let conflict = if rename.to.is_empty() {
+            Some("an empty name")
+        } else if !taken.insert(&rename.to) {
+            Some("a name taken by another profile")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(ctx::CTXError::InvalidInput {
+                message: format!(
+                    "profile ({}) would be renamed to {} ({})",
+                    rename.from, conflict, rename.to
+                ),
+                source: None,
+            });
+        }
+    }
+    Ok(renames)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[rstest(::trace)]
+    #[case("old-(.*)", "client-$1", Some(vec![("old-a", "client-a"), ("old-b", "client-b")]))]
+    // names are matched as a whole
+    #[case("old", "new", Some(vec![]))]
+    #[case("old-(a)", "$1-renamed", Some(vec![("old-a", "a-renamed")]))]
+    #[case("old-(.*)", "new-a", None)]
+    #[case("old-(.*)", "$1", None)]
+    #[case("old-(.*)", "", None)]
+    #[case("old-(", "new", None)]
+    fn test_plan(
+        #[case] pattern: &str,
+        #[case] to: &str,
+        #[case] expect: Option<Vec<(&str, &str)>>,
+    ) {
+        let actual =
+            plan(&names(&["a", "new-a", "old-a", "old-b"]), pattern, to);
+        let expect = expect.map(|renames| {
+            renames
+                .into_iter()
+                .map(|(from, to)| Rename {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+                .collect::<Vec<Rename>>()
+        });
+        assert_eq!(expect, actual.ok());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::configs::SamlConfigs;
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context};
+
+/// Logs in to the identity provider by saml2aws and returns issued STS credentials.
+///
+/// saml2aws prints credentials to stdout in the `credential_process` format,
+/// while its prompts for passwords or MFA codes are left on the terminal.
+pub fn login(
+    configs: &SamlConfigs,
+    profile: &str,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let mut command = Command::new(&configs.program);
+    command
+        .args(["login", "--skip-prompt", "--credential-process"])
+        .arg(format!("--profile={}", profile))
+        .arg(format!("--url={}", configs.url))
+        .arg(format!("--idp-provider={}", configs.idp_provider));
+    if let Some(role_arn) = &configs.role_arn {
+        command.arg(format!("--role={}", role_arn));
+    }
+    if let Some(username) = &configs.username {
+        command.arg(format!("--username={}", username));
+    }
+    if let Some(mfa) = &configs.mfa {
+        command.arg(format!("--mfa={}", mfa));
+    }
+    if let Some(session_duration) = configs.session_duration {
+        command.arg(format!("--session-duration={}", session_duration));
+    }
+
+    let failed = || {
+        format!(
+            "failed to login by SAML for profile ({}), check configurations",
+            profile
+        )
+    };
+    command.stdin(Stdio::inherit()).stderr(Stdio::inherit());
+    let output = command
+        .output()
+        .context(format!("failed to execute `{}`", configs.program))
+        .map_err(|e| ctx::CTXError::InvalidConfigurations {
+            message: failed(),
+            source: Some(e),
+        })?;
+    if !output.status.success() {
+        return Err(ctx::CTXError::InvalidConfigurations {
+            message: failed(),
+            source: Some(anyhow!("saml2aws failed, check output logs")),
+        });
+    }
+    serde_json::from_slice::<TemporaryCredentials>(&output.stdout)
+        .context("failed to parse credentials printed by saml2aws")
+        .map_err(|e| ctx::CTXError::InvalidConfigurations {
+            message: failed(),
+            source: Some(e),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    #[fixture]
+    pub fn saml_configs() -> SamlConfigs {
+        SamlConfigs {
+            url: "https://example.okta.com/home/amazon_aws/xxx".to_string(),
+            idp_provider: "Okta".to_string(),
+            role_arn: Some("arn:aws:iam::123456789012:role/foo".to_string()),
+            username: None,
+            mfa: None,
+            session_duration: None,
+            program: "saml2aws".to_string(),
+        }
+    }
+
+    #[rstest]
+    fn test_login(mut saml_configs: SamlConfigs) {
+        let tmpdir = TempDir::new().unwrap();
+        saml_configs.program = fake_executable(
+            &tmpdir,
+            "saml2aws",
+            r#"case "$*" in *"--credential-process"*"--role=arn:aws:iam::123456789012:role/foo"*) ;; *) exit 1 ;; esac
+echo '{"Version":1,"AccessKeyId":"XXXXXXXXXXX","SecretAccessKey":"XXXXXXXXXXX","SessionToken":"XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX","Expiration":"2022-01-01T00:00:00Z"}'"#,
+        )
+        .to_str()
+        .unwrap()
+        .to_string();
+        let expect = TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"
+                .to_string(),
+            expiration: DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+                .unwrap()
+                .into(),
+        };
+
+        let actual = login(&saml_configs, "foo").unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest(::trace)]
+    #[case("exit 1")]
+    #[case("echo 'not json'")]
+    fn test_login_failed(mut saml_configs: SamlConfigs, #[case] script: &str) {
+        let tmpdir = TempDir::new().unwrap();
+        saml_configs.program = fake_executable(&tmpdir, "saml2aws", script)
+            .to_str()
+            .unwrap()
+            .to_string();
+        let actual = login(&saml_configs, "foo");
+        match actual {
+            Err(ctx::CTXError::InvalidConfigurations { message, .. }) => {
+                assert_eq!(
+                    "failed to login by SAML for profile (foo), check configurations",
+                    message
+                )
+            }
+            _ => panic!("unexpected result: {:?}", actual),
+        }
+    }
+}
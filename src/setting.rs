@@ -0,0 +1,123 @@
+use crate::ctx;
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// Change of a key of a profile in the config planned by `AWS::plan_settings`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    pub profile: String,
+    pub key: String,
+    /// Value before the change, `None` if the key is added
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Fails unless the key can be written in a section of the config, like `output` or `cli_pager`.
+pub fn validate_key(key: &str) -> Result<(), ctx::CTXError> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(ctx::CTXError::InvalidInput {
+            message: format!("invalid key: {}", key),
+            source: None,
+        })
+    }
+}
+
+/// Selects the profiles tagged by `tag` whose whole names match `glob` like `prod-*`,
+/// where `*` matches any characters and `?` matches one. Both are optional.
+pub fn select(
+    names: &[String],
+    tags: &HashMap<String, Vec<String>>,
+    tag: Option<&str>,
+    glob: Option<&str>,
+) -> Result<Vec<String>, ctx::CTXError> {
+    let regex = glob.map(glob_regex).transpose()?;
+    Ok(names
+        .iter()
+        .filter(|n| {
+            tag.is_none_or(|tag| {
+                tags.get(*n).is_some_and(|t| t.iter().any(|t| t == tag))
+            })
+        })
+        .filter(|n| regex.as_ref().is_none_or(|r| r.is_match(n)))
+        .cloned()
+        .collect())
+}
+
+pub(crate) fn glob_regex(glob: &str) -> Result<Regex, ctx::CTXError> {
+    let pattern = glob
+        .split('*')
+        .map(|part| {
+            part.split('?')
+                .map(regex::escape)
+                .collect::<Vec<String>>()
+                .join(".")
+        })
+        .collect::<Vec<String>>()
+        .join(".*");
+    Regex::new(&format!("^{}$", pattern)).map_err(|e| {
+        ctx::CTXError::InvalidInput {
+            message: format!("invalid pattern: {}", glob),
+            source: Some(e.into()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[rstest(::trace)]
+    #[case("output", true)]
+    #[case("s3.max_concurrent_requests", true)]
+    #[case("cli_pager", true)]
+    #[case("", false)]
+    #[case("bad key", false)]
+    #[case("key=value", false)]
+    fn test_validate_key(#[case] key: &str, #[case] expect: bool) {
+        assert_eq!(expect, validate_key(key).is_ok());
+    }
+
+    #[rstest(::trace)]
+    #[case(None, None, vec!["dev", "prod-admin", "prod-readonly", "prod.x"])]
+    #[case(None, Some("prod-*"), vec!["prod-admin", "prod-readonly"])]
+    #[case(None, Some("prod?x"), vec!["prod.x"])]
+    #[case(None, Some("*admin"), vec!["prod-admin"])]
+    #[case(Some("prod"), None, vec!["prod-admin", "prod-readonly"])]
+    #[case(Some("prod"), Some("*-admin"), vec!["prod-admin"])]
+    #[case(Some("unknown"), None, vec![])]
+    fn test_select(
+        #[case] tag: Option<&str>,
+        #[case] glob: Option<&str>,
+        #[case] expect: Vec<&str>,
+    ) {
+        let tags = HashMap::from([
+            ("prod-admin".to_string(), vec!["prod".to_string()]),
+            (
+                "prod-readonly".to_string(),
+                vec!["prod".to_string(), "readonly".to_string()],
+            ),
+        ]);
+        let actual = select(
+            &names(&["dev", "prod-admin", "prod-readonly", "prod.x"]),
+            &tags,
+            tag,
+            glob,
+        )
+        .unwrap();
+        assert_eq!(names(&expect), actual);
+    }
+}
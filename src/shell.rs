@@ -0,0 +1,173 @@
+use clap::ArgEnum;
+
+/// Shells whose integration is generated by `shell-init` subcommand.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Functions shared by bash and zsh, which differ only in their hooks.
+const POSIX_FUNCTIONS: &str = r#"# export credentials of the active context or a profile to the shell
+awsctx-env() {
+    local vars
+    if [ -n "$1" ]; then
+        vars="$(command awsctx env --profile "$1")" || return
+    else
+        vars="$(command awsctx env)" || return
+    fi
+    eval "$vars"
+}
+
+# run a command with credentials of a profile, like `awsctx-exec prod aws s3 ls`
+awsctx-exec() {
+    if [ "$#" -lt 2 ]; then
+        echo "usage: awsctx-exec <profile> <command> [args...]" >&2
+        return 2
+    fi
+    local vars
+    vars="$(command awsctx env --profile "$1")" || return
+    shift
+    (eval "$vars" && exec "$@")
+}
+
+# switch to the profile of `.awsctx-profile` in the directory or its parents
+__awsctx_directory() {
+    local dir="$PWD" profile
+    while :; do
+        if [ -f "$dir/.awsctx-profile" ]; then
+            read -r profile < "$dir/.awsctx-profile"
+            if [ -n "$profile" ] && [ "$profile" != "$__awsctx_directory_profile" ]; then
+                __awsctx_directory_profile="$profile"
+                command awsctx active-context --check "$profile" ||
+                    command awsctx use-context "$profile"
+            fi
+            return
+        fi
+        [ -z "$dir" ] && break
+        dir="${dir%/*}"
+    done
+    __awsctx_directory_profile=
+}
+
+# keep the active context in `AWSCTX_PROMPT` for prompts
+__awsctx_prompt() {
+    AWSCTX_PROMPT="$(command awsctx prompt)"
+}
+"#;
+
+const BASH_HOOKS: &str = r#"
+__awsctx_hook() {
+    local status=$?
+    if [ "$__awsctx_pwd" != "$PWD" ]; then
+        __awsctx_pwd="$PWD"
+        __awsctx_directory
+    fi
+    __awsctx_prompt
+    return $status
+}
+
+if [[ ";${PROMPT_COMMAND:-};" != *";__awsctx_hook;"* ]]; then
+    PROMPT_COMMAND="__awsctx_hook;${PROMPT_COMMAND:-}"
+fi
+"#;
+
+const ZSH_HOOKS: &str = r#"
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd __awsctx_directory
+add-zsh-hook precmd __awsctx_prompt
+__awsctx_directory
+"#;
+
+const FISH_INIT: &str = r#"# export credentials of the active context or a profile to the shell
+function awsctx-env
+    if set -q argv[1]
+        set -f vars (command awsctx env --profile $argv[1]); or return
+    else
+        set -f vars (command awsctx env); or return
+    end
+    string join \n -- $vars | source
+end
+
+# run a command with credentials of a profile, like `awsctx-exec prod aws s3 ls`
+function awsctx-exec
+    if test (count $argv) -lt 2
+        echo "usage: awsctx-exec <profile> <command> [args...]" >&2
+        return 2
+    end
+    set -f vars (command awsctx env --profile $argv[1] --format dotenv); or return
+    for var in $vars
+        set -l pair (string split -m 1 = -- $var)
+        set -fx $pair[1] $pair[2]
+    end
+    command $argv[2..-1]
+end
+
+# switch to the profile of `.awsctx-profile` in the directory or its parents
+function __awsctx_directory --on-variable PWD
+    set -l dir $PWD
+    while true
+        if test -f "$dir/.awsctx-profile"
+            read -l profile <"$dir/.awsctx-profile"
+            if test -n "$profile"; and test "$profile" != "$__awsctx_directory_profile"
+                set -g __awsctx_directory_profile $profile
+                command awsctx active-context --check $profile
+                or command awsctx use-context $profile
+            end
+            return
+        end
+        test -z "$dir"; and break
+        set dir (string replace -r '/[^/]*$' '' -- $dir)
+    end
+    set -g __awsctx_directory_profile
+end
+
+# keep the active context in `AWSCTX_PROMPT` for prompts
+function __awsctx_prompt --on-event fish_prompt
+    set -g AWSCTX_PROMPT (command awsctx prompt)
+end
+
+__awsctx_directory
+"#;
+
+/// Renders functions and hooks of the shell, evaluated in its rc file like
+/// `eval "$(awsctx shell-init bash)"`.
+pub fn render_init(shell: ShellKind) -> String {
+    match shell {
+        ShellKind::Bash => format!("{}{}", POSIX_FUNCTIONS, BASH_HOOKS),
+        ShellKind::Zsh => format!("{}{}", POSIX_FUNCTIONS, ZSH_HOOKS),
+        ShellKind::Fish => FISH_INIT.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(ShellKind::Bash, "PROMPT_COMMAND")]
+    #[case(ShellKind::Zsh, "add-zsh-hook chpwd")]
+    #[case(ShellKind::Fish, "--on-variable PWD")]
+    fn test_render_init(#[case] shell: ShellKind, #[case] hook: &str) {
+        let actual = render_init(shell);
+        for name in ["awsctx-env", "awsctx-exec", "AWSCTX_PROMPT", hook] {
+            assert!(actual.contains(name), "{} is missing", name);
+        }
+    }
+
+    #[rstest]
+    fn test_render_init_bash_syntax() {
+        let status = Command::new("bash")
+            .arg("-n")
+            .arg("-c")
+            .arg(render_init(ShellKind::Bash))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
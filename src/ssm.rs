@@ -0,0 +1,64 @@
+use crate::awscli::AwsCli;
+use crate::ctx;
+
+use std::env;
+use std::ffi::OsStr;
+use std::process::ExitStatus;
+
+const PLUGIN: &str = "session-manager-plugin";
+
+/// Fails unless the Session Manager plugin, which the AWS CLI runs for sessions,
+/// is found in the directories of `path` like `PATH`.
+pub fn ensure_plugin(path: Option<&OsStr>) -> Result<(), ctx::CTXError> {
+    let found = path
+        .map(|path| {
+            env::split_paths(path).any(|dir| dir.join(PLUGIN).is_file())
+        })
+        .unwrap_or_default();
+    if found {
+        Ok(())
+    } else {
+        Err(ctx::CTXError::AWSRequestFailed {
+            message: format!(
+                "{} is not found in PATH, install it by https://docs.aws.amazon.com/systems-manager/latest/userguide/session-manager-working-with-install-plugin.html",
+                PLUGIN
+            ),
+            source: None,
+        })
+    }
+}
+
+/// Starts a session of Session Manager to the instance with credentials of `envs`,
+/// passing `args` like `--document-name` to `aws ssm start-session`. Returns when the session ends.
+pub fn start_session(
+    cli: &AwsCli,
+    target: &str,
+    args: &[String],
+    envs: &[(String, String)],
+) -> Result<ExitStatus, ctx::CTXError> {
+    ensure_plugin(env::var_os("PATH").as_deref())?;
+    let mut command = vec!["ssm", "start-session", "--target", target];
+    command.extend(args.iter().map(|a| a.as_str()));
+    cli.run_attached(&command, envs)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    #[rstest]
+    fn test_ensure_plugin() {
+        let tmpdir = TempDir::new().unwrap();
+        assert!(ensure_plugin(Some(tmpdir.path().as_os_str())).is_err());
+        fake_executable(&tmpdir, PLUGIN, "exit 0");
+        let path =
+            env::join_paths(["/nonexistent", tmpdir.path().to_str().unwrap()])
+                .unwrap();
+        assert!(ensure_plugin(Some(&path)).is_ok());
+        assert!(ensure_plugin(None).is_err());
+    }
+}
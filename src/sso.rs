@@ -0,0 +1,406 @@
+use crate::awscli::AwsCli;
+use crate::config::Config;
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+use crate::sso_cache::{SsoToken, SsoTokenCache};
+
+use std::thread;
+use std::time;
+
+use anyhow::anyhow;
+use chrono::{Duration, TimeZone, Utc};
+use serde::Deserialize;
+use url::Url;
+
+/// Shown in IAM Identity Center as the application asking for the authorization
+const CLIENT_NAME: &str = "awsctx";
+const DEVICE_CODE_GRANT_TYPE: &str =
+    "urn:ietf:params:oauth:grant-type:device_code";
+/// Added to the polling interval when IAM Identity Center asks to slow down
+const SLOW_DOWN_SECONDS: u64 = 5;
+
+/// Settings of IAM Identity Center of a profile, given directly by `sso_*` items
+/// or through the `[sso-session name]` section referred by `sso_session`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsoProfile {
+    pub session: Option<String>,
+    pub start_url: String,
+    pub region: String,
+    pub account_id: String,
+    pub role_name: String,
+}
+
+impl SsoProfile {
+    /// Reads the settings of the profile, `None` if it is not an SSO profile.
+    pub fn from_config(
+        config: &Config,
+        profile: &str,
+    ) -> Result<Option<Self>, ctx::CTXError> {
+        let items = match config.profile(profile) {
+            Some(items) => items,
+            None => return Ok(None),
+        };
+        let session = items.get("sso_session");
+        if session.is_none() && items.get("sso_start_url").is_none() {
+            return Ok(None);
+        }
+        let section = match session {
+            Some(session) => Some(
+                config
+                    .section(&format!("sso-session {}", session))
+                    .ok_or_else(|| ctx::CTXError::InvalidConfigurations {
+                        message: format!(
+                            "sso-session ({}) of profile ({}) is not found",
+                            session, profile
+                        ),
+                        source: None,
+                    })?,
+            ),
+            None => None,
+        };
+        let get = |key: &str| {
+            section
+                .and_then(|s| s.get(key))
+                .or_else(|| items.get(key))
+                .map(|v| v.to_string())
+                .ok_or_else(|| ctx::CTXError::InvalidConfigurations {
+                    message: format!(
+                        "{} of SSO profile ({}) is not set",
+                        key, profile
+                    ),
+                    source: None,
+                })
+        };
+        Ok(Some(Self {
+            session: session.map(|s| s.to_string()),
+            start_url: get("sso_start_url")?,
+            region: get("sso_region")?,
+            account_id: get("sso_account_id")?,
+            role_name: get("sso_role_name")?,
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegisterClientOutput {
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri_complete: String,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTokenOutput {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    /// Milliseconds since the epoch
+    expiration: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetRoleCredentialsOutput {
+    role_credentials: RoleCredentials,
+}
+
+fn authorize_device<F: Fn(&Url)>(
+    cli: &AwsCli,
+    profile: &SsoProfile,
+    open: F,
+) -> Result<SsoToken, ctx::CTXError> {
+    let region = profile.region.as_str();
+    let client = cli.run::<RegisterClientOutput>(&[
+        "sso-oidc",
+        "register-client",
+        "--client-name",
+        CLIENT_NAME,
+        "--client-type",
+        "public",
+        "--region",
+        region,
+        "--no-sign-request",
+    ])?;
+    let authorization = cli.run::<DeviceAuthorization>(&[
+        "sso-oidc",
+        "start-device-authorization",
+        "--client-id",
+        &client.client_id,
+        "--client-secret",
+        &client.client_secret,
+        "--start-url",
+        &profile.start_url,
+        "--region",
+        region,
+        "--no-sign-request",
+    ])?;
+    info!(
+        "open {} and confirm the code {} to sign in",
+        authorization.verification_uri_complete, authorization.user_code
+    );
+    match Url::parse(&authorization.verification_uri_complete) {
+        Ok(url) => open(&url),
+        Err(e) => debug!("invalid verification URL: {:?}", e),
+    }
+
+    let deadline = time::Instant::now()
+        + time::Duration::from_secs(authorization.expires_in);
+    let mut interval = authorization.interval.unwrap_or(1);
+    loop {
+        let result = cli.run::<CreateTokenOutput>(&[
+            "sso-oidc",
+            "create-token",
+            "--client-id",
+            &client.client_id,
+            "--client-secret",
+            &client.client_secret,
+            "--grant-type",
+            DEVICE_CODE_GRANT_TYPE,
+            "--device-code",
+            &authorization.device_code,
+            "--region",
+            region,
+            "--no-sign-request",
+        ]);
+        match result {
+            Ok(output) => {
+                return Ok(SsoToken {
+                    start_url: profile.start_url.clone(),
+                    region: profile.region.clone(),
+                    access_token: output.access_token,
+                    expires_at: Utc::now()
+                        + Duration::seconds(output.expires_in),
+                })
+            }
+            // the user has not confirmed the code yet
+            Err(ctx::CTXError::AWSRequestFailed {
+                source: Some(e), ..
+            }) if e.to_string().contains("AuthorizationPendingException")
+                || e.to_string().contains("SlowDownException") =>
+            {
+                if e.to_string().contains("SlowDownException") {
+                    interval += SLOW_DOWN_SECONDS;
+                }
+                if time::Instant::now() >= deadline {
+                    return Err(ctx::CTXError::AWSRequestFailed {
+                        message: "the code of SSO sign-in expired before it was confirmed"
+                            .to_string(),
+                        source: None,
+                    });
+                }
+                thread::sleep(time::Duration::from_secs(interval));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns credentials of the role of the SSO profile with the token.
+pub fn get_role_credentials(
+    cli: &AwsCli,
+    profile: &SsoProfile,
+    token: &SsoToken,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let output = cli.run::<GetRoleCredentialsOutput>(&[
+        "sso",
+        "get-role-credentials",
+        "--role-name",
+        &profile.role_name,
+        "--account-id",
+        &profile.account_id,
+        "--access-token",
+        &token.access_token,
+        "--region",
+        &profile.region,
+        "--no-sign-request",
+    ])?;
+    let credentials = output.role_credentials;
+    let expiration = Utc
+        .timestamp_millis_opt(credentials.expiration)
+        .single()
+        .ok_or_else(|| ctx::CTXError::AWSRequestFailed {
+            message: "unexpected output of `aws sso get-role-credentials`"
+                .to_string(),
+            source: Some(anyhow!(
+                "invalid expiration: {}",
+                credentials.expiration
+            )),
+        })?;
+    Ok(TemporaryCredentials {
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        session_token: credentials.session_token,
+        expiration,
+    })
+}
+
+/// Returns credentials of the role of the SSO profile, signing in by the device authorization flow
+/// unless a token in `cache` is still valid. New tokens are cached for the AWS CLI too.
+pub fn login<F: Fn(&Url)>(
+    cli: &AwsCli,
+    profile: &SsoProfile,
+    cache: &SsoTokenCache,
+    open: F,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let token = match cache.get(profile, Utc::now()) {
+        Some(token) => {
+            debug!("use cached SSO token for {}", profile.start_url);
+            token
+        }
+        None => {
+            let token = authorize_device(cli, profile, open)?;
+            // failing to cache the token does not affect the sign-in itself
+            if let Err(e) = cache.put(profile, &token) {
+                debug!("failed to write SSO token cache: {:?}", e);
+            }
+            token
+        }
+    };
+    get_role_credentials(cli, profile, &token)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use chrono::DateTime;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[fixture]
+    fn sso_profile() -> SsoProfile {
+        SsoProfile {
+            session: Some("corp".to_string()),
+            start_url: "https://example.awsapps.com/start".to_string(),
+            region: "us-east-1".to_string(),
+            account_id: "123456789012".to_string(),
+            role_name: "Admin".to_string(),
+        }
+    }
+
+    /// Behaves as `aws sso-oidc` and `aws sso`, answering `create-token` with
+    /// AuthorizationPendingException at first.
+    fn fake_sso_cli(tmpdir: &TempDir) -> AwsCli {
+        fake_aws_cli(
+            tmpdir,
+            &format!(
+                r#"case "$1 $2" in
+    "sso-oidc register-client") echo '{{"clientId": "client", "clientSecret": "secret"}}' ;;
+    "sso-oidc start-device-authorization") echo '{{"deviceCode": "device", "userCode": "ABCD-EFGH", "verificationUriComplete": "https://device.sso.us-east-1.amazonaws.com/?user_code=ABCD-EFGH", "expiresIn": 600, "interval": 0}}' ;;
+    "sso-oidc create-token")
+        [ -f {pending} ] || {{ touch {pending}; echo "An error occurred (AuthorizationPendingException)" >&2; exit 255; }}
+        echo '{{"accessToken": "token", "expiresIn": 28800, "tokenType": "Bearer"}}' ;;
+    "sso get-role-credentials")
+        case "$*" in *"--access-token token "*"--region us-east-1"*) ;; *) exit 1 ;; esac
+        echo '{{"roleCredentials": {{"accessKeyId": "XXXXXXXXXXX", "secretAccessKey": "XXXXXXXXXXX", "sessionToken": "XXXX", "expiration": 1640995200000}}}}' ;;
+    *) exit 1 ;;
+esac"#,
+                pending = tmpdir.path().join("pending").display()
+            ),
+        )
+    }
+
+    #[rstest]
+    fn test_sso_profile_from_config() {
+        let config = Config::from_text(
+            r#"[profile foo]
+sso_account_id=123456789012
+sso_role_name=Admin
+sso_session=corp
+
+[profile bar]
+sso_account_id=123456789012
+sso_role_name=ReadOnly
+sso_region=us-west-2
+sso_start_url=https://example.awsapps.com/start
+
+[profile baz]
+sso_session=unknown
+
+[profile qux]
+region=us-east-1
+
+[sso-session corp]
+sso_region=us-east-1
+sso_start_url=https://example.awsapps.com/start
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(sso_profile()),
+            SsoProfile::from_config(&config, "foo").unwrap()
+        );
+        assert_eq!(
+            Some(SsoProfile {
+                session: None,
+                region: "us-west-2".to_string(),
+                role_name: "ReadOnly".to_string(),
+                ..sso_profile()
+            }),
+            SsoProfile::from_config(&config, "bar").unwrap()
+        );
+        assert!(matches!(
+            SsoProfile::from_config(&config, "baz"),
+            Err(ctx::CTXError::InvalidConfigurations { .. })
+        ));
+        assert_eq!(None, SsoProfile::from_config(&config, "qux").unwrap());
+    }
+
+    #[rstest]
+    fn test_login(sso_profile: SsoProfile) {
+        let tmpdir = TempDir::new().unwrap();
+        let cache = SsoTokenCache::new(
+            Some(tmpdir.path().join("sso/cache")),
+            HashMap::new(),
+        );
+        let cli = fake_sso_cli(&tmpdir);
+        let opened = RefCell::new(vec![]);
+        let expect = TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXX".to_string(),
+            expiration: DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+                .unwrap()
+                .into(),
+        };
+
+        let actual = login(&cli, &sso_profile, &cache, |url| {
+            opened.borrow_mut().push(url.to_string())
+        })
+        .unwrap();
+        assert_eq!(expect, actual);
+        assert_eq!(
+            vec!["https://device.sso.us-east-1.amazonaws.com/?user_code=ABCD-EFGH"],
+            *opened.borrow()
+        );
+        let token = cache.get(&sso_profile, Utc::now()).unwrap();
+        assert_eq!("token", token.access_token);
+
+        // the cached token is used without signing in again
+        let actual =
+            login(&cli, &sso_profile, &cache, |_| panic!("sign in again"))
+                .unwrap();
+        assert_eq!(expect, actual);
+    }
+}
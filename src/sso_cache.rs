@@ -0,0 +1,192 @@
+use crate::ctx;
+use crate::file::write_atomic;
+use crate::sso::SsoProfile;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use dirs::home_dir;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// Cached tokens are treated as expired this long before their actual expiration
+const REFRESH_WINDOW_SECONDS: i64 = 5 * 60;
+
+/// Directory of SSO tokens shared with the AWS CLI.
+pub static SSO_CACHE_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    let mut path = home_dir().unwrap();
+    path.push(".aws/sso/cache");
+    path
+});
+
+/// Access token of IAM Identity Center in the format of the AWS CLI cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoToken {
+    pub start_url: String,
+    pub region: String,
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SsoToken {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at - Duration::seconds(REFRESH_WINDOW_SECONDS) > now
+    }
+}
+
+/// Tokens of IAM Identity Center shared by every profile of the same session,
+/// stored in the same files as the AWS CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsoTokenCache {
+    dir: PathBuf,
+    /// Names of sessions keyed by start URLs, which profiles without `sso_session` share
+    sessions: HashMap<String, String>,
+}
+
+impl SsoTokenCache {
+    pub fn new<P: AsRef<Path>>(
+        dir: Option<P>,
+        sessions: HashMap<String, String>,
+    ) -> Self {
+        Self {
+            dir: dir
+                .map(|d| d.as_ref().to_path_buf())
+                .unwrap_or_else(|| SSO_CACHE_DIR.clone()),
+            sessions,
+        }
+    }
+
+    /// Returns the session name or the start URL the token of the profile is cached by.
+    pub fn key<'a>(&'a self, profile: &'a SsoProfile) -> &'a str {
+        profile
+            .session
+            .as_deref()
+            .or_else(|| {
+                self.sessions.get(&profile.start_url).map(|s| s.as_str())
+            })
+            .unwrap_or(&profile.start_url)
+    }
+
+    /// Returns the path of the token, named by SHA-1 of the key in the same way as the AWS CLI.
+    fn path(&self, profile: &SsoProfile) -> PathBuf {
+        self.dir.join(format!(
+            "{:x}.json",
+            Sha1::digest(self.key(profile).as_bytes())
+        ))
+    }
+
+    /// Returns the cached token of the profile unless it is for another start URL or about to expire.
+    pub fn get(
+        &self,
+        profile: &SsoProfile,
+        now: DateTime<Utc>,
+    ) -> Option<SsoToken> {
+        let path = self.path(profile);
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<SsoToken>(&contents) {
+            Ok(token)
+                if token.start_url == profile.start_url
+                    && token.is_fresh(now) =>
+            {
+                Some(token)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                debug!("ignore broken {}: {:?}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn put(
+        &self,
+        profile: &SsoProfile,
+        token: &SsoToken,
+    ) -> Result<(), ctx::CTXError> {
+        let path = self.path(profile);
+        fs::create_dir_all(&self.dir)
+            .context("failed to create SSO cache directory")
+            .and_then(|_| {
+                serde_json::to_vec(token)
+                    .context("failed to serialize SSO token")
+            })
+            .and_then(|contents| {
+                write_atomic(&path, &contents, 0o600)
+                    .context(format!("failed to write {}", path.display()))
+            })
+            .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const START_URL: &str = "https://example.awsapps.com/start";
+
+    fn sso_profile(session: Option<&str>) -> SsoProfile {
+        SsoProfile {
+            session: session.map(|s| s.to_string()),
+            start_url: START_URL.to_string(),
+            region: "us-east-1".to_string(),
+            account_id: "123456789012".to_string(),
+            role_name: "Admin".to_string(),
+        }
+    }
+
+    #[rstest(::trace)]
+    // SHA-1 of "corp", the name the AWS CLI gives to the token of the session
+    #[case(Some("corp"), false, "ee0bfd2552fbd840c02cc48b6e823320543c450f")]
+    // profiles without sessions share the token of the session of their start URL
+    #[case(None, true, "ee0bfd2552fbd840c02cc48b6e823320543c450f")]
+    #[case(None, false, "e8be5486177c5b5392bd9aa76563515b29358e6e")]
+    fn test_sso_token_cache_path(
+        #[case] session: Option<&str>,
+        #[case] shared: bool,
+        #[case] expect: &str,
+    ) {
+        let sessions = if shared {
+            hashmap! {START_URL.to_string() => "corp".to_string()}
+        } else {
+            HashMap::new()
+        };
+        let cache = SsoTokenCache::new(Some("/cache"), sessions);
+        assert_eq!(
+            Path::new("/cache").join(format!("{}.json", expect)),
+            cache.path(&sso_profile(session))
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(START_URL, Duration::hours(1), true)]
+    #[case(START_URL, Duration::minutes(1), false)]
+    #[case("https://other.awsapps.com/start", Duration::hours(1), false)]
+    fn test_sso_token_cache_get(
+        #[case] start_url: &str,
+        #[case] expires_in: Duration,
+        #[case] expect_hit: bool,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let cache = SsoTokenCache::new(Some(tmpdir.path()), HashMap::new());
+        let token = SsoToken {
+            start_url: start_url.to_string(),
+            region: "us-east-1".to_string(),
+            access_token: "token".to_string(),
+            expires_at: Utc::now() + expires_in,
+        };
+        cache.put(&sso_profile(Some("corp")), &token).unwrap();
+        assert_eq!(
+            expect_hit,
+            cache.get(&sso_profile(Some("corp")), Utc::now()).is_some()
+        );
+    }
+}
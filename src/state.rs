@@ -0,0 +1,419 @@
+use crate::ctx;
+use crate::file::create_private_file;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use dirs::home_dir;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Version of formats of state and cache files.
+/// Files written in another version are ignored, so bump it on incompatible changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Max number of entries kept in the history
+const MAX_HISTORY_ENTRIES: usize = 100;
+
+pub static HISTORY_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("history.json"));
+pub static BACKUPS_DIR: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("backups"));
+pub static TRASH_DIR: Lazy<PathBuf> = Lazy::new(|| state_dir().join("trash"));
+pub static ADHOC_CONTEXTS_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("adhoc_contexts.json"));
+pub static MANIFEST_STATE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("manifest.json"));
+pub static METRICS_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("metrics.json"));
+pub static LOCK_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("lock.json"));
+pub static DAEMON_SOCKET_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("daemon.sock"));
+pub static UPDATE_CHECK_PATH: Lazy<PathBuf> =
+    Lazy::new(|| state_dir().join("update_check.json"));
+pub static ACCOUNT_ALIASES_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("account_aliases.json"));
+pub static CREDENTIALS_CACHE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("credentials.json"));
+pub static REGIONS_CACHE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("regions.json"));
+pub static IDENTITIES_CACHE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("identities.json"));
+pub static COSTS_CACHE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("costs.json"));
+pub static PROMPT_CACHE_PATH: Lazy<PathBuf> =
+    Lazy::new(|| cache_dir().join("prompt.json"));
+
+/// Variable of the directory holding every file of awsctx, for isolated setups like tests
+/// or separate clients on one machine
+pub const AWSCTX_HOME_VAR: &str = "AWSCTX_HOME";
+
+fn absolute_dir<F: Fn(&str) -> Option<String>>(
+    var: &F,
+    key: &str,
+) -> Option<PathBuf> {
+    var(key)
+        .map(PathBuf::from)
+        // relative paths are invalid in the XDG base directory specification, and so in `AWSCTX_HOME`
+        .filter(|p| p.is_absolute())
+}
+
+/// Resolves `subdir` of `AWSCTX_HOME` if it is set, or the directory of awsctx in the XDG directory.
+fn resolve_dir<F: Fn(&str) -> Option<String>>(
+    var: F,
+    subdir: &str,
+    xdg_key: &str,
+    fallback: &str,
+) -> PathBuf {
+    match absolute_dir(&var, AWSCTX_HOME_VAR) {
+        Some(home) => home.join(subdir),
+        None => absolute_dir(&var, xdg_key)
+            .unwrap_or_else(|| home_dir().unwrap().join(fallback))
+            .join("awsctx"),
+    }
+}
+
+/// Directory given by `AWSCTX_HOME`, which holds `configs.yaml`, `state` and `cache` instead of their defaults.
+pub fn awsctx_home() -> Option<PathBuf> {
+    absolute_dir(&|k: &str| env::var(k).ok(), AWSCTX_HOME_VAR)
+}
+
+/// Directory of data which should persist, `$AWSCTX_HOME/state`,
+/// `$XDG_STATE_HOME/awsctx` or `~/.local/state/awsctx`
+pub fn state_dir() -> PathBuf {
+    resolve_dir(
+        |k| env::var(k).ok(),
+        "state",
+        "XDG_STATE_HOME",
+        ".local/state",
+    )
+}
+
+/// Directory of data which can be recreated, `$AWSCTX_HOME/cache`,
+/// `$XDG_CACHE_HOME/awsctx` or `~/.cache/awsctx`
+pub fn cache_dir() -> PathBuf {
+    resolve_dir(|k| env::var(k).ok(), "cache", "XDG_CACHE_HOME", ".cache")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u32,
+    data: T,
+}
+
+/// Loads data of a state or cache file.
+/// Missing, broken or differently versioned files are never fatal and just give `None`.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Versioned<T>>(&contents) {
+        Ok(v) if v.version == FORMAT_VERSION => Some(v.data),
+        Ok(v) => {
+            debug!(
+                "ignore {} of format version {}",
+                path.to_str().unwrap(),
+                v.version
+            );
+            None
+        }
+        Err(e) => {
+            debug!("ignore broken {}: {:?}", path.to_str().unwrap(), e);
+            None
+        }
+    }
+}
+
+/// Creates the parent directory of the file and serializes data in the versioned format.
+fn prepare<T: Serialize>(path: &Path, data: &T) -> Result<String> {
+    path.parent()
+        .map_or_else(
+            || {
+                Err(anyhow!(
+                    "no parent directory found for path: {}",
+                    path.to_str().unwrap()
+                ))
+            },
+            |parent| {
+                fs::create_dir_all(parent)
+                    .context("failed to create state directory")
+            },
+        )
+        .and_then(|_| {
+            serde_json::to_string(&Versioned {
+                version: FORMAT_VERSION,
+                data,
+            })
+            .context("failed to serialize state")
+        })
+}
+
+/// Saves data to a state or cache file readable only by the user.
+pub fn save<T: Serialize>(path: &Path, data: &T) -> Result<(), ctx::CTXError> {
+    prepare(path, data)
+        .and_then(|contents| {
+            let mut file = create_private_file(path).context(format!(
+                "failed to create {}",
+                path.to_str().unwrap()
+            ))?;
+            file.write_all(contents.as_bytes())
+                .and_then(|_| file.flush())
+                .context(format!("failed to write {}", path.to_str().unwrap()))
+        })
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })
+}
+
+/// Saves data to a state file only if it does not exist, returning whether it was saved.
+///
+/// The data is written to a temporary file first and hard-linked to the path, which fails
+/// if the path exists, so that processes racing to create the file never see it half-written
+/// and only one of them wins.
+pub fn save_new<T: Serialize>(
+    path: &Path,
+    data: &T,
+) -> Result<bool, ctx::CTXError> {
+    let contents = prepare(path, data)
+        .map_err(|e| ctx::CTXError::UnexpectedError { source: Some(e) })?;
+    // unique also among threads of the process
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let linked = create_private_file(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.flush()
+        })
+        .and_then(|_| fs::hard_link(&tmp, path));
+    let _ = fs::remove_file(&tmp);
+    match linked {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(ctx::CTXError::UnexpectedError {
+            source: Some(anyhow::Error::from(e).context(format!(
+                "failed to create {}",
+                path.to_str().unwrap()
+            ))),
+        }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub context: String,
+    pub used_at: DateTime<Utc>,
+}
+
+/// Contexts used so far, the most recent first.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| HISTORY_PATH.clone());
+        Self {
+            entries: load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    pub fn record(&mut self, context: &str, used_at: DateTime<Utc>) {
+        self.entries.insert(
+            0,
+            HistoryEntry {
+                context: context.to_string(),
+                used_at,
+            },
+        );
+        self.entries.truncate(MAX_HISTORY_ENTRIES);
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Returns when the context was used last time.
+    pub fn last_used(&self, context: &str) -> Option<DateTime<Utc>> {
+        self.entries
+            .iter()
+            .find(|e| e.context == context)
+            .map(|e| e.used_at)
+    }
+
+    pub fn dump(&self) -> Result<(), ctx::CTXError> {
+        save(&self.path, &self.entries)
+    }
+}
+
+/// Aliases of AWS accounts keyed by account IDs.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct AccountAliases {
+    path: PathBuf,
+    aliases: HashMap<String, String>,
+}
+
+impl AccountAliases {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| ACCOUNT_ALIASES_PATH.clone());
+        Self {
+            aliases: load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    pub fn get(&self, account: &str) -> Option<&str> {
+        self.aliases.get(account).map(|a| a.as_str())
+    }
+
+    pub fn insert(&mut self, account: &str, alias: &str) {
+        self.aliases.insert(account.to_string(), alias.to_string());
+    }
+
+    pub fn dump(&self) -> Result<(), ctx::CTXError> {
+        save(&self.path, &self.aliases)
+    }
+}
+
+/// Contexts created by `assume`, with when their credentials expire.
+/// They are removed from the files once expired, unless active.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct AdhocContexts {
+    path: PathBuf,
+    expires_at: HashMap<String, DateTime<Utc>>,
+}
+
+impl AdhocContexts {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| ADHOC_CONTEXTS_PATH.clone());
+        Self {
+            expires_at: load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.expires_at.contains_key(name)
+    }
+
+    pub fn insert(&mut self, name: &str, expires_at: DateTime<Utc>) {
+        self.expires_at.insert(name.to_string(), expires_at);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.expires_at.remove(name);
+    }
+
+    /// Returns names of expired contexts, sorted by names.
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut names = self
+            .expires_at
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    pub fn dump(&self) -> Result<(), ctx::CTXError> {
+        save(&self.path, &self.expires_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(Some("/work/client-a"), Some("/xdg/state"), "/work/client-a/state")]
+    #[case(Some("relative"), Some("/xdg/state"), "/xdg/state/awsctx")]
+    #[case(None, Some("/xdg/state"), "/xdg/state/awsctx")]
+    fn test_resolve_dir(
+        #[case] awsctx_home: Option<&str>,
+        #[case] xdg_state_home: Option<&str>,
+        #[case] expect: &str,
+    ) {
+        let var = |k: &str| match k {
+            AWSCTX_HOME_VAR => awsctx_home.map(|h| h.to_string()),
+            "XDG_STATE_HOME" => xdg_state_home.map(|h| h.to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            PathBuf::from(expect),
+            resolve_dir(var, "state", "XDG_STATE_HOME", ".local/state")
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(r#"{"version": 1, "data": ["foo"]}"#, Some(vec!["foo".to_string()]))]
+    #[case(r#"{"version": 0, "data": ["foo"]}"#, None)]
+    #[case(r#"["foo"]"#, None)]
+    #[case("{broken", None)]
+    fn test_load(#[case] contents: &str, #[case] expect: Option<Vec<String>>) {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state.json");
+        fs::write(&path, contents).unwrap();
+        assert_eq!(expect, load::<Vec<String>>(&path));
+    }
+
+    #[rstest]
+    fn test_history_dump_and_load() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state/history.json");
+        let now = Utc::now();
+
+        let mut history = History::load(Some(&path));
+        history.record("foo", now - Duration::minutes(10));
+        history.record("bar", now - Duration::minutes(5));
+        history.record("foo", now);
+        history.dump().unwrap();
+
+        let actual = History::load(Some(&path));
+        assert_eq!(
+            vec!["foo", "bar", "foo"],
+            actual
+                .entries()
+                .iter()
+                .map(|e| e.context.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(Some(now), actual.last_used("foo"));
+        assert_eq!(None, actual.last_used("baz"));
+    }
+
+    #[rstest]
+    fn test_account_aliases_dump_and_load() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("cache/account_aliases.json");
+
+        let mut aliases = AccountAliases::load(Some(&path));
+        aliases.insert("123456789012", "foo");
+        aliases.dump().unwrap();
+
+        let actual = AccountAliases::load(Some(&path));
+        assert_eq!(Some("foo"), actual.get("123456789012"));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::configs::PromptConfigs;
+use crate::ctx;
+use crate::view::format_remaining;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ArgEnum;
+use serde_json::json;
+
+/// Formats of status bars printed by `status` subcommand.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// Custom modules of waybar with `return-type: json`
+    Waybar,
+    /// Blocks of i3blocks with `format=json`
+    I3blocks,
+    /// Plugins of xbar, which are lines of text
+    Xbar,
+}
+
+/// States of the session of the active context, by thresholds of `prompt` configurations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryState {
+    NoContext,
+    /// Sessions without expiration, like long-term access keys
+    Ok,
+    Warning,
+    Critical,
+    Expired,
+}
+
+impl ExpiryState {
+    pub fn of(
+        expires_at: Option<DateTime<Utc>>,
+        configs: &PromptConfigs,
+        now: DateTime<Utc>,
+    ) -> Self {
+        match expires_at.map(|e| (e - now).num_minutes()) {
+            _ if expires_at.is_some_and(|e| e <= now) => Self::Expired,
+            Some(r) if r < configs.critical_minutes => Self::Critical,
+            Some(r) if r < configs.warning_minutes => Self::Warning,
+            _ => Self::Ok,
+        }
+    }
+
+    /// Class of styles of the bars
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::NoContext => "none",
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+            Self::Expired => "expired",
+        }
+    }
+
+    fn color(&self) -> Option<&'static str> {
+        match self {
+            Self::Warning => Some("#FFAA00"),
+            Self::Critical | Self::Expired => Some("#FF5555"),
+            _ => None,
+        }
+    }
+}
+
+/// Renders the status of the active context for the bar, or a placeholder without it.
+pub fn render(
+    context: Option<&ctx::ContextMetadata>,
+    format: StatusFormat,
+    configs: &PromptConfigs,
+    now: DateTime<Utc>,
+) -> String {
+    let state = context.map_or(ExpiryState::NoContext, |c| {
+        ExpiryState::of(c.expires_at, configs, now)
+    });
+    let (text, tooltip) = match context {
+        Some(c) => {
+            let text = match (state, c.expires_at) {
+                (ExpiryState::Expired, _) => format!("{} expired", c.name),
+                (_, Some(e)) => format!(
+                    "{} {}",
+                    c.name,
+                    format_remaining((e - now).num_minutes())
+                ),
+                (_, None) => c.name.clone(),
+            };
+            let tooltip = [
+                Some(format!("profile: {}", c.name)),
+                c.account.as_ref().map(|a| format!("account: {}", a)),
+                c.region.as_ref().map(|r| format!("region: {}", r)),
+                c.expires_at.map(|e| {
+                    format!(
+                        "expires at: {}",
+                        e.to_rfc3339_opts(SecondsFormat::Secs, true)
+                    )
+                }),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>();
+            (text, tooltip)
+        }
+        None => ("no context".to_string(), vec![]),
+    };
+    match format {
+        StatusFormat::Waybar => json!({
+            "text": text,
+            "tooltip": tooltip.join("\n"),
+            "class": state.class(),
+            "alt": state.class(),
+        })
+        .to_string(),
+        StatusFormat::I3blocks => {
+            let mut block = json!({
+                "full_text": text,
+                "short_text": context.map_or("-", |c| c.name.as_str()),
+            });
+            if let Some(color) = state.color() {
+                block["color"] = json!(color);
+            }
+            block.to_string()
+        }
+        StatusFormat::Xbar => {
+            // `|` separates parameters of lines in xbar
+            let mut lines = vec![match state.color() {
+                Some(color) => format!("{} | color={}", text, color),
+                None => text,
+            }];
+            lines.push("---".to_string());
+            lines.extend(tooltip.iter().map(|t| t.replace('|', "/")));
+            lines.join("\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(None, ExpiryState::Ok)]
+    #[case(Some(Duration::minutes(30)), ExpiryState::Ok)]
+    #[case(Some(Duration::minutes(10)), ExpiryState::Warning)]
+    #[case(Some(Duration::minutes(3)), ExpiryState::Critical)]
+    #[case(Some(Duration::minutes(-1)), ExpiryState::Expired)]
+    fn test_expiry_state_of(
+        #[case] remaining: Option<Duration>,
+        #[case] expect: ExpiryState,
+    ) {
+        let now = Utc::now();
+        assert_eq!(
+            expect,
+            ExpiryState::of(
+                remaining.map(|r| now + r),
+                &PromptConfigs::default(),
+                now
+            )
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        StatusFormat::Waybar,
+        r#"{"alt":"warning","class":"warning","text":"foo 10m","tooltip":"profile: foo\naccount: 123456789012\nexpires at: 2022-08-01T12:10:30Z"}"#
+    )]
+    #[case(
+        StatusFormat::I3blocks,
+        r##"{"color":"#FFAA00","full_text":"foo 10m","short_text":"foo"}"##
+    )]
+    #[case(
+        StatusFormat::Xbar,
+        "foo 10m | color=#FFAA00\n---\nprofile: foo\naccount: 123456789012\nexpires at: 2022-08-01T12:10:30Z"
+    )]
+    fn test_render(#[case] format: StatusFormat, #[case] expect: &str) {
+        let now = DateTime::parse_from_rfc3339("2022-08-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let context = ctx::ContextMetadata {
+            name: "foo".to_string(),
+            active: true,
+            account: Some("123456789012".to_string()),
+            expires_at: Some(now + Duration::seconds(630)),
+            ..Default::default()
+        };
+        assert_eq!(
+            expect,
+            render(Some(&context), format, &PromptConfigs::default(), now)
+        );
+    }
+
+    #[rstest]
+    fn test_render_without_context() {
+        assert_eq!(
+            r#"{"alt":"none","class":"none","text":"no context","tooltip":""}"#,
+            render(
+                None,
+                StatusFormat::Waybar,
+                &PromptConfigs::default(),
+                Utc::now()
+            )
+        );
+    }
+}
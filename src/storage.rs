@@ -0,0 +1,336 @@
+use crate::backup;
+use crate::config::Config;
+use crate::configs::{BackupsConfigs, CredentialsRoute, FileModesConfigs};
+use crate::creds::Credentials;
+use crate::ctx;
+use crate::file::{version_control_root, Fingerprint, TextEncoding};
+use crate::state::BACKUPS_DIR;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use chrono::Utc;
+
+/// Backend which the config and credentials of AWS are loaded from and stored to,
+/// so that contexts are switched in the same way whatever stores them.
+pub trait Storage: Debug {
+    fn load_config(&self) -> Result<Config, ctx::CTXError>;
+    fn load_credentials(&self) -> Result<Credentials, ctx::CTXError>;
+    fn store_config(&self, config: &Config) -> Result<(), ctx::CTXError>;
+    fn store_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<(), ctx::CTXError>;
+    /// Fails with `FileChangedOnDisk` if another process changed the stored data
+    /// after it was loaded or stored last. Backends without the detection never fail.
+    fn ensure_unchanged(&self) -> Result<(), ctx::CTXError> {
+        Ok(())
+    }
+
+    /// Paths and contents of the files which storing the data would write, to preview them.
+    /// Backends without files write none.
+    fn pending_writes(
+        &self,
+        _credentials: Option<&Credentials>,
+        _config: Option<&Config>,
+    ) -> Result<Vec<(PathBuf, String)>, ctx::CTXError> {
+        Ok(vec![])
+    }
+}
+
+fn warn_non_utf8(path: &Path, encoding: TextEncoding) {
+    if encoding != TextEncoding::Utf8 {
+        warn!(
+            "{} is not UTF-8, read as {:?} and written back in the same bytes",
+            path.to_str().unwrap(),
+            encoding
+        );
+    }
+}
+
+/// Storage of INI files shared with the AWS CLI, `~/.aws/config` and `~/.aws/credentials` by default.
+#[derive(Debug)]
+pub struct FileStorage {
+    config_path: PathBuf,
+    credentials_path: PathBuf,
+    // fingerprints of the files as loaded or last written, to detect changes by other processes
+    config_fingerprint: RefCell<Option<Fingerprint>>,
+    // of the credentials file and the routed files
+    credentials_fingerprints: RefCell<HashMap<PathBuf, Option<Fingerprint>>>,
+    lenient: bool,
+    protect_version_controlled: bool,
+    file_modes: FileModesConfigs,
+    backups: BackupsConfigs,
+    backups_dir: PathBuf,
+    credentials_routes: Vec<CredentialsRoute>,
+    config_fragments_dir: Option<PathBuf>,
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(credentials_path: P, config_path: P) -> Self {
+        Self {
+            config_path: config_path.as_ref().to_path_buf(),
+            credentials_path: credentials_path.as_ref().to_path_buf(),
+            config_fingerprint: RefCell::new(None),
+            credentials_fingerprints: RefCell::new(HashMap::new()),
+            lenient: false,
+            protect_version_controlled: false,
+            file_modes: FileModesConfigs::default(),
+            backups: BackupsConfigs::default(),
+            backups_dir: BACKUPS_DIR.clone(),
+            credentials_routes: vec![],
+            config_fragments_dir: None,
+        }
+    }
+
+    /// Writes credentials of profiles matching the routes to their files instead,
+    /// and reads them together with the credentials file.
+    pub fn with_credentials_routes(
+        mut self,
+        routes: Vec<CredentialsRoute>,
+    ) -> Self {
+        self.credentials_routes = routes;
+        self
+    }
+
+    /// Reads profiles of the files in the directory together with the config file, in the order of names.
+    /// Hidden files are skipped, and the files are never written.
+    pub fn with_config_fragments_dir<P: AsRef<Path>>(
+        mut self,
+        dir: Option<P>,
+    ) -> Self {
+        self.config_fragments_dir = dir.map(|d| d.as_ref().to_path_buf());
+        self
+    }
+
+    fn config_fragment_paths(&self) -> Result<Vec<PathBuf>, ctx::CTXError> {
+        let dir = match &self.config_fragments_dir {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(vec![]),
+        };
+        let entries =
+            fs::read_dir(dir).map_err(|e| ctx::CTXError::CannotReadConfig {
+                source: Some(anyhow!("failed to read {:?}: {}", dir, e)),
+            })?;
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.'))
+            })
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Skips broken sections of the credentials instead of failing, reporting their lines.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Refuses to write files resolved into working trees of version control.
+    pub fn with_protect_version_controlled(mut self, protect: bool) -> Self {
+        self.protect_version_controlled = protect;
+        self
+    }
+
+    pub fn with_file_modes(mut self, file_modes: FileModesConfigs) -> Self {
+        self.file_modes = file_modes;
+        self
+    }
+
+    /// Backs up the files into the directory before overwriting them, pruned by the policy.
+    pub fn with_backups<P: AsRef<Path>>(
+        mut self,
+        backups: BackupsConfigs,
+        dir: P,
+    ) -> Self {
+        self.backups = backups;
+        self.backups_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Failures of backups never block writes, the files are just written without them.
+    fn back_up(&self, path: &Path) {
+        if !self.backups.enabled {
+            return;
+        }
+        let now = Utc::now();
+        if let Err(e) = backup::back_up(path, &self.backups_dir, now)
+            .and_then(|_| backup::prune(&self.backups_dir, &self.backups, now))
+        {
+            warn!("failed to back up {}: {:?}", path.to_str().unwrap(), e);
+        }
+    }
+
+    /// Reads a credentials file, taking its fingerprint before loading it,
+    /// so that changes in between are never missed.
+    fn read_credentials(
+        &self,
+        path: &Path,
+    ) -> Result<Credentials, ctx::CTXError> {
+        let fingerprint = Fingerprint::of(path);
+        let credentials = if self.lenient {
+            Credentials::load_credentials_lenient(path)?
+        } else {
+            Credentials::load_credentials(path)?
+        };
+        warn_non_utf8(path, credentials.encoding());
+        for ignored in credentials.ignored_lines() {
+            warn!(
+                "ignore broken lines {}-{} of {}: {}",
+                ignored.first_line,
+                ignored.last_line,
+                path.to_str().unwrap(),
+                ignored.reason
+            );
+        }
+        self.credentials_fingerprints
+            .borrow_mut()
+            .insert(path.to_path_buf(), fingerprint);
+        Ok(credentials)
+    }
+
+    fn write_credentials(
+        &self,
+        path: &Path,
+        credentials: &Credentials,
+    ) -> Result<(), ctx::CTXError> {
+        self.ensure_writable(path).map_err(|e| {
+            ctx::CTXError::CannotWriteCredentials { source: Some(e) }
+        })?;
+        self.back_up(path);
+        credentials
+            .dump_credentials_with_mode(path, self.file_modes.credentials)?;
+        self.credentials_fingerprints
+            .borrow_mut()
+            .insert(path.to_path_buf(), Fingerprint::of(path));
+        Ok(())
+    }
+
+    /// Splits the credentials into the files of the routes, passing each with its path to `f`,
+    /// the credentials file last. Routed files are skipped unless they have profiles or exist already.
+    fn route_credentials<F>(
+        &self,
+        credentials: &Credentials,
+        mut f: F,
+    ) -> Result<(), ctx::CTXError>
+    where
+        F: FnMut(&Path, &Credentials) -> Result<(), ctx::CTXError>,
+    {
+        let mut rest = None;
+        for route in self.credentials_routes.iter() {
+            let regex = route.regex()?;
+            let (remaining, routed) = rest
+                .as_ref()
+                .unwrap_or(credentials)
+                .split_off(|name| regex.is_match(name));
+            if routed.iter_profiles().next().is_some() || route.path.exists() {
+                f(&route.path, &routed)?;
+            }
+            rest = Some(remaining);
+        }
+        f(&self.credentials_path, rest.as_ref().unwrap_or(credentials))
+    }
+
+    fn ensure_writable(&self, path: &Path) -> anyhow::Result<()> {
+        match version_control_root(path)
+            .filter(|_| self.protect_version_controlled)
+        {
+            Some(root) => Err(anyhow!(
+                "refuse to write {} in the working tree of {}",
+                path.to_str().unwrap(),
+                root.to_str().unwrap()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_config(&self) -> Result<Config, ctx::CTXError> {
+        // take fingerprints before loading, so that changes in between are never missed
+        let fingerprint = Fingerprint::of(&self.config_path);
+        let config = Config::load_config_with_fragments(
+            &self.config_path,
+            &self.config_fragment_paths()?,
+        )?;
+        warn_non_utf8(&self.config_path, config.encoding());
+        *self.config_fingerprint.borrow_mut() = fingerprint;
+        Ok(config)
+    }
+
+    fn load_credentials(&self) -> Result<Credentials, ctx::CTXError> {
+        let mut credentials = self.read_credentials(&self.credentials_path)?;
+        for route in self.credentials_routes.iter() {
+            // routed files are created by the first write to them
+            if !route.path.exists() {
+                debug!("skip missing credentials file: {:?}", route.path);
+                self.credentials_fingerprints
+                    .borrow_mut()
+                    .insert(route.path.clone(), None);
+                continue;
+            }
+            credentials.merge_file(self.read_credentials(&route.path)?);
+        }
+        Ok(credentials)
+    }
+
+    fn store_config(&self, config: &Config) -> Result<(), ctx::CTXError> {
+        self.ensure_writable(&self.config_path).map_err(|e| {
+            ctx::CTXError::CannotWriteConfig { source: Some(e) }
+        })?;
+        self.back_up(&self.config_path);
+        config
+            .dump_config_with_mode(&self.config_path, self.file_modes.config)?;
+        *self.config_fingerprint.borrow_mut() =
+            Fingerprint::of(&self.config_path);
+        Ok(())
+    }
+
+    fn store_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<(), ctx::CTXError> {
+        self.route_credentials(credentials, |path, credentials| {
+            self.write_credentials(path, credentials)
+        })
+    }
+
+    fn ensure_unchanged(&self) -> Result<(), ctx::CTXError> {
+        for (path, fingerprint) in self.credentials_fingerprints.borrow().iter()
+        {
+            Fingerprint::ensure_unchanged(fingerprint.as_ref(), path)?;
+        }
+        Fingerprint::ensure_unchanged(
+            self.config_fingerprint.borrow().as_ref(),
+            &self.config_path,
+        )
+    }
+
+    fn pending_writes(
+        &self,
+        credentials: Option<&Credentials>,
+        config: Option<&Config>,
+    ) -> Result<Vec<(PathBuf, String)>, ctx::CTXError> {
+        let mut writes = vec![];
+        if let Some(credentials) = credentials {
+            self.route_credentials(credentials, |path, credentials| {
+                writes.push((path.to_path_buf(), credentials.to_string()));
+                Ok(())
+            })?;
+        }
+        if let Some(config) = config {
+            writes.push((self.config_path.clone(), config.to_string()));
+        }
+        Ok(writes)
+    }
+}
@@ -0,0 +1,579 @@
+use crate::awscli::{run_concurrently, AwsCli};
+use crate::config::AssumedRole;
+use crate::creds::TemporaryCredentials;
+use crate::ctx;
+use crate::state::{self, IDENTITIES_CACHE_PATH};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Caller identities change only when profiles are reconfigured, so they are reused this long
+const IDENTITY_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleOutput {
+    credentials: TemporaryCredentials,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CallerIdentity {
+    pub account: String,
+    pub arn: String,
+    pub user_id: String,
+}
+
+impl CallerIdentity {
+    /// Returns the name of the role or the user, like `AdminRole` of
+    /// `arn:aws:sts::123456789012:assumed-role/AdminRole/session`.
+    pub fn name(&self) -> &str {
+        let resource = self.arn.splitn(6, ':').nth(5).unwrap_or_default();
+        let mut parts = resource.split('/');
+        match parts.next() {
+            Some("assumed-role") => parts.next().unwrap_or(resource),
+            // users may have paths like user/division/name
+            _ => resource.rsplit('/').next().unwrap_or(resource),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedIdentity {
+    identity: CallerIdentity,
+    fetched_at: DateTime<Utc>,
+}
+
+impl CachedIdentity {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now - self.fetched_at < Duration::hours(IDENTITY_TTL_HOURS)
+    }
+}
+
+/// Caller identities keyed by profile names.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct IdentitiesCache {
+    path: PathBuf,
+    entries: HashMap<String, CachedIdentity>,
+}
+
+impl IdentitiesCache {
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Self {
+        let path = path
+            .map(|p| p.as_ref().to_path_buf())
+            .unwrap_or_else(|| IDENTITIES_CACHE_PATH.clone());
+        Self {
+            entries: state::load(&path).unwrap_or_default(),
+            path,
+        }
+    }
+
+    /// Returns the identity of the profile unless it is stale.
+    pub fn get(
+        &self,
+        profile: &str,
+        now: DateTime<Utc>,
+    ) -> Option<&CallerIdentity> {
+        self.entries
+            .get(profile)
+            .filter(|c| c.is_fresh(now))
+            .map(|c| &c.identity)
+    }
+
+    pub fn insert(
+        &mut self,
+        profile: &str,
+        identity: CallerIdentity,
+        fetched_at: DateTime<Utc>,
+    ) {
+        self.entries.insert(
+            profile.to_string(),
+            CachedIdentity {
+                identity,
+                fetched_at,
+            },
+        );
+    }
+
+    pub fn dump(&mut self) -> Result<(), ctx::CTXError> {
+        let now = Utc::now();
+        self.entries.retain(|_, c| c.is_fresh(now));
+
+        state::save(&self.path, &self.entries)
+    }
+}
+
+/// Returns a session name for roles assumed by awsctx unless it is configured.
+pub fn default_role_session_name() -> String {
+    format!("awsctx-{}", Utc::now().timestamp())
+}
+
+/// Calls STS AssumeRoleWithWebIdentity with the OIDC token stored in `web_identity_token_file`.
+pub fn assume_role_with_web_identity(
+    cli: &AwsCli,
+    role_arn: &str,
+    role_session_name: &str,
+    web_identity_token_file: &Path,
+    duration_seconds: Option<i64>,
+    region: Option<&str>,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    if !web_identity_token_file.is_file() {
+        return Err(ctx::CTXError::InvalidConfigurations {
+            message: format!(
+                "web identity token file ({}) is not found",
+                web_identity_token_file.display()
+            ),
+            source: None,
+        });
+    }
+    // pass the token by file to keep it out of the process list
+    let token = format!("file://{}", web_identity_token_file.display());
+    let duration_seconds = duration_seconds.map(|d| d.to_string());
+    let mut args = vec![
+        "sts",
+        "assume-role-with-web-identity",
+        "--role-arn",
+        role_arn,
+        "--role-session-name",
+        role_session_name,
+        "--web-identity-token",
+        &token,
+        // the token itself authenticates the request
+        "--no-sign-request",
+    ];
+    if let Some(duration_seconds) = duration_seconds.as_ref() {
+        args.extend(["--duration-seconds", duration_seconds]);
+    }
+    if let Some(region) = region {
+        args.extend(["--region", region]);
+    }
+    cli.run::<AssumeRoleOutput>(&args).map(|o| o.credentials)
+}
+
+/// Calls STS AssumeRole with credentials of the profile.
+pub fn assume_role(
+    cli: &AwsCli,
+    profile: &str,
+    role_arn: &str,
+    role_session_name: &str,
+    duration_seconds: Option<i64>,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let duration_seconds = duration_seconds.map(|d| d.to_string());
+    let mut args = vec![
+        "sts",
+        "assume-role",
+        "--profile",
+        profile,
+        "--role-arn",
+        role_arn,
+        "--role-session-name",
+        role_session_name,
+    ];
+    if let Some(duration_seconds) = duration_seconds.as_ref() {
+        args.extend(["--duration-seconds", duration_seconds]);
+    }
+    cli.run::<AssumeRoleOutput>(&args).map(|o| o.credentials)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSessionTokenOutput {
+    credentials: TemporaryCredentials,
+}
+
+/// Assumes the roles of the chain in turn, the first one with credentials of the source profile
+/// and the others with ones of the previous role, and returns credentials of the last one.
+/// Roles with `mfa_serial` are assumed with `token_code`.
+pub fn assume_role_chain(
+    cli: &AwsCli,
+    source_profile: &str,
+    roles: &[AssumedRole],
+    token_code: Option<&str>,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let mut credentials: Option<TemporaryCredentials> = None;
+    for role in roles.iter() {
+        let role_session_name = role
+            .role_session_name
+            .clone()
+            .unwrap_or_else(default_role_session_name);
+        let duration_seconds = role.duration_seconds.map(|d| d.to_string());
+        let mut args = vec![
+            "sts",
+            "assume-role",
+            "--role-arn",
+            &role.role_arn,
+            "--role-session-name",
+            &role_session_name,
+        ];
+        if let Some(external_id) = role.external_id.as_ref() {
+            args.extend(["--external-id", external_id]);
+        }
+        if let (Some(serial), Some(code)) =
+            (role.mfa_serial.as_ref(), token_code)
+        {
+            args.extend(["--serial-number", serial, "--token-code", code]);
+        }
+        if let Some(duration_seconds) = duration_seconds.as_ref() {
+            args.extend(["--duration-seconds", duration_seconds]);
+        }
+        debug!(
+            "assume role of profile ({}): {}",
+            role.profile, role.role_arn
+        );
+        let output = match credentials.as_ref() {
+            Some(previous) => cli
+                .clone()
+                .with_credentials(previous)
+                .run::<AssumeRoleOutput>(&args)?,
+            None => {
+                args.extend(["--profile", source_profile]);
+                cli.run::<AssumeRoleOutput>(&args)?
+            }
+        };
+        credentials = Some(output.credentials);
+    }
+    credentials.ok_or_else(|| ctx::CTXError::InvalidConfigurations {
+        message: format!("no role to assume from profile ({})", source_profile),
+        source: None,
+    })
+}
+
+/// Calls STS GetSessionToken with long-lived keys of the profile and a code of its MFA device.
+pub fn get_session_token(
+    cli: &AwsCli,
+    profile: &str,
+    serial: &str,
+    token_code: &str,
+    duration_seconds: Option<i64>,
+) -> Result<TemporaryCredentials, ctx::CTXError> {
+    let duration_seconds = duration_seconds.map(|d| d.to_string());
+    let mut args = vec![
+        "sts",
+        "get-session-token",
+        "--profile",
+        profile,
+        "--serial-number",
+        serial,
+        "--token-code",
+        token_code,
+    ];
+    if let Some(duration_seconds) = duration_seconds.as_ref() {
+        args.extend(["--duration-seconds", duration_seconds]);
+    }
+    cli.run::<GetSessionTokenOutput>(&args)
+        .map(|o| o.credentials)
+}
+
+/// Returns the name of an ad-hoc context of the role, like `AdminRole@123456789012`
+/// of `arn:aws:iam::123456789012:role/path/AdminRole`.
+pub fn adhoc_context_name(role_arn: &str) -> Option<String> {
+    let mut parts = role_arn.splitn(6, ':');
+    let (
+        Some("arn"),
+        Some(_),
+        Some("iam"),
+        Some(_),
+        Some(account),
+        Some(resource),
+    ) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    )
+    else {
+        return None;
+    };
+    let role = resource.strip_prefix("role/")?.rsplit('/').next()?;
+    if account.is_empty() || role.is_empty() {
+        return None;
+    }
+    Some(format!("{}@{}", role, account))
+}
+
+/// Calls STS GetCallerIdentity with credentials of the profile.
+pub fn get_caller_identity(
+    cli: &AwsCli,
+    profile: &str,
+) -> Result<CallerIdentity, ctx::CTXError> {
+    cli.run::<CallerIdentity>(&[
+        "sts",
+        "get-caller-identity",
+        "--profile",
+        profile,
+    ])
+}
+
+/// Returns caller identities of the profiles, calling GetCallerIdentity for ones missing in the cache
+/// with at most `parallelism` concurrent requests.
+/// Profiles which fail to call it give `None`, and fetched identities are stored in the cache.
+pub fn resolve_identities(
+    cli: &AwsCli,
+    cache: &mut IdentitiesCache,
+    profiles: &[String],
+    parallelism: usize,
+    now: DateTime<Utc>,
+) -> Vec<Option<CallerIdentity>> {
+    let stale = profiles
+        .iter()
+        .filter(|p| cache.get(p, now).is_none())
+        .collect::<Vec<&String>>();
+    let fetched = run_concurrently(&stale, parallelism, |profile| {
+        get_caller_identity(cli, profile)
+    });
+    for (profile, result) in stale.into_iter().zip(fetched) {
+        match result {
+            Ok(identity) => cache.insert(profile, identity, now),
+            Err(e) => debug!(
+                "failed to resolve identity of profile ({}): {:?}",
+                profile, e
+            ),
+        }
+    }
+    profiles
+        .iter()
+        .map(|p| cache.get(p, now).cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::DateTime;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[fixture]
+    pub fn assume_role_output() -> String {
+        r#"{
+    "Credentials": {
+        "AccessKeyId": "XXXXXXXXXXX",
+        "SecretAccessKey": "XXXXXXXXXXX",
+        "SessionToken": "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX",
+        "Expiration": "2022-01-01T00:00:00+00:00"
+    },
+    "AssumedRoleUser": {
+        "AssumedRoleId": "AROAXXXXXXXXXXXXXXXXX:awsctx",
+        "Arn": "arn:aws:sts::123456789012:assumed-role/foo/awsctx"
+    }
+}"#
+        .to_string()
+    }
+
+    #[rstest]
+    fn test_assume_role_with_web_identity(assume_role_output: String) {
+        let tmpdir = TempDir::new().unwrap();
+        let token_file = tmpdir.path().join("token");
+        fs::write(&token_file, "token").unwrap();
+        // fail unless the token is passed by file
+        let cli = fake_aws_cli(
+            &tmpdir,
+            &format!(
+                r#"case "$*" in *"--web-identity-token file://"*) ;; *) exit 1 ;; esac
+cat <<'EOF'
+{}
+EOF"#,
+                assume_role_output
+            ),
+        );
+        let expect = TemporaryCredentials {
+            access_key_id: "XXXXXXXXXXX".to_string(),
+            secret_access_key: "XXXXXXXXXXX".to_string(),
+            session_token: "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX"
+                .to_string(),
+            expiration: DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+                .unwrap()
+                .into(),
+        };
+
+        let actual = assume_role_with_web_identity(
+            &cli,
+            "arn:aws:iam::123456789012:role/foo",
+            "awsctx",
+            &token_file,
+            Some(3600),
+            None,
+        )
+        .unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest]
+    fn test_assume_role_chain(assume_role_output: String) {
+        let tmpdir = TempDir::new().unwrap();
+        // the first role is assumed by the profile and the second one by credentials of the first
+        let cli = fake_aws_cli(
+            &tmpdir,
+            &format!(
+                r#"case "$*" in
+    *"role/dev "*"--profile base"*) ;;
+    *"role/prod "*"--external-id secret --serial-number mfa --token-code 123456"*) [ "$AWS_ACCESS_KEY_ID" = "XXXXXXXXXXX" ] || exit 1 ;;
+    *) exit 1 ;;
+esac
+cat <<'EOF'
+{}
+EOF"#,
+                assume_role_output
+            ),
+        );
+        let role = |profile: &str, external_id: Option<&str>| AssumedRole {
+            profile: profile.to_string(),
+            role_arn: format!("arn:aws:iam::123456789012:role/{}", profile),
+            external_id: external_id.map(|e| e.to_string()),
+            mfa_serial: external_id.map(|_| "mfa".to_string()),
+            role_session_name: None,
+            duration_seconds: None,
+        };
+
+        let actual = assume_role_chain(
+            &cli,
+            "base",
+            &[role("dev", None), role("prod", Some("secret"))],
+            Some("123456"),
+        )
+        .unwrap();
+        assert_eq!("XXXXXXXXXXX", actual.access_key_id);
+        assert!(assume_role_chain(&cli, "base", &[role("prod", None)], None)
+            .is_err());
+    }
+
+    #[rstest]
+    fn test_get_session_token(assume_role_output: String) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            &format!(
+                r#"[ "$*" = "sts get-session-token --profile foo --serial-number arn:aws:iam::123456789012:mfa/foo --token-code 123456 --duration-seconds 900 --output json --cli-connect-timeout=10 --cli-read-timeout=30" ] || exit 1
+cat <<'EOF'
+{}
+EOF"#,
+                assume_role_output
+            ),
+        );
+        let actual = get_session_token(
+            &cli,
+            "foo",
+            "arn:aws:iam::123456789012:mfa/foo",
+            "123456",
+            Some(900),
+        )
+        .unwrap();
+        assert_eq!("XXXXXXXXXXX", actual.access_key_id);
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        "arn:aws:iam::123456789012:role/AdminRole",
+        Some("AdminRole@123456789012")
+    )]
+    #[case(
+        "arn:aws:iam::123456789012:role/path/to/Dev",
+        Some("Dev@123456789012")
+    )]
+    #[case("arn:aws:iam::123456789012:user/foo", None)]
+    #[case("arn:aws:iam:::role/AdminRole", None)]
+    #[case("AdminRole", None)]
+    fn test_adhoc_context_name(
+        #[case] role_arn: &str,
+        #[case] expect: Option<&str>,
+    ) {
+        assert_eq!(expect.map(|e| e.to_string()), adhoc_context_name(role_arn));
+    }
+
+    #[rstest]
+    fn test_get_caller_identity() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"[ "$3 $4" = "--profile foo" ] || exit 1
+echo '{"UserId": "AROAXXXXXXXXXXXXXXXXX:foo", "Account": "123456789012", "Arn": "arn:aws:sts::123456789012:assumed-role/admin/foo"}'"#,
+        );
+        let expect = CallerIdentity {
+            account: "123456789012".to_string(),
+            arn: "arn:aws:sts::123456789012:assumed-role/admin/foo".to_string(),
+            user_id: "AROAXXXXXXXXXXXXXXXXX:foo".to_string(),
+        };
+        let actual = get_caller_identity(&cli, "foo").unwrap();
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest]
+    fn test_assume_role_with_web_identity_without_token_file() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(&tmpdir, "exit 1");
+        let actual = assume_role_with_web_identity(
+            &cli,
+            "arn:aws:iam::123456789012:role/foo",
+            "awsctx",
+            &tmpdir.path().join("unknown"),
+            None,
+            None,
+        );
+        match actual {
+            Err(ctx::CTXError::InvalidConfigurations { .. }) => (),
+            _ => panic!("unexpected result: {:?}", actual),
+        }
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        "arn:aws:sts::123456789012:assumed-role/AdminRole/awsctx",
+        "AdminRole"
+    )]
+    #[case("arn:aws:iam::123456789012:user/division/alice", "alice")]
+    #[case("arn:aws:iam::123456789012:root", "root")]
+    fn test_caller_identity_name(#[case] arn: &str, #[case] expect: &str) {
+        let identity = CallerIdentity {
+            account: "123456789012".to_string(),
+            arn: arn.to_string(),
+            user_id: "AIDAXXXXXXXXXXXXXXXXX".to_string(),
+        };
+        assert_eq!(expect, identity.name());
+    }
+
+    #[rstest]
+    fn test_resolve_identities() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("cache/identities.json");
+        let now = Utc::now();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"case "$*" in
+    *"--profile bar"*) echo '{"Account": "123456789012", "Arn": "arn:aws:iam::123456789012:user/bar", "UserId": "AIDAXXXXXXXXXXXXXXXXX"}' ;;
+    *) echo "An error occurred (ExpiredToken)" >&2; exit 255 ;;
+esac"#,
+        );
+        let cached = CallerIdentity {
+            account: "210987654321".to_string(),
+            arn: "arn:aws:sts::210987654321:assumed-role/ReadOnly/foo"
+                .to_string(),
+            user_id: "AROAXXXXXXXXXXXXXXXXX:foo".to_string(),
+        };
+        let mut cache = IdentitiesCache::load(Some(&path));
+        cache.insert("foo", cached.clone(), now);
+
+        let profiles = ["bar", "baz", "foo"].map(|p| p.to_string());
+        let actual = resolve_identities(&cli, &mut cache, &profiles, 2, now)
+            .iter()
+            .map(|i| i.as_ref().map(|i| i.name().to_string()))
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(
+            vec![Some("bar".to_string()), None, Some("ReadOnly".to_string())],
+            actual
+        );
+
+        cache.dump().unwrap();
+        let actual = IdentitiesCache::load(Some(&path));
+        assert!(actual.get("bar", now).is_some());
+        assert_eq!(Some(&cached), actual.get("foo", now));
+    }
+}
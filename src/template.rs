@@ -0,0 +1,25 @@
+use handlebars::Handlebars;
+
+/// Returns a registry of Handlebars templates of configurations, like auth commands and formats
+/// of listings, which render values as they are without escapes for HTML.
+pub fn registry<'a>() -> Handlebars<'a> {
+    let mut reg = Handlebars::new();
+    reg.register_escape_fn(handlebars::no_escape);
+    reg
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    fn test_registry() {
+        let actual = registry()
+            .render_template("{{profile}}", &json!({ "profile": "R&D's" }))
+            .unwrap();
+        assert_eq!("R&D's", actual);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::ctx;
+use crate::state::{self, TRASH_DIR};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Sections of a deleted profile kept in the state directory, until it is restored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletedProfile {
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    /// Items of the section in the credentials
+    pub credentials: Option<HashMap<String, String>>,
+    /// Items of the section in the config
+    pub config: Option<HashMap<String, String>>,
+}
+
+fn path_of<P: AsRef<Path>>(name: &str, dir: Option<P>) -> PathBuf {
+    dir.map(|d| d.as_ref().to_path_buf())
+        .unwrap_or_else(|| TRASH_DIR.clone())
+        // never escape the directory by names with separators
+        .join(format!("{}.json", name.replace('/', "%2F")))
+}
+
+impl DeletedProfile {
+    pub fn load<P: AsRef<Path>>(name: &str, dir: Option<P>) -> Option<Self> {
+        state::load(&path_of(name, dir))
+    }
+
+    pub fn dump<P: AsRef<Path>>(
+        &self,
+        dir: Option<P>,
+    ) -> Result<(), ctx::CTXError> {
+        state::save(&path_of(&self.name, dir), self)
+    }
+
+    /// Removes the snippet once the profile is restored.
+    pub fn discard<P: AsRef<Path>>(
+        &self,
+        dir: Option<P>,
+    ) -> Result<(), ctx::CTXError> {
+        fs::remove_file(path_of(&self.name, dir)).map_err(|e| {
+            ctx::CTXError::UnexpectedError {
+                source: Some(e.into()),
+            }
+        })
+    }
+}
+
+/// Lists deleted profiles, the most recently deleted first.
+pub fn list<P: AsRef<Path>>(dir: Option<P>) -> Vec<DeletedProfile> {
+    let dir = dir
+        .map(|d| d.as_ref().to_path_buf())
+        .unwrap_or_else(|| TRASH_DIR.clone());
+    let mut deleted = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| state::load::<DeletedProfile>(&e.path()))
+                .collect::<Vec<DeletedProfile>>()
+        })
+        .unwrap_or_default();
+    deleted.sort_by_key(|d| std::cmp::Reverse(d.deleted_at));
+    deleted
+}
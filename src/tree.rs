@@ -0,0 +1,270 @@
+use crate::config::Config;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Sections of the config which are not profiles
+const NON_PROFILE_PREFIXES: [&str; 2] = ["sso-session ", "services "];
+
+/// Prefixes of lines drawing branches of trees
+struct Connectors {
+    branch: &'static str,
+    last: &'static str,
+    pipe: &'static str,
+    blank: &'static str,
+}
+
+const BOX_DRAWING: Connectors = Connectors {
+    branch: "├── ",
+    last: "└── ",
+    pipe: "│   ",
+    blank: "    ",
+};
+
+/// Connectors of plain output, which screen readers read out without symbols
+const INDENTS: Connectors = Connectors {
+    branch: "- ",
+    last: "- ",
+    pipe: "  ",
+    blank: "  ",
+};
+
+/// Relationships of profiles assuming roles with credentials of their `source_profile`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RoleTree {
+    /// Profiles keyed by names, with their labels and source profiles
+    profiles: BTreeMap<String, (String, Option<String>)>,
+    children: BTreeMap<String, Vec<String>>,
+}
+
+impl RoleTree {
+    pub fn from_config(config: &Config) -> Self {
+        let mut tree = Self::default();
+        for profile in config.list_profiles() {
+            if NON_PROFILE_PREFIXES
+                .iter()
+                .any(|p| profile.name.starts_with(p))
+            {
+                continue;
+            }
+            let role = profile
+                .get("role_arn")
+                // arn:aws:iam::123456789012:role/path/name
+                .map(|arn| arn.rsplit('/').next().unwrap_or(arn).to_string());
+            let label = match (role, profile.get("credential_source")) {
+                (Some(role), Some(source)) => {
+                    format!("{} (role {} from {})", profile.name, role, source)
+                }
+                (Some(role), None) => {
+                    format!("{} (role {})", profile.name, role)
+                }
+                _ => profile.name.clone(),
+            };
+            tree.insert(&profile.name, &label, profile.get("source_profile"));
+        }
+        tree
+    }
+
+    fn insert(&mut self, name: &str, label: &str, source: Option<&str>) {
+        self.profiles.insert(
+            name.to_string(),
+            (label.to_string(), source.map(|s| s.to_string())),
+        );
+        if let Some(source) = source {
+            self.children
+                .entry(source.to_string())
+                .or_default()
+                .push(name.to_string());
+        }
+    }
+
+    fn source(&self, name: &str) -> Option<&str> {
+        self.profiles.get(name).and_then(|(_, s)| s.as_deref())
+    }
+
+    /// Returns chains of source profiles which loop, like `[a, b]` for `a -> b -> a`.
+    /// Each cycle starts with its smallest name.
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        let mut cycles = BTreeSet::new();
+        for name in self.profiles.keys() {
+            let mut chain: Vec<&str> = vec![];
+            let mut current = Some(name.as_str());
+            while let Some(c) = current {
+                if let Some(i) = chain.iter().position(|n| *n == c) {
+                    let mut cycle = chain[i..]
+                        .iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<String>>();
+                    let min = (0..cycle.len())
+                        .min_by_key(|j| &cycle[*j])
+                        .unwrap_or_default();
+                    cycle.rotate_left(min);
+                    cycles.insert(cycle);
+                    break;
+                }
+                chain.push(c);
+                current = self.source(c);
+            }
+        }
+        cycles.into_iter().collect()
+    }
+
+    fn render_children(
+        &self,
+        name: &str,
+        prefix: &str,
+        connectors: &Connectors,
+        visited: &mut BTreeSet<String>,
+        lines: &mut Vec<String>,
+    ) {
+        let children = self
+            .children
+            .get(name)
+            .map(|c| c.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(|c| !visited.contains(*c))
+            .cloned()
+            .collect::<Vec<String>>();
+        for (i, child) in children.iter().enumerate() {
+            let last = i == children.len() - 1;
+            let label = self.profiles.get(child).map_or(child, |(l, _)| l);
+            lines.push(format!(
+                "{}{}{}",
+                prefix,
+                if last {
+                    connectors.last
+                } else {
+                    connectors.branch
+                },
+                label
+            ));
+            visited.insert(child.clone());
+            let prefix = format!(
+                "{}{}",
+                prefix,
+                if last {
+                    connectors.blank
+                } else {
+                    connectors.pipe
+                }
+            );
+            self.render_children(child, &prefix, connectors, visited, lines);
+        }
+    }
+
+    /// Renders the profiles as trees from the ones with their own credentials.
+    /// Sources missing in the config are shown as roots, and cycles are shown at the end.
+    pub fn render(&self) -> Vec<String> {
+        self.render_with(&BOX_DRAWING)
+    }
+
+    /// Renders the profiles like `render`, indenting children instead of drawing boxes.
+    pub fn render_plain(&self) -> Vec<String> {
+        self.render_with(&INDENTS)
+    }
+
+    fn render_with(&self, connectors: &Connectors) -> Vec<String> {
+        let mut lines = vec![];
+        let mut visited = BTreeSet::new();
+        let mut roots = self
+            .profiles
+            .iter()
+            .filter(|(_, (_, s))| s.is_none())
+            .map(|(name, (label, _))| (name.clone(), label.clone()))
+            .collect::<Vec<(String, String)>>();
+        for source in self.children.keys() {
+            if !self.profiles.contains_key(source) {
+                roots.push((
+                    source.clone(),
+                    format!("{} (missing in the config)", source),
+                ));
+            }
+        }
+        roots.sort();
+        for (name, label) in roots {
+            lines.push(label);
+            visited.insert(name.clone());
+            self.render_children(
+                &name,
+                "",
+                connectors,
+                &mut visited,
+                &mut lines,
+            );
+        }
+        for cycle in self.cycles() {
+            lines.push(format!(
+                "{} -> {} (cycle)",
+                cycle.join(" -> "),
+                cycle[0]
+            ));
+            visited.extend(cycle.iter().cloned());
+            for name in cycle.iter() {
+                self.render_children(
+                    name,
+                    "",
+                    connectors,
+                    &mut visited,
+                    &mut lines,
+                );
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    pub fn tree() -> RoleTree {
+        let mut tree = RoleTree::default();
+        tree.insert("base", "base", None);
+        tree.insert("dev", "dev (role Admin)", Some("base"));
+        tree.insert("prod", "prod (role ReadOnly)", Some("base"));
+        tree.insert("prod-deploy", "prod-deploy (role Deploy)", Some("prod"));
+        tree.insert("legacy", "legacy", Some("removed"));
+        tree.insert("a", "a", Some("b"));
+        tree.insert("b", "b", Some("a"));
+        tree.insert("c", "c", Some("a"));
+        tree
+    }
+
+    #[rstest]
+    fn test_role_tree_cycles(tree: RoleTree) {
+        assert_eq!(vec![vec!["a".to_string(), "b".to_string()]], tree.cycles());
+    }
+
+    #[rstest]
+    fn test_role_tree_render(tree: RoleTree) {
+        let expect = vec![
+            "base",
+            "├── dev (role Admin)",
+            "└── prod (role ReadOnly)",
+            "    └── prod-deploy (role Deploy)",
+            "removed (missing in the config)",
+            "└── legacy",
+            "a -> b -> a (cycle)",
+            "└── c",
+        ];
+        assert_eq!(expect, tree.render());
+    }
+
+    #[rstest]
+    fn test_role_tree_render_plain(tree: RoleTree) {
+        let expect = vec![
+            "base",
+            "- dev (role Admin)",
+            "- prod (role ReadOnly)",
+            "  - prod-deploy (role Deploy)",
+            "removed (missing in the config)",
+            "- legacy",
+            "a -> b -> a (cycle)",
+            "- c",
+        ];
+        assert_eq!(expect, tree.render_plain());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::configs::ProxyConfigs;
+use crate::ctx;
+use crate::state::{self, UPDATE_CHECK_PATH};
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+const LATEST_RELEASE_ENDPOINT: &str =
+    "https://api.github.com/repos/hiro-o918/awsctx/releases/latest";
+const CHECK_TIMEOUT_SECONDS: u64 = 3;
+/// Releases are checked at most once in the hours
+const CHECK_INTERVAL_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Result of the last check of releases, kept in the state directory to rate-limit checks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    pub checked_at: DateTime<Utc>,
+    /// Latest version known so far, kept over failed checks
+    pub latest_version: Option<String>,
+}
+
+impl UpdateCheck {
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        now - self.checked_at >= Duration::hours(CHECK_INTERVAL_HOURS)
+    }
+
+    /// Returns the latest version if it is newer than `current`.
+    pub fn newer_than(&self, current: &str) -> Option<&str> {
+        self.latest_version
+            .as_deref()
+            .filter(|latest| is_newer(latest, current))
+    }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect()
+}
+
+/// Compares versions like `v0.6.0` and `0.5.0` numerically, unknown formats are never newer.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Fetches the version of the latest release by `curl`, since GitHub is served only over HTTPS.
+pub fn fetch_latest_version(
+    curl: &str,
+    proxy: Option<&ProxyConfigs>,
+) -> Result<String, ctx::CTXError> {
+    let mut command = Command::new(curl);
+    command
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .arg(format!("--max-time={}", CHECK_TIMEOUT_SECONDS))
+        .args(["--header", "Accept: application/vnd.github+json"])
+        .arg(LATEST_RELEASE_ENDPOINT)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(proxy) = proxy {
+        proxy.apply(&mut command);
+    }
+    debug!("check the latest release by {}", curl);
+    let output =
+        command
+            .output()
+            .map_err(|e| ctx::CTXError::AWSRequestFailed {
+                message: format!("failed to execute `{}`", curl),
+                source: Some(e.into()),
+            })?;
+    if !output.status.success() {
+        return Err(ctx::CTXError::AWSRequestFailed {
+            message: "failed to fetch the latest release".to_string(),
+            source: Some(anyhow!(
+                "{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+        });
+    }
+    serde_json::from_slice::<Release>(&output.stdout)
+        .context("failed to parse the latest release")
+        .map(|release| release.tag_name)
+        .map_err(|e| ctx::CTXError::AWSRequestFailed {
+            message: "unexpected response of GitHub".to_string(),
+            source: Some(e),
+        })
+}
+
+/// Checks the latest release unless it was checked recently, and returns the result.
+///
+/// Failures are never fatal, they are just retried after the interval.
+pub fn check<P: AsRef<Path>>(
+    path: Option<P>,
+    curl: &str,
+    proxy: Option<&ProxyConfigs>,
+    now: DateTime<Utc>,
+) -> Option<UpdateCheck> {
+    let path = path
+        .map(|p| p.as_ref().to_path_buf())
+        .unwrap_or_else(|| UPDATE_CHECK_PATH.clone());
+    let last = state::load::<UpdateCheck>(&path);
+    if let Some(last) = last.as_ref().filter(|c| !c.is_due(now)) {
+        return Some(last.clone());
+    }
+    let latest_version = match fetch_latest_version(curl, proxy) {
+        Ok(version) => Some(version),
+        Err(e) => {
+            debug!("failed to check the latest release: {:?}", e);
+            last.and_then(|c| c.latest_version)
+        }
+    };
+    let check = UpdateCheck {
+        checked_at: now,
+        latest_version,
+    };
+    if let Err(e) = state::save(&path, &check) {
+        debug!("failed to record the update check: {:?}", e);
+    }
+    Some(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    #[rstest(::trace)]
+    #[case("v0.6.0", "0.5.0", true)]
+    #[case("0.5.10", "0.5.9", true)]
+    #[case("v0.5.0", "0.5.0", false)]
+    #[case("0.4.9", "0.5.0", false)]
+    #[case("nightly", "0.5.0", false)]
+    fn test_is_newer(
+        #[case] latest: &str,
+        #[case] current: &str,
+        #[case] expect: bool,
+    ) {
+        assert_eq!(expect, is_newer(latest, current));
+    }
+
+    #[rstest]
+    fn test_check() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.path().join("state/update_check.json");
+        let now = Utc::now();
+        let curl = fake_executable(
+            &tmpdir,
+            "curl",
+            r#"echo '{"tag_name": "v0.6.0"}'"#,
+        );
+        let curl = curl.to_str().unwrap();
+
+        let actual = check(Some(&path), curl, None, now).unwrap();
+        assert_eq!(Some("v0.6.0"), actual.newer_than("0.5.0"));
+        assert_eq!(None, actual.newer_than("0.6.0"));
+
+        // checks within the interval use the recorded result
+        let failing = fake_executable(&tmpdir, "failing", "exit 22");
+        let failing = failing.to_str().unwrap();
+        assert_eq!(
+            actual,
+            check(Some(&path), failing, None, now + Duration::hours(1))
+                .unwrap()
+        );
+
+        // failed checks keep the latest version known so far
+        let later = now + Duration::hours(CHECK_INTERVAL_HOURS);
+        let actual = check(Some(&path), failing, None, later).unwrap();
+        assert_eq!(later, actual.checked_at);
+        assert_eq!(Some("v0.6.0".to_string()), actual.latest_version);
+    }
+}
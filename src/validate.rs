@@ -0,0 +1,216 @@
+use crate::awscli::{run_concurrently, AwsCli};
+use crate::ctx;
+use crate::sts::{self, CallerIdentity};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsStatus {
+    Ok,
+    Expired,
+    Invalid,
+    NeedsSSOLogin,
+    NoCredentials,
+    Error,
+}
+
+impl fmt::Display for CredentialsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Ok => "OK",
+            Self::Expired => "expired",
+            Self::Invalid => "invalid",
+            Self::NeedsSSOLogin => "needs SSO login",
+            Self::NoCredentials => "no credentials",
+            Self::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validation {
+    pub profile: String,
+    pub status: CredentialsStatus,
+    pub identity: Option<CallerIdentity>,
+    /// Error message reported by AWS for failed validations
+    pub message: Option<String>,
+}
+
+/// Classifies an error message of the AWS CLI into a credentials status.
+fn classify(message: &str) -> CredentialsStatus {
+    // errors of SSO profiles also say "expired", check them first
+    if message.to_lowercase().contains("sso") {
+        return CredentialsStatus::NeedsSSOLogin;
+    }
+    if message.contains("ExpiredToken")
+        || message.contains("RequestExpired")
+        || message.contains("expired")
+    {
+        return CredentialsStatus::Expired;
+    }
+    if message.contains("InvalidClientTokenId")
+        || message.contains("SignatureDoesNotMatch")
+        || message.contains("UnrecognizedClient")
+        || message.contains("AuthFailure")
+    {
+        return CredentialsStatus::Invalid;
+    }
+    if message.contains("Unable to locate credentials") {
+        return CredentialsStatus::NoCredentials;
+    }
+    CredentialsStatus::Error
+}
+
+/// Returns the message reported by AWS for failed requests.
+fn error_message(e: &ctx::CTXError) -> String {
+    match e {
+        ctx::CTXError::AWSRequestFailed {
+            source: Some(source),
+            ..
+        } => source.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+pub fn validate_profile(cli: &AwsCli, profile: &str) -> Validation {
+    match sts::get_caller_identity(cli, profile) {
+        Ok(identity) => Validation {
+            profile: profile.to_string(),
+            status: CredentialsStatus::Ok,
+            identity: Some(identity),
+            message: None,
+        },
+        Err(e) => {
+            let message = error_message(&e);
+            Validation {
+                profile: profile.to_string(),
+                status: classify(&message),
+                identity: None,
+                message: Some(message),
+            }
+        }
+    }
+}
+
+/// Calls GetCallerIdentity with credentials given to `cli`, failing with `CredentialsInvalid`
+/// when AWS rejects them. Other failures, like network errors, are returned as they are.
+pub fn verify_credentials(
+    cli: &AwsCli,
+    profile: &str,
+) -> Result<CallerIdentity, ctx::CTXError> {
+    cli.run::<CallerIdentity>(&["sts", "get-caller-identity"])
+        .map_err(|e| {
+            let message = error_message(&e);
+            match classify(&message) {
+                CredentialsStatus::Error => e,
+                _ => ctx::CTXError::CredentialsInvalid {
+                    profile: profile.to_string(),
+                    message,
+                    source: Some(e.into()),
+                },
+            }
+        })
+}
+
+/// Validates credentials of the profiles with at most `parallelism` concurrent requests.
+/// Results are returned in the same order as `profiles`.
+pub fn validate_profiles(
+    cli: &AwsCli,
+    profiles: &[String],
+    parallelism: usize,
+) -> Vec<Validation> {
+    run_concurrently(profiles, parallelism, |profile| {
+        validate_profile(cli, profile)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_aws_cli;
+
+    #[rstest(::trace)]
+    #[case(
+        "An error occurred (ExpiredToken) when calling the GetCallerIdentity operation: The security token included in the request is expired",
+        CredentialsStatus::Expired
+    )]
+    #[case(
+        "An error occurred (InvalidClientTokenId) when calling the GetCallerIdentity operation: The security token included in the request is invalid.",
+        CredentialsStatus::Invalid
+    )]
+    #[case(
+        "Error when retrieving token from sso: Token has expired and refresh failed",
+        CredentialsStatus::NeedsSSOLogin
+    )]
+    #[case(
+        "Unable to locate credentials. You can configure credentials by running \"aws configure\".",
+        CredentialsStatus::NoCredentials
+    )]
+    #[case("Could not connect to the endpoint URL", CredentialsStatus::Error)]
+    fn test_classify(#[case] message: &str, #[case] expect: CredentialsStatus) {
+        assert_eq!(expect, classify(message));
+    }
+
+    #[rstest]
+    fn test_validate_profiles() {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"case "$4" in
+  foo) echo '{"UserId": "AIDAXXXXXXXXXXXXXXXXX", "Account": "123456789012", "Arn": "arn:aws:iam::123456789012:user/foo"}' ;;
+  bar) echo 'An error occurred (ExpiredToken) when calling the GetCallerIdentity operation' >&2; exit 254 ;;
+  *) echo 'An error occurred (InvalidClientTokenId) when calling the GetCallerIdentity operation' >&2; exit 254 ;;
+esac"#,
+        );
+        let profiles =
+            vec!["foo".to_string(), "bar".to_string(), "baz".to_string()];
+
+        let actual = validate_profiles(&cli, &profiles, 2)
+            .into_iter()
+            .map(|v| (v.profile, v.status))
+            .collect::<Vec<(String, CredentialsStatus)>>();
+        assert_eq!(
+            vec![
+                ("foo".to_string(), CredentialsStatus::Ok),
+                ("bar".to_string(), CredentialsStatus::Expired),
+                ("baz".to_string(), CredentialsStatus::Invalid),
+            ],
+            actual
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case("XXXXXXXXXXX", Ok("123456789012"))]
+    #[case("YYYYYYYYYYY", Err(ctx::ErrorKind::CredentialsInvalid))]
+    #[case("ZZZZZZZZZZZ", Err(ctx::ErrorKind::AWSRequestFailed))]
+    fn test_verify_credentials(
+        #[case] access_key_id: &str,
+        #[case] expect: Result<&str, ctx::ErrorKind>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let cli = fake_aws_cli(
+            &tmpdir,
+            r#"case "$AWS_ACCESS_KEY_ID" in
+  XXXXXXXXXXX) echo '{"UserId": "AIDAXXXXXXXXXXXXXXXXX", "Account": "123456789012", "Arn": "arn:aws:iam::123456789012:user/foo"}' ;;
+  YYYYYYYYYYY) echo 'An error occurred (InvalidClientTokenId) when calling the GetCallerIdentity operation' >&2; exit 254 ;;
+  *) echo 'Could not connect to the endpoint URL' >&2; exit 255 ;;
+esac"#,
+        )
+        .with_credentials_env(&[(
+            "AWS_ACCESS_KEY_ID".to_string(),
+            access_key_id.to_string(),
+        )]);
+        let actual = verify_credentials(&cli, "foo");
+        assert_eq!(
+            expect,
+            actual
+                .as_ref()
+                .map(|i| i.account.as_str())
+                .map_err(|e| e.kind())
+        );
+    }
+}
@@ -1,63 +1,204 @@
+use crate::backup::Backup;
+use crate::cleanup::UnusedProfile;
+use crate::console;
+use crate::costs::ContextCost;
 use crate::ctx;
+use crate::effective::EffectiveContext;
+use crate::i18n::{tr, Message};
+use crate::lint::{Finding, Severity};
+use crate::manifest::Change;
+use crate::rename::Rename;
+use crate::setting::Setting;
+use crate::trash::DeletedProfile;
+use crate::validate::{CredentialsStatus, Validation};
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+static PLAIN_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Switches to plain output for screen readers, which marks states by words instead of glyphs.
+pub fn set_plain_output(plain: bool) {
+    PLAIN_OUTPUT.store(plain, Ordering::Relaxed);
+}
+
+pub fn plain_output() -> bool {
+    PLAIN_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Marks the line of the active context, by `*` or by `ACTIVE` in plain output.
+fn mark_active(line: &str, active: bool) -> String {
+    match (active, plain_output()) {
+        (true, true) => format!("{}  ACTIVE", line),
+        (false, true) => line.to_string(),
+        (true, false) => format!("<green>* {}</>", line),
+        (false, false) => format!("  {}", line),
+    }
+}
 
 pub fn fatal_ctxerr<T>(result: Result<T, ctx::CTXError>) -> T {
     match result {
         Ok(t) => t,
         Err(e) => match e {
+            ctx::CTXError::AWSRequestFailed { message, source } => {
+                error!(
+                    "<red>{}</>",
+                    tr(Message::AWSRequestFailed, &[&message])
+                );
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::AuthTimedOut {
+                profile,
+                timeout_seconds,
+                source,
+            } => {
+                error!(
+                    "<red>{}</>",
+                    tr(
+                        Message::AuthTimedOut,
+                        &[&profile, &timeout_seconds.to_string()]
+                    )
+                );
+                error!("");
+                error!("{}", tr(Message::TimeoutHint, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
             ctx::CTXError::CannotReadCredentials { source } => {
-                error!("<red>failed to read credentials, check your ~/.aws/credentials file</>");
+                error!("<red>{}</>", tr(Message::CannotReadCredentials, &[]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::CannotWriteCredentials { source } => {
-                error!("<red>failed to write credentials to ~/.aws/credentials file</>");
+                error!("<red>{}</>", tr(Message::CannotWriteCredentials, &[]));
                 if let Some(source) = source {
+                    // like characters which the encoding of the file cannot represent
+                    error!("{:#}", source);
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::CredentialsIsBroken { source } => {
-                error!("<red>broken credentials, check your ~/.aws/credentials file</>");
+                error!("<red>{}</>", tr(Message::CredentialsIsBroken, &[]));
+                info!("{}", tr(Message::LenientHint, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::CredentialsInvalid {
+                profile,
+                message,
+                source,
+            } => {
+                error!(
+                    "<red>{}</>",
+                    tr(Message::CredentialsInvalid, &[&profile, &message])
+                );
+                error!("");
+                error!("{}", tr(Message::AuthHint, &[&profile]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::CannotReadConfig { source } => {
-                error!("<red>failed to read config, check your ~/.aws/config file</>");
+                error!("<red>{}</>", tr(Message::CannotReadConfig, &[]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::CannotWriteConfig { source } => {
-                error!("<red>failed to write config to ~/.aws/config file</>");
+                error!("<red>{}</>", tr(Message::CannotWriteConfig, &[]));
                 if let Some(source) = source {
+                    // like characters which the encoding of the file cannot represent
+                    error!("{:#}", source);
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::ConfigIsBroken { source } => {
-                error!("<red>broken config, check your ~/.aws/config file</>");
+                error!("<red>{}</>", tr(Message::ConfigIsBroken, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::ContextLocked {
+                context,
+                holder,
+                source,
+            } => {
+                error!(
+                    "<red>{}</>",
+                    tr(Message::ContextLocked, &[&context, &holder])
+                );
+                error!("");
+                error!("{}", tr(Message::UnlockHint, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::DefaultDrifted { closest, source } => {
+                error!("<red>{}</>", tr(Message::DefaultDrifted, &[]));
+                if let Some(closest) = closest {
+                    error!("{}", tr(Message::ClosestProfile, &[&closest]));
+                }
+                error!("");
+                error!("{}", tr(Message::RepairHint, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::FileChangedOnDisk { path, source } => {
+                error!("<red>{}</>", tr(Message::FileChangedOnDisk, &[&path]));
+                error!("");
+                error!("{}", tr(Message::ReloadHint, &[]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::InvalidConfigurations { message, source } => {
-                error!("<red>invalid configurations: {}</>", message);
+                error!(
+                    "<red>{}</>",
+                    tr(Message::InvalidConfigurations, &[&message])
+                );
                 error!("");
-                error!("modify ~/.awsctx/configs.yaml manually and try again");
-                error!("<bold>Example Usage</>: <u>https://github.com/hiro-o918/awsctx/tree/v{}#configsyaml</>", env!("CARGO_PKG_VERSION"));
+                error!("{}", tr(Message::EditConfigsHint, &[]));
+                error!(
+                    "<bold>{}</>: <u>https://github.com/hiro-o918/awsctx/tree/v{}#configsyaml</>",
+                    tr(Message::ExampleUsage, &[]),
+                    env!("CARGO_PKG_VERSION")
+                );
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
+            ctx::CTXError::InvalidInput { message, source } => {
+                error!("<red>{}</>", tr(Message::InvalidInput, &[&message]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(2);
+            }
             ctx::CTXError::NoActiveContext { source } => {
-                info!("<red>no active context</>");
+                info!("<red>{}</>", tr(Message::NoActiveContext, &[]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
@@ -65,36 +206,51 @@ pub fn fatal_ctxerr<T>(result: Result<T, ctx::CTXError>) -> T {
             }
             ctx::CTXError::NoAuthConfiguration { profile, source } => {
                 error!(
-                    "<red>no auth configuration found for the profile: {}</>",
-                    profile
+                    "<red>{}</>",
+                    tr(Message::NoAuthConfiguration, &[&profile])
                 );
                 error!("");
-                error!("modify ~/.awsctx/configs.yaml manually and try again");
-                error!("<bold>Example Usage</>: <u>https://github.com/hiro-o918/awsctx/tree/v{}#configsyaml</>", env!("CARGO_PKG_VERSION"));
+                error!("{}", tr(Message::EditConfigsHint, &[]));
+                error!(
+                    "<bold>{}</>: <u>https://github.com/hiro-o918/awsctx/tree/v{}#configsyaml</>",
+                    tr(Message::ExampleUsage, &[]),
+                    env!("CARGO_PKG_VERSION")
+                );
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::NoContextIsSelected { source } => {
-                error!("<red>no context is selected</>");
+                error!("<red>{}</>", tr(Message::NoContextIsSelected, &[]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::Offline { operation, source } => {
+                error!("<red>{}</>", tr(Message::Offline, &[&operation]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::NoSuchProfile { profile, source } => {
-                error!(
-                    "<red>no such profile: {}, check your ~/.aws/credentials file</>",
-                    profile
-                );
+                error!("<red>{}</>", tr(Message::NoSuchProfile, &[&profile]));
+                if let Some(source) = source {
+                    debug!("caused error: {:?}", source);
+                }
+                std::process::exit(1);
+            }
+            ctx::CTXError::NoSuchRegion { region, source } => {
+                error!("<red>{}</>", tr(Message::NoSuchRegion, &[&region]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
                 std::process::exit(1);
             }
             ctx::CTXError::UnexpectedError { source } => {
-                error!("<red>unexpected error occurred, you can check detailed error by `verbose` option</>");
+                error!("<red>{}</>", tr(Message::UnexpectedError, &[]));
                 if let Some(source) = source {
                     debug!("caused error: {:?}", source);
                 }
@@ -105,15 +261,753 @@ pub fn fatal_ctxerr<T>(result: Result<T, ctx::CTXError>) -> T {
 }
 
 pub fn show_contexts(contexts: &[ctx::Context]) {
+    let now = Utc::now();
     for c in contexts.iter() {
-        if c.active {
-            info!("<green>* {}</>", c.name);
-        } else {
-            info!("  {}", c.name);
-        }
+        info!("{}", mark_active(&context_line(c, now), c.active));
+    }
+}
+
+/// Returns the name followed by the expiration of the session, like `foo (expires in 12m)`.
+fn context_line(context: &ctx::Context, now: DateTime<Utc>) -> String {
+    match context.expires_at {
+        Some(e) => format!("{} ({})", context.name, format_expiration(e, now)),
+        None => context.name.clone(),
+    }
+}
+
+/// Terminals known to render OSC 8 hyperlinks by `TERM_PROGRAM`
+const HYPERLINK_TERM_PROGRAMS: [&str; 5] =
+    ["iTerm.app", "WezTerm", "vscode", "Hyper", "ghostty"];
+
+fn detect_hyperlinks<F: Fn(&str) -> Option<String>>(
+    is_terminal: bool,
+    var: F,
+) -> bool {
+    if let Some(force) = var("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    if !is_terminal || var("CI").is_some() {
+        return false;
+    }
+    let term = var("TERM").unwrap_or_default();
+    if term == "dumb" {
+        return false;
+    }
+    var("TERM_PROGRAM")
+        .is_some_and(|p| HYPERLINK_TERM_PROGRAMS.contains(&p.as_str()))
+        || var("VTE_VERSION")
+            .and_then(|v| v.parse::<u32>().ok())
+            .is_some_and(|v| v >= 5000)
+        || ["WT_SESSION", "KITTY_WINDOW_ID", "DOMTERM"]
+            .iter()
+            .any(|k| var(k).is_some())
+        || ["xterm-kitty", "alacritty", "foot"]
+            .iter()
+            .any(|t| term.starts_with(t))
+}
+
+/// Whether stdout is a terminal rendering OSC 8 hyperlinks.
+/// `FORCE_HYPERLINK=1` or `FORCE_HYPERLINK=0` overrides the detection.
+pub fn supports_hyperlinks() -> bool {
+    detect_hyperlinks(io::stdout().is_terminal(), |k| env::var(k).ok())
+}
+
+/// Wraps the text in an OSC 8 hyperlink.
+fn hyperlink(text: &str, url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Renders rows of the table of contexts, whose columns are aligned and separated by two spaces.
+/// Names are linked to the console of the contexts if `hyperlinks` is set.
+pub fn table_rows(
+    contexts: &[ctx::ContextMetadata],
+    columns: &[ctx::Column],
+    hyperlinks: bool,
+) -> Vec<String> {
+    let now = Utc::now();
+    let values = contexts
+        .iter()
+        .map(|c| {
+            columns
+                .iter()
+                .map(|column| match column {
+                    // states are words in plain output, never left to be read as true or dates
+                    ctx::Column::Active if plain_output() => {
+                        if c.active { "ACTIVE" } else { "" }.to_string()
+                    }
+                    ctx::Column::Expiry
+                        if plain_output()
+                            && c.expires_at.is_some_and(|e| e <= now) =>
+                    {
+                        "EXPIRED".to_string()
+                    }
+                    ctx::Column::Name => c.name_with_aliases(),
+                    _ => column.value(c),
+                })
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<Vec<String>>>();
+    let widths = (0..columns.len())
+        .map(|i| {
+            values
+                .iter()
+                .map(|v| v[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<usize>>();
+    values
+        .iter()
+        .zip(contexts.iter())
+        .map(|(v, c)| {
+            v.iter()
+                .zip(widths.iter())
+                .zip(columns.iter())
+                .map(|((value, width), column)| {
+                    let padding = " ".repeat(width - value.chars().count());
+                    if hyperlinks && column == &ctx::Column::Name {
+                        let url = console::context_url(c);
+                        format!("{}{}", hyperlink(value, url.as_str()), padding)
+                    } else {
+                        format!("{}{}", value, padding)
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+pub fn show_contexts_table(
+    contexts: &[ctx::ContextMetadata],
+    columns: &[ctx::Column],
+    hyperlinks: bool,
+) {
+    show_contexts_lines(contexts, &table_rows(contexts, columns, hyperlinks));
+}
+
+/// Renders banners of labels of the contexts in their colors, aligned if any context has one.
+pub fn label_prefixes(contexts: &[ctx::ContextMetadata]) -> Vec<String> {
+    let width = contexts
+        .iter()
+        .filter_map(|c| c.label.as_ref())
+        .map(|l| l.banner().chars().count())
+        .max();
+    contexts
+        .iter()
+        .map(|c| match (width, &c.label) {
+            (Some(width), Some(label)) => {
+                let banner = label.banner();
+                format!(
+                    "<on {}>{}</>{} ",
+                    label.color.name(),
+                    banner,
+                    " ".repeat(width - banner.chars().count())
+                )
+            }
+            (Some(width), None) => " ".repeat(width + 1),
+            (None, _) => String::new(),
+        })
+        .collect()
+}
+
+/// Shows a line of each context, marking the active one after its label.
+pub fn show_contexts_lines(
+    contexts: &[ctx::ContextMetadata],
+    lines: &[String],
+) {
+    for ((c, row), label) in
+        contexts.iter().zip(lines).zip(label_prefixes(contexts))
+    {
+        info!("{}{}", label, mark_active(row, c.active));
     }
 }
 
 pub fn show_context(contexts: &ctx::Context) {
     info!("{}", contexts.name)
 }
+
+pub fn show_effective_context(effective: &EffectiveContext) {
+    info!("{}", effective.display_name())
+}
+
+/// Formats remaining time of a session like `1h05m` or `12m`.
+pub fn format_remaining(minutes: i64) -> String {
+    if minutes >= 60 {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn format_expiration(expires_at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    if expires_at <= now {
+        "expired".to_string()
+    } else {
+        format!(
+            "expires in {}",
+            format_remaining((expires_at - now).num_minutes())
+        )
+    }
+}
+
+pub fn show_context_json(context: &ctx::Context) {
+    println!("{}", serde_json::to_string(context).unwrap());
+}
+
+pub fn show_contexts_json(contexts: &[ctx::ContextMetadata]) {
+    println!("{}", serde_json::to_string_pretty(contexts).unwrap());
+}
+
+/// Prints contexts as JSON Lines, flushing each line for pipelines.
+pub fn show_contexts_jsonl(contexts: &[ctx::ContextMetadata]) {
+    let mut stdout = io::stdout().lock();
+    for context in contexts {
+        let line = serde_json::to_string(context).unwrap();
+        if writeln!(stdout, "{}", line)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            // the reader of the pipe has gone
+            return;
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders contexts as CSV of [RFC 4180](https://www.rfc-editor.org/rfc/rfc4180) with a header row.
+pub fn render_csv(
+    contexts: &[ctx::ContextMetadata],
+    columns: &[ctx::Column],
+) -> String {
+    let row = |fields: Vec<String>| {
+        fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<String>>()
+            .join(",")
+            + "\r\n"
+    };
+    let header = row(columns.iter().map(|c| c.header().to_string()).collect());
+    contexts.iter().fold(header, |acc, context| {
+        acc + &row(columns.iter().map(|c| c.value(context)).collect())
+    })
+}
+
+pub fn show_contexts_csv(
+    contexts: &[ctx::ContextMetadata],
+    columns: &[ctx::Column],
+) {
+    print!("{}", render_csv(contexts, columns));
+}
+
+/// Builds items of [Alfred script filter](https://www.alfredapp.com/help/workflows/inputs/script-filter/json/).
+pub fn alfred_items(
+    contexts: &[ctx::ContextMetadata],
+    now: DateTime<Utc>,
+) -> Value {
+    let items = contexts
+        .iter()
+        .map(|c| {
+            let subtitle = [
+                c.active.then(|| "active".to_string()),
+                c.account.clone(),
+                c.region.clone(),
+                c.expires_at.map(|e| format_expiration(e, now)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(" | ");
+            json!({
+                "uid": c.name,
+                "title": c.name,
+                "subtitle": subtitle,
+                "arg": c.name,
+                "autocomplete": c.name,
+            })
+        })
+        .collect::<Vec<Value>>();
+    json!({ "items": items })
+}
+
+pub fn show_contexts_alfred(contexts: &[ctx::ContextMetadata]) {
+    println!("{}", alfred_items(contexts, Utc::now()));
+}
+
+pub fn show_backups(backups: &[Backup]) {
+    if backups.is_empty() {
+        info!("{}", tr(Message::NoBackups, &[]));
+        return;
+    }
+    for b in backups.iter() {
+        info!(
+            "{}  {:<11}  {}",
+            b.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            b.file,
+            b.path.to_str().unwrap()
+        );
+    }
+}
+
+pub fn show_deleted_profiles(deleted: &[DeletedProfile]) {
+    if deleted.is_empty() {
+        info!("{}", tr(Message::NoDeletedProfiles, &[]));
+        return;
+    }
+    for d in deleted.iter() {
+        info!(
+            "{}  {}",
+            d.deleted_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            d.name
+        );
+    }
+}
+
+pub fn show_adoptable_profiles(names: &[String]) {
+    if names.is_empty() {
+        info!("{}", tr(Message::NoAdoptableProfiles, &[]));
+        return;
+    }
+    for name in names.iter() {
+        info!("{} (SSO)", name);
+    }
+}
+
+/// Lines of the unused profiles with when they were used last time, also shown in the finder.
+pub fn unused_profile_lines(unused: &[UnusedProfile]) -> Vec<String> {
+    let width = unused
+        .iter()
+        .map(|p| p.name.len())
+        .max()
+        .unwrap_or_default();
+    unused
+        .iter()
+        .map(|p| {
+            let last_used = match p.last_used {
+                Some(t) => t.to_rfc3339_opts(SecondsFormat::Secs, true),
+                None => "never used".to_string(),
+            };
+            format!("{:<width$}  {}", p.name, last_used, width = width)
+        })
+        .collect()
+}
+
+pub fn show_unused_profiles(unused: &[UnusedProfile]) {
+    if unused.is_empty() {
+        info!("{}", tr(Message::NoUnusedProfiles, &[]));
+        return;
+    }
+    for line in unused_profile_lines(unused).iter() {
+        info!("{}", line);
+    }
+}
+
+pub fn show_renames(renames: &[Rename]) {
+    if renames.is_empty() {
+        info!("{}", tr(Message::NoMatchingProfiles, &[]));
+        return;
+    }
+    for r in renames.iter() {
+        info!("{} -> {}", r.from, r.to);
+    }
+}
+
+pub fn show_settings(settings: &[Setting]) {
+    if settings.is_empty() {
+        info!("{}", tr(Message::NoSettingChanges, &[]));
+        return;
+    }
+    for s in settings.iter() {
+        match &s.from {
+            Some(from) => info!(
+                "<yellow>~ {}: {} {:?} -> {:?}</>",
+                s.profile, s.key, from, s.to
+            ),
+            None => {
+                info!("<green>+ {}: {} {:?}</>", s.profile, s.key, s.to)
+            }
+        }
+    }
+}
+
+pub fn show_manifest_changes(changes: &[Change]) {
+    if changes.is_empty() {
+        info!("{}", tr(Message::NoManifestChanges, &[]));
+        return;
+    }
+    for c in changes.iter() {
+        match c {
+            Change::Add(name) => info!("<green>+ {}</>", name),
+            Change::Update(name) => info!("<yellow>~ {}</>", name),
+            Change::Remove(name) => info!("<red>- {}</>", name),
+        }
+    }
+}
+
+pub fn show_regions(regions: &[String]) {
+    for r in regions.iter() {
+        info!("{}", r);
+    }
+}
+
+pub fn show_costs(costs: &[ContextCost]) {
+    let width = costs
+        .iter()
+        .map(|c| c.profile.len())
+        .max()
+        .unwrap_or_default();
+    for c in costs.iter() {
+        let account = format!("{:<12}", c.account.as_deref().unwrap_or("-"));
+        match (&c.cost, &c.message) {
+            (Some(cost), _) => info!(
+                "{:<width$}  {}  {:>12.2} {}",
+                c.profile,
+                account,
+                cost.amount,
+                cost.unit,
+                width = width
+            ),
+            (None, message) => info!(
+                "{:<width$}  {}  <red>{}</>",
+                c.profile,
+                account,
+                message.as_deref().unwrap_or("unknown"),
+                width = width
+            ),
+        }
+    }
+}
+
+pub fn show_tree(lines: &[String]) {
+    for line in lines.iter() {
+        if line.ends_with("(cycle)") {
+            info!("<red>{}</>", line);
+        } else {
+            info!("{}", line);
+        }
+    }
+}
+
+pub fn show_findings(findings: &[Finding]) {
+    if findings.is_empty() {
+        info!("<green>{}</>", tr(Message::NoProblems, &[]));
+        return;
+    }
+    for f in findings.iter() {
+        let severity = match f.severity {
+            Severity::Warning => format!("<yellow>{}</>", f.severity),
+            Severity::Error => format!("<red>{}</>", f.severity),
+        };
+        info!("{}  {}  {}", severity, f.check, f.message);
+    }
+}
+
+/// Returns the status and the detail of a result of `auth --all`, like `("failed", "<reason>")`.
+fn auth_status(result: &Result<ctx::Context, ctx::CTXError>) -> (&str, String) {
+    match result {
+        Ok(context) => (
+            "OK",
+            context
+                .expires_at
+                .map(|e| format_expiration(e, Utc::now()))
+                .unwrap_or_default(),
+        ),
+        Err(ctx::CTXError::NoAuthConfiguration { .. }) => {
+            ("skipped", "no auth configuration".to_string())
+        }
+        Err(ctx::CTXError::AuthTimedOut {
+            timeout_seconds, ..
+        }) => ("timeout", format!("no exit in {} seconds", timeout_seconds)),
+        Err(
+            ctx::CTXError::InvalidConfigurations { message, source }
+            | ctx::CTXError::AWSRequestFailed { message, source },
+        ) => (
+            "failed",
+            match source {
+                Some(source) => format!("{}: {}", message, source),
+                None => message.clone(),
+            },
+        ),
+        Err(e) => ("failed", e.to_string()),
+    }
+}
+
+/// Shows a summary table of `auth --all`.
+pub fn show_auth_results(
+    results: &[(String, Result<ctx::Context, ctx::CTXError>)],
+) {
+    let width = results
+        .iter()
+        .map(|(p, _)| p.len())
+        .max()
+        .unwrap_or_default();
+    for (profile, result) in results.iter() {
+        let (status, detail) = auth_status(result);
+        let status = match result {
+            Ok(_) => format!("<green>{:<7}</>", status),
+            Err(ctx::CTXError::NoAuthConfiguration { .. }) => {
+                format!("<yellow>{:<7}</>", status)
+            }
+            Err(_) => format!("<red>{:<7}</>", status),
+        };
+        info!("{:<width$}  {}  {}", profile, status, detail, width = width);
+    }
+}
+
+pub fn show_validations(validations: &[Validation]) {
+    let width = validations
+        .iter()
+        .map(|v| v.profile.len())
+        .max()
+        .unwrap_or_default();
+    for v in validations.iter() {
+        let status = format!("{:<15}", v.status.to_string());
+        let status = match v.status {
+            CredentialsStatus::Ok => format!("<green>{}</>", status),
+            CredentialsStatus::Expired | CredentialsStatus::NeedsSSOLogin => {
+                format!("<yellow>{}</>", status)
+            }
+            _ => format!("<red>{}</>", status),
+        };
+        let detail = v
+            .identity
+            .as_ref()
+            .map(|i| i.arn.as_str())
+            .or(v.message.as_deref())
+            .unwrap_or_default();
+        info!(
+            "{:<width$}  {}  {}",
+            v.profile,
+            status,
+            detail,
+            width = width
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest(::trace)]
+    #[case(None, "foo")]
+    #[case(Some(Duration::minutes(-1)), "foo (expired)")]
+    #[case(Some(Duration::minutes(65)), "foo (expires in 1h05m)")]
+    fn test_context_line(
+        #[case] remaining: Option<Duration>,
+        #[case] expect: &str,
+    ) {
+        let now = Utc::now();
+        let context = ctx::Context {
+            name: "foo".to_string(),
+            expires_at: remaining.map(|r| now + r),
+            ..Default::default()
+        };
+        assert_eq!(expect, context_line(&context, now));
+    }
+
+    #[rstest(::trace)]
+    #[case(Ok(ctx::Context::default()), ("OK", ""))]
+    #[case(
+        Err(ctx::CTXError::NoAuthConfiguration { profile: "foo".to_string(), source: None }),
+        ("skipped", "no auth configuration")
+    )]
+    #[case(
+        Err(ctx::CTXError::InvalidConfigurations {
+            message: "failed to execute an auth script of profile (foo)".to_string(),
+            source: Some(anyhow::anyhow!("exit 1")),
+        }),
+        ("failed", "failed to execute an auth script of profile (foo): exit 1")
+    )]
+    #[case(
+        Err(ctx::CTXError::AuthTimedOut { profile: "foo".to_string(), timeout_seconds: 300, source: None }),
+        ("timeout", "no exit in 300 seconds")
+    )]
+    fn test_auth_status(
+        #[case] result: Result<ctx::Context, ctx::CTXError>,
+        #[case] expect: (&str, &str),
+    ) {
+        let (status, detail) = auth_status(&result);
+        assert_eq!(expect, (status, detail.as_str()));
+    }
+
+    #[rstest]
+    fn test_render_csv() {
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "bar".to_string(),
+                active: false,
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "foo, \"prod\"".to_string(),
+                active: true,
+                account: Some("123456789012".to_string()),
+                region: Some("ap-northeast-1".to_string()),
+                expires_at: Some(
+                    DateTime::parse_from_rfc3339("2022-08-01T12:00:00Z")
+                        .unwrap()
+                        .with_timezone(&Utc),
+                ),
+                tags: vec!["prod".to_string(), "web".to_string()],
+                description: Some("Client X".to_string()),
+                ..Default::default()
+            },
+        ];
+        let expect = "name,active,account,region,expiry,tags,description\r\n\
+            bar,false,,,,,\r\n\
+            \"foo, \"\"prod\"\"\",true,123456789012,ap-northeast-1,2022-08-01T12:00:00Z,\"prod,web\",Client X\r\n";
+        assert_eq!(expect, render_csv(&contexts, &ctx::DEFAULT_COLUMNS));
+        assert_eq!(
+            "region,name\r\n,bar\r\nap-northeast-1,\"foo, \"\"prod\"\"\"\r\n",
+            render_csv(&contexts, &[ctx::Column::Region, ctx::Column::Name])
+        );
+    }
+
+    #[rstest]
+    fn test_label_prefixes() {
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "prod".to_string(),
+                label: Some(ctx::Label {
+                    text: "PROD".to_string(),
+                    color: ctx::LabelColor::Red,
+                }),
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "dev".to_string(),
+                label: Some(ctx::Label {
+                    text: "DEV".to_string(),
+                    color: ctx::LabelColor::Green,
+                }),
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "misc".to_string(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            vec!["<on red> PROD </> ", "<on green> DEV </>  ", "       "],
+            label_prefixes(&contexts)
+        );
+        assert_eq!(vec![""], label_prefixes(&contexts[2..]));
+    }
+
+    #[rstest]
+    fn test_table_rows() {
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "staging".to_string(),
+                region: Some("us-east-1".to_string()),
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "foo".to_string(),
+                active: true,
+                region: Some("ap-northeast-1".to_string()),
+                tags: vec!["prod".to_string()],
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            vec!["      staging  us-east-1", "prod  foo      ap-northeast-1"],
+            table_rows(
+                &contexts,
+                &[ctx::Column::Tags, ctx::Column::Name, ctx::Column::Region],
+                false
+            )
+        );
+        assert_eq!(
+            vec!["staging", "foo"],
+            table_rows(&contexts, &[ctx::Column::Name], false)
+        );
+        assert_eq!(
+            vec![
+                "\x1b]8;;https://us-east-1.console.aws.amazon.com/console/home?region=us-east-1\x1b\\staging\x1b]8;;\x1b\\  us-east-1",
+                "\x1b]8;;https://ap-northeast-1.console.aws.amazon.com/console/home?region=ap-northeast-1\x1b\\foo\x1b]8;;\x1b\\      ap-northeast-1",
+            ],
+            table_rows(
+                &contexts,
+                &[ctx::Column::Name, ctx::Column::Region],
+                true
+            )
+        );
+    }
+
+    #[rstest(::trace)]
+    #[case(true, vec![("TERM_PROGRAM", "iTerm.app")], true)]
+    #[case(false, vec![("TERM_PROGRAM", "iTerm.app")], false)]
+    #[case(true, vec![("TERM_PROGRAM", "Apple_Terminal")], false)]
+    #[case(true, vec![("VTE_VERSION", "6003")], true)]
+    #[case(true, vec![("VTE_VERSION", "4802")], false)]
+    #[case(true, vec![("TERM", "xterm-kitty")], true)]
+    #[case(true, vec![("WT_SESSION", "x"), ("CI", "true")], false)]
+    #[case(false, vec![("FORCE_HYPERLINK", "1")], true)]
+    #[case(true, vec![("FORCE_HYPERLINK", "0"), ("WT_SESSION", "x")], false)]
+    fn test_detect_hyperlinks(
+        #[case] is_terminal: bool,
+        #[case] vars: Vec<(&str, &str)>,
+        #[case] expect: bool,
+    ) {
+        let actual = detect_hyperlinks(is_terminal, |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        });
+        assert_eq!(expect, actual);
+    }
+
+    #[rstest]
+    fn test_alfred_items() {
+        let now = Utc::now();
+        let contexts = vec![
+            ctx::ContextMetadata {
+                name: "bar".to_string(),
+                active: false,
+                ..Default::default()
+            },
+            ctx::ContextMetadata {
+                name: "foo".to_string(),
+                active: true,
+                account: Some("123456789012".to_string()),
+                region: Some("ap-northeast-1".to_string()),
+                expires_at: Some(now + Duration::seconds(12 * 60 + 30)),
+                ..Default::default()
+            },
+        ];
+        let expect = json!({
+            "items": [
+                {
+                    "uid": "bar",
+                    "title": "bar",
+                    "subtitle": "",
+                    "arg": "bar",
+                    "autocomplete": "bar",
+                },
+                {
+                    "uid": "foo",
+                    "title": "foo",
+                    "subtitle": "active | 123456789012 | ap-northeast-1 | expires in 12m",
+                    "arg": "foo",
+                    "autocomplete": "foo",
+                },
+            ]
+        });
+        assert_eq!(expect, alfred_items(&contexts, now));
+    }
+}
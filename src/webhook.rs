@@ -0,0 +1,413 @@
+use crate::configs::{
+    ChatNotificationConfigs, ChatService, ProxyConfigs, WebhookConfigs,
+    WebhookEvent,
+};
+use crate::ctx;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::anyhow;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Seconds to wait for a response of a webhook, not to block commands for long
+const POST_TIMEOUT_SECONDS: u64 = 5;
+/// Header of the shared secret of webhooks
+const SECRET_HEADER: &str = "X-Awsctx-Secret";
+/// Retries of posts to chat services
+const CHAT_RETRIES: u32 = 2;
+
+/// JSON posted to webhooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Payload {
+    pub event: WebhookEvent,
+    pub context: String,
+    pub account: Option<String>,
+    pub region: Option<String>,
+    pub tags: Vec<String>,
+    pub user: Option<String>,
+    pub at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Payload {
+    /// Creates the payload of the event of the context, by the current user.
+    pub fn new(
+        event: WebhookEvent,
+        context: &ctx::ContextMetadata,
+        at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            event,
+            context: context.name.clone(),
+            account: context.account.clone(),
+            region: context.region.clone(),
+            tags: context.tags.clone(),
+            user: env::var("USER").ok().filter(|u| !u.is_empty()),
+            at,
+            expires_at: context.expires_at,
+        }
+    }
+}
+
+/// Quotes the value in the config of curl, escaping backslashes and quotes.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Config of curl with the body and the secret header, passed by stdin
+/// so that neither of them appears in arguments.
+fn curl_config(body: &str, secret: Option<&str>) -> String {
+    let mut config = format!("data-binary = {}\n", quote(body));
+    if let Some(secret) = secret {
+        config.push_str(&format!(
+            "header = {}\n",
+            quote(&format!("{}: {}", SECRET_HEADER, secret))
+        ));
+    }
+    config
+}
+
+/// Posts the JSON to the URL by `curl`, since receivers are usually served over HTTPS.
+/// Transient failures like timeouts and `5xx` responses are retried by curl.
+fn post_json(
+    curl: &str,
+    url: &str,
+    body: &str,
+    secret: Option<&str>,
+    retries: u32,
+    proxy: Option<&ProxyConfigs>,
+) -> Result<(), ctx::CTXError> {
+    let mut command = Command::new(curl);
+    command
+        .args(["--silent", "--show-error", "--fail"])
+        .args(["--max-time", &POST_TIMEOUT_SECONDS.to_string()])
+        .args(["--retry", &retries.to_string()])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--config", "-"])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    if let Some(proxy) = proxy {
+        proxy.apply(&mut command);
+    }
+    let config = curl_config(body, secret);
+    debug!("post to {} by {}", url, curl);
+    let output = command
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(config.as_bytes())?;
+            child.wait_with_output()
+        })
+        .map_err(|e| ctx::CTXError::UnexpectedError {
+            source: Some(
+                anyhow::Error::from(e)
+                    .context(format!("failed to execute `{}`", curl)),
+            ),
+        })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ctx::CTXError::UnexpectedError {
+            source: Some(anyhow!(
+                "failed to post to {}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+        })
+    }
+}
+
+/// Posts the payload to the webhook.
+pub fn post(
+    curl: &str,
+    webhook: &WebhookConfigs,
+    payload: &Payload,
+    proxy: Option<&ProxyConfigs>,
+) -> Result<(), ctx::CTXError> {
+    let body = serde_json::to_string(payload).map_err(|e| {
+        ctx::CTXError::UnexpectedError {
+            source: Some(e.into()),
+        }
+    })?;
+    post_json(
+        curl,
+        &webhook.url,
+        &body,
+        webhook.secret.as_deref(),
+        webhook.retries,
+        proxy,
+    )
+}
+
+/// Posts the event to every webhook accepting it. Failures are only warned,
+/// since the event itself has happened already.
+pub fn notify(
+    curl: &str,
+    webhooks: &[WebhookConfigs],
+    payload: &Payload,
+    proxy: Option<&ProxyConfigs>,
+) {
+    for webhook in webhooks.iter().filter(|w| w.accepts(payload.event)) {
+        if let Err(e) = post(curl, webhook, payload, proxy) {
+            warn!("failed to notify {}: {:?}", webhook.url, e);
+        }
+    }
+}
+
+/// Renders the event as a message of the chat service, like
+/// `alice switched to (prod-admin) of account 123456789012 at 2024-01-01T11:00:00Z`.
+pub fn chat_message(service: ChatService, payload: &Payload) -> Value {
+    let user = payload.user.as_deref().unwrap_or("someone");
+    let mut text = match payload.event {
+        WebhookEvent::Switch => {
+            format!("{} switched to ({})", user, payload.context)
+        }
+        WebhookEvent::Auth => {
+            format!("{} authenticated ({})", user, payload.context)
+        }
+        WebhookEvent::Expiry => {
+            format!("credentials of ({}) expired", payload.context)
+        }
+    };
+    if let Some(account) = &payload.account {
+        text.push_str(&format!(" of account {}", account));
+    }
+    text.push_str(&format!(
+        " at {}",
+        payload.at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    ));
+    match service {
+        ChatService::Slack => json!({ "text": text }),
+        // legacy message cards, which incoming webhooks of Teams still accept
+        ChatService::Teams => json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": text,
+            "text": text,
+        }),
+    }
+}
+
+/// Posts switches and authentications of contexts to the chat channels of their tags,
+/// once for each channel. Failures are only warned like webhooks.
+pub fn notify_chats(
+    curl: &str,
+    notifications: &HashMap<String, ChatNotificationConfigs>,
+    payload: &Payload,
+    proxy: Option<&ProxyConfigs>,
+) {
+    if payload.event == WebhookEvent::Expiry {
+        return;
+    }
+    let mut channels = payload
+        .tags
+        .iter()
+        .filter_map(|tag| notifications.get(tag))
+        .collect::<Vec<&ChatNotificationConfigs>>();
+    channels.sort_by(|a, b| a.url.cmp(&b.url));
+    channels.dedup_by(|a, b| a.url == b.url);
+    for channel in channels {
+        let message = chat_message(channel.service, payload).to_string();
+        if let Err(e) =
+            post_json(curl, &channel.url, &message, None, CHAT_RETRIES, proxy)
+        {
+            warn!("failed to notify {:?}: {:?}", channel.service, e);
+        }
+    }
+}
+
+/// Selects the contexts whose credentials expired after `since` until `now`,
+/// so that each expiry is notified once by the daemon checking them periodically.
+pub fn expired_between(
+    contexts: &[ctx::ContextMetadata],
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<&ctx::ContextMetadata> {
+    contexts
+        .iter()
+        .filter(|c| c.expires_at.is_some_and(|e| since < e && e <= now))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rstest::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::awscli::tests::fake_executable;
+
+    fn webhook(
+        events: Vec<WebhookEvent>,
+        secret: Option<&str>,
+    ) -> WebhookConfigs {
+        WebhookConfigs {
+            url: "https://hooks.example.com/awsctx".to_string(),
+            events,
+            secret: secret.map(|s| s.to_string()),
+            retries: 2,
+        }
+    }
+
+    fn payload(event: WebhookEvent) -> Payload {
+        let context = ctx::ContextMetadata {
+            name: "foo".to_string(),
+            account: Some("123456789012".to_string()),
+            ..Default::default()
+        };
+        Payload::new(event, &context, "2022-01-02T03:04:05Z".parse().unwrap())
+    }
+
+    #[rstest(::trace)]
+    #[case(vec![], WebhookEvent::Expiry, true)]
+    #[case(vec![WebhookEvent::Switch], WebhookEvent::Switch, true)]
+    #[case(vec![WebhookEvent::Switch], WebhookEvent::Auth, false)]
+    fn test_webhook_accepts(
+        #[case] events: Vec<WebhookEvent>,
+        #[case] event: WebhookEvent,
+        #[case] expect: bool,
+    ) {
+        assert_eq!(expect, webhook(events, None).accepts(event));
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        r#"{"a":"b\c"}"#,
+        None,
+        "data-binary = \"{\\\"a\\\":\\\"b\\\\c\\\"}\"\n"
+    )]
+    #[case(
+        "{}",
+        Some("s\""),
+        "data-binary = \"{}\"\nheader = \"X-Awsctx-Secret: s\\\"\"\n"
+    )]
+    fn test_curl_config(
+        #[case] body: &str,
+        #[case] secret: Option<&str>,
+        #[case] expect: &str,
+    ) {
+        assert_eq!(expect, curl_config(body, secret));
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        r#"config=$(cat)
+case "$*:$config" in
+    *'--retry 2'*'--config -'*'https://hooks.example.com/awsctx:'*'\"event\":\"switch\"'*'\"account\":\"123456789012\"'*'X-Awsctx-Secret: secret'*) ;;
+    *) echo "unexpected request" >&2; exit 22 ;;
+esac"#,
+        true
+    )]
+    #[case("echo 'curl: (22) 500' >&2; exit 22", false)]
+    fn test_post(#[case] script: &str, #[case] expect: bool) {
+        let tmpdir = TempDir::new().unwrap();
+        let curl = fake_executable(&tmpdir, "curl", script);
+        let actual = post(
+            curl.to_str().unwrap(),
+            &webhook(vec![], Some("secret")),
+            &payload(WebhookEvent::Switch),
+            None,
+        );
+        assert_eq!(expect, actual.is_ok());
+    }
+
+    #[rstest(::trace)]
+    #[case(
+        ChatService::Slack,
+        WebhookEvent::Switch,
+        json!({"text": "alice switched to (foo) of account 123456789012 at 2022-01-02T03:04:05Z"})
+    )]
+    #[case(
+        ChatService::Teams,
+        WebhookEvent::Auth,
+        json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": "alice authenticated (foo) of account 123456789012 at 2022-01-02T03:04:05Z",
+            "text": "alice authenticated (foo) of account 123456789012 at 2022-01-02T03:04:05Z",
+        })
+    )]
+    fn test_chat_message(
+        #[case] service: ChatService,
+        #[case] event: WebhookEvent,
+        #[case] expect: Value,
+    ) {
+        let payload = Payload {
+            user: Some("alice".to_string()),
+            ..payload(event)
+        };
+        assert_eq!(expect, chat_message(service, &payload));
+    }
+
+    #[rstest(::trace)]
+    #[case(vec!["prod", "critical"], WebhookEvent::Switch, vec!["https://a", "https://b"])]
+    #[case(vec!["prod", "shared"], WebhookEvent::Auth, vec!["https://a"])]
+    #[case(vec!["prod"], WebhookEvent::Expiry, vec![])]
+    #[case(vec!["sandbox"], WebhookEvent::Switch, vec![])]
+    fn test_notify_chats(
+        #[case] tags: Vec<&str>,
+        #[case] event: WebhookEvent,
+        #[case] expect: Vec<&str>,
+    ) {
+        let tmpdir = TempDir::new().unwrap();
+        let posted = tmpdir.path().join("posted");
+        let curl = fake_executable(
+            &tmpdir,
+            "curl",
+            &format!(
+                r#"for url; do :; done; echo "$url" >> {}"#,
+                posted.display()
+            ),
+        );
+        let channel = |url: &str| ChatNotificationConfigs {
+            service: ChatService::Slack,
+            url: url.to_string(),
+        };
+        let notifications = HashMap::from([
+            ("prod".to_string(), channel("https://a")),
+            ("shared".to_string(), channel("https://a")),
+            ("critical".to_string(), channel("https://b")),
+        ]);
+        let payload = Payload {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..payload(event)
+        };
+        notify_chats(curl.to_str().unwrap(), &notifications, &payload, None);
+        let actual = std::fs::read_to_string(&posted).unwrap_or_default();
+        assert_eq!(expect, actual.lines().collect::<Vec<&str>>());
+    }
+
+    #[rstest]
+    fn test_expired_between() {
+        let now = Utc::now();
+        let context = |name: &str, expires_at: Option<DateTime<Utc>>| {
+            ctx::ContextMetadata {
+                name: name.to_string(),
+                expires_at,
+                ..Default::default()
+            }
+        };
+        let contexts = vec![
+            context("before", Some(now - Duration::minutes(2))),
+            context("expired", Some(now - Duration::seconds(30))),
+            context("later", Some(now + Duration::minutes(1))),
+            context("static", None),
+        ];
+        let actual =
+            expired_between(&contexts, now - Duration::minutes(1), now);
+        assert_eq!(
+            vec!["expired"],
+            actual
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+}
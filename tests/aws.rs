@@ -1,8 +1,31 @@
+use std::cell::RefCell;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::rc::Rc;
 
-use awsctx::{aws::AWS, configs::Configs, ctx};
+use awsctx::{
+    aws::{DefaultMismatch, AWS},
+    config::Config,
+    configs::{
+        AuthCommand, AuthCommandConfigs, AwsCliConfigs, Configs,
+        CredentialsRoute, SessionProfiles,
+    },
+    confirm::Confirmation,
+    creds::{Credentials, TemporaryCredentials},
+    ctx,
+    effective::Override,
+    lock::{self, ContextLock},
+    manifest::{Change, Manifest},
+    mfa::MfaCode,
+    rename,
+    setting::Setting,
+    storage::{FileStorage, Storage},
+    trash::DeletedProfile,
+};
+use chrono::{Duration, SecondsFormat, SubsecRound, Utc};
+use maplit::hashmap;
 use rstest::*;
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempDir};
 
 mod common;
 use common::*;
@@ -11,7 +34,7 @@ use common::*;
 #[case(
     configs(),
     "foo",
-    Ok(ctx::Context {name: "foo".to_string(), active: true}),
+    Ok(ctx::Context {name: "foo".to_string(), active: true, ..Default::default()}),
 )]
 #[case(
     configs(),
@@ -25,7 +48,7 @@ use common::*;
 #[case(
     configs(),
     "baz",
-    Ok(ctx::Context {name: "baz".to_string(), active: true}),
+    Ok(ctx::Context {name: "baz".to_string(), active: true, ..Default::default()}),
 )]
 // baz is not defined in configs.auth_commands and default is not set
 #[case(
@@ -83,6 +106,233 @@ fn test_aws_auth(
     }
 }
 
+#[rstest]
+fn test_aws_auth_with_argv(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let argv = |args: &[&str]| {
+        AuthCommand::Argv(args.iter().map(|a| a.to_string()).collect()).into()
+    };
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {
+            // arguments are never split nor expanded by a shell
+            "foo".to_string() => argv(&["test", "{{profile}} $HOME", "=", "foo $HOME"]),
+            "bar".to_string() => argv(&["test", "{{profile}}", "=", "foo"]),
+            "baz".to_string() => argv(&[]),
+        },
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!("foo", ctx::CTX::auth(&mut aws, "foo").unwrap().name);
+    for profile in ["bar", "baz"] {
+        assert!(matches!(
+            ctx::CTX::auth(&mut aws, profile),
+            Err(ctx::CTXError::InvalidConfigurations { .. })
+        ));
+    }
+}
+
+#[rstest]
+fn test_aws_auth_with_credential_outputs(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {
+            "bar".to_string() => r#"echo 'login as {{profile}}' >&2
+        echo '{"Version":1,"AccessKeyId":"WWWWWWWWWWW","SecretAccessKey":"WWWWWWWWWWW","SessionToken":"WWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWW","Expiration":"2099-01-01T00:00:00Z"}'"#.into(),
+            "baz".to_string() => "echo 'not credentials'".into(),
+        },
+        credential_outputs: vec!["bar".to_string(), "baz".to_string()],
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!("bar", ctx::CTX::auth(&mut aws, "bar").unwrap().name);
+    assert_eq!(
+        Some("WWWWWWWWWWW".to_string()),
+        aws.context_env("bar", None)
+            .unwrap()
+            .into_iter()
+            .find(|(key, _)| key == "AWS_ACCESS_KEY_ID")
+            .map(|(_, value)| value)
+    );
+    assert!(matches!(
+        ctx::CTX::auth(&mut aws, "baz"),
+        Err(ctx::CTXError::InvalidConfigurations { .. })
+    ));
+}
+
+#[rstest]
+fn test_aws_auth_with_web_identity(aws_credentials: NamedTempFile) {
+    let tmpdir = TempDir::new().unwrap();
+    let program = tmpdir.path().join("aws");
+    let args = tmpdir.path().join("args");
+    fs::write(
+        &program,
+        format!(
+            r#"#!/bin/sh
+echo "$@" > {}
+echo '{{"Credentials": {{"AccessKeyId": "WWWWWWWWWWW", "SecretAccessKey": "WWWWWWWWWWW", "SessionToken": "WWWWWWWWWWW", "Expiration": "2099-01-01T00:00:00Z"}}}}'
+"#,
+            args.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let token_file = tmpdir.path().join("token");
+    fs::write(&token_file, "eyJhbGciOiJSUzI1NiJ9").unwrap();
+    let aws_config = aws_config(format!(
+        "[profile ci]\nrole_arn=arn:aws:iam::123456789012:role/CI\nweb_identity_token_file={}\n",
+        token_file.display()
+    ));
+    // no auth command is configured for the profile
+    let configs = Rc::new(Configs {
+        aws_cli: AwsCliConfigs {
+            program: program.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+
+    assert_eq!("ci", ctx::CTX::auth(&mut aws, "ci").unwrap().name);
+    let args = fs::read_to_string(&args).unwrap();
+    assert!(args.contains("sts assume-role-with-web-identity"));
+    assert!(args.contains("--role-arn arn:aws:iam::123456789012:role/CI"));
+    assert!(args.contains(&format!("file://{}", token_file.display())));
+    let credentials =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    assert_eq!(
+        Some("WWWWWWWWWWW"),
+        credentials
+            .get_profile("ci")
+            .unwrap()
+            .get("aws_access_key_id")
+    );
+}
+
+#[rstest]
+fn test_aws_auth_all(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    // each command waits for the other one, which passes only when they run concurrently
+    let command = |me: &str, other: &str| {
+        format!(
+            r#"touch {dir}/{me}
+for i in $(seq 50); do [ -f {dir}/{other} ] && break; sleep 0.1; done
+[ -f {dir}/{other} ] || exit 1
+echo '{{"Version":1,"AccessKeyId":"{key}","SecretAccessKey":"{key}","SessionToken":"{key}","Expiration":"2099-01-01T00:00:00Z"}}'"#,
+            dir = tmpdir.path().display(),
+            me = me,
+            other = other,
+            key = me.to_uppercase(),
+        )
+    };
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {
+            "bar".to_string() => command("bar", "baz").into(),
+            "baz".to_string() => command("baz", "bar").into(),
+        },
+        credential_outputs: vec!["bar".to_string(), "baz".to_string()],
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let profiles =
+        vec!["bar".to_string(), "baz".to_string(), "foo".to_string()];
+
+    let actual = ctx::CTX::auth_all(&mut aws, &profiles, 2);
+    assert_eq!("bar", actual[0].as_ref().unwrap().name);
+    assert_eq!("baz", actual[1].as_ref().unwrap().name);
+    assert!(matches!(
+        actual[2],
+        Err(ctx::CTXError::NoAuthConfiguration { .. })
+    ));
+    // the files are written after the commands, keeping credentials of both
+    let credentials =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    for (profile, key) in [("bar", "BAR"), ("baz", "BAZ")] {
+        assert_eq!(
+            Some(key),
+            credentials
+                .get_profile(profile)
+                .unwrap()
+                .get("aws_access_key_id")
+        );
+    }
+    assert_eq!("baz", ctx::CTX::get_active_context(&aws).unwrap().name);
+}
+
+#[rstest]
+fn test_aws_auth_with_timeout_and_retries(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let dir = tmpdir.path().display();
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {
+            // children left by detached scripts are killed together with them
+            "foo".to_string() => AuthCommandConfigs {
+                command: format!("(sleep 2; touch {}/late) &\nwait", dir).into(),
+                timeout_seconds: Some(1),
+                retries: 0,
+            },
+            "bar".to_string() => AuthCommandConfigs {
+                command: format!("echo >> {}/attempts; exit 1", dir).into(),
+                timeout_seconds: None,
+                retries: 2,
+            },
+        },
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+
+    let actual = ctx::CTX::auth(&mut aws, "foo");
+    assert!(matches!(
+        actual,
+        Err(ctx::CTXError::AuthTimedOut {
+            timeout_seconds: 1,
+            ..
+        })
+    ));
+    // only the attached script is killed
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    assert!(tmpdir.path().join("late").exists());
+    fs::remove_file(tmpdir.path().join("late")).unwrap();
+
+    let actual = ctx::CTX::auth_all(&mut aws, &["foo".to_string()], 2);
+    assert!(matches!(
+        actual[..],
+        [Err(ctx::CTXError::AuthTimedOut {
+            timeout_seconds: 1,
+            ..
+        })]
+    ));
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    assert!(!tmpdir.path().join("late").exists());
+
+    let actual = ctx::CTX::auth(&mut aws, "bar");
+    assert!(matches!(
+        actual,
+        Err(ctx::CTXError::InvalidConfigurations { .. })
+    ));
+    assert_eq!(
+        3,
+        fs::read_to_string(tmpdir.path().join("attempts"))
+            .unwrap()
+            .lines()
+            .count()
+    );
+}
+
 #[rstest(aws_credentials, expect)]
 #[case(aws_credentials(aws_credentials_text()), contexts())]
 #[case(
@@ -101,10 +351,117 @@ fn test_aws_list_contexts(
     assert_eq!(expect, actual);
 }
 
+#[rstest]
+fn test_aws_describe_contexts(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let aws: &dyn ctx::CTX =
+        &AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let actual = aws.describe_contexts().unwrap();
+    let expect = contexts()
+        .into_iter()
+        .zip(["YYYYYYYYYYY", "ZZZZZZZZZZZ", "XXXXXXXXXXX"])
+        .map(|(c, region)| ctx::ContextMetadata {
+            name: c.name,
+            active: c.active,
+            region: Some(region.to_string()),
+            source: Some("static".to_string()),
+            ..Default::default()
+        })
+        .collect::<Vec<ctx::ContextMetadata>>();
+    assert_eq!(expect, actual);
+}
+
+#[rstest]
+fn test_aws_describe_contexts_with_sources(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+) {
+    let aws_config = aws_config(
+        r#"[profile bar]
+sso_start_url=https://example.awsapps.com/start
+
+[profile baz]
+role_arn=arn:aws:iam::123456789012:role/baz
+source_profile=foo
+"#
+        .to_string(),
+    );
+    let aws: &dyn ctx::CTX =
+        &AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let actual = aws
+        .describe_contexts()
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.name, c.source))
+        .collect::<Vec<(String, Option<String>)>>();
+    let expect = vec![
+        ("bar".to_string(), Some("sso".to_string())),
+        ("baz".to_string(), Some("role".to_string())),
+        ("foo".to_string(), Some("static".to_string())),
+    ];
+    assert_eq!(expect, actual);
+}
+
+#[rstest]
+fn test_aws_describe_contexts_with_tags(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let configs = Rc::new(Configs {
+        tags: hashmap! {
+            "bar".to_string() => vec!["prod".to_string(), "web".to_string()],
+        },
+        ..Default::default()
+    });
+    let aws: &dyn ctx::CTX =
+        &AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let actual = aws
+        .describe_contexts()
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.name, c.tags))
+        .collect::<Vec<(String, Vec<String>)>>();
+    let expect = vec![
+        (
+            "bar".to_string(),
+            vec!["prod".to_string(), "web".to_string()],
+        ),
+        ("baz".to_string(), vec![]),
+        ("foo".to_string(), vec![]),
+    ];
+    assert_eq!(expect, actual);
+}
+
+#[rstest]
+fn test_aws_describe_contexts_with_aliases(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let configs = Rc::new(Configs {
+        aliases: hashmap! {
+            "b".to_string() => "bar".to_string(),
+            "f".to_string() => "foo".to_string(),
+        },
+        ..Default::default()
+    });
+    let aws: &dyn ctx::CTX =
+        &AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let actual = aws
+        .describe_contexts()
+        .unwrap()
+        .iter()
+        .map(|c| c.name_with_aliases())
+        .collect::<Vec<String>>();
+    assert_eq!(vec!["bar (b)", "baz", "foo (f)"], actual);
+}
+
 #[rstest(aws_credentials, expect)]
 #[case(
     aws_credentials(aws_credentials_text()),
-    Ok(ctx::Context {name: "foo".to_string(),active: true,}),
+    Ok(ctx::Context {name: "foo".to_string(),active: true, ..Default::default()}),
 )]
 #[case(
     aws_credentials(aws_credentials_text_without_default()),
@@ -137,7 +494,7 @@ fn test_aws_get_active_context(
 #[rstest(input, expect)]
 #[case(
     "bar",
-    Ok(ctx::Context {name: "bar".to_string(), active: true}),
+    Ok(ctx::Context {name: "bar".to_string(), active: true, ..Default::default()}),
 )]
 #[case(
     "unknown",
@@ -177,3 +534,1142 @@ fn test_aws_use_context(
         _ => panic!("expect and actual are not match"),
     }
 }
+
+#[rstest(session_profiles, expect_profile)]
+#[case(SessionProfiles::default(), "bar")]
+#[case(
+    SessionProfiles { enabled: true, ..Default::default() },
+    "bar-session",
+)]
+fn test_aws_store_session_credentials(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+    session_profiles: SessionProfiles,
+    expect_profile: &str,
+) {
+    let configs = Rc::new(Configs {
+        session_profiles,
+        ..Default::default()
+    });
+    let credentials = TemporaryCredentials {
+        access_key_id: "WWWWWWWWWWW".to_string(),
+        secret_access_key: "WWWWWWWWWWW".to_string(),
+        session_token: "WWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWWW"
+            .to_string(),
+        expiration: Utc::now() + Duration::hours(1),
+    };
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    // expirations are written to the credentials in seconds
+    let expect = ctx::Context {
+        name: "bar".to_string(),
+        active: true,
+        expires_at: Some(credentials.expiration.trunc_subsecs(0)),
+        ..Default::default()
+    };
+
+    let actual = aws.store_session_credentials("bar", &credentials).unwrap();
+    assert_eq!(expect, actual);
+
+    let aws: &dyn ctx::CTX = &aws;
+    assert_eq!(expect, aws.get_active_context().unwrap());
+    // session profiles are not listed as contexts
+    assert_eq!(
+        vec!["bar", "baz", "foo"],
+        aws.list_contexts()
+            .unwrap()
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<&str>>()
+    );
+    let written =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    assert_eq!(
+        expect_profile,
+        written.get_default_profile().unwrap().name.as_str()
+    );
+}
+
+#[rstest]
+fn test_aws_repair_default_profile(
+    configs: Rc<Configs>,
+    aws_config: NamedTempFile,
+) {
+    let aws_credentials = aws_credentials(
+        r#"[bar]
+aws_access_key_id=YYYYYYYYYYY
+aws_secret_access_key=YYYYYYYYYYY
+
+[foo]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+
+[default]
+aws_access_key_id=YYYYYYYYYYY
+aws_secret_access_key=ZZZZZZZZZZZ
+"#
+        .to_string(),
+    );
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(matches!(
+        ctx::CTX::get_active_context(&aws),
+        Err(ctx::CTXError::DefaultDrifted { .. })
+    ));
+
+    let actual = aws.repair_default_profile(None).unwrap();
+    assert_eq!("bar", actual.name);
+    let credentials =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    assert_eq!("bar", credentials.get_default_profile().unwrap().name);
+}
+
+#[rstest]
+fn test_aws_default_mismatch(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+) {
+    let aws_config = aws_config(
+        r#"[profile bar]
+output=text
+region=YYYYYYYYYYY
+
+[profile foo]
+region=XXXXXXXXXXX
+
+[default]
+output=text
+region=YYYYYYYYYYY
+"#
+        .to_string(),
+    );
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!(
+        Some(DefaultMismatch {
+            credentials: "foo".to_string(),
+            config: "bar".to_string(),
+        }),
+        aws.default_mismatch()
+    );
+
+    aws.repair_default_profile(Some("bar")).unwrap();
+    assert_eq!(None, aws.default_mismatch());
+}
+
+#[rstest(::trace)]
+// profiles equal to each other
+#[case("[profile bar]\nregion=XXXXXXXXXXX\n\n[profile foo]\nregion=XXXXXXXXXXX\n\n[default]\nregion=XXXXXXXXXXX\n")]
+// the region of the default section overridden by `use-context foo --region`
+#[case("[profile bar]\nregion=YYYYYYYYYYY\n\n[profile foo]\nregion=XXXXXXXXXXX\n\n[default]\nregion=YYYYYYYYYYY\n")]
+fn test_aws_default_mismatch_prefers_credentials(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    #[case] config_text: &str,
+) {
+    let aws_config = aws_config(config_text.to_string());
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!(None, aws.default_mismatch());
+    assert_eq!("foo", ctx::CTX::get_active_context(&aws).unwrap().name);
+}
+
+#[rstest]
+fn test_aws_use_context_changed_on_disk(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    let mut forced_aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path())
+            .unwrap()
+            .with_force(true);
+    // another process writes the credentials after they are loaded
+    let mut text = aws_credentials_text();
+    text.push_str("\n[qux]\naws_access_key_id=QQQQQQQQQQQ\n");
+    std::fs::write(aws_credentials.path(), text).unwrap();
+
+    match ctx::CTX::use_context(&mut aws, "bar") {
+        Err(ctx::CTXError::FileChangedOnDisk { path, .. }) => {
+            assert_eq!(aws_credentials.path().to_str().unwrap(), path)
+        }
+        actual => panic!("unexpected result: {:?}", actual),
+    }
+    let actual = ctx::CTX::use_context(&mut forced_aws, "bar").unwrap();
+    assert_eq!("bar", actual.name);
+}
+
+#[rstest]
+fn test_aws_use_context_locked(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let lock_path = tmpdir.path().join("lock.json");
+    let mut aws = AWS::new(configs, aws_credentials.path(), aws_config.path())
+        .unwrap()
+        .with_lock(&lock_path);
+    lock::acquire(&lock_path, &ContextLock::new("foo", None, false)).unwrap();
+
+    match ctx::CTX::use_context(&mut aws, "bar") {
+        Err(ctx::CTXError::ContextLocked {
+            context, holder, ..
+        }) => {
+            assert_eq!("foo", context);
+            assert!(holder.contains(&std::process::id().to_string()));
+        }
+        actual => panic!("unexpected result: {:?}", actual),
+    }
+    // the locked context itself can be refreshed
+    assert!(ctx::CTX::use_context(&mut aws, "foo").is_ok());
+
+    lock::release(&lock_path, None).unwrap();
+    assert_eq!("bar", ctx::CTX::use_context(&mut aws, "bar").unwrap().name);
+}
+
+#[rstest]
+fn test_aws_use_context_in_region(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    let actual =
+        ctx::CTX::use_context_in_region(&mut aws, "bar", "eu-west-1").unwrap();
+    assert_eq!("bar", actual.name);
+
+    let text = std::fs::read_to_string(aws_config.path()).unwrap();
+    assert!(text.contains("[default]\noutput=YYYYYYYYYYY\nregion=eu-west-1\n"));
+    assert!(text
+        .contains("[profile bar]\noutput=YYYYYYYYYYY\nregion=YYYYYYYYYYY\n"));
+
+    // the overridden region is kept for the active context on the next load
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    let region_of = |aws: &AWS, name: &str| {
+        ctx::CTX::describe_contexts(aws)
+            .unwrap()
+            .into_iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.region)
+    };
+    assert_eq!(Some("eu-west-1".to_string()), region_of(&aws, "bar"));
+
+    // switching without a region resets the override
+    ctx::CTX::use_context(&mut aws, "bar").unwrap();
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!(Some("YYYYYYYYYYY".to_string()), region_of(&aws, "bar"));
+}
+
+/// Storage keeping texts of the files in memory
+#[derive(Debug, Default)]
+struct MemoryStorage {
+    config: Rc<RefCell<String>>,
+    credentials: Rc<RefCell<String>>,
+}
+
+impl Storage for MemoryStorage {
+    fn load_config(&self) -> Result<Config, ctx::CTXError> {
+        Config::from_text(&self.config.borrow())
+    }
+
+    fn load_credentials(&self) -> Result<Credentials, ctx::CTXError> {
+        Credentials::from_text(&self.credentials.borrow())
+    }
+
+    fn store_config(&self, config: &Config) -> Result<(), ctx::CTXError> {
+        *self.config.borrow_mut() = config.to_string();
+        Ok(())
+    }
+
+    fn store_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<(), ctx::CTXError> {
+        *self.credentials.borrow_mut() = credentials.to_string();
+        Ok(())
+    }
+}
+
+#[rstest]
+fn test_aws_with_storage(configs: Rc<Configs>) {
+    let storage = MemoryStorage {
+        config: Rc::new(RefCell::new(aws_config_text())),
+        credentials: Rc::new(RefCell::new(aws_credentials_text())),
+    };
+    let credentials = Rc::clone(&storage.credentials);
+    let mut aws = AWS::with_storage(configs, Box::new(storage)).unwrap();
+    assert_eq!("foo", ctx::CTX::get_active_context(&aws).unwrap().name);
+
+    ctx::CTX::use_context(&mut aws, "bar").unwrap();
+    let stored = Credentials::from_text(&credentials.borrow()).unwrap();
+    assert_eq!("bar", stored.get_default_profile().unwrap().name);
+}
+
+#[rstest]
+fn test_aws_reload_if_changed(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(!aws.reload_if_changed().unwrap());
+
+    let mut text = aws_credentials_text();
+    text.push_str("\n[qux]\naws_access_key_id=QQQQQQQQQQQ\n");
+    std::fs::write(aws_credentials.path(), text).unwrap();
+    assert!(aws.reload_if_changed().unwrap());
+    assert!(ctx::CTX::list_contexts(&aws)
+        .unwrap()
+        .iter()
+        .any(|c| c.name == "qux"));
+    // switching works on the reloaded files without conflicts
+    assert_eq!("bar", ctx::CTX::use_context(&mut aws, "bar").unwrap().name);
+}
+
+#[rstest]
+fn test_aws_context_env(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let expect = vec![
+        ("AWS_ACCESS_KEY_ID".to_string(), "YYYYYYYYYYY".to_string()),
+        (
+            "AWS_SECRET_ACCESS_KEY".to_string(),
+            "YYYYYYYYYYY".to_string(),
+        ),
+        (
+            "AWS_SESSION_TOKEN".to_string(),
+            "YYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYY".to_string(),
+        ),
+        ("AWS_REGION".to_string(), "YYYYYYYYYYY".to_string()),
+        ("AWS_DEFAULT_REGION".to_string(), "YYYYYYYYYYY".to_string()),
+    ];
+    assert_eq!(expect, aws.context_env("bar", None).unwrap());
+    assert_eq!(
+        ("AWS_DEFAULT_REGION".to_string(), "eu-west-1".to_string()),
+        aws.context_env("bar", Some("eu-west-1"))
+            .unwrap()
+            .pop()
+            .unwrap()
+    );
+    assert!(matches!(
+        aws.context_env("qux", None),
+        Err(ctx::CTXError::NoSuchProfile { .. })
+    ));
+}
+
+#[rstest]
+fn test_aws_delete_and_restore_profile(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let trash = TempDir::new().unwrap();
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(matches!(
+        aws.delete_profile("foo", Some(trash.path())),
+        Err(ctx::CTXError::InvalidInput { .. })
+    ));
+    assert!(matches!(
+        aws.delete_profile("qux", Some(trash.path())),
+        Err(ctx::CTXError::NoSuchProfile { .. })
+    ));
+
+    let deleted = aws.delete_profile("bar", Some(trash.path())).unwrap();
+    assert!(deleted.credentials.is_some() && deleted.config.is_some());
+    let credentials = Credentials::load_credentials(aws_credentials.path())
+        .unwrap()
+        .list_profiles();
+    assert_eq!(vec!["baz", "foo"], names(&credentials));
+    assert!(Config::load_config(aws_config.path())
+        .unwrap()
+        .profile("bar")
+        .is_none());
+
+    aws.restore_profile("bar", Some(trash.path())).unwrap();
+    assert_eq!(
+        vec!["bar", "baz", "foo"],
+        names(
+            &Credentials::load_credentials(aws_credentials.path())
+                .unwrap()
+                .list_profiles()
+        )
+    );
+    assert!(Config::load_config(aws_config.path())
+        .unwrap()
+        .profile("bar")
+        .is_some());
+    // the snippet is discarded once restored
+    assert_eq!(None, DeletedProfile::load("bar", Some(trash.path())));
+}
+
+#[rstest]
+fn test_aws_rename_profiles(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let renames = rename::plan(
+        &aws.profile_names().unwrap(),
+        "(ba|fo)(.)",
+        "client-$1$2",
+    )
+    .unwrap();
+    aws.rename_profiles(&renames).unwrap();
+    let credentials = Credentials::load_credentials(aws_credentials.path())
+        .unwrap()
+        .list_profiles();
+    assert_eq!(
+        vec!["client-bar", "client-baz", "client-foo"],
+        names(&credentials)
+    );
+    // the active context stays active by the new name
+    assert_eq!(
+        "client-foo",
+        ctx::CTX::get_active_context(&aws).unwrap().name
+    );
+    let config = Config::load_config(aws_config.path()).unwrap();
+    assert!(config.profile("client-bar").is_some());
+    assert!(config.profile("bar").is_none());
+}
+
+#[rstest]
+fn test_aws_plan_and_apply_settings(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let names = aws.profile_names().unwrap();
+    let settings = aws.plan_settings(&names, "output", "YYYYYYYYYYY").unwrap();
+    // bar has the value already
+    assert_eq!(
+        vec![
+            Setting {
+                profile: "baz".to_string(),
+                key: "output".to_string(),
+                from: Some("ZZZZZZZZZZZ".to_string()),
+                to: "YYYYYYYYYYY".to_string(),
+            },
+            Setting {
+                profile: "foo".to_string(),
+                key: "output".to_string(),
+                from: Some("XXXXXXXXXXX".to_string()),
+                to: "YYYYYYYYYYY".to_string(),
+            },
+        ],
+        settings
+    );
+    aws.apply_settings(&settings).unwrap();
+    aws.apply_settings(&aws.plan_settings(&names, "cli_pager", "").unwrap())
+        .unwrap();
+
+    let config = Config::load_config(aws_config.path()).unwrap();
+    for name in names.iter() {
+        let profile = config.profile(name).unwrap();
+        assert_eq!(Some("YYYYYYYYYYY"), profile.get("output"));
+        assert_eq!(Some(""), profile.get("cli_pager"));
+    }
+    assert!(aws
+        .plan_settings(&names, "output", "YYYYYYYYYYY")
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+fn test_aws_credentials_routes(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let routed_path = tmpdir.path().join("credentials.clients");
+    let configs = Rc::new(Configs {
+        credentials_routes: vec![CredentialsRoute {
+            pattern: "ba.".to_string(),
+            path: routed_path.clone(),
+        }],
+        ..Default::default()
+    });
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    ctx::CTX::use_context(&mut aws, "bar").unwrap();
+    let names = |path: &std::path::Path| {
+        Credentials::load_credentials(path)
+            .unwrap()
+            .list_profiles()
+            .into_iter()
+            .map(|p| p.name)
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(vec!["foo"], names(aws_credentials.path()));
+    assert_eq!(vec!["bar", "baz"], names(&routed_path));
+    // the default section stays in the credentials file for the AWS CLI
+    assert!(fs::read_to_string(aws_credentials.path())
+        .unwrap()
+        .contains("[default]"));
+
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert_eq!("bar", ctx::CTX::get_active_context(&aws).unwrap().name);
+    assert_eq!(
+        vec!["bar", "baz", "foo"],
+        ctx::CTX::list_contexts(&aws)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<String>>()
+    );
+}
+
+#[rstest]
+fn test_file_storage_routed_credentials_changed_on_disk(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let routed_path = tmpdir.path().join("credentials.clients");
+    // broken lines of routed files are skipped in the lenient mode as well
+    fs::write(
+        &routed_path,
+        "[bar]\naws_access_key_id=YYYYYYYYYYY\naws_secret_access_key=YYYYYYYYYYY\n\n[broken\n",
+    )
+    .unwrap();
+    let storage = FileStorage::new(aws_credentials.path(), aws_config.path())
+        .with_lenient(true)
+        .with_credentials_routes(vec![CredentialsRoute {
+            pattern: "ba.".to_string(),
+            path: routed_path.clone(),
+        }]);
+    let credentials = storage.load_credentials().unwrap();
+    storage.load_config().unwrap();
+    assert!(storage.ensure_unchanged().is_ok());
+
+    fs::write(&routed_path, "[baz]\naws_access_key_id=ZZZZZZZZZZZ\n").unwrap();
+    assert!(matches!(
+        storage.ensure_unchanged(),
+        Err(ctx::CTXError::FileChangedOnDisk { path, .. })
+            if path == routed_path.to_str().unwrap()
+    ));
+
+    storage.store_credentials(&credentials).unwrap();
+    assert!(storage.ensure_unchanged().is_ok());
+}
+
+#[rstest]
+fn test_file_storage_pending_writes(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let routed_path = tmpdir.path().join("credentials.clients");
+    let storage = FileStorage::new(aws_credentials.path(), aws_config.path())
+        .with_credentials_routes(vec![CredentialsRoute {
+            pattern: "ba.".to_string(),
+            path: routed_path.clone(),
+        }]);
+    let credentials = storage.load_credentials().unwrap();
+    let config = storage.load_config().unwrap();
+
+    let writes = storage.pending_writes(Some(&credentials), None).unwrap();
+    assert_eq!(
+        vec![routed_path, aws_credentials.path().to_path_buf()],
+        writes.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>()
+    );
+    assert!(writes[0].1.contains("[bar]") && !writes[0].1.contains("[foo]"));
+    let writes = storage.pending_writes(None, Some(&config)).unwrap();
+    assert_eq!(
+        vec![(aws_config.path().to_path_buf(), config.to_string())],
+        writes
+    );
+}
+
+#[rstest(::trace)]
+#[case(false, false)]
+#[case(true, true)]
+fn test_aws_use_context_with_diff_confirmation(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+    #[case] yes: bool,
+    #[case] written: bool,
+) {
+    let before = fs::read_to_string(aws_credentials.path()).unwrap();
+    let mut aws = AWS::new(
+        Rc::new(Configs::default()),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap()
+    .with_diff_confirmation(Some(Confirmation {
+        yes,
+        ..Default::default()
+    }));
+    let result = ctx::CTX::use_context(&mut aws, "foo");
+    assert_eq!(written, result.is_ok());
+    assert_eq!(
+        written,
+        before != fs::read_to_string(aws_credentials.path()).unwrap()
+    );
+}
+
+#[rstest]
+fn test_aws_config_fragments(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    fs::write(
+        tmpdir.path().join("clients"),
+        "[profile client]\nrole_arn=arn:aws:iam::123456789012:role/Admin\nsource_profile=foo\n",
+    )
+    .unwrap();
+    let configs = Rc::new(Configs {
+        config_fragments_dir: Some(tmpdir.path().to_path_buf()),
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(aws.profile_names().unwrap().contains(&"client".to_string()));
+    assert!(aws
+        .delete_profile("client", Some(tmpdir.path().join("trash")))
+        .is_err());
+
+    ctx::CTX::use_context(&mut aws, "bar").unwrap();
+    // profiles of fragments are never written to the config
+    assert!(!fs::read_to_string(aws_config.path())
+        .unwrap()
+        .contains("client"));
+}
+
+#[rstest]
+fn test_aws_apply_manifest(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    // changes of the configurations are out of the files
+    let configs = Rc::new(Configs::default());
+    let manifest = serde_yaml::from_str::<Manifest>(
+        r#"profiles:
+  - name: bar
+    role_arn: arn:aws:iam::123456789012:role/Admin
+    source_profile: foo
+  - name: qux
+    region: us-east-1
+"#,
+    )
+    .unwrap();
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    let changes =
+        aws.plan_manifest(&manifest, &["bar".to_string(), "baz".to_string()]);
+    assert_eq!(
+        vec![
+            Change::Update("bar".to_string()),
+            Change::Add("qux".to_string()),
+            Change::Remove("baz".to_string()),
+        ],
+        changes
+    );
+    aws.apply_manifest(&manifest, &changes, Some(tmpdir.path()))
+        .unwrap();
+
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(aws.plan_manifest(&manifest, &[]).is_empty());
+    assert_eq!(
+        vec!["bar", "foo", "qux"],
+        ctx::CTX::list_contexts(&aws)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<String>>()
+    );
+    // access keys of existing profiles are kept, and new ones are left empty
+    let credentials =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    assert_eq!(
+        Some("YYYYYYYYYYY"),
+        credentials.profile("bar").unwrap().get("aws_access_key_id")
+    );
+    assert_eq!(
+        Some(""),
+        credentials.profile("qux").unwrap().get("aws_access_key_id")
+    );
+    assert!(DeletedProfile::load("baz", Some(tmpdir.path())).is_some());
+}
+
+#[rstest(::trace)]
+#[case(None, Some("foo"), false)]
+#[case(Some(Override::Profile { variable: "AWS_PROFILE".to_string(), name: "foo".to_string() }), Some("foo"), false)]
+#[case(Some(Override::Profile { variable: "AWS_PROFILE".to_string(), name: "bar".to_string() }), Some("bar"), true)]
+#[case(Some(Override::Credentials { access_key_id: "YYYYYYYYYYY".to_string() }), Some("bar"), true)]
+#[case(Some(Override::Credentials { access_key_id: "WWWWWWWWWWW".to_string() }), None, true)]
+fn test_aws_effective_context(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+    #[case] overridden_by: Option<Override>,
+    #[case] expect: Option<&str>,
+    #[case] discrepant: bool,
+) {
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let effective = aws.effective_context(overridden_by);
+    assert_eq!(expect, effective.name.as_deref());
+    assert_eq!(Some("foo"), effective.active.as_deref());
+    assert_eq!(discrepant, effective.is_discrepant());
+}
+
+#[rstest]
+fn test_aws_adopt_profiles(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let text = fs::read_to_string(aws_config.path()).unwrap();
+    fs::write(
+        aws_config.path(),
+        format!(
+            r#"{}
+[profile corp]
+sso_session=corp
+sso_account_id=123456789012
+sso_role_name=Admin
+
+[profile legacy]
+sso_start_url=https://example.awsapps.com/start
+sso_region=us-east-1
+
+[sso-session corp]
+sso_start_url=https://example.awsapps.com/start
+sso_region=us-east-1
+"#,
+            text
+        ),
+    )
+    .unwrap();
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {"legacy".to_string() => "echo 1".into()},
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    // profiles with their own auth commands are adopted already
+    let names = aws.adoptable_profiles();
+    assert_eq!(vec!["corp"], names);
+    assert_eq!(vec!["corp"], aws.adopt_profiles(&names).unwrap());
+    assert!(aws.profile_names().unwrap().contains(&"corp".to_string()));
+    assert!(aws.adopt_profiles(&names).unwrap().is_empty());
+    // placeholders have no credentials until the first auth
+    let corp = aws.describe_context(ctx::Context {
+        name: "corp".to_string(),
+        active: false,
+        ..Default::default()
+    });
+    assert!(corp.missing_credentials);
+}
+
+#[rstest]
+fn test_aws_export_manifest(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let aws = AWS::new(
+        Rc::new(Configs::default()),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    let manifest = aws
+        .export_manifest(Some(tmpdir.path().join("adhoc_contexts.json")))
+        .unwrap();
+    assert!(!manifest.to_yaml().unwrap().contains("aws_"));
+    // the exported manifest describes the profiles as they are
+    let names = manifest
+        .profiles
+        .iter()
+        .map(|p| p.name.clone())
+        .collect::<Vec<String>>();
+    assert_eq!(vec!["bar", "baz", "foo"], names);
+    assert!(aws.plan_manifest(&manifest, &names).is_empty());
+}
+
+#[rstest]
+fn test_aws_add_config_profiles(
+    configs: Rc<Configs>,
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let items = hashmap! {
+        "role_arn".to_string() => "arn:aws:iam::123456789012:role/Admin".to_string(),
+        "source_profile".to_string() => "foo".to_string(),
+    };
+    let added = aws
+        .add_config_profiles(vec![
+            ("qux".to_string(), items.clone()),
+            ("bar".to_string(), items),
+        ])
+        .unwrap();
+    // existing profiles are never overwritten
+    assert_eq!(vec!["qux"], added);
+    let config = Config::load_config(aws_config.path()).unwrap();
+    assert_eq!(
+        Some("foo"),
+        config.profile("qux").unwrap().get("source_profile")
+    );
+    assert_eq!(None, config.profile("bar").unwrap().get("role_arn"));
+}
+
+#[rstest]
+fn test_aws_assume_role(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let adhoc = tmpdir.path().join("adhoc_contexts.json");
+    let program = tmpdir.path().join("aws");
+    // credentials of the active context are used, and expire in the past
+    fs::write(
+        &program,
+        r#"#!/bin/sh
+[ "$3 $4" = "--profile foo" ] || exit 1
+echo '{"Credentials": {"AccessKeyId": "WWWWWWWWWWW", "SecretAccessKey": "WWWWWWWWWWW", "SessionToken": "WWWWWWWWWWW", "Expiration": "2022-01-01T00:00:00Z"}}'
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let configs = Rc::new(Configs {
+        aws_cli: AwsCliConfigs {
+            program: program.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    assert!(matches!(
+        aws.assume_role(
+            "arn:aws:iam::123456789012:role/Admin",
+            Some("bar"),
+            None,
+            Some(&adhoc)
+        ),
+        Err(ctx::CTXError::InvalidInput { .. })
+    ));
+
+    let context = aws
+        .assume_role(
+            "arn:aws:iam::123456789012:role/Admin",
+            None,
+            None,
+            Some(&adhoc),
+        )
+        .unwrap();
+    assert_eq!("Admin@123456789012", context.name);
+    assert!(context.active);
+    assert!(Config::load_config(aws_config.path())
+        .unwrap()
+        .profile("Admin@123456789012")
+        .is_some());
+
+    // expired ad-hoc contexts are removed unless they are active
+    ctx::CTX::use_context(&mut aws, "foo").unwrap();
+    aws.assume_role(
+        "arn:aws:iam::123456789012:role/Dev",
+        None,
+        None,
+        Some(&adhoc),
+    )
+    .unwrap();
+    assert_eq!(
+        vec!["Dev@123456789012", "bar", "baz", "foo"],
+        names(
+            &Credentials::load_credentials(aws_credentials.path())
+                .unwrap()
+                .list_profiles()
+        )
+    );
+}
+
+#[rstest]
+fn test_aws_use_context_with_mfa(aws_credentials: NamedTempFile) {
+    let tmpdir = TempDir::new().unwrap();
+    let program = tmpdir.path().join("aws");
+    // the role is assumed with credentials of the source profile and the code
+    fs::write(
+        &program,
+        r#"#!/bin/sh
+case "$*" in *"--serial-number arn:aws:iam::123456789012:mfa/foo --token-code 123456 --profile foo"*) ;; *) exit 1 ;; esac
+echo '{"Credentials": {"AccessKeyId": "WWWWWWWWWWW", "SecretAccessKey": "WWWWWWWWWWW", "SessionToken": "WWWWWWWWWWW", "Expiration": "2099-01-01T00:00:00Z"}}'
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let aws_config = aws_config(
+        r#"[profile admin]
+role_arn=arn:aws:iam::123456789012:role/Admin
+source_profile=foo
+mfa_serial=arn:aws:iam::123456789012:mfa/foo
+
+[profile foo]
+mfa_serial=arn:aws:iam::123456789012:mfa/foo
+"#
+        .to_string(),
+    );
+    let configs = Rc::new(Configs {
+        aws_cli: AwsCliConfigs {
+            program: program.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    let mut aws = aws.with_mfa_code(None);
+    assert!(aws.interaction_needed("admin").is_some());
+    assert!(matches!(
+        ctx::CTX::use_context(&mut aws, "admin"),
+        Err(ctx::CTXError::InvalidInput { .. })
+    ));
+    // session credentials never overwrite long-lived keys without session profiles
+    assert!(matches!(
+        ctx::CTX::auth(&mut aws, "foo"),
+        Err(ctx::CTXError::InvalidConfigurations { .. })
+    ));
+
+    let mut aws = aws.with_mfa_code(Some(MfaCode::Given("123456".to_string())));
+    let context = ctx::CTX::use_context(&mut aws, "admin").unwrap();
+    assert_eq!("admin", context.name);
+    assert!(context.active);
+    let credentials =
+        Credentials::load_credentials(aws_credentials.path()).unwrap();
+    assert_eq!(
+        Some("WWWWWWWWWWW"),
+        credentials
+            .get_profile("admin")
+            .unwrap()
+            .get("aws_access_key_id")
+    );
+    // the credentials are reused until they expire
+    fs::write(
+        &program,
+        "#!/bin/sh
+exit 1
+",
+    )
+    .unwrap();
+    ctx::CTX::use_context(&mut aws, "foo").unwrap();
+    ctx::CTX::use_context(&mut aws, "admin").unwrap();
+}
+
+#[rstest]
+fn test_aws_assume_with_credentials_cache(aws_credentials: NamedTempFile) {
+    let tmpdir = TempDir::new().unwrap();
+    let program = tmpdir.path().join("aws");
+    let calls = tmpdir.path().join("calls");
+    fs::write(
+        &program,
+        format!(
+            r#"#!/bin/sh
+echo >> {}
+echo '{{"Credentials": {{"AccessKeyId": "WWWWWWWWWWW", "SecretAccessKey": "WWWWWWWWWWW", "SessionToken": "WWWWWWWWWWW", "Expiration": "2099-01-01T00:00:00Z"}}}}'
+"#,
+            calls.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let aws_config = aws_config(
+        "[profile admin]\nrole_arn=arn:aws:iam::123456789012:role/Admin\nsource_profile=foo\n\n\
+        [profile admin-ext]\nrole_arn=arn:aws:iam::123456789012:role/Admin\nsource_profile=foo\nexternal_id=XXXX\n\n\
+        [profile admin-bar]\nrole_arn=arn:aws:iam::123456789012:role/Admin\nsource_profile=bar\n\n\
+        [profile foo]\n\n[profile bar]\n"
+            .to_string(),
+    );
+    let configs = Rc::new(Configs {
+        aws_cli: AwsCliConfigs {
+            program: program.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let count = || fs::read_to_string(&calls).unwrap().lines().count();
+    let mut aws = AWS::new(
+        Rc::clone(&configs),
+        aws_credentials.path(),
+        aws_config.path(),
+    )
+    .unwrap();
+    ctx::CTX::assume(&mut aws, "admin").unwrap();
+    ctx::CTX::assume(&mut aws, "admin").unwrap();
+    assert_eq!(2, count());
+
+    // the role is assumed once while its credentials are cached
+    let mut aws = aws.with_credentials_cache(tmpdir.path().join("cache.json"));
+    ctx::CTX::assume(&mut aws, "admin").unwrap();
+    ctx::CTX::assume(&mut aws, "admin").unwrap();
+    assert_eq!(3, count());
+
+    // credentials of the same role by another external ID or source are never shared
+    ctx::CTX::assume(&mut aws, "admin-ext").unwrap();
+    assert_eq!(4, count());
+    ctx::CTX::assume(&mut aws, "admin-bar").unwrap();
+    assert_eq!(5, count());
+    ctx::CTX::assume(&mut aws, "admin-ext").unwrap();
+    assert_eq!(5, count());
+}
+
+#[rstest]
+fn test_aws_use_context_with_verify_identity(
+    aws_credentials: NamedTempFile,
+    aws_config: NamedTempFile,
+) {
+    let tmpdir = TempDir::new().unwrap();
+    let program = tmpdir.path().join("aws");
+    // keys of bar are rejected, and ones of foo are accepted
+    fs::write(
+        &program,
+        r#"#!/bin/sh
+case "$AWS_ACCESS_KEY_ID" in
+  XXXXXXXXXXX) echo '{"UserId": "AIDAXXXXXXXXXXXXXXXXX", "Account": "123456789012", "Arn": "arn:aws:iam::123456789012:user/foo"}' ;;
+  *) echo 'An error occurred (InvalidClientTokenId) when calling the GetCallerIdentity operation' >&2; exit 254 ;;
+esac
+"#,
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let configs = Rc::new(Configs {
+        aws_cli: AwsCliConfigs {
+            program: program.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        verify_identity: true,
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+
+    match ctx::CTX::use_context(&mut aws, "bar") {
+        Err(ctx::CTXError::CredentialsInvalid { profile, .. }) => {
+            assert_eq!("bar", profile)
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // the rejected context is never switched to
+    assert_eq!("foo", ctx::CTX::get_active_context(&aws).unwrap().name);
+
+    let context = ctx::CTX::use_context(&mut aws, "foo").unwrap();
+    assert_eq!(Some("123456789012".to_string()), context.account);
+    assert_eq!(
+        Some("arn:aws:iam::123456789012:user/foo".to_string()),
+        context.arn
+    );
+}
+
+#[rstest]
+#[case(true, "2099-01-01T00:00:00Z", 1)]
+#[case(false, "2000-01-01T00:00:00Z", 0)]
+fn test_aws_use_context_with_auto_refresh(
+    aws_config: NamedTempFile,
+    #[case] auto_refresh: bool,
+    #[case] expect_expiration: &str,
+    #[case] expect_runs: usize,
+) {
+    let aws_credentials = aws_credentials(
+        r#"[bar]
+aws_access_key_id=YYYYYYYYYYY
+aws_secret_access_key=YYYYYYYYYYY
+aws_session_token=YYYYYYYYYYY
+expiration=2000-01-01T00:00:00Z
+
+[foo]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+
+[default]
+aws_access_key_id=XXXXXXXXXXX
+aws_secret_access_key=XXXXXXXXXXX
+"#
+        .to_string(),
+    );
+    let tmpdir = TempDir::new().unwrap();
+    let runs = tmpdir.path().join("runs");
+    let renewed = tmpdir.path().join("credentials");
+    // the auth command renews the session of the profile in the credentials by itself
+    let configs = Rc::new(Configs {
+        auth_commands: hashmap! {
+            "bar".to_string() => format!(
+                "echo {{{{profile}}}} >> {runs} && sed 's/2000-01-01/2099-01-01/' {path} > {renewed} && cat {renewed} > {path}",
+                runs = runs.display(),
+                path = aws_credentials.path().display(),
+                renewed = renewed.display(),
+            )
+            .into(),
+        },
+        auto_refresh,
+        ..Default::default()
+    });
+    let mut aws =
+        AWS::new(configs, aws_credentials.path(), aws_config.path()).unwrap();
+    // callers without the terminal, like the daemon, refuse switches running the auth
+    assert_eq!(auto_refresh, aws.interaction_needed("bar").is_some());
+    assert_eq!(None, aws.interaction_needed("foo"));
+
+    let context = ctx::CTX::use_context(&mut aws, "bar").unwrap();
+    assert_eq!("bar", context.name);
+    assert!(context.active);
+    assert_eq!(None, aws.interaction_needed("bar"));
+    assert_eq!(
+        Some(expect_expiration.to_string()),
+        context
+            .expires_at
+            .map(|e| e.to_rfc3339_opts(SecondsFormat::Secs, true))
+    );
+    assert_eq!(
+        expect_runs,
+        fs::read_to_string(&runs)
+            .unwrap_or_default()
+            .lines()
+            .count()
+    );
+}
+
+fn names(profiles: &[awsctx::creds::Profile]) -> Vec<&str> {
+    profiles.iter().map(|p| p.name.as_str()).collect()
+}
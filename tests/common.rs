@@ -127,14 +127,17 @@ pub fn contexts() -> Vec<ctx::Context> {
         ctx::Context {
             name: "bar".to_string(),
             active: false,
+            ..Default::default()
         },
         ctx::Context {
             name: "baz".to_string(),
             active: false,
+            ..Default::default()
         },
         ctx::Context {
             name: "foo".to_string(),
             active: true,
+            ..Default::default()
         },
     ]
 }
@@ -145,10 +148,12 @@ pub fn contexts_without_default() -> Vec<ctx::Context> {
         ctx::Context {
             name: "bar".to_string(),
             active: false,
+            ..Default::default()
         },
         ctx::Context {
             name: "foo".to_string(),
             active: false,
+            ..Default::default()
         },
     ]
 }
@@ -157,10 +162,11 @@ pub fn contexts_without_default() -> Vec<ctx::Context> {
 pub fn configs() -> Rc<Configs> {
     Rc::new(Configs {
         auth_commands: hashmap! {
-            "foo".to_string() => "echo auth".to_string(),
-            "bar".to_string() => "exit 1".to_string(),
-            Configs::DEFAULT_AUTH_COMMAND_KEY.to_string() => "echo default auth".to_string(),
+            "foo".to_string() => "echo auth".into(),
+            "bar".to_string() => "exit 1".into(),
+            Configs::DEFAULT_AUTH_COMMAND_KEY.to_string() => "echo default auth".into(),
         },
+        ..Default::default()
     })
 }
 
@@ -168,8 +174,9 @@ pub fn configs() -> Rc<Configs> {
 pub fn configs_without_default() -> Rc<Configs> {
     Rc::new(Configs {
         auth_commands: hashmap! {
-            "foo".to_string() => "echo auth".to_string(),
-            "bar".to_string() => "exit 1".to_string(),
+            "foo".to_string() => "echo auth".into(),
+            "bar".to_string() => "exit 1".into(),
         },
+        ..Default::default()
     })
 }